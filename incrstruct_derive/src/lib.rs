@@ -5,14 +5,50 @@ use std::collections::HashSet;
 extern crate proc_macro;
 use proc_macro::TokenStream;
 
-use quote::quote;
+use quote::ToTokens;
+use syn::ext::IdentExt;
 use syn::parse::Parse;
 use syn::spanned::Spanned;
 use syn::{parse_macro_input, DeriveInput, Error};
 
+/// Like `quote!`, but using `Span::mixed_site()`. Local variables in
+/// the generated code can then neither shadow nor be shadowed by
+/// user identifiers, like field names, which is what makes the
+/// derive work for structs inside functions.
+macro_rules! quote_mixed {
+    ($($tt:tt)*) => {
+        quote::quote_spanned! { proc_macro2::Span::mixed_site() => $($tt)* }
+    };
+}
+
+/// The maximum number of tail fields. This must match
+/// `incrstruct::Header::MAX_TAILS`.
+const MAX_TAILS: usize = 64;
+
 /// Derives initialization functions for a struct. See the
 /// [crate documentation](../incrstruct).
-#[proc_macro_derive(IncrStruct, attributes(borrows, header, init_err))]
+#[proc_macro_derive(
+    IncrStruct,
+    attributes(
+        borrows,
+        constructor,
+        covariant,
+        cyclic,
+        default,
+        head_err,
+        header,
+        incrstruct,
+        init,
+        init_args,
+        init_err,
+        init_trait,
+        lazy,
+        nested,
+        not_covariant,
+        phase,
+        stable_deref
+    )
+)]
 pub fn derive_incr_struct(tokens: TokenStream) -> TokenStream {
     let input = parse_macro_input!(tokens as DeriveInput);
 
@@ -22,15 +58,198 @@ pub fn derive_incr_struct(tokens: TokenStream) -> TokenStream {
     }
 }
 
+/// Reorders the fields of a struct, so every field comes before the
+/// fields it borrows, and the header comes last, and derives
+/// `IncrStruct`. A `hdr` header field is added, if there is no
+/// `#[header]` field. Arguments are passed on as `#[incrstruct(...)]`
+/// options. See the [crate documentation](../incrstruct).
+#[proc_macro_attribute]
+pub fn incrstruct(args: TokenStream, tokens: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(tokens as DeriveInput);
+
+    let args = proc_macro2::TokenStream::from(args);
+    let (options, crate_path, no_header) = if args.is_empty() {
+        (quote_mixed! {}, None, false)
+    } else {
+        let attr: syn::Attribute = syn::parse_quote! { #[incrstruct(#args)] };
+        match parse_options(std::slice::from_ref(&attr)) {
+            Ok(options) => (
+                quote_mixed! { #attr },
+                options.crate_path,
+                options.no_header,
+            ),
+            Err(err) => return err.into_compile_error().into(),
+        }
+    };
+
+    if let Err(err) = reorder_fields(&mut input, no_header) {
+        return err.into_compile_error().into();
+    }
+
+    let output = quote_mixed! {
+        #[derive(incrstruct::IncrStruct)]
+        #options
+        #input
+    };
+
+    with_crate_path(output, crate_path.as_ref()).into()
+}
+
+/// Sorts the fields topologically, so every field comes before the
+/// fields it borrows, and fields in later phases come first. The
+/// original order is kept where possible. The header is moved last,
+/// or added, with bitsets sized to the tail fields. In `no_header`
+/// mode, the added header is a `PhantomPinned`.
+fn reorder_fields(input: &mut DeriveInput, no_header: bool) -> Result<(), Error> {
+    let fields = match &mut input.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "#[incrstruct] can only be used on structs with named fields",
+            ))
+        }
+    };
+
+    let mut remaining: Vec<syn::Field> = fields.named.iter().cloned().collect();
+    let mut borrows = remaining
+        .iter()
+        .map(get_borrows)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut phases = remaining
+        .iter()
+        .map(get_init_phase)
+        .collect::<Result<Vec<_>, _>>()?;
+    let header = remaining
+        .iter()
+        .position(|field| has_attribute(&field.attrs, "header"))
+        .map(|i| {
+            borrows.remove(i);
+            phases.remove(i);
+            remaining.remove(i)
+        })
+        .unwrap_or_else(|| {
+            if no_header {
+                syn::parse_quote! {
+                    #[header]
+                    hdr: ::core::marker::PhantomPinned
+                }
+            } else {
+                // The tail bitsets are as small as the tail fields allow.
+                let num_tails = remaining
+                    .iter()
+                    .filter(|field| has_attribute(&field.attrs, "borrows"))
+                    .count();
+                let bits: syn::Type = match num_tails {
+                    0..=8 => syn::parse_quote! { u8 },
+                    9..=16 => syn::parse_quote! { u16 },
+                    17..=32 => syn::parse_quote! { u32 },
+                    _ => syn::parse_quote! { u64 },
+                };
+
+                syn::parse_quote! {
+                    #[header]
+                    hdr: incrstruct::Header<#bits>
+                }
+            }
+        });
+    let mut sorted = syn::punctuated::Punctuated::new();
+
+    while !remaining.is_empty() {
+        // A field can be placed once no remaining field borrows it.
+        let is_free = |field: &syn::Field| {
+            !borrows.iter().any(|names| {
+                field
+                    .ident
+                    .as_ref()
+                    .is_some_and(|name| names.contains(name))
+            })
+        };
+        let i = (0..remaining.len())
+            .filter(|i| is_free(&remaining[*i]))
+            .min_by_key(|i| (core::cmp::Reverse(phases[*i]), *i))
+            .ok_or_else(|| {
+                Error::new_spanned(&remaining[0], "fields borrow each other in a cycle")
+            })?;
+
+        borrows.remove(i);
+        phases.remove(i);
+        sorted.push(remaining.remove(i));
+    }
+    sorted.push(header);
+    fields.named = sorted;
+
+    Ok(())
+}
+
 fn incr_struct(input: &DeriveInput) -> Result<TokenStream, Error> {
     let data_struct = match &input.data {
-        syn::Data::Struct(data) => Ok(data),
-        syn::Data::Enum(data) => Err(data.enum_token.span()),
-        syn::Data::Union(data) => Err(data.union_token.span()),
+        syn::Data::Struct(data) => data,
+        syn::Data::Enum(data) => return incr_enum(input, data),
+        syn::Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span(),
+                "IncrStruct can only be used on structs",
+            ))
+        }
+    };
+
+    // Generated items must exist in the same configurations as the
+    // struct.
+    let cfg_attrs: Vec<&syn::Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .collect();
+
+    // Tuple fields are named like `_1` here, and turned back into
+    // indices in the output.
+    let is_tuple = matches!(data_struct.fields, syn::Fields::Unnamed(_));
+    // Field types are copied into other items, like AStructHeads,
+    // where `Self` would mean something else, so it's spelled out.
+    let (_, self_generics, _) = input.generics.split_for_impl();
+    let self_name = &input.ident;
+    let self_ty = quote_mixed! { #self_name #self_generics };
+    let own_fields: Vec<syn::Field> = data_struct
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            Ok(syn::Field {
+                ident: if is_tuple {
+                    Some(tuple_field_ident(i, field.ty.span()))
+                } else {
+                    field.ident.clone()
+                },
+                colon_token: Some(Default::default()),
+                ty: syn::parse2(replace_self(field.ty.to_token_stream(), &self_ty))?,
+                ..field.clone()
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    let mut fields: Vec<&syn::Field> = own_fields.iter().collect();
+
+    // Without tail fields, nothing is self-referencing, so the header
+    // is optional.
+    let is_plain = !fields.iter().any(|field| {
+        has_attribute(&field.attrs, "header") || has_attribute(&field.attrs, "borrows")
+    });
+    if is_plain && matches!(data_struct.fields, syn::Fields::Named(_)) {
+        return plain_struct(input, fields);
     }
-    .map_err(|span| Error::new(span, "IncrStruct can only be used on structs"))?;
+    if parse_options(&input.attrs)?.no_header {
+        if is_tuple {
+            return Err(Error::new_spanned(
+                &data_struct.fields,
+                "the no_header option can only be used on structs with named fields",
+            ));
+        }
 
-    let mut fields = get_named_fields(&input);
+        return no_header_struct(input, fields);
+    }
 
     let header = if let Some(header) = fields.pop() {
         if !has_attribute(&header.attrs, "header") {
@@ -48,6 +267,10 @@ fn incr_struct(input: &DeriveInput) -> Result<TokenStream, Error> {
         ));
     };
     let header_name = header.ident.as_ref().unwrap();
+    let header_member = match tuple_field_index(header_name) {
+        Some(index) if is_tuple => syn::Member::Unnamed(index.into()),
+        _ => syn::Member::Named(header_name.clone()),
+    };
 
     // We are mostly concerned with initialization, which means heads
     // before tails. We simply reverse the list (now that header is
@@ -56,203 +279,5803 @@ fn incr_struct(input: &DeriveInput) -> Result<TokenStream, Error> {
     // introducing it.
     fields.reverse();
 
-    let heads = find_phase(fields.as_slice(), false);
+    // Heads are passed to constructors, so they are kept in
+    // declaration order.
+    let mut heads = find_phase(fields.as_slice(), false);
+    heads.reverse();
     let tails = find_phase(fields.as_slice(), true);
     let num_tails = tails.len();
 
-    let head_params = make_field_params(heads.as_slice(), None);
-    let head_args = make_field_args(heads.as_slice(), None);
-    let tail_names = make_field_args(tails.as_slice(), None);
+    // The header tracks each tail field in a bitset.
+    if let Some(tail) = tails.get(MAX_TAILS) {
+        return Err(Error::new_spanned(
+            tail,
+            format!("at most {} tail fields are supported", MAX_TAILS),
+        ));
+    }
 
-    // Drop order is the reverse of the reverse.
-    let mut drop_head_names = head_args.clone();
-    drop_head_names.reverse();
-    let mut drop_tail_names = tail_names.clone();
-    drop_tail_names.reverse();
+    for head in heads.iter() {
+        get_init_phase(head)?;
 
-    let (generics_decls, generics_args, generics_where) = input.generics.split_for_impl();
-    let first_lifetime = input
-        .generics
-        .lifetimes()
-        .nth(0)
-        .map(|param| &param.lifetime);
+        if let Some(attr) = find_attribute(&head.attrs, "lazy") {
+            return Err(Error::new_spanned(
+                attr,
+                "#[lazy] can only be used on tail fields",
+            ));
+        }
+        if let Some(attr) = find_attribute(&head.attrs, "init") {
+            return Err(Error::new_spanned(
+                attr,
+                "#[init] can only be used on tail fields, see #[default] for head fields",
+            ));
+        }
+    }
+    for head in heads.iter() {
+        if let Some(attr) = find_attribute(&head.attrs, "covariant")
+            .or_else(|| find_attribute(&head.attrs, "not_covariant"))
+        {
+            return Err(Error::new_spanned(
+                attr,
+                "covariance attributes can only be used on tail fields",
+            ));
+        }
+    }
+    for tail in tails.iter() {
+        if has_attribute(&tail.attrs, "covariant") {
+            if let Some(attr) = find_attribute(&tail.attrs, "not_covariant") {
+                return Err(Error::new_spanned(
+                    attr,
+                    "a field can't be both #[covariant] and #[not_covariant]",
+                ));
+            }
+        }
+        if let Some(attr) = find_attribute(&tail.attrs, "stable_deref") {
+            return Err(Error::new_spanned(
+                attr,
+                "#[stable_deref] can only be used on head fields",
+            ));
+        }
+        if let Some(attr) = find_attribute(&tail.attrs, "nested") {
+            return Err(Error::new_spanned(
+                attr,
+                "#[nested] can only be used on head fields",
+            ));
+        }
+    }
+    let tail_lazy: Vec<bool> = tails
+        .iter()
+        .map(|field| has_attribute(&field.attrs, "lazy"))
+        .collect();
+
+    // Tails in later phases must come first in the struct, so each
+    // phase is a contiguous range of tails, in initialization order.
+    let tail_phases = tails
+        .iter()
+        .map(|field| get_init_phase(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(i) = (1..num_tails).find(|i| tail_phases[i - 1] > tail_phases[*i]) {
+        return Err(Error::new_spanned(
+            tails[i - 1],
+            "fields in later phases must come before fields in earlier phases",
+        ));
+    }
+    let num_phases = tail_phases.last().copied().unwrap_or(1);
+    let phase_ranges: Vec<(usize, usize)> = (1..=num_phases)
+        .map(|phase| {
+            (
+                tail_phases.iter().filter(|p| **p < phase).count(),
+                tail_phases.iter().filter(|p| **p <= phase).count(),
+            )
+        })
+        .collect();
+
+    let head_params = make_field_params(heads.as_slice(), None, false)?;
+    let head_args = make_field_args(heads.as_slice(), None, false);
+    let tail_names = make_field_args(tails.as_slice(), None, false);
+
+    // Heads with #[default] are not constructor parameters. The
+    // constructors bind them to local variables instead, so the rest of
+    // the code can treat all heads alike.
+    for tail in tails.iter() {
+        get_head_default(tail)?;
+    }
+    let head_defaults = heads
+        .iter()
+        .map(|field| get_head_default(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    let ctor_head_params: Vec<&proc_macro2::TokenStream> = head_params
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(param, _)| param)
+        .collect();
+    let ctor_head_args: Vec<&proc_macro2::TokenStream> = head_args
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(arg, _)| arg)
+        .collect();
+    let ctor_head_types: Vec<&syn::Type> = heads
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(field, _)| &field.ty)
+        .collect();
+    let default_heads: Vec<proc_macro2::TokenStream> = head_args
+        .iter()
+        .zip(head_defaults.iter())
+        .filter_map(|(arg, default)| {
+            default
+                .as_ref()
+                .map(|expr| quote_mixed! { let #arg = #expr; })
+        })
+        .collect();
+
+    let mut options = parse_options(&input.attrs)?;
+    check_not_copy(&input.attrs)?;
+
+    // Generated traits and helper types inherit the struct visibility,
+    // and constructors are public, unless overridden.
+    let vis = options.vis.as_ref().unwrap_or(&input.vis);
+    let ctor_vis = match &options.vis {
+        Some(vis) => quote_mixed! { #vis },
+        None => quote_mixed! { pub },
+    };
+
+    // If all borrowed fields are #[stable_deref] or AliasableBox, moving
+    // the struct doesn't move the borrowed data, like in boxed mode.
+    let stable_deref_types: Vec<&syn::Type> = heads
+        .iter()
+        .filter(|field| has_attribute(&field.attrs, "stable_deref"))
+        .map(|field| &field.ty)
+        .collect();
+    let borrowed_names = tails
+        .iter()
+        .map(|field| get_borrows(field))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<_>>();
+    if !stable_deref_types.is_empty()
+        && fields.iter().all(|field| {
+            !borrowed_names.contains(field.ident.as_ref().unwrap())
+                || has_attribute(&field.attrs, "stable_deref")
+                || get_boxed_type(field).is_ok()
+        })
+    {
+        options.boxed = true;
+    }
+
+    // In boxed mode, the struct can be mutably borrowed, so borrowed
+    // fields must be private, or they could be replaced under the
+    // tails. They get read-only getters instead.
+    let borrowed_getter_fields: Vec<&syn::Field> = if options.boxed {
+        fields
+            .iter()
+            .copied()
+            .filter(|field| borrowed_names.contains(field.ident.as_ref().unwrap()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if let Some(field) = borrowed_getter_fields
+        .iter()
+        .find(|field| !matches!(field.vis, syn::Visibility::Inherited))
+    {
+        return Err(Error::new_spanned(
+            &field.vis,
+            "in boxed mode, borrowed fields must be private",
+        ));
+    }
+    // Tails could be moved out of a `&mut Self`, while still borrowing
+    // from the struct, so they are private too.
+    if options.boxed {
+        options.private_tails = true;
+    }
+    check_mut_borrows(heads.as_slice(), tails.as_slice(), options.boxed)?;
+
+    // The lifetime tying head references to tail types, used in all
+    // init_field_X signatures. Defaults to the first lifetime.
+    let self_lifetime = match &options.self_lifetime {
+        Some(lifetime) => Some(
+            input
+                .generics
+                .lifetimes()
+                .map(|param| &param.lifetime)
+                .find(|param| *param == lifetime)
+                .ok_or_else(|| {
+                    Error::new_spanned(lifetime, "lifetime is not a parameter of the struct")
+                })?,
+        ),
+        None => input
+            .generics
+            .lifetimes()
+            .next()
+            .map(|param| &param.lifetime),
+    };
+
+    // The init_field_X signatures imply that all type parameters
+    // outlive the self lifetime, so make that explicit. The bound is
+    // added next to any existing ones, rather than in a where clause.
+    let mut generics = input.generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        for param in generics.type_params_mut() {
+            param
+                .bounds
+                .push(syn::TypeParamBound::Lifetime(lifetime.clone()));
+        }
+    }
+    let (generics_decls, generics_args, generics_where) = generics.split_for_impl();
 
+    let mut trait_generics = generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        trait_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote! { Self: #lifetime });
+    }
+    let trait_where = &trait_generics.where_clause;
     let init_err = find_attribute(&input.attrs, "init_err")
         .map(|attr| attr.parse_args_with(syn::Type::parse))
         .transpose()?;
-    let init_err_or_unit = init_err
-        .clone()
-        .unwrap_or(syn::Type::Verbatim(quote! { () }));
-    let ensure_init_type = match &init_err {
-        Some(err) => quote! { Result<&mut Self, #err> },
-        None => quote! { &mut Self },
-    };
-    let force_init_type = match &init_err {
-        Some(err) => quote! { Result<(), #err> },
-        None => quote! { () },
-    };
-    let (init_field_decls, init_field_args) = make_init_field_decls_and_args(
+    let init_err_or_infallible = init_err.clone().unwrap_or(syn::Type::Verbatim(
+        quote_mixed! { ::core::convert::Infallible },
+    ));
+
+    // The error type returned by the generated functions, if any. In
+    // no-panic mode, state errors are also returned.
+    let result_err = if options.no_panic {
+        Some(quote_mixed! { incrstruct::Error<#init_err_or_infallible> })
+    } else {
+        init_err.as_ref().map(|err| quote_mixed! { #err })
+    };
+    // Extra arguments, passed by reference to all init_field_X
+    // functions, instead of being stored in the struct.
+    let init_args = parse_init_args(&input.attrs)?;
+    if let Some(arg) = init_args.first() {
+        for (set, name) in [
+            (options.boxed, "boxed"),
+            (options.default, "default"),
+            (options.zeroed, "zeroed"),
+            (options.async_init, "async_init"),
+            (options.builder, "builder"),
+            (options.sync, "sync"),
+            (options.serde, "serde"),
+            (options.clone, "clone"),
+            (options.yoke, "yoke"),
+        ] {
+            if set {
+                return Err(Error::new_spanned(
+                    &arg.name,
+                    format!("#[init_args] can't be combined with the {} option", name),
+                ));
+            }
+        }
+    }
+    let init_arg_params: Vec<proc_macro2::TokenStream> = init_args
+        .iter()
+        .map(|arg| {
+            let name = &arg.name;
+            let ty = &arg.ty;
+            quote_mixed! { #name: &#ty }
+        })
+        .collect();
+    let init_arg_names: Vec<&syn::Ident> = init_args.iter().map(|arg| &arg.name).collect();
+    let ctor_params: Vec<&proc_macro2::TokenStream> = ctor_head_params
+        .iter()
+        .copied()
+        .chain(init_arg_params.iter())
+        .collect();
+
+    let struct_name = &input.ident;
+    let init_trait = parse_init_trait(&input.attrs)?;
+    let init_trait_name = init_trait
+        .name
+        .unwrap_or_else(|| join_ident("", struct_name, "Init"));
+    let (init_field_decls, init_field_args, init_field_fn_types) = make_init_field_decls_and_args(
         fields.as_slice(),
-        first_lifetime,
-        Some(&syn::Ident::new("r", proc_macro2::Span::call_site())),
+        self_lifetime,
+        Some(&syn::Ident::new("r", proc_macro2::Span::mixed_site())),
         init_err.as_ref(),
+        options.boxed,
+        init_args.as_slice(),
+        &init_trait.fn_prefix,
     )?;
-    let init_field_names = make_init_field_names(tails.as_slice());
+    let init_field_names = make_init_field_names(tails.as_slice(), &init_trait.fn_prefix);
+    // Lazy tails are initialized on first access, not by
+    // ensure_init_with or the async trait.
+    let eager_init_field_names: Vec<&syn::Ident> = init_field_names
+        .iter()
+        .zip(tail_lazy.iter())
+        .filter_map(|(name, lazy)| (!lazy).then_some(name))
+        .collect();
+    let eager_init_field_fn_types: Vec<&proc_macro2::TokenStream> = init_field_fn_types
+        .iter()
+        .zip(tail_lazy.iter())
+        .filter_map(|(ty, lazy)| (!lazy).then_some(ty))
+        .collect();
+    let eager_init_field_decls: Vec<&proc_macro2::TokenStream> = init_field_decls
+        .iter()
+        .zip(tail_lazy.iter())
+        .filter_map(|(decl, lazy)| (!lazy).then_some(decl))
+        .collect();
 
-    let init_unwrap = match &init_err {
-        Some(_) => quote! {},
-        None => quote! { .unwrap() },
-    };
-    let new_funcs: Vec<proc_macro2::TokenStream> = [
-        (
-            quote! { new_box },
-            quote! { core::pin::Pin<std::boxed::Box<Self>> },
-        ),
-        (
-            quote! { new_rc },
-            quote! { core::pin::Pin<std::rc::Rc<Self>> },
-        ),
-    ]
-    .map(|(name, ty)| {
-        let ret_type = match &init_err {
-            Some(err) => quote! { Result<#ty, #err> },
-            None => quote! { #ty },
-        };
+    // Nested structs are initialized again first, since they may have
+    // moved with this struct, and tails may borrow from them.
+    let nested_inits: Vec<proc_macro2::TokenStream> = heads
+        .iter()
+        .filter(|field| has_attribute(&field.attrs, "nested"))
+        .map(|field| {
+            let name = &field.ident;
 
-        quote! {
-            pub fn #name(#(#head_params),*) -> #ret_type {
-                // SAFETY: the callee is aware the struct is partially initialized.
-                incrstruct::internal::#name(unsafe { Self::new_uninit(#(#head_args),*) }) #init_unwrap
+            quote_mixed! {
+                if let ::core::result::Result::Err(err) = incrstruct::internal::force_init_nested::<_, #init_err_or_infallible>(&mut r.#name) {
+                    return ::core::result::Result::Err(err);
+                }
             }
+        })
+        .collect();
+
+    let phase_nums: Vec<u8> = (1..=num_phases).collect();
+    let phase_ends: Vec<usize> = phase_ranges.iter().map(|(_, end)| *end).collect();
+
+    let init_unwrap = match &result_err {
+        Some(_) => quote_mixed! {},
+        None => quote_mixed! { .unwrap() },
+    };
+    // The constructors, as (name, return type, runtime function, how
+    // it takes the heads). In boxed mode, the struct can move, so no
+    // pinning is needed.
+    let mut containers = if options.boxed {
+        vec![
+            (
+                quote_mixed! { new },
+                quote_mixed! { Self },
+                "new_movable",
+                HeadsArg::Uninit,
+            ),
+            (
+                quote_mixed! { new_box },
+                quote_mixed! { incrstruct::internal::Box<Self> },
+                "new_movable_box",
+                HeadsArg::Uninit,
+            ),
+        ]
+    } else {
+        vec![
+            (
+                quote_mixed! { new_box },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> },
+                "new_box",
+                HeadsArg::Writer,
+            ),
+            (
+                quote_mixed! { new_rc },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Rc<Self>> },
+                "new_rc_with",
+                HeadsArg::Writer,
+            ),
+            (
+                quote_mixed! { new_arc },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Arc<Self>> },
+                "new_arc_with",
+                HeadsArg::Writer,
+            ),
+        ]
+    };
+    // Without an allocator, only the plain value can be created.
+    if !cfg!(feature = "alloc") {
+        containers.retain(|(_, _, internal_name, _)| *internal_name == "new_movable");
+    }
+    if cfg!(feature = "triomphe") && !options.boxed {
+        containers.push((
+            quote_mixed! { new_triomphe },
+            quote_mixed! { ::core::pin::Pin<incrstruct::triomphe::Arc<Self>> },
+            "new_triomphe_with",
+            HeadsArg::Writer,
+        ));
+    }
+    // The constructor bodies expect all heads as local variables, so
+    // they can be shared by the `new_*` and `*_from_heads` functions.
+    let uninit_heads = quote_mixed! {
+        incrstruct::internal::new_uninit::<Self, _>(|out: &mut Self| {
+            #(
+                ::core::ptr::write(&mut out.#head_args, #head_args);
+            )*
+        })
+    };
+    let write_heads = quote_mixed! {
+        |out: &mut Self| {
+            #(
+                ::core::ptr::write(&mut out.#head_args, #head_args);
+            )*
         }
-    })
-    .into();
+    };
 
-    let struct_name = &input.ident;
-    let init_trait_name = syn::Ident::new(
-        &(struct_name.to_string() + "Init"),
-        proc_macro2::Span::call_site(),
+    // Named constructors map their arguments to head fields using a
+    // function in the Init trait.
+    let constructors = parse_constructors(&input.attrs)?;
+    let head_types: Vec<&syn::Type> = heads.iter().map(|field| &field.ty).collect();
+    let tail_types: Vec<&syn::Type> = tails.iter().map(|field| &field.ty).collect();
+
+    // The head fields as a struct, so call sites can name them. Only
+    // generic parameters used by head fields are kept, since unused
+    // parameters are an error.
+    let heads_name = join_ident("", struct_name, "Heads");
+    let mut heads_generics = input.generics.clone();
+    heads_generics.where_clause = None;
+    heads_generics.params = heads_generics
+        .params
+        .into_iter()
+        .filter(|param| {
+            heads
+                .iter()
+                .any(|field| mentions_param(field.ty.to_token_stream(), param))
+        })
+        .collect();
+    let (_, heads_args, _) = heads_generics.split_for_impl();
+    let heads_ty = quote_mixed! { #heads_name #heads_args };
+
+    // Accessors for initialized tails check, in debug builds, that the
+    // struct hasn't moved, since that's when tails dangle. Moving is
+    // fine in boxed mode.
+    let move_check = if options.boxed {
+        quote_mixed! {}
+    } else {
+        quote_mixed! { incrstruct::internal::debug_assert_not_moved(this, &this.#header_name); }
+    };
+
+    // Moves the head fields out, when deconstructing the struct.
+    let read_heads = quote_mixed! {
+        |this: &mut Self| #heads_name {
+            #(
+                #head_args: ::core::ptr::read(&this.#head_args),
+            )*
+        }
+    };
+
+    // Lifetimes of a borrow of the struct, and replacing the self
+    // lifetime in closure accessors.
+    let this_lifetime = syn::Lifetime::new("'isthis", proc_macro2::Span::mixed_site());
+    let field_lifetime = syn::Lifetime::new("'isfield", proc_macro2::Span::mixed_site());
+
+    // References to all fields but the header, for destructuring in
+    // with_all and projections.
+    let mut borrowed_generics = input.generics.clone();
+    borrowed_generics.params.insert(
+        0,
+        syn::GenericParam::Lifetime(syn::LifetimeParam::new(this_lifetime.clone())),
     );
 
-    let init_field_calls = match &init_err {
-        Some(_) => quote! {
-            let mut at = #num_tails;
+    let info = StructInfo {
+        input,
+        options: &options,
+        cfg_attrs,
+        is_tuple,
+        struct_name,
+        vis,
+        ctor_vis,
+        fields: &fields,
+        heads,
+        tails,
+        num_tails,
+        header,
+        header_name,
+        tail_lazy,
+        num_phases,
+        phase_ranges,
+        phase_nums,
+        phase_ends,
+        head_params: &head_params,
+        head_args: &head_args,
+        tail_names,
+        head_defaults,
+        ctor_head_params,
+        ctor_head_args,
+        ctor_head_types,
+        default_heads,
+        head_types,
+        tail_types,
+        borrowed_names,
+        borrowed_getter_fields,
+        self_lifetime,
+        generics: &generics,
+        generics_decls,
+        generics_args,
+        generics_where,
+        trait_where,
+        init_err,
+        init_err_or_infallible,
+        result_err,
+        init_unwrap,
+        init_args: &init_args,
+        init_arg_params: &init_arg_params,
+        init_arg_names,
+        ctor_params,
+        init_trait_name,
+        init_field_decls: &init_field_decls,
+        init_field_args,
+        init_field_names: &init_field_names,
+        eager_init_field_names,
+        eager_init_field_fn_types,
+        eager_init_field_decls,
+        nested_inits,
+        constructors,
+        containers,
+        uninit_heads,
+        write_heads,
+        read_heads,
+        heads_name,
+        heads_generics,
+        heads_ty,
+        move_check,
+        this_lifetime,
+        field_lifetime,
+        borrowed_generics,
+    };
+
+    let mut new_funcs = make_new_funcs(&info);
+    new_funcs.extend(make_in_place_funcs(&info));
+    new_funcs.extend(make_new_in_funcs(&info));
+    new_funcs.push(make_arena_func(&info));
+    new_funcs.push(make_emplace_func(&info));
+    new_funcs.extend(make_cell_funcs(&info));
+    new_funcs.push(make_cyclic_func(&info)?);
+    let (constructor_decls, named_funcs) = make_named_constructors(&info)?;
+    new_funcs.extend(named_funcs);
+    new_funcs.extend(make_from_heads_funcs(&info));
+    let (clone_funcs, clone_impl) = make_clone(&info);
+    new_funcs.extend(clone_funcs);
+
+    let derive_heads_impls = make_derive_heads_impls(&info);
+    let (debug_uninit_func, debug_impl) = make_debug(&info);
+    new_funcs.push(debug_uninit_func);
+    let lazy_getters = make_lazy_getters(&info)?;
+    let tail_accessors = make_tail_accessors(&info)?;
+    let ensure_init_phase_func = make_ensure_init_phase_func(&info);
+    let pinned_funcs = make_pinned_funcs(&info);
+    let into_heads_func = make_into_heads_func(&info);
+    let yoke_funcs = make_yoke_funcs(&info)?;
+    let reinit_funcs = make_reinit_funcs(&info)?;
+    let (serde_funcs, serde_impls, heads_serde_attrs) = make_serde(&info);
+    let heads_struct = make_heads_struct(&info, &heads_serde_attrs);
+    let (borrowed_struct, with_all_func) = make_with_all(&info);
+    let (projection_struct, project_func) = make_projection(&info);
+    let (default_funcs, default_impl) = make_default(&info);
+    let (zeroed_func, zeroed_impl) = make_zeroed(&info);
+    let mmap_func = make_mmap_func(&info)?;
+    let (async_funcs, async_items) = make_async(&info)?;
+    let (builder_func, builder_items) = make_builder(&info)?;
+    let new_box_with_func = make_new_box_with_func(&info)?;
+    let send_sync_impls = make_send_sync_impls(&info);
+    let (sync_func, write_header_func) = make_sync(&info);
+    let (force_init_func, ensure_init_funcs, init_impl) = make_init_funcs(&info);
+    let base_impl = make_base_impl(&info, &write_header_func);
+
+    let StructInfo {
+        options,
+        cfg_attrs,
+        is_tuple,
+        struct_name,
+        vis,
+        ctor_vis,
+        num_phases,
+        head_types,
+        tail_types,
+        generics_decls,
+        generics_args,
+        generics_where,
+        trait_where,
+        init_trait_name,
+        init_field_decls,
+        ..
+    } = &info;
+    let not_impl_guards = make_not_impl_guards(struct_name, &generics, cfg_attrs, options.project);
+
+    let output = quote_mixed! {
+        #(#cfg_attrs)*
+        impl #generics_decls #struct_name #generics_args #generics_where {
+            #(#new_funcs)*
+
+            #force_init_func
+
+            #builder_func
+
+            #new_box_with_func
+
+            #pinned_funcs
+
+            #into_heads_func
+
+            #yoke_funcs
+
+            #reinit_funcs
+
+            #ensure_init_funcs
+
+            #ensure_init_phase_func
+
+            #sync_func
+
+            #tail_accessors
+
+            #with_all_func
+
+            #project_func
+
+            #(#lazy_getters)*
+
+            #(#default_funcs)*
+
+            #zeroed_func
+
+            #serde_funcs
+
+            #mmap_func
+
+            #async_funcs
+
+            /// Returns the sum of the sizes of all head fields,
+            /// excluding padding.
+            #ctor_vis const fn heads_size() -> usize {
+                0 #( + ::core::mem::size_of::<#head_types>() )*
+            }
+
+            /// Returns the sum of the sizes of all tail fields,
+            /// excluding padding.
+            #ctor_vis const fn tails_size() -> usize {
+                0 #( + ::core::mem::size_of::<#tail_types>() )*
+            }
+
+            /// Returns the offset of the header field in the struct.
+            #ctor_vis const fn header_offset() -> usize {
+                ::core::mem::offset_of!(Self, #header_member)
+            }
+
+            /// Describes the memory layout of the struct.
+            #ctor_vis const DESCRIPTOR: incrstruct::Descriptor = incrstruct::Descriptor {
+                size: ::core::mem::size_of::<Self>(),
+                align: ::core::mem::align_of::<Self>(),
+                heads_size: Self::heads_size(),
+                tails_size: Self::tails_size(),
+                header_offset: Self::header_offset(),
+                phases: #num_phases,
+            };
+        }
+
+        // The parameters take the field types by reference, e.g.
+        // `&'a String`, even where a slice would do.
+        #(#cfg_attrs)*
+        #[allow(clippy::ptr_arg)]
+        #vis trait #init_trait_name #generics_decls #trait_where {
             #(
-                match <Self as #init_trait_name #generics_args>::#init_field_names(#( #init_field_args ),*) {
-                    Ok(v) => {
-                        core::ptr::write(&mut r.#tail_names as *mut _, v);
-                        at -= 1;
-                    }
-                    Err(err) => {
-                        // SAFETY: we are undoing what we have
-                        // done, and any field references will be
-                        // dropped.
-                        Self::drop_tail_in_place(&mut *this, at);
-                        return Err(err);
-                    }
-                };
+                #constructor_decls
             )*
-            debug_assert_eq!(at, 0);
-        },
-        None => quote! {
+
             #(
-                core::ptr::write(&mut r.#tail_names as *mut _, <Self as #init_trait_name #generics_args>::#init_field_names(#( #init_field_args ),*));
+                #init_field_decls
             )*
-        },
+        }
+
+        #not_impl_guards
+
+        // Fails to compile if a #[stable_deref] field could move its
+        // target along with it.
+        #(#cfg_attrs)*
+        const _: () = {
+            #[allow(dead_code)]
+            fn is_stable_deref<T: ?::core::marker::Sized + incrstruct::StableDeref>() {}
+
+            #[allow(dead_code)]
+            fn assert_stable_deref #generics_decls () #generics_where {
+                #( is_stable_deref::<#stable_deref_types>(); )*
+            }
+        };
+
+        #(#send_sync_impls)*
+
+        #base_impl
+
+        #init_impl
+
+        #default_impl
+
+        #zeroed_impl
+
+        #serde_impls
+
+        #clone_impl
+
+        #debug_impl
+
+        #(#derive_heads_impls)*
+
+        #async_items
+
+        #builder_items
+
+        #heads_struct
+
+        #borrowed_struct
+
+        #projection_struct
+    };
+    let output = if *is_tuple {
+        with_tuple_members(output)
+    } else {
+        output
+    };
+
+    Ok(with_crate_path(output, options.crate_path.as_ref()).into())
+}
+
+/// The analysis of a struct deriving `IncrStruct`, shared by the
+/// functions generating each part of the output.
+struct StructInfo<'a> {
+    /// The derive input.
+    input: &'a DeriveInput,
+
+    /// The struct-level options.
+    options: &'a Options,
+
+    /// The `#[cfg]` attributes of the struct, which generated items must
+    /// also have.
+    cfg_attrs: Vec<&'a syn::Attribute>,
+
+    /// Whether the struct has tuple fields, named like `_1` here.
+    is_tuple: bool,
+
+    /// The name of the struct.
+    struct_name: &'a syn::Ident,
+
+    /// The visibility of generated traits and helper types.
+    vis: &'a syn::Visibility,
+
+    /// The visibility of constructors.
+    ctor_vis: proc_macro2::TokenStream,
+
+    /// All fields but the header, in initialization order.
+    fields: &'a [&'a syn::Field],
+
+    /// The head fields, in declaration order.
+    heads: Vec<&'a syn::Field>,
+
+    /// The tail fields, in initialization order.
+    tails: Vec<&'a syn::Field>,
+
+    /// The number of tail fields.
+    num_tails: usize,
+
+    /// The `#[header]` field.
+    header: &'a syn::Field,
+
+    /// The name of the `#[header]` field.
+    header_name: &'a syn::Ident,
+
+    /// Whether each tail is `#[lazy]`.
+    tail_lazy: Vec<bool>,
+
+    /// The number of initialization phases.
+    num_phases: u8,
+
+    /// The range of tails of each phase.
+    phase_ranges: Vec<(usize, usize)>,
+
+    /// The number of each phase, starting at 1.
+    phase_nums: Vec<u8>,
+
+    /// The end of the range of tails of each phase.
+    phase_ends: Vec<usize>,
+
+    /// The head fields, as function parameters.
+    head_params: &'a [proc_macro2::TokenStream],
+
+    /// The head field names, as arguments.
+    head_args: &'a [proc_macro2::TokenStream],
+
+    /// The tail field names.
+    tail_names: Vec<proc_macro2::TokenStream>,
+
+    /// The `#[default]` of each head.
+    head_defaults: Vec<Option<syn::Expr>>,
+
+    /// The heads without `#[default]`, which are constructor parameters.
+    ctor_head_params: Vec<&'a proc_macro2::TokenStream>,
+
+    /// The heads without `#[default]`, as arguments.
+    ctor_head_args: Vec<&'a proc_macro2::TokenStream>,
+
+    /// The types of the heads without `#[default]`.
+    ctor_head_types: Vec<&'a syn::Type>,
+
+    /// Binds the heads with `#[default]` to local variables.
+    default_heads: Vec<proc_macro2::TokenStream>,
+
+    /// The types of the head fields.
+    head_types: Vec<&'a syn::Type>,
+
+    /// The types of the tail fields.
+    tail_types: Vec<&'a syn::Type>,
+
+    /// The fields borrowed by any tail.
+    borrowed_names: HashSet<syn::Ident>,
+
+    /// The borrowed fields getting read-only getters, in boxed mode.
+    borrowed_getter_fields: Vec<&'a syn::Field>,
+
+    /// The lifetime tying head references to tail types.
+    self_lifetime: Option<&'a syn::Lifetime>,
+
+    /// The struct generics, with type parameters outliving the self
+    /// lifetime.
+    generics: &'a syn::Generics,
+
+    /// The generic parameters, for impls.
+    generics_decls: syn::ImplGenerics<'a>,
+
+    /// The generic arguments of the struct type.
+    generics_args: syn::TypeGenerics<'a>,
+
+    /// The where clause of the struct.
+    generics_where: Option<&'a syn::WhereClause>,
+
+    /// The where clause of the Init traits.
+    trait_where: &'a Option<syn::WhereClause>,
+
+    /// The `#[init_err]` type, if any.
+    init_err: Option<syn::Type>,
+
+    /// The `#[init_err]` type, or `Infallible`.
+    init_err_or_infallible: syn::Type,
+
+    /// The error type returned by the generated functions, if any. In
+    /// no-panic mode, state errors are also returned.
+    result_err: Option<proc_macro2::TokenStream>,
+
+    /// Unwraps the result of a runtime function, unless it is returned.
+    init_unwrap: proc_macro2::TokenStream,
+
+    /// The `#[init_args]`, passed by reference to all init_field_X
+    /// functions.
+    init_args: &'a [ConstructorParam],
+
+    /// The `#[init_args]`, as function parameters.
+    init_arg_params: &'a [proc_macro2::TokenStream],
+
+    /// The `#[init_args]` names.
+    init_arg_names: Vec<&'a syn::Ident>,
+
+    /// The constructor parameters, heads and then init_args.
+    ctor_params: Vec<&'a proc_macro2::TokenStream>,
+
+    /// The name of the Init trait.
+    init_trait_name: syn::Ident,
+
+    /// The init_field_X declarations in the Init trait.
+    init_field_decls: &'a [proc_macro2::TokenStream],
+
+    /// The arguments of each init_field_X function.
+    init_field_args: Vec<Vec<proc_macro2::TokenStream>>,
+
+    /// The init_field_X function names.
+    init_field_names: &'a [syn::Ident],
+
+    /// The init functions of tails that aren't `#[lazy]`.
+    eager_init_field_names: Vec<&'a syn::Ident>,
+
+    /// The closure types of tails that aren't `#[lazy]`.
+    eager_init_field_fn_types: Vec<&'a proc_macro2::TokenStream>,
+
+    /// The init_field_X declarations of tails that aren't `#[lazy]`.
+    eager_init_field_decls: Vec<&'a proc_macro2::TokenStream>,
+
+    /// Initializes `#[nested]` heads again, in the first phase.
+    nested_inits: Vec<proc_macro2::TokenStream>,
+
+    /// The named constructors.
+    constructors: Vec<Constructor>,
+
+    /// The constructors, as (name, return type, runtime function, how
+    /// it takes the heads).
+    containers: Vec<(
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+        &'static str,
+        HeadsArg,
+    )>,
+
+    /// Creates a partial struct from the heads, as local variables.
+    uninit_heads: proc_macro2::TokenStream,
+
+    /// A closure writing the heads, as local variables.
+    write_heads: proc_macro2::TokenStream,
+
+    /// A closure reading the heads into the Heads struct.
+    read_heads: proc_macro2::TokenStream,
+
+    /// The name of the Heads struct.
+    heads_name: syn::Ident,
+
+    /// The generic parameters used by head fields.
+    heads_generics: syn::Generics,
+
+    /// The Heads struct type.
+    heads_ty: proc_macro2::TokenStream,
+
+    /// Checks that the struct hasn't moved, in debug builds.
+    move_check: proc_macro2::TokenStream,
+
+    /// The lifetime of a borrow of the struct.
+    this_lifetime: syn::Lifetime,
+
+    /// Replaces the self lifetime in closure accessors.
+    field_lifetime: syn::Lifetime,
+
+    /// The generics of the BorrowedFields and Projection structs.
+    borrowed_generics: syn::Generics,
+}
+
+impl StructInfo<'_> {
+    /// Returns `ty` wrapped in a `Result`, if the generated functions
+    /// can fail.
+    fn wrap_result(&self, ty: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match &self.result_err {
+            Some(err) => quote_mixed! { ::core::result::Result<#ty, #err> },
+            None => ty,
+        }
+    }
+
+    /// Returns the name of a runtime function, with the `try_` prefix
+    /// in no-panic mode.
+    fn internal_fn(&self, name: &str) -> syn::Ident {
+        let name = if self.options.no_panic {
+            "try_".to_string() + name
+        } else {
+            name.to_string()
+        };
+
+        syn::Ident::new(&name, proc_macro2::Span::call_site())
+    }
+
+    /// Returns the tail initialization code of each phase, calling
+    /// init functions given by `callee`, and applying `suffix` to the
+    /// result, e.g. `.await`. Lazy tails start out empty. The struct
+    /// type is `self_ty`, which is `Self` in the struct impl.
+    fn phase_calls(
+        &self,
+        callee: &dyn Fn(&syn::Ident) -> proc_macro2::TokenStream,
+        suffix: &proc_macro2::TokenStream,
+        self_ty: &proc_macro2::TokenStream,
+    ) -> Vec<proc_macro2::TokenStream> {
+        let StructInfo {
+            options,
+            header_name,
+            tail_lazy,
+            phase_ranges,
+            tail_names,
+            init_err,
+            init_field_args,
+            init_field_names,
+            nested_inits,
+            ..
+        } = self;
+
+        phase_ranges
+            .iter()
+            .enumerate()
+            .map(|(phase_index, (start, end))| {
+                let nested_inits = if phase_index == 0 {
+                    nested_inits.as_slice()
+                } else {
+                    &[]
+                };
+                let at_incr = match &init_err {
+                    Some(_) => quote_mixed! { at += 1; },
+                    None => quote_mixed! {},
+                };
+                let stmts: Vec<proc_macro2::TokenStream> = (*start..*end)
+                    .map(|i| {
+                        let tail_name = &tail_names[i];
+                        let init_field_args = &init_field_args[i];
+
+                        if tail_lazy[i] {
+                            return quote_mixed! {
+                                ::core::ptr::write(&mut r.#tail_name as *mut _, ::core::default::Default::default());
+                                incrstruct::internal::set_tail_inited(&mut r.#header_name, #i);
+                                #at_incr
+                            };
+                        }
+
+                        let init_field_callee = callee(&init_field_names[i]);
+                        match &init_err {
+                            Some(_) => quote_mixed! {
+                                match #init_field_callee(#( #init_field_args ),*) #suffix {
+                                    ::core::result::Result::Ok(v) => {
+                                        ::core::ptr::write(&mut r.#tail_name as *mut _, v);
+                                        incrstruct::internal::set_tail_inited(&mut r.#header_name, #i);
+                                        at += 1;
+                                    }
+                                    ::core::result::Result::Err(err) => {
+                                        // SAFETY: we are undoing what we have
+                                        // done, and any field references will be
+                                        // dropped.
+                                        <#self_ty as incrstruct::internal::IncrStructBase>::drop_tail_in_place(&mut *this, #start..at);
+                                        return ::core::result::Result::Err(err);
+                                    }
+                                };
+                            },
+                            None => quote_mixed! {
+                                ::core::ptr::write(&mut r.#tail_name as *mut _, #init_field_callee(#( #init_field_args ),*) #suffix);
+                                incrstruct::internal::set_tail_inited(&mut r.#header_name, #i);
+                            },
+                        }
+                    })
+                    .collect();
+
+                match &init_err {
+                    Some(_) => {
+                        let at_assert = if options.no_panic {
+                            quote_mixed! {}
+                        } else {
+                            quote_mixed! { ::core::debug_assert_eq!(at, #end); }
+                        };
+
+                        quote_mixed! {
+                            #(#nested_inits)*
+                            let mut at = #start;
+                            #(#stmts)*
+                            #at_assert
+                        }
+                    }
+                    None => quote_mixed! {
+                        #(#nested_inits)*
+                        #(#stmts)*
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the tail initialization code of each phase, using the
+    /// Init trait.
+    fn init_phase_calls(&self) -> Vec<proc_macro2::TokenStream> {
+        let StructInfo {
+            generics_args,
+            init_trait_name,
+            ..
+        } = self;
+
+        self.phase_calls(
+            &|name| quote_mixed! { <Self as #init_trait_name #generics_args>::#name },
+            &quote_mixed! {},
+            &quote_mixed! { Self },
+        )
+    }
+
+    /// Returns a closure initializing a phase, for the `_with` runtime
+    /// functions.
+    fn init_closure(
+        &self,
+        phase_calls: &[proc_macro2::TokenStream],
+        self_ty: &proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let phase_nums = &self.phase_nums;
+
+        quote_mixed! {
+            |this: *mut #self_ty, phase: u8| unsafe {
+                let r = &mut *this;
+
+                match phase {
+                    #(
+                        #phase_nums => {
+                            #phase_calls
+                        }
+                    )*
+                    _ => {}
+                }
+
+                ::core::result::Result::Ok(())
+            }
+        }
+    }
+
+    /// Returns a closure initializing a phase using the Init trait.
+    /// With init_args, the IncrStructInit trait isn't implemented, so
+    /// the generated functions use this closure instead.
+    fn args_init_closure(&self) -> proc_macro2::TokenStream {
+        self.init_closure(&self.init_phase_calls(), &quote_mixed! { Self })
+    }
+
+    /// Returns the body of the constructor for each container. The
+    /// bodies expect all heads as local variables, so they can be
+    /// shared by the `new_*` and `*_from_heads` functions.
+    fn new_bodies(&self) -> Vec<proc_macro2::TokenStream> {
+        let StructInfo {
+            options,
+            head_args,
+            init_unwrap,
+            init_args,
+            containers,
+            uninit_heads,
+            ..
+        } = self;
+        let args_init_closure = self.args_init_closure();
+
+        containers
+            .iter()
+            .map(|(_, _, internal_name, heads_arg)| {
+                let internal_name = self.internal_fn(internal_name);
+
+                if !init_args.is_empty() {
+                    let new_in_with_init_fn = self.internal_fn("new_in_with_init");
+
+                    quote_mixed! {
+                        let init = #args_init_closure;
+
+                        // SAFETY: the closure writes all head fields,
+                        // and nothing else.
+                        unsafe {
+                            incrstruct::internal::#new_in_with_init_fn(
+                                |out: &mut Self| {
+                                    #(
+                                        ::core::ptr::write(&mut out.#head_args, #head_args);
+                                    )*
+                                },
+                                init,
+                            )
+                        } #init_unwrap
+                    }
+                } else if options.boxed {
+                    quote_mixed! {
+                        // SAFETY: the callee is aware the struct is
+                        // partially initialized, and all borrowed
+                        // fields are boxed.
+                        unsafe { incrstruct::internal::#internal_name(#uninit_heads) } #init_unwrap
+                    }
+                } else if let HeadsArg::Writer = heads_arg {
+                    quote_mixed! {
+                        // SAFETY: the closure writes all head fields,
+                        // and nothing else.
+                        unsafe {
+                            incrstruct::internal::#internal_name(|out: &mut Self| {
+                                #(
+                                    ::core::ptr::write(&mut out.#head_args, #head_args);
+                                )*
+                            })
+                        } #init_unwrap
+                    }
+                } else {
+                    quote_mixed! {
+                        // SAFETY: the callee is aware the struct is partially initialized.
+                        incrstruct::internal::#internal_name(unsafe { #uninit_heads }) #init_unwrap
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns the constructors for each container, like `new_box`.
+fn make_new_funcs(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        ctor_vis,
+        default_heads,
+        ctor_params,
+        containers,
+        ..
+    } = info;
+
+    let new_bodies = info.new_bodies();
+
+    containers
+        .iter()
+        .zip(new_bodies.iter())
+        .map(|((name, ty, _, _), body)| {
+            let ret_type = info.wrap_result(ty.clone());
+
+            quote_mixed! {
+                #ctor_vis fn #name(#(#ctor_params),*) -> #ret_type {
+                    #(#default_heads)*
+                    #body
+                }
+            }
+        })
+        .collect()
+}
+
+/// Returns the constructors using caller-provided memory, like
+/// `init_at`, `new_in_place` and `pin_init`.
+fn make_in_place_funcs(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        options,
+        ctor_vis,
+        ctor_head_params,
+        ctor_head_args,
+        default_heads,
+        init_err_or_infallible,
+        init_unwrap,
+        init_args,
+        ctor_params,
+        write_heads,
+        ..
+    } = info;
+
+    let mut new_funcs = Vec::new();
+
+    // Constructs the struct in caller-provided memory.
+    if !options.boxed && init_args.is_empty() {
+        let init_at_fn = info.internal_fn("init_at");
+        let ret_type = info.wrap_result(quote_mixed! { ::core::pin::Pin<&'isthis mut Self> });
+
+        new_funcs.push(quote_mixed! {
+            /// See [incrstruct::internal::init_at].
+            #ctor_vis unsafe fn init_at<'isthis>(ptr: *mut Self, #(#ctor_params),*) -> #ret_type
+            where
+                Self: 'isthis,
+            {
+                #(#default_heads)*
+                incrstruct::internal::#init_at_fn(ptr, #write_heads) #init_unwrap
+            }
+        });
+
+        let new_with_fn = info.internal_fn("new_with");
+        let ret_type = info.wrap_result(quote_mixed! { incrstruct::InPlace<'isthis, Self> });
+
+        new_funcs.push(quote_mixed! {
+            /// See [incrstruct::InPlace].
+            ///
+            /// # Safety
+            ///
+            /// The returned guard must not be leaked, unless the slot
+            /// is never reused. See [incrstruct::InPlace::new_with].
+            #ctor_vis unsafe fn new_in_place<'isthis>(slot: &'isthis mut ::core::mem::MaybeUninit<Self>, #(#ctor_params),*) -> #ret_type {
+                #(#default_heads)*
+                // SAFETY: the closure writes all head fields, and
+                // nothing else. The caller promised not to leak the
+                // guard.
+                unsafe { incrstruct::InPlace::#new_with_fn(slot, #write_heads) } #init_unwrap
+            }
+
+            /// See [incrstruct::UninitGuard].
+            #ctor_vis fn new_uninit_guard(#(#ctor_head_params),*) -> incrstruct::UninitGuard<Self> {
+                // SAFETY: the guard drops the head fields.
+                unsafe { incrstruct::UninitGuard::new(Self::new_uninit(#(#ctor_head_args),*)) }
+            }
+        });
+    }
+
+    // Adapts the two-phase initialization to the pinned-init protocol.
+    if !options.boxed && init_args.is_empty() && cfg!(feature = "pinned_init") {
+        new_funcs.push(quote_mixed! {
+            /// Returns an initializer for `pinned_init` aware
+            /// allocators and macros. On error, the head fields are
+            /// dropped again.
+            #ctor_vis fn pin_init(#(#ctor_params),*) -> impl incrstruct::pinned_init::PinInit<Self, #init_err_or_infallible> {
+                #(#default_heads)*
+                let init = move |slot: *mut Self| {
+                    // SAFETY: the closure writes all head fields, and
+                    // nothing else. The pinned-init protocol
+                    // guarantees the slot doesn't move.
+                    unsafe { incrstruct::internal::init_at(slot, #write_heads) }.map(|_| ())
+                };
+
+                // SAFETY: the slot is fully initialized on success,
+                // and left uninitialized on error.
+                unsafe { incrstruct::pinned_init::pin_init_from_closure(init) }
+            }
+        });
+    }
+
+    new_funcs
+}
+
+/// Returns the constructors for any pinned container, and for boxed
+/// slices.
+fn make_new_in_funcs(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        options,
+        ctor_vis,
+        head_args,
+        ctor_head_args,
+        ctor_head_types,
+        default_heads,
+        init_unwrap,
+        init_args,
+        ctor_params,
+        write_heads,
+        ..
+    } = info;
+    let args_init_closure = info.args_init_closure();
+
+    let mut new_funcs = Vec::new();
+
+    // Constructs the struct in any pinned container.
+    if !options.boxed && cfg!(feature = "alloc") {
+        let ret_type = info.wrap_result(quote_mixed! { ::core::pin::Pin<IsContainer> });
+        let new_in_body = if init_args.is_empty() {
+            let new_in_fn = info.internal_fn("new_in");
+            quote_mixed! {
+                // SAFETY: the closure writes all head fields, and
+                // nothing else.
+                unsafe { incrstruct::internal::#new_in_fn::<IsContainer, Self, _>(#write_heads) } #init_unwrap
+            }
+        } else {
+            let new_in_with_init_fn = info.internal_fn("new_in_with_init");
+            quote_mixed! {
+                let init = #args_init_closure;
+
+                // SAFETY: the closure writes all head fields, and
+                // nothing else.
+                unsafe { incrstruct::internal::#new_in_with_init_fn::<IsContainer, Self, _, _>(#write_heads, init) } #init_unwrap
+            }
+        };
+
+        new_funcs.push(quote_mixed! {
+            /// See [incrstruct::IncrContainer].
+            #ctor_vis fn new_in<IsContainer: incrstruct::IncrContainer<Self>>(#(#ctor_params),*) -> #ret_type {
+                #(#default_heads)*
+                #new_in_body
+            }
+        });
+
+        if init_args.is_empty() {
+            let new_boxed_slice_fn = info.internal_fn("new_boxed_slice");
+            let ret_type = info
+                .wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<[Self]>> });
+
+            new_funcs.push(quote_mixed! {
+                /// Creates a boxed slice with one struct per item of
+                /// `heads`, which are tuples of the head fields.
+                #ctor_vis fn new_boxed_slice<IsHeads: ::core::iter::IntoIterator<Item = (#(#ctor_head_types,)*)>>(heads: IsHeads) -> #ret_type {
+                    // SAFETY: the closure writes all head fields, and
+                    // nothing else.
+                    unsafe {
+                        incrstruct::internal::#new_boxed_slice_fn(heads, |out: &mut Self, (#(#ctor_head_args,)*)| {
+                            #(#default_heads)*
+                            #(
+                                ::core::ptr::write(&mut out.#head_args, #head_args);
+                            )*
+                        })
+                    } #init_unwrap
+                }
+            });
+        }
+    }
+
+    new_funcs
+}
+
+/// Returns the constructor allocating in an arena, through
+/// `IncrArenaAlloc`.
+fn make_arena_func(info: &StructInfo) -> proc_macro2::TokenStream {
+    let StructInfo {
+        options,
+        ctor_vis,
+        default_heads,
+        init_unwrap,
+        init_args,
+        ctor_params,
+        write_heads,
+        ..
+    } = info;
+
+    if options.boxed || !init_args.is_empty() || !cfg!(feature = "alloc") {
+        return quote_mixed! {};
+    }
+
+    let alloc_with_fn = info.internal_fn("alloc_with");
+    let ret_type = info.wrap_result(quote_mixed! { ::core::pin::Pin<&'isarena mut Self> });
+
+    quote_mixed! {
+        /// Constructs the struct in `arena`, where it lives
+        /// until the arena is dropped.
+        #[allow(clippy::mut_from_ref)]
+        #ctor_vis fn new_in_arena<'isarena, IsArena: incrstruct::IncrArenaAlloc<Self>>(arena: &'isarena IsArena, #(#ctor_params),*) -> #ret_type
+        where
+            Self: 'isarena,
+        {
+            #(#default_heads)*
+            // SAFETY: the closure writes all head fields, and
+            // nothing else.
+            unsafe { incrstruct::IncrArenaAlloc::#alloc_with_fn(arena, #write_heads) } #init_unwrap
+        }
+    }
+}
+
+/// Returns the constructor emplacing the struct in a pool, like a
+/// slab, through `IncrEmplace`.
+fn make_emplace_func(info: &StructInfo) -> proc_macro2::TokenStream {
+    let StructInfo {
+        options,
+        ctor_vis,
+        default_heads,
+        init_unwrap,
+        init_args,
+        ctor_params,
+        write_heads,
+        ..
+    } = info;
+
+    if options.boxed || !init_args.is_empty() || !cfg!(feature = "alloc") {
+        return quote_mixed! {};
+    }
+
+    let emplace_with_fn = info.internal_fn("emplace_with");
+    let ret_type = info.wrap_result(quote_mixed! { IsPool::Key<'ispool> });
+
+    quote_mixed! {
+        /// See [incrstruct::IncrEmplace].
+        #ctor_vis fn emplace<'ispool, IsPool: incrstruct::IncrEmplace<Self>>(pool: &'ispool mut IsPool, #(#ctor_params),*) -> #ret_type {
+            #(#default_heads)*
+            // SAFETY: the closure writes all head fields, and
+            // nothing else.
+            unsafe { incrstruct::IncrEmplace::#emplace_with_fn(pool, #write_heads) } #init_unwrap
+        }
+    }
+}
+
+/// Returns the constructors for shared pointers to cells, which pin
+/// their contents.
+fn make_cell_funcs(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        options,
+        ctor_vis,
+        default_heads,
+        init_unwrap,
+        init_args,
+        ctor_params,
+        uninit_heads,
+        ..
+    } = info;
+
+    let mut new_funcs = Vec::new();
+    let mut cell_containers = Vec::new();
+    if !options.boxed && init_args.is_empty() && cfg!(feature = "alloc") {
+        cell_containers.push((
+            quote_mixed! { new_rc_refcell },
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Rc<incrstruct::PinRefCell<Self>>> },
+            "new_rc_refcell",
+        ));
+    }
+    if !options.boxed && init_args.is_empty() && cfg!(feature = "std") {
+        cell_containers.push((
+            quote_mixed! { new_arc_mutex },
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Arc<incrstruct::PinMutex<Self>>> },
+            "new_arc_mutex",
+        ));
+    }
+    for (name, ty, internal_name) in cell_containers {
+        let ret_type = info.wrap_result(ty);
+        let doc = format!("See [incrstruct::internal::{}].", internal_name);
+        let internal_name = info.internal_fn(internal_name);
+
+        new_funcs.push(quote_mixed! {
+            #[doc = #doc]
+            #ctor_vis fn #name(#(#ctor_params),*) -> #ret_type {
+                #(#default_heads)*
+                // SAFETY: the closure writes all head fields, and
+                // nothing else.
+                unsafe { incrstruct::internal::#internal_name(#uninit_heads) } #init_unwrap
+            }
+        });
+    }
+
+    new_funcs
+}
+
+/// Returns the constructor for a struct with a `#[cyclic(rc)]` or
+/// `#[cyclic(arc)]` head field, which is a weak pointer to the struct
+/// itself, written by a constructor like `Rc::new_cyclic`.
+fn make_cyclic_func(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        options,
+        ctor_vis,
+        heads,
+        tails,
+        head_params,
+        head_args,
+        head_defaults,
+        default_heads,
+        init_unwrap,
+        init_arg_params,
+        ..
+    } = info;
+    let args_init_closure = info.args_init_closure();
+
+    for tail in tails.iter() {
+        if let Some(attr) = find_attribute(&tail.attrs, "cyclic") {
+            return Err(Error::new_spanned(
+                attr,
+                "#[cyclic] can only be used on head fields",
+            ));
+        }
+    }
+    let cyclic_heads: Vec<(usize, &syn::Attribute)> = heads
+        .iter()
+        .enumerate()
+        .filter_map(|(i, field)| find_attribute(&field.attrs, "cyclic").map(|attr| (i, attr)))
+        .collect();
+    if let Some((_, attr)) = cyclic_heads.get(1) {
+        return Err(Error::new_spanned(
+            attr,
+            "only one head field can be #[cyclic]",
+        ));
+    }
+    let (cyclic_index, attr) = match cyclic_heads.first() {
+        Some(cyclic) => cyclic,
+        None => return Ok(quote_mixed! {}),
+    };
+    let kind = attr.parse_args::<syn::Ident>()?;
+    let (fn_name, ret_type, runtime_fn) = if kind == "rc" {
+        (
+            quote_mixed! { new_rc_cyclic },
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Rc<Self>> },
+            info.internal_fn("new_rc_cyclic_with_init"),
+        )
+    } else if kind == "arc" {
+        (
+            quote_mixed! { new_arc_cyclic },
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Arc<Self>> },
+            info.internal_fn("new_arc_cyclic_with_init"),
+        )
+    } else {
+        return Err(Error::new_spanned(kind, "expected rc or arc"));
+    };
+    if options.boxed {
+        return Err(Error::new_spanned(
+            attr,
+            "#[cyclic] can't be combined with the boxed option",
+        ));
+    }
+    if let Some(attr) = find_attribute(&heads[*cyclic_index].attrs, "default") {
+        return Err(Error::new_spanned(
+            attr,
+            "a #[cyclic] field can't have a #[default]",
+        ));
+    }
+
+    // The weak pointer is upgraded into a pin, so it must not be
+    // replaced with one to an unpinned value.
+    if !matches!(heads[*cyclic_index].vis, syn::Visibility::Inherited) {
+        return Err(Error::new_spanned(
+            heads[*cyclic_index],
+            "a #[cyclic] field must be private",
+        ));
+    }
+
+    // The weak pointer can be upgraded, so tails must not see it
+    // while the struct is partial.
+    let cyclic_name = heads[*cyclic_index].ident.as_ref().unwrap();
+    for tail in tails.iter() {
+        if get_borrows(tail)?.contains(cyclic_name) {
+            return Err(Error::new_spanned(
+                tail,
+                "tail fields can't borrow a #[cyclic] field",
+            ));
+        }
+    }
+
+    let params: Vec<&proc_macro2::TokenStream> = head_params
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i != cyclic_index && head_defaults[*i].is_none())
+        .map(|(_, param)| param)
+        .chain(init_arg_params.iter())
+        .collect();
+    let other_head_args: Vec<&proc_macro2::TokenStream> = head_args
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i != cyclic_index)
+        .map(|(_, arg)| arg)
+        .collect();
+    let ret_type = info.wrap_result(ret_type);
+
+    if !cfg!(feature = "alloc") {
+        return Ok(quote_mixed! {});
+    }
+
+    Ok(quote_mixed! {
+        #ctor_vis fn #fn_name(#(#params),*) -> #ret_type {
+            #(#default_heads)*
+            let init = #args_init_closure;
+
+            // SAFETY: the closure writes all head fields, and
+            // nothing else.
+            unsafe {
+                incrstruct::internal::#runtime_fn(
+                    |out: &mut Self, weak| {
+                        #(
+                            ::core::ptr::write(&mut out.#other_head_args, #other_head_args);
+                        )*
+                        ::core::ptr::write(&mut out.#cyclic_name, weak);
+                    },
+                    init,
+                )
+            } #init_unwrap
+        }
+    })
+}
+
+/// Returns the declarations of the named constructor functions in the
+/// Init trait, and the constructors using them. Named constructors map
+/// their arguments to head fields using the trait function, and then
+/// call the normal constructors.
+fn make_named_constructors(
+    info: &StructInfo,
+) -> Result<(Vec<proc_macro2::TokenStream>, Vec<proc_macro2::TokenStream>), Error> {
+    let StructInfo {
+        input,
+        options,
+        ctor_head_args,
+        ctor_head_types,
+        generics_args,
+        init_err,
+        result_err,
+        init_arg_params,
+        init_arg_names,
+        init_trait_name,
+        constructors,
+        containers,
+        ..
+    } = info;
+
+    let mut new_funcs = Vec::new();
+
+    let heads_type = quote_mixed! { (#(#ctor_head_types,)*) };
+    let heads_try = match &init_err {
+        Some(_) if options.no_panic => quote_mixed! { .map_err(incrstruct::Error::Init)? },
+        Some(_) => quote_mixed! { ? },
+        None => quote_mixed! {},
+    };
+    // With #[head_err], the mapping functions fail with their own
+    // error type, and initialization errors are converted to it.
+    let head_err = find_attribute(&input.attrs, "head_err")
+        .map(|attr| {
+            if constructors.is_empty() {
+                return Err(Error::new_spanned(
+                    attr,
+                    "#[head_err] requires a #[constructor]",
+                ));
+            }
+
+            attr.parse_args_with(syn::Type::parse)
+        })
+        .transpose()?;
+    let mut constructor_decls = Vec::new();
+    for constructor in constructors.iter() {
+        let ctor_name = &constructor.name;
+        let named_params: Vec<proc_macro2::TokenStream> = constructor
+            .params
+            .iter()
+            .map(|param| {
+                let name = &param.name;
+                let ty = &param.ty;
+                quote_mixed! { #name: #ty }
+            })
+            .collect();
+        let ctor_args: Vec<&syn::Ident> =
+            constructor.params.iter().map(|param| &param.name).collect();
+        let ret_type = match head_err.as_ref().or(init_err.as_ref()) {
+            Some(err) => quote_mixed! { ::core::result::Result<#heads_type, #err> },
+            None => heads_type.clone(),
+        };
+
+        constructor_decls.push(quote_mixed! {
+            fn #ctor_name(#(#named_params),*) -> #ret_type;
+        });
+
+        for (name, ty, _, _) in containers.iter() {
+            let fn_name = join_ident(&format!("{}_", name), ctor_name, "");
+            let call_heads = quote_mixed! {
+                <Self as #init_trait_name #generics_args>::#ctor_name(#(#ctor_args),*)
+            };
+            let call_new = quote_mixed! {
+                Self::#name(#(#ctor_head_args,)* #(#init_arg_names),*)
+            };
+
+            new_funcs.push(match &head_err {
+                Some(err) => {
+                    let value = match &result_err {
+                        Some(_) => quote_mixed! { #call_new.map_err(::core::convert::From::from) },
+                        None => quote_mixed! { ::core::result::Result::Ok(#call_new) },
+                    };
+
+                    quote_mixed! {
+                        pub fn #fn_name(#(#named_params,)* #(#init_arg_params),*) -> ::core::result::Result<#ty, #err> {
+                            let (#(#ctor_head_args,)*) = #call_heads?;
+
+                            #value
+                        }
+                    }
+                }
+                None => {
+                    let ret_type = info.wrap_result(ty.clone());
+
+                    quote_mixed! {
+                        pub fn #fn_name(#(#named_params,)* #(#init_arg_params),*) -> #ret_type {
+                            let (#(#ctor_head_args,)*) = #call_heads #heads_try;
+
+                            #call_new
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    Ok((constructor_decls, new_funcs))
+}
+
+/// Returns the constructors taking the Heads struct, like
+/// `new_box_from_heads`.
+fn make_from_heads_funcs(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        ctor_vis,
+        head_args,
+        init_arg_params,
+        containers,
+        heads_name,
+        heads_ty,
+        ..
+    } = info;
+
+    let new_bodies = info.new_bodies();
+
+    containers
+        .iter()
+        .zip(new_bodies.iter())
+        .map(|((name, ty, _, _), body)| {
+            let fn_name = syn::Ident::new(
+                &format!("{}_from_heads", name),
+                proc_macro2::Span::call_site(),
+            );
+            let ret_type = info.wrap_result(ty.clone());
+
+            quote_mixed! {
+                #ctor_vis fn #fn_name(heads: #heads_ty, #(#init_arg_params),*) -> #ret_type {
+                    let #heads_name { #(#head_args),* } = heads;
+
+                    #body
+                }
+            }
+        })
+        .collect()
+}
+
+/// Returns the clone functions, and the Clone implementation, if any.
+/// Cloning constructs a new struct from clones of the head fields.
+/// Only a movable struct can implement Clone itself.
+fn make_clone(info: &StructInfo) -> (Vec<proc_macro2::TokenStream>, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        ctor_vis,
+        head_args,
+        head_types,
+        generics,
+        generics_decls,
+        generics_args,
+        result_err,
+        containers,
+        heads_name,
+        ..
+    } = info;
+
+    let mut new_funcs = Vec::new();
+    let mut clone_impl = quote_mixed! {};
+    if options.clone {
+        let clone_heads = quote_mixed! {
+            #heads_name {
+                #(
+                    #head_args: ::core::clone::Clone::clone(&self.#head_args),
+                )*
+            }
+        };
+
+        for (name, ty, _, _) in containers.iter() {
+            let name = name.to_string();
+            let from_heads_name = syn::Ident::new(
+                &format!("{}_from_heads", name),
+                proc_macro2::Span::call_site(),
+            );
+
+            if name == "new" && result_err.is_none() {
+                let mut clone_generics = (*generics).clone();
+                let where_clause = clone_generics.make_where_clause();
+                for ty in head_types.iter() {
+                    where_clause
+                        .predicates
+                        .push(syn::parse_quote! { #ty: ::core::clone::Clone });
+                }
+                let clone_where = &clone_generics.where_clause;
+
+                clone_impl = quote_mixed! {
+                    #(#cfg_attrs)*
+                    impl #generics_decls ::core::clone::Clone for #struct_name #generics_args #clone_where {
+                        fn clone(&self) -> Self {
+                            Self::#from_heads_name(#clone_heads)
+                        }
+                    }
+                };
+                continue;
+            }
+
+            let fn_name = syn::Ident::new(
+                &name.replacen("new", if name == "new" { "try_clone" } else { "clone" }, 1),
+                proc_macro2::Span::call_site(),
+            );
+            let ret_type = info.wrap_result(ty.clone());
+            let doc = format!(
+                "Clones the head fields, and constructs a new struct from them, like `{}`.",
+                name
+            );
+
+            new_funcs.push(quote_mixed! {
+                #[doc = #doc]
+                #ctor_vis fn #fn_name(&self) -> #ret_type
+                where
+                    #(#head_types: ::core::clone::Clone,)*
+                {
+                    Self::#from_heads_name(#clone_heads)
+                }
+            });
+        }
+    }
+
+    (new_funcs, clone_impl)
+}
+
+/// Returns the `derive_heads` implementations, comparing and hashing
+/// head fields only. Tail fields are derived from them, and comparing
+/// borrowed views is meaningless.
+fn make_derive_heads_impls(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        head_args,
+        head_types,
+        generics,
+        generics_decls,
+        generics_args,
+        ..
+    } = info;
+
+    options
+        .derive_heads
+        .iter()
+        .map(|name| {
+            let trait_path = match name.to_string().as_str() {
+                "PartialEq" => quote_mixed! { ::core::cmp::PartialEq },
+                "Eq" => quote_mixed! { ::core::cmp::Eq },
+                _ => quote_mixed! { ::core::hash::Hash },
+            };
+            let mut derive_generics = (*generics).clone();
+            let where_clause = derive_generics.make_where_clause();
+            for ty in head_types.iter() {
+                where_clause
+                    .predicates
+                    .push(syn::parse_quote! { #ty: #trait_path });
+            }
+            let derive_where = &derive_generics.where_clause;
+            let body = match name.to_string().as_str() {
+                "PartialEq" => quote_mixed! {
+                    fn eq(&self, other: &Self) -> bool {
+                        true #( && self.#head_args == other.#head_args )*
+                    }
+                },
+                "Eq" => quote_mixed! {},
+                _ => quote_mixed! {
+                    fn hash<IsHasher: ::core::hash::Hasher>(&self, state: &mut IsHasher) {
+                        #( ::core::hash::Hash::hash(&self.#head_args, state); )*
+                    }
+                },
+            };
+
+            quote_mixed! {
+                #(#cfg_attrs)*
+                impl #generics_decls #trait_path for #struct_name #generics_args #derive_where {
+                    #body
+                }
+            }
+        })
+        .collect()
+}
+
+/// Returns `debug_uninit` and the Debug implementation, which only
+/// reads initialized fields, so it also works on partial structs.
+fn make_debug(info: &StructInfo) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        is_tuple,
+        struct_name,
+        fields,
+        tails,
+        header_name,
+        generics,
+        generics_decls,
+        generics_args,
+        ..
+    } = info;
+
+    let mut debug_uninit_func = quote_mixed! {};
+    let mut debug_impl = quote_mixed! {};
+    if options.debug {
+        let debug_name = struct_name.unraw().to_string();
+        let debug_types: Vec<&syn::Type> = fields
+            .iter()
+            .filter(|field| !has_attribute(&field.attrs, "cfg"))
+            .map(|field| &field.ty)
+            .collect();
+        let mut debug_generics = (*generics).clone();
+        let where_clause = debug_generics.make_where_clause();
+        for ty in debug_types.iter() {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #ty: ::core::fmt::Debug });
+        }
+        let debug_where = &debug_generics.where_clause;
+        let debug_where_preds = &debug_where.as_ref().unwrap().predicates;
+        let field_label = |name: &syn::Ident| {
+            if *is_tuple {
+                quote_mixed! {}
+            } else {
+                let label = name.unraw().to_string();
+                quote_mixed! { #label, }
+            }
+        };
+        let debug_fields: Vec<proc_macro2::TokenStream> = fields
+            .iter()
+            .rev()
+            .map(|field| {
+                let name = field.ident.as_ref().unwrap();
+                let label = field_label(name);
+                let cfgs = field
+                    .attrs
+                    .iter()
+                    .filter(|attr| attr.path().is_ident("cfg"));
+
+                match tails.iter().position(|tail| tail.ident == field.ident) {
+                    Some(i) => quote_mixed! {
+                        #(#cfgs)*
+                        if incrstruct::internal::is_tail_inited(header, #i) {
+                            s.field(#label unsafe { &(*this).#name });
+                        } else {
+                            s.field(#label &incrstruct::internal::DebugUninit);
+                        }
+                    },
+                    None => quote_mixed! {
+                        #(#cfgs)*
+                        s.field(#label unsafe { &(*this).#name });
+                    },
+                }
+            })
+            .collect();
+        let header_label = field_label(header_name);
+        let debug_builder = if *is_tuple {
+            quote_mixed! { debug_tuple }
+        } else {
+            quote_mixed! { debug_struct }
+        };
+
+        debug_uninit_func = quote_mixed! {
+            /// Returns a `Debug` view of a partial struct, e.g. from
+            /// `new_uninit`, or after a failed `ensure_init`. Only
+            /// initialized tail fields are read.
+            ///
+            /// # Safety
+            ///
+            /// All head fields, and the header, must be initialized.
+            pub unsafe fn debug_uninit<'isthis>(this: &'isthis ::core::mem::MaybeUninit<Self>) -> incrstruct::internal::DebugUninitRef<'isthis, Self>
+            where
+                #debug_where_preds
+            {
+                let fmt: fn(&::core::mem::MaybeUninit<Self>, &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result = |this, f| {
+                    let this = this.as_ptr();
+                    // SAFETY: the caller promised the header and head
+                    // fields are initialized, and tail fields are only
+                    // read if the header says they are.
+                    let header: &incrstruct::Header<<Self as incrstruct::internal::IncrStructBase>::Tails, <Self as incrstruct::internal::IncrStructBase>::Dirty> = unsafe { &(*this).#header_name };
+                    let mut s = f.#debug_builder(#debug_name);
+
+                    #(#debug_fields)*
+                    s.field(#header_label header);
+                    s.finish()
+                };
+
+                // SAFETY: forwarded to the caller.
+                unsafe { incrstruct::internal::DebugUninitRef::new(this, fmt) }
+            }
+        };
+        debug_impl = quote_mixed! {
+            #(#cfg_attrs)*
+            impl #generics_decls ::core::fmt::Debug for #struct_name #generics_args #debug_where {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    // SAFETY: the struct is fully initialized.
+                    let this = unsafe { &*(self as *const Self as *const ::core::mem::MaybeUninit<Self>) };
+
+                    ::core::fmt::Debug::fmt(&unsafe { Self::debug_uninit(this) }, f)
+                }
+            }
+        };
+    }
+
+    (debug_uninit_func, debug_impl)
+}
+
+/// Returns the accessors for lazy tails, which initialize the cell on
+/// first access.
+fn make_lazy_getters(info: &StructInfo) -> Result<Vec<proc_macro2::TokenStream>, Error> {
+    let StructInfo {
+        tails,
+        tail_lazy,
+        generics_args,
+        init_err,
+        init_arg_params,
+        init_trait_name,
+        init_field_args,
+        init_field_names,
+        move_check,
+        ..
+    } = info;
+
+    tails
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| tail_lazy[*i])
+        .map(|(i, field)| {
+            let name = field.ident.as_ref().unwrap();
+            let ty = get_lazy_type(field)?;
+            let init_field_name = &init_field_names[i];
+            let init_field_args = &init_field_args[i];
+            let doc = format!(
+                "Returns the `{}` field, initializing it on first access.",
+                name.unraw()
+            );
+            // SAFETY: borrowed fields live as long as the struct, and
+            // are never mutably borrowed.
+            let r_decl = quote_mixed! {
+                #move_check
+                let r = unsafe { &*(this as *const Self) };
+            };
+
+            Ok(match &init_err {
+                Some(err) => quote_mixed! {
+                    #[doc = #doc]
+                    pub fn #name<'isthis>(this: &'isthis Self, #(#init_arg_params),*) -> ::core::result::Result<&'isthis #ty, #err> {
+                        #r_decl
+
+                        if let ::core::option::Option::Some(v) = r.#name.get() {
+                            return ::core::result::Result::Ok(v);
+                        }
+
+                        let v = <Self as #init_trait_name #generics_args>::#init_field_name(#( #init_field_args ),*)?;
+
+                        ::core::result::Result::Ok(r.#name.get_or_init(|| v))
+                    }
+                },
+                None => quote_mixed! {
+                    #[doc = #doc]
+                    pub fn #name<'isthis>(this: &'isthis Self, #(#init_arg_params),*) -> &'isthis #ty {
+                        #r_decl
+
+                        r.#name.get_or_init(|| <Self as #init_trait_name #generics_args>::#init_field_name(#( #init_field_args ),*))
+                    }
+                },
+            })
+        })
+        .collect()
+}
+
+/// Returns the accessors for tail fields, and getters for borrowed
+/// fields. Tails may be used before the struct is fully initialized,
+/// e.g. between phases.
+fn make_tail_accessors(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        options,
+        tails,
+        header_name,
+        tail_lazy,
+        borrowed_getter_fields,
+        self_lifetime,
+        move_check,
+        this_lifetime,
+        field_lifetime,
+        ..
+    } = info;
+
+    let tail_getters: Vec<proc_macro2::TokenStream> = tails
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let name = field.ident.as_ref().unwrap();
+            let getter_name = join_ident("try_", name, "");
+            let ty = &field.ty;
+            let doc = format!(
+                "Returns the `{}` field, if it has been initialized.",
+                name.unraw()
+            );
+
+            quote_mixed! {
+                #[doc = #doc]
+                ///
+                /// # Safety
+                ///
+                /// `this` must have been created by `new_uninit`.
+                pub unsafe fn #getter_name(this: &::core::mem::MaybeUninit<Self>) -> ::core::option::Option<&#ty> {
+                    // SAFETY: the header is always initialized, and
+                    // it knows if the tail field is.
+                    let p = this.as_ptr();
+                    if incrstruct::internal::is_tail_inited(&*::core::ptr::addr_of!((*p).#header_name), #i) {
+                        ::core::option::Option::Some(&*::core::ptr::addr_of!((*p).#name))
+                    } else {
+                        ::core::option::Option::None
+                    }
+                }
+            }
+        })
+        .collect();
+
+    // Accessors for covariant tails, with the self lifetime narrowed
+    // to the borrow of the struct. The coercion in the body only
+    // compiles if the field type really is covariant.
+    let tail_borrowers: Vec<proc_macro2::TokenStream> = tails
+        .iter()
+        .filter(|field| has_attribute(&field.attrs, "covariant"))
+        .map(|field| {
+            let name = field.ident.as_ref().unwrap();
+            let fn_name = join_ident("borrow_", name, "");
+            let ty = &field.ty;
+            let narrowed_ty = match self_lifetime {
+                Some(lifetime) => replace_lifetime(ty.to_token_stream(), lifetime, this_lifetime),
+                None => ty.to_token_stream(),
+            };
+            let doc = format!(
+                "Returns the `{}` field, borrowed no longer than the struct.",
+                name.unraw()
+            );
+
+            quote_mixed! {
+                #[doc = #doc]
+                pub fn #fn_name<#this_lifetime>(this: &#this_lifetime Self) -> &#this_lifetime #narrowed_ty {
+                    #move_check
+                    &this.#name
+                }
+            }
+        })
+        .collect();
+
+    // Read-only getters for private tails, except lazy tails, which
+    // already have them.
+    if options.private_tails {
+        if let Some(tail) = tails
+            .iter()
+            .find(|field| !matches!(field.vis, syn::Visibility::Inherited))
+        {
+            return Err(Error::new_spanned(
+                &tail.vis,
+                if options.boxed {
+                    "in boxed mode, tail fields must be private"
+                } else {
+                    "tail fields must be private with the private_tails option"
+                },
+            ));
+        }
+    }
+    let private_tail_getters: Vec<proc_macro2::TokenStream> = tails
+        .iter()
+        .zip(tail_lazy.iter())
+        .filter(|(_, lazy)| options.private_tails && !**lazy)
+        .map(|(field, _)| {
+            let name = field.ident.as_ref().unwrap();
+            let ty = &field.ty;
+            let doc = format!("Returns the `{}` field.", name.unraw());
+
+            quote_mixed! {
+                #[doc = #doc]
+                pub fn #name<#this_lifetime>(this: &#this_lifetime Self) -> &#this_lifetime #ty {
+                    #move_check
+                    &this.#name
+                }
+            }
+        })
+        .collect();
+
+    // Read-only getters for borrowed fields in boxed mode, unless a
+    // getter for the tail is generated above.
+    let borrowed_getters: Vec<proc_macro2::TokenStream> = borrowed_getter_fields
+        .iter()
+        .filter(
+            |field| match tails.iter().position(|tail| tail.ident == field.ident) {
+                Some(i) => !options.private_tails && !tail_lazy[i],
+                None => true,
+            },
+        )
+        .map(|field| {
+            let name = field.ident.as_ref().unwrap();
+            let ty = &field.ty;
+            let doc = format!("Returns the `{}` field.", name.unraw());
+
+            quote_mixed! {
+                #[doc = #doc]
+                pub fn #name<#this_lifetime>(this: &#this_lifetime Self) -> &#this_lifetime #ty {
+                    &this.#name
+                }
+            }
+        })
+        .collect();
+
+    // Closure accessors for all tails. The closure must accept any
+    // lifetime in place of the self lifetime, so it can't leak it.
+    let tail_withs: Vec<proc_macro2::TokenStream> = tails
+        .iter()
+        .map(|field| {
+            let name = field.ident.as_ref().unwrap();
+            let fn_name = join_ident("with_", name, "");
+            let ty = &field.ty;
+            let (hrtb, erased_ty) = match self_lifetime {
+                Some(lifetime) => (
+                    quote_mixed! { for<#field_lifetime> },
+                    replace_lifetime(ty.to_token_stream(), lifetime, field_lifetime),
+                ),
+                None => (quote_mixed! {}, ty.to_token_stream()),
+            };
+            let doc = format!(
+                "Calls `f` with the `{}` field, without exposing the struct lifetime.",
+                name.unraw()
+            );
+
+            quote_mixed! {
+                #[doc = #doc]
+                pub fn #fn_name<#this_lifetime, IsResult>(this: &#this_lifetime Self, f: impl #hrtb ::core::ops::FnOnce(&#this_lifetime #erased_ty) -> IsResult) -> IsResult {
+                    #move_check
+                    f(&this.#name)
+                }
+            }
+        })
+        .collect();
+
+    // Tails that borrow mutably hold the only reference to their
+    // heads, so they can be mutated through the pinned struct. Like
+    // `with_`, the closure can't smuggle anything with the struct
+    // lifetime in or out.
+    let mut tail_mut_withs = Vec::new();
+    for field in tails.iter() {
+        if get_mut_borrows(field)?.is_empty() {
+            continue;
+        }
+
+        let name = field.ident.as_ref().unwrap();
+        let fn_name = join_ident("with_", name, "_mut");
+        let ty = &field.ty;
+        let (hrtb, erased_ty) = match self_lifetime {
+            Some(lifetime) => (
+                quote_mixed! { for<#field_lifetime> },
+                replace_lifetime(ty.to_token_stream(), lifetime, field_lifetime),
+            ),
+            None => (quote_mixed! {}, ty.to_token_stream()),
+        };
+        let doc = format!(
+            "Calls `f` with the `{}` field, mutably, without exposing the struct lifetime.",
+            name.unraw()
+        );
+
+        tail_mut_withs.push(quote_mixed! {
+            #[doc = #doc]
+            pub fn #fn_name<#this_lifetime, IsResult>(this: ::core::pin::Pin<&#this_lifetime mut Self>, f: impl #hrtb ::core::ops::FnOnce(&#this_lifetime mut #erased_ty) -> IsResult) -> IsResult {
+                // SAFETY: nothing is moved out, and no other field
+                // borrows this one.
+                let this = unsafe { ::core::pin::Pin::get_unchecked_mut(this) };
+
+                f(&mut this.#name)
+            }
+        });
+    }
+
+    Ok(quote_mixed! {
+        #(#tail_getters)*
+
+        #(#private_tail_getters)*
+
+        #(#borrowed_getters)*
+
+        #(#tail_borrowers)*
+
+        #(#tail_withs)*
+
+        #(#tail_mut_withs)*
+    })
+}
+
+/// Returns `ensure_init_phase`, if there are multiple phases.
+fn make_ensure_init_phase_func(info: &StructInfo) -> proc_macro2::TokenStream {
+    let StructInfo {
+        num_phases,
+        init_unwrap,
+        init_args,
+        ..
+    } = info;
+    let force_init_type = info.wrap_result(quote_mixed! { () });
+    let ensure_init_phase_fn = info.internal_fn("ensure_init_phase");
+
+    if *num_phases > 1 && init_args.is_empty() {
+        quote_mixed! {
+            /// See [incrstruct::internal::ensure_init_phase].
+            pub unsafe fn ensure_init_phase(this: &mut ::core::mem::MaybeUninit<Self>, phase: u8) -> #force_init_type {
+                incrstruct::internal::#ensure_init_phase_fn(this, phase) #init_unwrap
+            }
+        }
+    } else {
+        quote_mixed! {}
+    }
+}
+
+/// Returns the functions only pinned structs have. Moving is fine in
+/// boxed mode.
+fn make_pinned_funcs(info: &StructInfo) -> proc_macro2::TokenStream {
+    let StructInfo {
+        options,
+        header_name,
+        init_unwrap,
+        init_args,
+        ..
+    } = info;
+
+    if options.boxed {
+        quote_mixed! {}
+    } else {
+        let into_rc_type =
+            info.wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Rc<Self>> });
+        let into_arc_type =
+            info.wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Arc<Self>> });
+        let box_into_rc_fn = info.internal_fn("box_into_rc");
+        let box_into_arc_fn = info.internal_fn("box_into_arc");
+
+        let conversion_funcs = if cfg!(feature = "alloc") && init_args.is_empty() {
+            quote_mixed! {
+                /// See [incrstruct::internal::box_into_rc].
+                pub fn into_rc(this: ::core::pin::Pin<incrstruct::internal::Box<Self>>) -> #into_rc_type {
+                    incrstruct::internal::#box_into_rc_fn(this) #init_unwrap
+                }
+
+                /// See [incrstruct::internal::box_into_arc].
+                pub fn into_arc(this: ::core::pin::Pin<incrstruct::internal::Box<Self>>) -> #into_arc_type {
+                    incrstruct::internal::#box_into_arc_fn(this) #init_unwrap
+                }
+            }
+        } else {
+            quote_mixed! {}
+        };
+
+        quote_mixed! {
+            /// See [incrstruct::internal::debug_assert_not_moved].
+            pub fn debug_assert_not_moved(this: &Self) {
+                incrstruct::internal::debug_assert_not_moved(this, &this.#header_name)
+            }
+
+            #conversion_funcs
+        }
+    }
+}
+
+/// Returns `into_heads`, which deconstructs the struct, by moving the
+/// head fields into the Heads struct. Pinned structs must be boxed, to
+/// be owned.
+fn make_into_heads_func(info: &StructInfo) -> proc_macro2::TokenStream {
+    let StructInfo {
+        options,
+        read_heads,
+        heads_ty,
+        ..
+    } = info;
+
+    let into_heads_type = if options.no_panic {
+        quote_mixed! { ::core::result::Result<#heads_ty, incrstruct::StateError> }
+    } else {
+        heads_ty.clone()
+    };
+    if options.boxed {
+        let movable_into_heads_fn = info.internal_fn("movable_into_heads");
+
+        quote_mixed! {
+            /// Drops the tail fields, and returns the head fields.
+            pub fn into_heads(this: Self) -> #into_heads_type {
+                // SAFETY: the closure moves out all head fields, and
+                // nothing else.
+                unsafe { incrstruct::internal::#movable_into_heads_fn(this, #read_heads) }
+            }
+        }
+    } else if cfg!(feature = "alloc") {
+        let box_into_heads_fn = info.internal_fn("box_into_heads");
+
+        quote_mixed! {
+            /// Drops the tail fields, and returns the head fields.
+            pub fn into_heads(this: ::core::pin::Pin<incrstruct::internal::Box<Self>>) -> #into_heads_type {
+                // SAFETY: the closure moves out all head fields, and
+                // nothing else.
+                unsafe { incrstruct::internal::#box_into_heads_fn(this, #read_heads) }
+            }
+        }
+    } else {
+        quote_mixed! {}
+    }
+}
+
+/// Returns the Yoke conversions, for the "one owned cart, one borrowed
+/// view" shape. The view is initialized again, since a Yoke can't be
+/// built from an existing reference.
+fn make_yoke_funcs(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        input,
+        options,
+        struct_name,
+        ctor_vis,
+        heads,
+        tails,
+        self_lifetime,
+        init_err,
+        init_trait_name,
+        init_field_names,
+        heads_name,
+        ..
+    } = info;
+
+    Ok(if options.yoke {
+        let (cart, view, lifetime) = match (heads.as_slice(), tails.as_slice(), self_lifetime) {
+            ([cart], [view], Some(lifetime))
+                if options.boxed && !options.no_panic && input.generics.params.len() == 1 =>
+            {
+                (cart, view, lifetime)
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "the yoke option needs a boxed-mode struct with one lifetime, one head field, one tail field, and no no_panic option",
+                ))
+            }
+        };
+        let cart_name = &cart.ident;
+        let cart_ty = &cart.ty;
+        let view_ty = replace_lifetime(
+            view.ty.to_token_stream(),
+            lifetime,
+            &syn::Lifetime::new("'static", proc_macro2::Span::mixed_site()),
+        );
+        let init_fn = &init_field_names[0];
+        let yoke_ty = quote_mixed! { incrstruct::yoke::Yoke<#view_ty, #cart_ty> };
+        let from_yoke_type = info.wrap_result(quote_mixed! { Self });
+        let (into_yoke_type, attach_fn) = match &init_err {
+            Some(err) => (
+                quote_mixed! { ::core::result::Result<#yoke_ty, #err> },
+                quote_mixed! { try_attach_to_cart },
+            ),
+            None => (yoke_ty.clone(), quote_mixed! { attach_to_cart }),
+        };
+
+        quote_mixed! {
+            /// Drops the tail field, and returns a `Yoke` with the head
+            /// field as the cart, and the tail field initialized again
+            /// as the yokeable.
+            #ctor_vis fn into_yoke(this: Self) -> #into_yoke_type {
+                let heads = Self::into_heads(this);
+
+                <#yoke_ty>::#attach_fn(heads.#cart_name, |cart| <#struct_name as #init_trait_name>::#init_fn(cart))
+            }
+
+            /// Takes the cart of a `Yoke` as the head field, and
+            /// initializes the tail field again.
+            #ctor_vis fn from_yoke(yoke: #yoke_ty) -> #from_yoke_type {
+                Self::new_from_heads(#heads_name { #cart_name: yoke.into_backing_cart() })
+            }
+        }
+    } else {
+        quote_mixed! {}
+    })
+}
+
+/// Returns the functions mutating head fields, by moving them into the
+/// Heads struct while the tail fields are dropped. If re-initializing
+/// a live struct fails, it's restored, and the init error returned.
+fn make_reinit_funcs(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        options,
+        heads,
+        tails,
+        num_tails,
+        header_name,
+        tail_lazy,
+        head_params,
+        head_args,
+        tail_names,
+        generics_args,
+        init_err,
+        init_arg_params,
+        init_trait_name,
+        init_field_args,
+        init_field_names,
+        read_heads,
+        heads_name,
+        heads_ty,
+        head_types,
+        init_unwrap,
+        ..
+    } = info;
+    let args_init_closure = info.args_init_closure();
+
+    let with_heads_mut_type = info.wrap_result(quote_mixed! { R });
+    let refresh_type = info.wrap_result(quote_mixed! { () });
+    let with_heads_mut_fn = info.internal_fn("with_heads_mut");
+    let (with_heads_mut_this, with_heads_mut_this_mut) = if options.boxed {
+        (quote_mixed! { &mut Self }, quote_mixed! { this })
+    } else {
+        (
+            quote_mixed! { ::core::pin::Pin<&mut Self> },
+            quote_mixed! { ::core::pin::Pin::get_unchecked_mut(this) },
+        )
+    };
+    // With an init error, the heads are copied first, so they can be
+    // restored if the tails fail to initialize from the changed ones.
+    // The bounds are higher-ranked, so non-Clone heads only make the
+    // function uncallable.
+    let (with_heads_mut_where, backup_heads) = match init_err {
+        Some(_) => (
+            quote_mixed! {
+                where
+                    #(for<'isclone> #head_types: ::core::clone::Clone,)*
+            },
+            quote_mixed! {
+                |this: &Self| ::core::option::Option::Some(#heads_name {
+                    #(
+                        #head_args: ::core::clone::Clone::clone(&this.#head_args),
+                    )*
+                })
+            },
+        ),
+        None => (
+            quote_mixed! {},
+            quote_mixed! { |_: &Self| ::core::option::Option::None },
+        ),
+    };
+    let with_heads_mut_func = quote_mixed! {
+        /// Drops the tail fields, calls `f` with the head fields, and
+        /// initializes the tail fields again. If that fails, the head
+        /// fields are restored, the tail fields initialized from them
+        /// again, and the error returned. Aborts if that fails too, or
+        /// anything panics.
+        pub fn with_heads_mut<R>(this: #with_heads_mut_this, #(#init_arg_params,)* f: impl ::core::ops::FnOnce(&mut #heads_ty) -> R) -> #with_heads_mut_type #with_heads_mut_where {
+            let init = #args_init_closure;
+
+            // SAFETY: the heads are moved back before the tail fields
+            // are initialized, and the struct is never moved.
+            unsafe {
+                incrstruct::internal::#with_heads_mut_fn(
+                    #with_heads_mut_this_mut,
+                    #backup_heads,
+                    #read_heads,
+                    |this: &mut Self, heads: #heads_ty| {
+                        let #heads_name { #(#head_args),* } = heads;
+                        #(
+                            ::core::ptr::write(&mut this.#head_args, #head_args);
+                        )*
+                    },
+                    f,
+                    init,
+                )
+            } #init_unwrap
+        }
+    };
+
+    // The fields each tail borrows, to find the tails that must be
+    // initialized again when a field changes.
+    let tail_borrows = tails
+        .iter()
+        .map(|field| get_borrows(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Returns the tails borrowing any of `names`, directly or through
+    // other tails, in initialization order.
+    let find_dependents = |mut names: HashSet<syn::Ident>| -> Vec<usize> {
+        let mut out = Vec::new();
+
+        for (i, borrows) in tail_borrows.iter().enumerate() {
+            if borrows.iter().any(|name| names.contains(name)) {
+                names.insert(tails[i].ident.clone().unwrap());
+                out.push(i);
+            }
+        }
+
+        out
+    };
+    // Returns the closures for refresh_with, dropping the given tails,
+    // in drop order, and initializing them again, in initialization
+    // order. The `aside` tails are instead initialized before anything
+    // is dropped, and swapped in, keeping the old values for rollback,
+    // unless their condition holds, since a tail they borrow is also
+    // initialized again. The closures must be called in an unsafe
+    // block. With a `head`, it's replaced after dropping, and moved
+    // back on rollback. With `only_dirty`, tails not marked as dirty
+    // are skipped.
+    let make_refresh_closures = |indices: &[usize],
+                                 aside: &[(usize, Option<proc_macro2::TokenStream>)],
+                                 head: Option<&proc_macro2::TokenStream>,
+                                 only_dirty: bool| {
+        let if_dirty = |i: usize, stmts: proc_macro2::TokenStream| {
+            if only_dirty {
+                quote_mixed! {
+                    if incrstruct::internal::is_tail_dirty(&r.#header_name, #i) {
+                        #stmts
+                    }
+                }
+            } else {
+                stmts
+            }
+        };
+        let init_value = |i: usize| {
+            let init_field_name = &init_field_names[i];
+            let init_field_args = &init_field_args[i];
+            let call = quote_mixed! {
+                <Self as #init_trait_name #generics_args>::#init_field_name(#( #init_field_args ),*)
+            };
+
+            match &init_err {
+                Some(_) => quote_mixed! {
+                    match #call {
+                        ::core::result::Result::Ok(v) => v,
+                        ::core::result::Result::Err(err) => return ::core::result::Result::Err(err),
+                    }
+                },
+                None => call,
+            }
+        };
+        // The in-place tails, with the condition of those that are
+        // only sometimes set aside.
+        let in_place: Vec<(usize, Option<&proc_macro2::TokenStream>)> = indices
+            .iter()
+            .filter_map(|i| match aside.iter().find(|(j, _)| j == i) {
+                Some((_, Some(cond))) => Some((*i, Some(cond))),
+                Some((_, None)) => None,
+                None => Some((*i, None)),
+            })
+            .collect();
+        let if_in_place = |i: usize, cond: Option<&proc_macro2::TokenStream>, stmts| {
+            if_dirty(
+                i,
+                match cond {
+                    Some(cond) => quote_mixed! {
+                        if #cond {
+                            #stmts
+                        }
+                    },
+                    None => stmts,
+                },
+            )
+        };
+        let drops: Vec<proc_macro2::TokenStream> = in_place
+            .iter()
+            .rev()
+            .map(|(i, cond)| {
+                let tail_name = &tail_names[*i];
+
+                if_in_place(
+                    *i,
+                    *cond,
+                    quote_mixed! {
+                        if incrstruct::internal::take_tail_inited(&mut r.#header_name, #i) {
+                            ::core::ptr::drop_in_place(&mut r.#tail_name);
+                        }
+                    },
+                )
+            })
+            .collect();
+        let stmts = in_place.iter().map(|(i, cond)| {
+            let tail_name = &tail_names[*i];
+
+            if tail_lazy[*i] {
+                return if_in_place(
+                    *i,
+                    *cond,
+                    quote_mixed! {
+                        ::core::ptr::write(&mut r.#tail_name as *mut _, ::core::default::Default::default());
+                        incrstruct::internal::set_tail_inited(&mut r.#header_name, #i);
+                    },
+                );
+            }
+
+            let value = init_value(*i);
+
+            if_in_place(
+                *i,
+                *cond,
+                quote_mixed! {
+                    ::core::ptr::write(&mut r.#tail_name as *mut _, #value);
+                    incrstruct::internal::set_tail_inited(&mut r.#header_name, #i);
+                },
+            )
+        });
+        let r_pat = if head.is_none() && indices.is_empty() {
+            quote_mixed! { _ }
+        } else {
+            quote_mixed! { r }
+        };
+        let (swap_head, old_pat, restore_head) = match head {
+            Some(head) => (
+                quote_mixed! { ::core::mem::replace(&mut r.#head, #head) },
+                quote_mixed! { old },
+                quote_mixed! { r.#head = old; },
+            ),
+            None => (quote_mixed! { () }, quote_mixed! { _ }, quote_mixed! {}),
+        };
+        let refresh = if in_place.is_empty() {
+            quote_mixed! { |_: *mut Self| ::core::result::Result::Ok(()) }
+        } else {
+            quote_mixed! {
+                |this: *mut Self| {
+                    let r = &mut *this;
+                    #(#stmts)*
+
+                    ::core::result::Result::Ok(())
+                }
+            }
+        };
+
+        let aside_fields: Vec<&proc_macro2::TokenStream> =
+            aside.iter().map(|(i, _)| &tail_names[*i]).collect();
+        let aside_names: Vec<syn::Ident> = aside
+            .iter()
+            .map(|(i, _)| syn::Ident::new(&format!("aside{}", i), proc_macro2::Span::mixed_site()))
+            .collect();
+        let aside_values = aside.iter().map(|(i, cond)| {
+            let value = init_value(*i);
+            let value = quote_mixed! { ::core::option::Option::Some(#value) };
+            let dirty = only_dirty.then(|| {
+                quote_mixed! { incrstruct::internal::is_tail_dirty(&r.#header_name, #i) }
+            });
+
+            match (dirty, cond) {
+                (None, None) => value,
+                (Some(dirty), None) => quote_mixed! {
+                    if #dirty { #value } else { ::core::option::Option::None }
+                },
+                (None, Some(cond)) => quote_mixed! {
+                    if !#cond { #value } else { ::core::option::Option::None }
+                },
+                (Some(dirty), Some(cond)) => quote_mixed! {
+                    if #dirty && !#cond { #value } else { ::core::option::Option::None }
+                },
+            }
+        });
+        let nones = aside
+            .iter()
+            .map(|_| quote_mixed! { ::core::option::Option::None });
+        let (build, new_pat, swap_aside, old_aside_pat, restore_aside) = if aside.is_empty() {
+            (
+                quote_mixed! { |_: *const Self| ::core::result::Result::Ok(()) },
+                quote_mixed! { _ },
+                quote_mixed! { () },
+                quote_mixed! { () },
+                quote_mixed! {},
+            )
+        } else {
+            (
+                quote_mixed! {
+                    |this: *const Self| {
+                        let r = &*this;
+
+                        ::core::result::Result::Ok((#(#aside_values,)*))
+                    }
+                },
+                quote_mixed! { new },
+                quote_mixed! {
+                    {
+                        let (#(#aside_names,)*) = match new {
+                            ::core::option::Option::Some(new) => new,
+                            ::core::option::Option::None => (#(#nones,)*),
+                        };
+
+                        (#(#aside_names.map(|v| ::core::mem::replace(&mut r.#aside_fields, v)),)*)
+                    }
+                },
+                quote_mixed! { (#(#aside_names,)*) },
+                quote_mixed! {
+                    #(
+                        if let ::core::option::Option::Some(v) = #aside_names {
+                            r.#aside_fields = v;
+                        }
+                    )*
+                },
+            )
+        };
+
+        (
+            build,
+            quote_mixed! {
+                move |#r_pat: &mut Self, #new_pat| {
+                    #(#drops)*
+
+                    (#swap_head, #swap_aside)
+                }
+            },
+            refresh,
+            quote_mixed! {
+                |#r_pat: &mut Self, (#old_pat, #old_aside_pat)| {
+                    #(#drops)*
+                    #restore_aside
+                    #restore_head
+                }
+            },
+        )
+    };
+    let refresh_with_fn = info.internal_fn("refresh_with");
+
+    // Setters for head fields, initializing the tails borrowing them
+    // again.
+    let head_setters: Vec<proc_macro2::TokenStream> = heads
+        .iter()
+        .enumerate()
+        .map(|(j, field)| {
+            let name = field.ident.as_ref().unwrap();
+            let fn_name = join_ident("set_", name, "");
+            let head_param = &head_params[j];
+            let head_arg = &head_args[j];
+            let (build, swap, refresh, rollback) = make_refresh_closures(
+                &find_dependents(HashSet::from([name.clone()])),
+                &[],
+                Some(head_arg),
+                false,
+            );
+            let doc = format!(
+                "Sets the `{}` field, and initializes the tail fields borrowing it again. If that fails, the old value is restored, the tail fields initialized from it again, and the error returned. Aborts if that fails too, or anything panics.",
+                name.unraw()
+            );
+
+            quote_mixed! {
+                #[doc = #doc]
+                pub fn #fn_name(this: #with_heads_mut_this, #(#init_arg_params,)* #head_param) -> #refresh_type {
+                    let init = #args_init_closure;
+
+                    // SAFETY: only tail fields borrowing the head field
+                    // are dropped, and initialized again. The struct is
+                    // never moved.
+                    unsafe {
+                        incrstruct::internal::#refresh_with_fn(
+                            #with_heads_mut_this_mut,
+                            0,
+                            #build,
+                            #swap,
+                            #refresh,
+                            #rollback,
+                            init,
+                        )
+                    } #init_unwrap
+                }
+            }
+        })
+        .collect();
+
+    // Re-initializes a single tail, and the tails borrowing it, e.g.
+    // when it depends on something outside the struct.
+    let tail_refreshers: Vec<proc_macro2::TokenStream> = tails
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let name = field.ident.as_ref().unwrap();
+            let fn_name = join_ident("refresh_", name, "");
+            let mut indices = find_dependents(HashSet::from([name.clone()]));
+            indices.insert(0, i);
+            // The tail is initialized before the old value is dropped,
+            // unless it's lazy, or mutably borrows a head field, which
+            // the old value still does.
+            let aside = if tail_lazy[i] || !get_mut_borrows(field)?.is_empty() {
+                vec![]
+            } else {
+                vec![(i, None)]
+            };
+            let dirty: u64 = indices.iter().fold(0, |mask, i| mask | (1 << i));
+            let (build, swap, refresh, rollback) = make_refresh_closures(&indices, &aside, None, false);
+            let doc = if aside.is_empty() {
+                format!(
+                    "Initializes the `{}` field, and the tail fields borrowing it, again. If that fails, they are initialized again, marked as dirty, and the error returned. Aborts if that fails too, or anything panics.",
+                    name.unraw()
+                )
+            } else {
+                format!(
+                    "Initializes the `{}` field, and the tail fields borrowing it, again. If that fails, the old values are kept, marked as dirty, and the error returned. Aborts if the tail fields borrowing it fail again from the old value, or anything but the `{}` field panics.",
+                    name.unraw(),
+                    name.unraw()
+                )
+            };
+
+            Ok(quote_mixed! {
+                #[doc = #doc]
+                pub fn #fn_name(this: #with_heads_mut_this, #(#init_arg_params),*) -> #refresh_type {
+                    let init = #args_init_closure;
+
+                    // SAFETY: only the tail field, and tail fields
+                    // borrowing it, are dropped, and initialized again.
+                    // The struct is never moved.
+                    unsafe {
+                        incrstruct::internal::#refresh_with_fn(
+                            #with_heads_mut_this_mut,
+                            #dirty,
+                            #build,
+                            #swap,
+                            #refresh,
+                            #rollback,
+                            init,
+                        )
+                    } #init_unwrap
+                }
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    // Dirty tracking, for heads that change through interior
+    // mutability. Marking a head as dirty marks the tails borrowing it,
+    // and `recompute` initializes only those again.
+    let unpin_this = if options.boxed {
+        quote_mixed! {}
+    } else {
+        quote_mixed! {
+            // SAFETY: only the header changes.
+            let this = unsafe { ::core::pin::Pin::get_unchecked_mut(this) };
+        }
+    };
+    // The bounds are higher-ranked, so a header without dirty bits only
+    // makes the functions uncallable.
+    let dirty_where = quote_mixed! {
+        where
+            for<'isdirty> <Self as incrstruct::internal::IncrStructBase>::Dirty: incrstruct::internal::DirtyBits
+    };
+    let mark_dirty_funcs = heads.iter().map(|field| {
+        let name = field.ident.as_ref().unwrap();
+        let fn_name = join_ident("mark_", name, "_dirty");
+        let mask: u64 = find_dependents(HashSet::from([name.clone()]))
+            .into_iter()
+            .fold(0, |mask, i| mask | (1 << i));
+        let doc = format!(
+            "Marks the tail fields borrowing the `{}` field as dirty, so `recompute` initializes them again.",
+            name.unraw()
+        );
+
+        quote_mixed! {
+            #[doc = #doc]
+            pub fn #fn_name(this: #with_heads_mut_this) #dirty_where {
+                #unpin_this
+                incrstruct::internal::mark_tails_dirty(&mut this.#header_name, #mask);
+            }
+        }
+    });
+    let recompute_fn = info.internal_fn("recompute_with");
+    // A dirty tail is set aside, like in the refreshers, unless a tail
+    // it borrows is also dirty.
+    let recompute_aside = tails
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !tail_lazy[*i])
+        .map(|(i, field)| {
+            if !get_mut_borrows(field)?.is_empty() {
+                return Ok(None);
+            }
+
+            let borrowed_dirty: Vec<proc_macro2::TokenStream> = tails
+                .iter()
+                .enumerate()
+                .filter(|(_, tail)| tail_borrows[i].contains(tail.ident.as_ref().unwrap()))
+                .map(|(j, _)| {
+                    quote_mixed! { incrstruct::internal::is_tail_dirty(&r.#header_name, #j) }
+                })
+                .collect();
+
+            Ok(Some((
+                i,
+                (!borrowed_dirty.is_empty()).then(|| quote_mixed! { (#(#borrowed_dirty)||*) }),
+            )))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, Error>>()?;
+    let (recompute_build, recompute_swap, recompute_refresh, recompute_rollback) =
+        make_refresh_closures(
+            &(0..*num_tails).collect::<Vec<_>>(),
+            &recompute_aside,
+            None,
+            true,
+        );
+    let dirty_funcs = quote_mixed! {
+        #(#mark_dirty_funcs)*
+
+        /// Initializes the tail fields marked as dirty again, and
+        /// marks them as clean. A dirty tail field only borrowing
+        /// clean fields is initialized before the old value is dropped.
+        /// If that fails, the old values are kept, still marked as
+        /// dirty, and the error returned. Aborts if the tail fields
+        /// borrowing it fail again from the old value, or anything else
+        /// panics.
+        pub fn recompute(this: #with_heads_mut_this, #(#init_arg_params),*) -> #refresh_type #dirty_where {
+            let init = #args_init_closure;
+
+            // SAFETY: only dirty tail fields are dropped, and
+            // initialized again. Tail fields borrowing a dirty field
+            // are also dirty. The struct is never moved.
+            unsafe {
+                incrstruct::internal::#recompute_fn(
+                    #with_heads_mut_this_mut,
+                    #recompute_build,
+                    #recompute_swap,
+                    #recompute_refresh,
+                    #recompute_rollback,
+                    init,
+                )
+            } #init_unwrap
+        }
+    };
+
+    Ok(quote_mixed! {
+        #with_heads_mut_func
+
+        #(#head_setters)*
+
+        #(#tail_refreshers)*
+
+        #dirty_funcs
+    })
+}
+
+/// Returns the serde functions, implementations, and the attributes of
+/// the Heads struct. Only head fields are serialized. Deserializing
+/// constructs the struct from AStructHeads, which derives Deserialize.
+fn make_serde(
+    info: &StructInfo,
+) -> (
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        ctor_vis,
+        heads,
+        head_args,
+        head_types,
+        generics,
+        generics_decls,
+        generics_args,
+        result_err,
+        heads_ty,
+        ..
+    } = info;
+
+    if options.serde {
+        let serde_crate = match &options.crate_path {
+            Some(path) => format!(
+                "{}::serde",
+                path.to_token_stream().to_string().replace(' ', "")
+            ),
+            None => "incrstruct::serde".to_string(),
+        };
+        let rename = struct_name.unraw().to_string();
+        let heads_serde_attrs = quote_mixed! {
+            #[derive(incrstruct::serde::Deserialize)]
+            #[serde(crate = #serde_crate, rename = #rename)]
+        };
+
+        // In the same order as the fields of AStructHeads.
+        let serialize_names: Vec<String> = heads
+            .iter()
+            .map(|field| field.ident.as_ref().unwrap().unraw().to_string())
+            .collect();
+        let serialize_args: Vec<&proc_macro2::TokenStream> = head_args.iter().collect();
+        let num_heads = heads.len();
+        let mut serialize_generics = (*generics).clone();
+        let where_clause = serialize_generics.make_where_clause();
+        for ty in head_types.iter() {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #ty: incrstruct::serde::Serialize });
+        }
+        let serialize_where = &serialize_generics.where_clause;
+        let serialize_impl = quote_mixed! {
+            #(#cfg_attrs)*
+            impl #generics_decls incrstruct::serde::Serialize for #struct_name #generics_args #serialize_where {
+                fn serialize<IsSerializer: incrstruct::serde::Serializer>(&self, serializer: IsSerializer) -> ::core::result::Result<IsSerializer::Ok, IsSerializer::Error> {
+                    use incrstruct::serde::ser::SerializeStruct;
+
+                    let mut state = serializer.serialize_struct(#rename, #num_heads)?;
+                    #(
+                        state.serialize_field(#serialize_names, &self.#serialize_args)?;
+                    )*
+                    state.end()
+                }
+            }
+        };
+        let init_failed = format!("{} initialization failed", struct_name.unraw());
+        let wrap_init = |call: proc_macro2::TokenStream| match &result_err {
+            Some(_) => quote_mixed! {
+                #call.map_err(|_| <IsDeserializer::Error as incrstruct::serde::de::Error>::custom(#init_failed))
+            },
+            None => quote_mixed! { ::core::result::Result::Ok(#call) },
+        };
+        let new_from_heads = wrap_init(quote_mixed! { Self::new_from_heads(heads) });
+        let new_box_from_heads = wrap_init(quote_mixed! { Self::new_box_from_heads(heads) });
+
+        if options.boxed {
+            // The struct can move, so it implements Deserialize itself.
+            let mut deserialize_generics = (*generics).clone();
+            deserialize_generics
+                .params
+                .insert(0, syn::parse_quote! { 'isde });
+            deserialize_generics
+                .make_where_clause()
+                .predicates
+                .push(syn::parse_quote! { #heads_ty: incrstruct::serde::Deserialize<'isde> });
+            let (deserialize_decls, _, deserialize_where) = deserialize_generics.split_for_impl();
+
+            (
+                quote_mixed! {},
+                quote_mixed! {
+                    #serialize_impl
+
+                    #(#cfg_attrs)*
+                    impl #deserialize_decls incrstruct::serde::Deserialize<'isde> for #struct_name #generics_args #deserialize_where {
+                        fn deserialize<IsDeserializer: incrstruct::serde::Deserializer<'isde>>(deserializer: IsDeserializer) -> ::core::result::Result<Self, IsDeserializer::Error> {
+                            let heads = <#heads_ty as incrstruct::serde::Deserialize<'isde>>::deserialize(deserializer)?;
+
+                            #new_from_heads
+                        }
+                    }
+                },
+                heads_serde_attrs,
+            )
+        } else {
+            let deserialize_func = if cfg!(feature = "alloc") {
+                quote_mixed! {
+                    /// Deserializes the head fields, and constructs the
+                    /// struct in a new box. Use it with
+                    /// `#[serde(deserialize_with = "...")]`.
+                    #ctor_vis fn deserialize_box<'isde, IsDeserializer: incrstruct::serde::Deserializer<'isde>>(deserializer: IsDeserializer) -> ::core::result::Result<::core::pin::Pin<incrstruct::internal::Box<Self>>, IsDeserializer::Error>
+                    where
+                        #heads_ty: incrstruct::serde::Deserialize<'isde>,
+                    {
+                        let heads = <#heads_ty as incrstruct::serde::Deserialize<'isde>>::deserialize(deserializer)?;
+
+                        #new_box_from_heads
+                    }
+                }
+            } else {
+                quote_mixed! {}
+            };
+
+            (deserialize_func, serialize_impl, heads_serde_attrs)
+        }
+    } else {
+        (quote_mixed! {}, quote_mixed! {}, quote_mixed! {})
+    }
+}
+
+/// Returns the Heads struct. Heads keep their documentation and
+/// configuration, but other attributes may belong to other derives.
+fn make_heads_struct(
+    info: &StructInfo,
+    heads_serde_attrs: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let StructInfo {
+        cfg_attrs,
+        struct_name,
+        vis,
+        heads,
+        heads_name,
+        heads_generics,
+        ..
+    } = info;
+
+    let (heads_decls, _, _) = heads_generics.split_for_impl();
+    let heads_fields: Vec<proc_macro2::TokenStream> = heads
+        .iter()
+        .map(|field| {
+            let attrs = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc") || attr.path().is_ident("cfg"));
+            let field_vis = &field.vis;
+            let name = &field.ident;
+            let ty = &field.ty;
+
+            quote_mixed! { #(#attrs)* #field_vis #name: #ty }
+        })
+        .collect();
+    let heads_doc = format!(
+        "The head fields of `{}`, for `new_box_from_heads`.",
+        struct_name
+    );
+    quote_mixed! {
+        #(#cfg_attrs)*
+        #[doc = #heads_doc]
+        #[allow(dead_code)]
+        #heads_serde_attrs
+        #vis struct #heads_name #heads_decls {
+            #(#heads_fields,)*
+        }
+    }
+}
+
+/// Returns the struct of references to all fields but the header, and
+/// `with_all`, which destructures it.
+fn make_with_all(info: &StructInfo) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let StructInfo {
+        cfg_attrs,
+        struct_name,
+        vis,
+        fields,
+        self_lifetime,
+        this_lifetime,
+        field_lifetime,
+        borrowed_generics,
+        ..
+    } = info;
+
+    let borrowed_name = join_ident("", struct_name, "BorrowedFields");
+    let (borrowed_decls, borrowed_args, borrowed_where) = borrowed_generics.split_for_impl();
+    let borrowed_ty = match self_lifetime {
+        Some(lifetime) => replace_lifetime(
+            quote_mixed! { #borrowed_name #borrowed_args },
+            lifetime,
+            field_lifetime,
+        ),
+        None => quote_mixed! { #borrowed_name #borrowed_args },
+    };
+    let borrowed_hrtb = if self_lifetime.is_some() {
+        quote_mixed! { for<#field_lifetime> }
+    } else {
+        quote_mixed! {}
+    };
+    let (borrowed_fields, borrowed_inits): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .rev()
+        .map(|field| {
+            let attrs: Vec<&syn::Attribute> = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc") || attr.path().is_ident("cfg"))
+                .collect();
+            let cfgs = attrs.iter().filter(|attr| attr.path().is_ident("cfg"));
+            let field_vis = &field.vis;
+            let name = &field.ident;
+            let ty = &field.ty;
+
+            (
+                quote_mixed! { #(#attrs)* #field_vis #name: &#this_lifetime #ty },
+                quote_mixed! { #(#cfgs)* #name: &this.#name },
+            )
+        })
+        .unzip();
+    let borrowed_doc = format!(
+        "References to the fields of `{}`, for `with_all`.",
+        struct_name
+    );
+    let borrowed_struct = quote_mixed! {
+        #(#cfg_attrs)*
+        #[doc = #borrowed_doc]
+        #[allow(dead_code)]
+        #vis struct #borrowed_name #borrowed_decls #borrowed_where {
+            #(#borrowed_fields,)*
+        }
+    };
+    let with_all_func = quote_mixed! {
+        /// Calls `f` with references to all fields, without exposing
+        /// the struct lifetime.
+        pub fn with_all<#this_lifetime, IsResult>(this: &#this_lifetime Self, f: impl #borrowed_hrtb ::core::ops::FnOnce(#borrowed_ty) -> IsResult) -> IsResult {
+            f(#borrowed_name {
+                #(#borrowed_inits,)*
+            })
+        }
+    };
+
+    (borrowed_struct, with_all_func)
+}
+
+/// Returns the pinned projection, like pin-project, with the project
+/// option. Heads that no tail borrows are pinned mutably. The others,
+/// and tails, are only shared, so nothing a tail borrows can change.
+/// The guards are added by [make_not_impl_guards].
+fn make_projection(info: &StructInfo) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        vis,
+        fields,
+        borrowed_names,
+        this_lifetime,
+        borrowed_generics,
+        ..
+    } = info;
+
+    if !options.project {
+        return (quote_mixed! {}, quote_mixed! {});
+    }
+
+    let (borrowed_decls, borrowed_args, borrowed_where) = borrowed_generics.split_for_impl();
+    let projection_name = join_ident("", struct_name, "Projection");
+    let (projection_fields, projection_inits): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .rev()
+        .map(|field| {
+            let attrs: Vec<&syn::Attribute> = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc") || attr.path().is_ident("cfg"))
+                .collect();
+            let cfgs = attrs.iter().filter(|attr| attr.path().is_ident("cfg"));
+            let field_vis = &field.vis;
+            let name = &field.ident;
+            let ty = &field.ty;
+
+            if has_attribute(&field.attrs, "borrows")
+                || borrowed_names.contains(field.ident.as_ref().unwrap())
+            {
+                (
+                    quote_mixed! { #(#attrs)* #field_vis #name: &#this_lifetime #ty },
+                    quote_mixed! { #(#cfgs)* #name: &this.#name },
+                )
+            } else {
+                (
+                    quote_mixed! { #(#attrs)* #field_vis #name: ::core::pin::Pin<&#this_lifetime mut #ty> },
+                    quote_mixed! { #(#cfgs)* #name: unsafe { ::core::pin::Pin::new_unchecked(&mut this.#name) } },
+                )
+            }
+        })
+        .unzip();
+    let projection_doc = format!(
+        "Pinned references to the fields of `{}`, returned by `project`.",
+        struct_name
+    );
+    let projection_struct = quote_mixed! {
+        #(#cfg_attrs)*
+        #[doc = #projection_doc]
+        #[allow(dead_code)]
+        #vis struct #projection_name #borrowed_decls #borrowed_where {
+            #(#projection_fields,)*
+        }
+    };
+
+    let project_func = quote_mixed! {
+        /// Returns pinned mutable references to the head fields no
+        /// tail field borrows, and shared references to the other
+        /// fields. This is enough to implement e.g. `Future`.
+        pub fn project<#this_lifetime>(self: ::core::pin::Pin<&#this_lifetime mut Self>) -> #projection_name #borrowed_args {
+            // SAFETY: the fields are pinned, since the struct is, and
+            // nothing is moved out.
+            let this = unsafe { self.get_unchecked_mut() };
+
+            #projection_name {
+                #(#projection_inits,)*
+            }
+        }
+    };
+
+    (projection_struct, project_func)
+}
+
+/// Returns the `_default` variant of every constructor, with the
+/// default option, so call sites don't need to spell out the head
+/// fields, and the Default implementation, if any.
+fn make_default(info: &StructInfo) -> (Vec<proc_macro2::TokenStream>, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        ctor_vis,
+        head_args,
+        ctor_head_args,
+        ctor_head_types,
+        default_heads,
+        generics_decls,
+        generics_args,
+        generics_where,
+        init_args,
+        init_arg_params,
+        init_arg_names,
+        containers,
+        ..
+    } = info;
+
+    if options.default {
+        let mut default_funcs: Vec<proc_macro2::TokenStream> = containers
+            .iter()
+            .map(|(name, ty, _, _)| {
+                let fn_name = syn::Ident::new(
+                    &format!("{}_default", name),
+                    proc_macro2::Span::call_site(),
+                );
+                let ret_type = info.wrap_result(ty.clone());
+
+                quote_mixed! {
+                    #ctor_vis fn #fn_name(#(#init_arg_params),*) -> #ret_type {
+                        Self::#name(#( <#ctor_head_types as ::core::default::Default>::default(), )* #(#init_arg_names),*)
+                    }
+                }
+            })
+            .collect();
+        // In no-panic mode, new_box_default already covers it.
+        if cfg!(feature = "alloc") && init_args.is_empty() && !options.no_panic {
+            let box_type = if options.boxed {
+                quote_mixed! { incrstruct::internal::Box<Self> }
+            } else {
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> }
+            };
+            let ret_type = info.wrap_result(box_type);
+
+            default_funcs.push(quote_mixed! {
+                /// Creates a box with default head fields, like
+                /// `Default::default()`. With `#[init_err]`, it
+                /// returns the initialization error.
+                #ctor_vis fn default_box() -> #ret_type {
+                    Self::new_box_default()
+                }
+            });
+        }
+
+        (
+            default_funcs,
+            quote_mixed! {
+                #(#cfg_attrs)*
+                impl #generics_decls incrstruct::internal::IncrStructDefault for #struct_name #generics_args #generics_where {
+                    unsafe fn write_default_heads(out: &mut Self) {
+                        #(
+                            let #ctor_head_args = <#ctor_head_types as ::core::default::Default>::default();
+                        )*
+                        #(#default_heads)*
+                        #(
+                            ::core::ptr::write(&mut out.#head_args, #head_args);
+                        )*
+                    }
+                }
+            },
+        )
+    } else {
+        (Vec::new(), quote_mixed! {})
+    }
+}
+
+/// Returns the zeroed constructor, and the IncrStructZeroed
+/// implementation. The constructor only exists if all head fields are
+/// Zeroable, which the unsafe trait implementation requires. It's
+/// bounded on the head types, since a `Self: IncrStructZeroed` bound
+/// would hide the init error type behind the trait.
+fn make_zeroed(info: &StructInfo) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        ctor_vis,
+        head_types,
+        generics,
+        generics_decls,
+        generics_args,
+        init_unwrap,
+        ..
+    } = info;
+
+    if options.zeroed {
+        let mut zeroed_generics = (*generics).clone();
+        let where_clause = zeroed_generics.make_where_clause();
+        for ty in head_types.iter() {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #ty: incrstruct::bytemuck::Zeroable });
+        }
+        let zeroed_where = &zeroed_generics.where_clause;
+        let ret_type =
+            info.wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> });
+        let new_box_zeroed_fn = info.internal_fn("new_box_zeroed");
+
+        let zeroed_func = if cfg!(feature = "alloc") {
+            quote_mixed! {
+                /// See [incrstruct::internal::new_box_zeroed].
+                #ctor_vis fn new_box_zeroed() -> #ret_type
+                where
+                    #(#head_types: incrstruct::bytemuck::Zeroable,)*
+                {
+                    incrstruct::internal::#new_box_zeroed_fn() #init_unwrap
+                }
+            }
+        } else {
+            quote_mixed! {}
+        };
+
+        (
+            zeroed_func,
+            quote_mixed! {
+                // SAFETY: all head fields are Zeroable.
+                #(#cfg_attrs)*
+                unsafe impl #generics_decls incrstruct::internal::IncrStructZeroed for #struct_name #generics_args #zeroed_where {}
+            },
+        )
+    } else {
+        (quote_mixed! {}, quote_mixed! {})
+    }
+}
+
+/// Returns the file constructor, which maps the file into the only
+/// head field without a default, and then calls the normal box
+/// constructor.
+fn make_mmap_func(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        options,
+        struct_name,
+        ctor_head_params,
+        init_err,
+        result_err,
+        init_arg_params,
+        init_arg_names,
+        ..
+    } = info;
+
+    Ok(if options.mmap {
+        if ctor_head_params.len() != 1 {
+            return Err(Error::new_spanned(
+                struct_name,
+                "the mmap option requires exactly one head field without #[default]",
+            ));
+        }
+        // In no-panic mode, I/O errors are converted to the init
+        // error, so there must be one.
+        if options.no_panic && init_err.is_none() {
+            return Err(Error::new_spanned(
+                struct_name,
+                "the mmap option with no_panic requires #[init_err], implementing From<std::io::Error>",
+            ));
+        }
+
+        let box_type = if options.boxed {
+            quote_mixed! { incrstruct::internal::Box<Self> }
+        } else {
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> }
+        };
+        let (ret_type, io_try, ok_wrap) = match &result_err {
+            Some(err) if options.no_panic => (
+                quote_mixed! { ::core::result::Result<#box_type, #err> },
+                quote_mixed! { .map_err(|err| incrstruct::Error::Init(::core::convert::From::from(err)))? },
+                quote_mixed! {},
+            ),
+            Some(err) => (
+                quote_mixed! { ::core::result::Result<#box_type, #err> },
+                quote_mixed! { ? },
+                quote_mixed! {},
+            ),
+            None => (
+                quote_mixed! { ::std::io::Result<#box_type> },
+                quote_mixed! { ? },
+                quote_mixed! { ::core::result::Result::Ok },
+            ),
+        };
+
+        quote_mixed! {
+            /// Maps the file at `path` into the head field, and
+            /// creates a `Box`.
+            ///
+            /// # Safety
+            ///
+            /// See [incrstruct::internal::map_path].
+            pub unsafe fn new_box_from_path(path: impl ::core::convert::AsRef<::std::path::Path>, #(#init_arg_params),*) -> #ret_type {
+                // SAFETY: forwarded to the caller.
+                let map = unsafe { incrstruct::internal::map_path(path) } #io_try;
+
+                #ok_wrap(Self::new_box(map, #(#init_arg_names),*))
+            }
+        }
+    } else {
+        quote_mixed! {}
+    })
+}
+
+/// Returns the async constructors, and the async Init trait. The trait
+/// mirrors the normal one, and the constructors await it for each
+/// phase.
+fn make_async(
+    info: &StructInfo,
+) -> Result<(proc_macro2::TokenStream, proc_macro2::TokenStream), Error> {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        vis,
+        ctor_vis,
+        phase_nums,
+        head_args,
+        ctor_head_params,
+        default_heads,
+        generics_decls,
+        generics_args,
+        generics_where,
+        trait_where,
+        init_unwrap,
+        init_trait_name,
+        eager_init_field_decls,
+        ..
+    } = info;
+    let ensure_init_type = info.wrap_result(quote_mixed! { &mut Self });
+
+    Ok(if options.async_init {
+        let init_async_trait_name = join_ident("", init_trait_name, "Async");
+        let init_async_phase_calls = info.phase_calls(
+            &|name| quote_mixed! { <Self as #init_async_trait_name #generics_args>::#name },
+            &quote_mixed! { .await },
+            &quote_mixed! { Self },
+        );
+        let ensure_init_async_fn = info.internal_fn("ensure_init_async");
+        let new_in_async_fn = info.internal_fn("new_in_async");
+        let async_containers = if cfg!(feature = "alloc") && !options.boxed {
+            vec![
+                (
+                    quote_mixed! { new_box_async },
+                    quote_mixed! { incrstruct::internal::Box<Self> },
+                ),
+                (
+                    quote_mixed! { new_rc_async },
+                    quote_mixed! { incrstruct::internal::Rc<Self> },
+                ),
+                (
+                    quote_mixed! { new_arc_async },
+                    quote_mixed! { incrstruct::internal::Arc<Self> },
+                ),
+            ]
+        } else {
+            vec![]
+        };
+        let new_async_funcs: Vec<proc_macro2::TokenStream> = async_containers
+            .iter()
+            .map(|(name, container)| {
+                let ret_type = info.wrap_result(quote_mixed! { ::core::pin::Pin<#container> });
+
+                quote_mixed! {
+                    #ctor_vis async fn #name(#(#ctor_head_params),*) -> #ret_type {
+                        #(#default_heads)*
+                        // SAFETY: the closure writes all head fields,
+                        // and nothing else.
+                        unsafe {
+                            incrstruct::internal::#new_in_async_fn::<#container, Self, _>(|out: &mut Self| {
+                                #(
+                                    ::core::ptr::write(&mut out.#head_args, #head_args);
+                                )*
+                            })
+                        }.await #init_unwrap
+                    }
+                }
+            })
+            .collect();
+
+        (
+            quote_mixed! {
+                #(#new_async_funcs)*
+
+                /// Like `ensure_init`, but awaits the `InitAsync` trait.
+                pub async unsafe fn ensure_init_async(this: &mut ::core::mem::MaybeUninit<Self>) -> #ensure_init_type {
+                    incrstruct::internal::#ensure_init_async_fn(this).await #init_unwrap
+                }
+            },
+            quote_mixed! {
+                #(#cfg_attrs)*
+                #vis trait #init_async_trait_name #generics_decls #trait_where {
+                    #(
+                        async #eager_init_field_decls
+                    )*
+                }
+
+                #(#cfg_attrs)*
+                impl #generics_decls incrstruct::internal::IncrStructInitAsync for #struct_name #generics_args #generics_where {
+                    // SAFETY: see init_phase.
+                    unsafe fn init_phase_async(this: &mut Self, phase: u8) -> impl ::core::future::Future<Output = ::core::result::Result<(), Self::Error>> + '_ {
+                        async move {
+                            // Like in init_phase, the reference isn't
+                            // tied to `this`, so fields can be
+                            // borrowed for the struct lifetime.
+                            let r = &mut *(&mut *this as *mut Self);
+
+                            match phase {
+                                #(
+                                    #phase_nums => {
+                                        #init_async_phase_calls
+                                    }
+                                )*
+                                _ => {}
+                            }
+
+                            ::core::result::Result::Ok(())
+                        }
+                    }
+                }
+            },
+        )
+    } else {
+        (quote_mixed! {}, quote_mixed! {})
+    })
+}
+
+/// Returns the builder constructor, and the builder type. The builder
+/// takes one closure per eager tail field, instead of the Init trait.
+/// Each setter replaces a `()` type parameter with the closure type,
+/// so closure arguments can be inferred.
+fn make_builder(
+    info: &StructInfo,
+) -> Result<(proc_macro2::TokenStream, proc_macro2::TokenStream), Error> {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        vis,
+        ctor_vis,
+        tails,
+        tail_lazy,
+        head_args,
+        ctor_head_params,
+        default_heads,
+        head_types,
+        generics,
+        generics_args,
+        result_err,
+        init_unwrap,
+        eager_init_field_names,
+        eager_init_field_fn_types,
+        ..
+    } = info;
+
+    Ok(if options.builder && cfg!(feature = "alloc") {
+        let (builder_name, builder_fn, build_fn) = if result_err.is_some() {
+            (
+                join_ident("", struct_name, "TryBuilder"),
+                quote_mixed! { try_builder },
+                quote_mixed! { try_build },
+            )
+        } else {
+            (
+                join_ident("", struct_name, "Builder"),
+                quote_mixed! { builder },
+                quote_mixed! { build },
+            )
+        };
+        let struct_ty = quote_mixed! { #struct_name #generics_args };
+        let struct_args: Vec<proc_macro2::TokenStream> = generics
+            .params
+            .iter()
+            .map(|param| match param {
+                syn::GenericParam::Lifetime(param) => {
+                    let lifetime = &param.lifetime;
+                    quote_mixed! { #lifetime }
+                }
+                syn::GenericParam::Type(param) => {
+                    let ident = &param.ident;
+                    quote_mixed! { #ident }
+                }
+                syn::GenericParam::Const(param) => {
+                    let ident = &param.ident;
+                    quote_mixed! { #ident }
+                }
+            })
+            .collect();
+        let eager_tail_names: Vec<&syn::Ident> = tails
+            .iter()
+            .zip(tail_lazy.iter())
+            .filter(|(_, lazy)| !**lazy)
+            .map(|(field, _)| field.ident.as_ref().unwrap())
+            .collect();
+        let setter_names: Vec<syn::Ident> = eager_tail_names
+            .iter()
+            .map(|name| join_ident("", name, "_builder"))
+            .collect();
+        let builder_params: Vec<syn::Ident> = setter_names
+            .iter()
+            .map(|name| {
+                let camel: String = name
+                    .unraw()
+                    .to_string()
+                    .split('_')
+                    .map(|word| {
+                        let mut chars = word.chars();
+                        chars
+                            .next()
+                            .map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+                    })
+                    .collect();
+
+                syn::Ident::new(&format!("Is{}", camel), proc_macro2::Span::call_site())
+            })
+            .collect();
+        let with_params = |params: &[proc_macro2::TokenStream]| {
+            let mut out = (*generics).clone();
+            for param in params.iter() {
+                out.params.push(syn::parse_quote! { #param });
+            }
+            out
+        };
+        let builder_ty = |args: &[proc_macro2::TokenStream]| {
+            quote_mixed! { #builder_name<#(#struct_args,)* #(#args),*> }
+        };
+
+        let all_generics = with_params(
+            &builder_params
+                .iter()
+                .map(|param| quote_mixed! { #param })
+                .collect::<Vec<_>>(),
+        );
+        let (all_decls, _, all_where) = all_generics.split_for_impl();
+        let units: Vec<proc_macro2::TokenStream> =
+            builder_params.iter().map(|_| quote_mixed! { () }).collect();
+        let unit_builder_ty = builder_ty(&units);
+
+        let setters: Vec<proc_macro2::TokenStream> = (0..setter_names.len())
+            .map(|k| {
+                let setter_name = &setter_names[k];
+                let bound = &eager_init_field_fn_types[k];
+                let other_params: Vec<proc_macro2::TokenStream> = builder_params
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != k)
+                    .map(|(_, param)| quote_mixed! { #param })
+                    .collect();
+                let setter_generics = with_params(&other_params);
+                let (setter_decls, _, setter_where) = setter_generics.split_for_impl();
+                let before: Vec<proc_macro2::TokenStream> = builder_params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        if i == k {
+                            quote_mixed! { () }
+                        } else {
+                            quote_mixed! { #param }
+                        }
+                    })
+                    .collect();
+                let after: Vec<proc_macro2::TokenStream> = builder_params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        if i == k {
+                            quote_mixed! { IsSetter }
+                        } else {
+                            quote_mixed! { #param }
+                        }
+                    })
+                    .collect();
+                let fields: Vec<proc_macro2::TokenStream> = setter_names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        if i == k {
+                            quote_mixed! { #name: f }
+                        } else {
+                            quote_mixed! { #name: self.#name }
+                        }
+                    })
+                    .collect();
+                let before_ty = builder_ty(&before);
+                let after_ty = builder_ty(&after);
+                let doc = format!(
+                    "Sets the closure initializing the `{}` field.",
+                    eager_tail_names[k].unraw()
+                );
+
+                quote_mixed! {
+                    #(#cfg_attrs)*
+                    impl #setter_decls #before_ty #setter_where {
+                        #[doc = #doc]
+                        pub fn #setter_name<IsSetter: #bound>(self, f: IsSetter) -> #after_ty {
+                            #builder_name {
+                                heads: self.heads,
+                                #(#fields,)*
+                                marker: ::core::marker::PhantomData,
+                            }
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        let builder_bounds: Vec<proc_macro2::TokenStream> = builder_params
+            .iter()
+            .zip(eager_init_field_fn_types.iter())
+            .map(|(param, bound)| quote_mixed! { #param: #bound })
+            .collect();
+        let build_generics = with_params(&builder_bounds);
+        let (build_decls, _, build_where) = build_generics.split_for_impl();
+        let all_builder_ty = builder_ty(
+            &builder_params
+                .iter()
+                .map(|param| quote_mixed! { #param })
+                .collect::<Vec<_>>(),
+        );
+        let build_phase_calls =
+            info.phase_calls(&|name| quote_mixed! { #name }, &quote_mixed! {}, &struct_ty);
+        let build_closure = info.init_closure(&build_phase_calls, &struct_ty);
+        let ret_type = info
+            .wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<#struct_ty>> });
+        let new_in_with_init_fn = info.internal_fn("new_in_with_init");
+        let builder_doc = format!(
+            "Builds a `{}` using closures instead of the Init trait. See `{}::{}`.",
+            struct_name, struct_name, builder_fn
+        );
+
+        (
+            quote_mixed! {
+                /// Returns a builder, which initializes tail fields
+                /// using closures, instead of the Init trait.
+                #ctor_vis fn #builder_fn(#(#ctor_head_params),*) -> #unit_builder_ty {
+                    #(#default_heads)*
+                    #builder_name {
+                        heads: (#(#head_args,)*),
+                        #( #setter_names: (), )*
+                        marker: ::core::marker::PhantomData,
+                    }
+                }
+            },
+            quote_mixed! {
+                #(#cfg_attrs)*
+                #[doc = #builder_doc]
+                #[must_use]
+                #vis struct #builder_name #all_decls #all_where {
+                    heads: (#(#head_types,)*),
+                    #( #setter_names: #builder_params, )*
+                    marker: ::core::marker::PhantomData<fn() -> #struct_ty>,
+                }
+
+                #(#setters)*
+
+                #(#cfg_attrs)*
+                impl #build_decls #all_builder_ty #build_where {
+                    /// Creates a `Box` in place, calling the closures
+                    /// in initialization order.
+                    pub fn #build_fn(self) -> #ret_type {
+                        let (#(#head_args,)*) = self.heads;
+                        #( let mut #eager_init_field_names = self.#setter_names; )*
+                        let init = #build_closure;
+
+                        // SAFETY: the closure writes all head fields,
+                        // and nothing else.
+                        unsafe {
+                            incrstruct::internal::#new_in_with_init_fn(
+                                |out: &mut #struct_ty| {
+                                    #(
+                                        ::core::ptr::write(&mut out.#head_args, #head_args);
+                                    )*
+                                },
+                                init,
+                            )
+                        } #init_unwrap
+                    }
+                }
+            },
+        )
+    } else {
+        (quote_mixed! {}, quote_mixed! {})
+    })
+}
+
+/// Returns `new_box_with`, for simple structs, where tails only borrow
+/// heads. All tails are computed by a single closure, without the Init
+/// trait.
+fn make_new_box_with_func(info: &StructInfo) -> Result<proc_macro2::TokenStream, Error> {
+    let StructInfo {
+        options,
+        ctor_vis,
+        heads,
+        tails,
+        num_tails,
+        header_name,
+        tail_lazy,
+        num_phases,
+        head_args,
+        ctor_head_params,
+        default_heads,
+        self_lifetime,
+        init_err,
+        init_unwrap,
+        ..
+    } = info;
+
+    let tails_borrow_heads = tails
+        .iter()
+        .map(|field| get_borrows(field))
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .all(|borrows| {
+            tails
+                .iter()
+                .all(|tail| !borrows.contains(tail.ident.as_ref().unwrap()))
+        });
+    Ok(match self_lifetime {
+        Some(lifetime)
+            if *num_phases == 1
+                && tails_borrow_heads
+                && !options.boxed
+                && cfg!(feature = "alloc") =>
+        {
+            let head_ref_types = make_field_types(heads.as_slice(), Some(lifetime), false)?;
+            let head_refs = make_field_args(
+                heads.as_slice(),
+                Some(&syn::Ident::new("r", proc_macro2::Span::mixed_site())),
+                false,
+            );
+            let eager_tails: Vec<&&syn::Field> = tails
+                .iter()
+                .zip(tail_lazy.iter())
+                .filter(|(_, lazy)| !**lazy)
+                .map(|(field, _)| field)
+                .collect();
+            let eager_tail_names: Vec<&Option<syn::Ident>> =
+                eager_tails.iter().map(|field| &field.ident).collect();
+            let eager_tail_types: Vec<&syn::Type> =
+                eager_tails.iter().map(|field| &field.ty).collect();
+            let eager_tail_indices: Vec<usize> =
+                (0..*num_tails).filter(|i| !tail_lazy[*i]).collect();
+            let lazy_tail_names: Vec<&Option<syn::Ident>> = tails
+                .iter()
+                .zip(tail_lazy.iter())
+                .filter(|(_, lazy)| **lazy)
+                .map(|(field, _)| &field.ident)
+                .collect();
+            let lazy_tail_indices: Vec<usize> = (0..*num_tails).filter(|i| tail_lazy[*i]).collect();
+            let tails_type = quote_mixed! { (#(#eager_tail_types,)*) };
+            let f_call = quote_mixed! { f(#(#head_refs),*) };
+            let (f_ret_type, f_call) = match &init_err {
+                Some(err) => (
+                    quote_mixed! { ::core::result::Result<#tails_type, #err> },
+                    quote_mixed! {
+                        match #f_call {
+                            ::core::result::Result::Ok(tails) => tails,
+                            ::core::result::Result::Err(err) => return ::core::result::Result::Err(err),
+                        }
+                    },
+                ),
+                None => (tails_type, f_call),
+            };
+            let ret_type = info
+                .wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> });
+            let new_in_with_init_fn = info.internal_fn("new_in_with_init");
+
+            quote_mixed! {
+                /// Creates a `Box`, calling `f` with references to the
+                /// head fields, in the same order as the arguments, to
+                /// get all tail fields, in initialization order.
+                #ctor_vis fn new_box_with(#(#ctor_head_params,)* f: impl ::core::ops::FnOnce(#(#head_ref_types),*) -> #f_ret_type) -> #ret_type {
+                    #(#default_heads)*
+                    let mut f = ::core::option::Option::Some(f);
+                    let init = |this: *mut Self, phase: u8| unsafe {
+                        let r = &mut *this;
+
+                        if let (1, ::core::option::Option::Some(f)) = (phase, f.take()) {
+                            let (#(#eager_tail_names,)*) = #f_call;
+
+                            #(
+                                ::core::ptr::write(&mut r.#eager_tail_names as *mut _, #eager_tail_names);
+                                incrstruct::internal::set_tail_inited(&mut r.#header_name, #eager_tail_indices);
+                            )*
+                            #(
+                                ::core::ptr::write(&mut r.#lazy_tail_names as *mut _, ::core::default::Default::default());
+                                incrstruct::internal::set_tail_inited(&mut r.#header_name, #lazy_tail_indices);
+                            )*
+                        }
+
+                        ::core::result::Result::Ok(())
+                    };
+
+                    // SAFETY: the closure writes all head fields, and
+                    // nothing else.
+                    unsafe {
+                        incrstruct::internal::#new_in_with_init_fn(
+                            |out: &mut Self| {
+                                #(
+                                    ::core::ptr::write(&mut out.#head_args, #head_args);
+                                )*
+                            },
+                            init,
+                        )
+                    } #init_unwrap
+                }
+            }
+        }
+        _ => quote_mixed! {},
+    })
+}
+
+/// Returns the explicit auto trait impls, which fail to compile unless
+/// every field type implements the trait.
+fn make_send_sync_impls(info: &StructInfo) -> Vec<proc_macro2::TokenStream> {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        head_types,
+        tail_types,
+        generics_decls,
+        generics_args,
+        generics_where,
+        ..
+    } = info;
+
+    [
+        (options.unsafe_send, quote_mixed! { Send }, "assert_send"),
+        (options.unsafe_sync, quote_mixed! { Sync }, "assert_sync"),
+    ]
+    .into_iter()
+    .filter(|(set, _, _)| *set)
+    .map(|(_, auto_trait, assert_name)| {
+        let assert_name = syn::Ident::new(assert_name, proc_macro2::Span::mixed_site());
+
+        quote_mixed! {
+            // SAFETY: the fields are checked below, and the header is
+            // both Send and Sync.
+            #(#cfg_attrs)*
+            unsafe impl #generics_decls ::core::marker::#auto_trait for #struct_name #generics_args #generics_where {}
+
+            // Fails to compile if a field doesn't implement the
+            // trait.
+            #(#cfg_attrs)*
+            const _: () = {
+                #[allow(dead_code)]
+                fn is_auto_trait<T: ?::core::marker::Sized + ::core::marker::#auto_trait>() {}
+
+                #[allow(dead_code)]
+                fn #assert_name #generics_decls () #generics_where {
+                    #( is_auto_trait::<#head_types>(); )*
+                    #( is_auto_trait::<#tail_types>(); )*
+                }
+            };
+        }
+    })
+    .collect()
+}
+
+/// Returns `ensure_init_sync` and `write_header`, with a SyncHeader.
+/// Threads can race to initialize the struct, and the sync state must
+/// be written together with the header.
+fn make_sync(info: &StructInfo) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let StructInfo {
+        options,
+        header_name,
+        init_unwrap,
+        ..
+    } = info;
+
+    if options.sync {
+        let ensure_init_sync_fn = info.internal_fn("ensure_init_sync");
+        let ret_type = info.wrap_result(quote_mixed! { &'isthis Self });
+
+        (
+            quote_mixed! {
+                /// See [incrstruct::internal::ensure_init_sync].
+                pub unsafe fn ensure_init_sync<'isthis>(this: *const ::core::mem::MaybeUninit<Self>) -> #ret_type {
+                    let header = ::core::ptr::addr_of!((*this.cast::<Self>()).#header_name);
+
+                    incrstruct::internal::#ensure_init_sync_fn(this, header) #init_unwrap
+                }
+            },
+            quote_mixed! {
+                unsafe fn write_header(this: &mut Self) {
+                    ::core::ptr::write(&mut this.#header_name, incrstruct::SyncHeader::default());
+                }
+            },
+        )
+    } else {
+        (quote_mixed! {}, quote_mixed! {})
+    }
+}
+
+/// Returns `force_init`, the functions creating, dropping and
+/// initializing partial structs, and the IncrStructInit
+/// implementation. With init_args, only IncrStructInitWith is
+/// implemented, so the generic entry points, which can't pass the
+/// arguments, don't accept the struct. The generated functions use the
+/// `_with` runtime variants instead.
+fn make_init_funcs(
+    info: &StructInfo,
+) -> (
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+) {
+    let StructInfo {
+        options,
+        cfg_attrs,
+        struct_name,
+        header_name,
+        phase_nums,
+        head_args,
+        ctor_head_params,
+        default_heads,
+        generics,
+        generics_decls,
+        generics_args,
+        generics_where,
+        init_unwrap,
+        init_args,
+        init_arg_params,
+        init_arg_names,
+        eager_init_field_names,
+        eager_init_field_fn_types,
+        ..
+    } = info;
+    let ensure_init_type = info.wrap_result(quote_mixed! { &mut Self });
+    // With init_args, the returned reference must be tied to `this`.
+    let ensure_init_this_type = info.wrap_result(quote_mixed! { &'isthis mut Self });
+    let ensure_init_pin_type = info.wrap_result(quote_mixed! { ::core::pin::Pin<&mut Self> });
+    let force_init_type = info.wrap_result(quote_mixed! { () });
+    let drop_uninit_type = if options.no_panic {
+        quote_mixed! { ::core::result::Result<(), incrstruct::StateError> }
+    } else {
+        quote_mixed! { () }
+    };
+    let args_init_closure = info.args_init_closure();
+    let init_with_closure = info.init_closure(
+        &info.phase_calls(
+            &|name| quote_mixed! { #name },
+            &quote_mixed! {},
+            &quote_mixed! { Self },
+        ),
+        &quote_mixed! { Self },
+    );
+    let init_phase_calls = info.init_phase_calls();
+    let ensure_init_fn = info.internal_fn("ensure_init");
+    let ensure_init_with_fn = info.internal_fn("ensure_init_with");
+    let ensure_init_pin_fn = info.internal_fn("ensure_init_pin");
+    let force_init_fn = info.internal_fn("force_init");
+    let drop_uninit_fn = info.internal_fn("drop_uninit");
+
+    let (force_init_func, ensure_init_func, init_impl) = if init_args.is_empty() {
+        (
+            quote_mixed! {
+                pub fn force_init(this: &mut Self) -> #force_init_type {
+                    incrstruct::internal::#force_init_fn(this) #init_unwrap
+                }
+            },
+            quote_mixed! {
+                pub unsafe fn ensure_init(this: &mut ::core::mem::MaybeUninit<Self>) -> #ensure_init_type {
+                    incrstruct::internal::#ensure_init_fn(this) #init_unwrap
+                }
+
+                /// See [incrstruct::internal::ensure_init_pin].
+                pub unsafe fn ensure_init_pin(this: ::core::pin::Pin<&mut ::core::mem::MaybeUninit<Self>>) -> #ensure_init_pin_type {
+                    incrstruct::internal::#ensure_init_pin_fn(this) #init_unwrap
+                }
+            },
+            quote_mixed! {
+                #(#cfg_attrs)*
+                impl #generics_decls incrstruct::internal::IncrStructInit for #struct_name #generics_args #generics_where {
+                    // SAFETY: since we only support referencing earlier
+                    // fields, in a DAG, this always writes to
+                    // uninitialized space. The generated trait guarantees
+                    // that init_field_X is not unsafe.
+                    unsafe fn init_phase(this: *mut Self, phase: u8) -> ::core::result::Result<(), Self::Error> {
+                        let r = &mut *this;
+
+                        match phase {
+                            #(
+                                #phase_nums => {
+                                    #init_phase_calls
+                                }
+                            )*
+                            _ => {}
+                        }
+
+                        ::core::result::Result::Ok(())
+                    }
+                }
+            },
+        )
+    } else {
+        let force_init_with_fn = info.internal_fn("force_init_with");
+        let init_arg_types: Vec<&syn::Type> = init_args.iter().map(|arg| &arg.ty).collect();
+        let mut args_generics = (*generics).clone();
+        args_generics
+            .params
+            .insert(0, syn::parse_quote! { 'isargs });
+        let (args_decls, _, _) = args_generics.split_for_impl();
+
+        (
+            quote_mixed! {
+                pub fn force_init(this: &mut Self, #(#init_arg_params),*) -> #force_init_type {
+                    let init = #args_init_closure;
+
+                    // SAFETY: the closure is the same as init_phase_with.
+                    unsafe { incrstruct::internal::#force_init_with_fn(this, init) } #init_unwrap
+                }
+            },
+            quote_mixed! {
+                pub unsafe fn ensure_init<'isthis>(this: &'isthis mut ::core::mem::MaybeUninit<Self>, #(#init_arg_params),*) -> #ensure_init_this_type {
+                    incrstruct::internal::#ensure_init_with_fn(this, #args_init_closure) #init_unwrap
+                }
+            },
+            quote_mixed! {
+                #(#cfg_attrs)*
+                impl #args_decls incrstruct::internal::IncrStructInitWith<(#(&'isargs #init_arg_types,)*)> for #struct_name #generics_args #generics_where {
+                    // SAFETY: like init_phase without init_args, this
+                    // only writes to uninitialized tail fields.
+                    unsafe fn init_phase_with(this: *mut Self, phase: u8, (#(#init_arg_names,)*): (#(&'isargs #init_arg_types,)*)) -> ::core::result::Result<(), Self::Error> {
+                        let r = &mut *this;
+
+                        match phase {
+                            #(
+                                #phase_nums => {
+                                    #init_phase_calls
+                                }
+                            )*
+                            _ => {}
+                        }
+
+                        ::core::result::Result::Ok(())
+                    }
+                }
+            },
+        )
+    };
+
+    let ensure_init_funcs = quote_mixed! {
+        /// See [incrstruct::internal::new_uninit].
+        pub unsafe fn new_uninit(#(#ctor_head_params),*) -> ::core::mem::MaybeUninit<Self> {
+            #(#default_heads)*
+            // SAFETY: we only write each field once, so this
+            // overwrites uninitialized values.
+            incrstruct::internal::new_uninit::<Self, _>(|out| unsafe {
+                #(
+                    ::core::ptr::write(&mut out.#head_args, #head_args);
+                )*
+            })
+        }
+
+        /// See [incrstruct::internal::drop_uninit_in_place].
+        pub unsafe fn drop_uninit(this: ::core::mem::MaybeUninit<Self>) -> #drop_uninit_type {
+            incrstruct::internal::#drop_uninit_fn(this)
+        }
+
+        /// Returns whether all tail fields are initialized. This is
+        /// false e.g. after `force_init` failed, or panicked.
+        pub fn is_initialized(this: &Self) -> bool {
+            incrstruct::Header::is_inited(&this.#header_name)
+        }
+
+        #ensure_init_func
+
+        /// Like `ensure_init`, but initializes tail fields using
+        /// the given closures, in initialization order, instead
+        /// of the Init trait.
+        pub unsafe fn ensure_init_with<'isthis>(this: &'isthis mut ::core::mem::MaybeUninit<Self>, #(#init_arg_params,)* #( mut #eager_init_field_names: impl #eager_init_field_fn_types ),*) -> #ensure_init_this_type {
+            // SAFETY: see init_phase.
+            incrstruct::internal::#ensure_init_with_fn(this, #init_with_closure) #init_unwrap
+        }
+    };
+
+    // In boxed mode, safe code holds the struct while it's re-initialized,
+    // so a failure aborts, like when re-initializing head fields.
+    let force_init_func = if options.boxed {
+        let force_init_movable_with_fn = info.internal_fn("force_init_movable_with");
+
+        quote_mixed! {
+            /// Initializes the tail fields again. Aborts if that fails,
+            /// or panics, since the struct is in use.
+            pub fn force_init(this: &mut Self, #(#init_arg_params),*) -> #force_init_type {
+                let init = #args_init_closure;
+
+                // SAFETY: the closure is the same as init_phase.
+                unsafe { incrstruct::internal::#force_init_movable_with_fn(this, init) } #init_unwrap
+            }
+        }
+    } else {
+        force_init_func
+    };
+
+    (force_init_func, ensure_init_funcs, init_impl)
+}
+
+/// Returns the IncrStructBase implementation.
+fn make_base_impl(
+    info: &StructInfo,
+    write_header_func: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let StructInfo {
+        cfg_attrs,
+        struct_name,
+        num_tails,
+        header,
+        header_name,
+        num_phases,
+        phase_nums,
+        phase_ends,
+        head_args,
+        tail_names,
+        generics_decls,
+        generics_args,
+        generics_where,
+        init_err_or_infallible,
+        ..
+    } = info;
+    let header_ty = &header.ty;
+
+    // Drop order is declaration order, which for tails is the reverse
+    // of the reverse.
+    let drop_head_names = head_args;
+    let mut drop_tail_names = tail_names.clone();
+    drop_tail_names.reverse();
+    let drop_tail_indices: Vec<usize> = (0..*num_tails).rev().collect();
+
+    quote_mixed! {
+        #(#cfg_attrs)*
+        impl #generics_decls incrstruct::internal::IncrStructBase for #struct_name #generics_args #generics_where {
+            type Error = #init_err_or_infallible;
+            type Tails = <#header_ty as incrstruct::internal::HeaderBits>::Tails;
+            type Dirty = <#header_ty as incrstruct::internal::HeaderBits>::Dirty;
+
+            const PHASES: u8 = #num_phases;
+
+            /// Drops a value previously created with `new_uninit`.
+            unsafe fn drop_uninit_in_place(this: &mut ::core::mem::MaybeUninit<Self>) -> ::core::result::Result<(), incrstruct::StateError> {
+                // SAFETY: we only drop head fields, and only once.
+                incrstruct::internal::drop_uninit_in_place(this, |this| unsafe {
+                    #(
+                        ::core::ptr::drop_in_place(&mut this.#drop_head_names);
+                    )*
+                })
+            }
+
+            /// Drops tail fields in `range`, in natural drop order,
+            /// causing a partially initialized struct.
+            ///
+            /// # Safety
+            ///
+            /// We only drop tail fields, and only once.
+            unsafe fn drop_tail_in_place(this: &mut Self, range: ::core::ops::Range<usize>) {
+                #(
+                    if range.contains(&#drop_tail_indices) && incrstruct::internal::take_tail_inited(&mut this.#header_name, #drop_tail_indices) {
+                        ::core::ptr::drop_in_place(&mut this.#drop_tail_names);
+                    }
+                )*
+            }
+
+            fn phase_end(phase: u8) -> usize {
+                match phase {
+                    0 => 0,
+                    #( #phase_nums => #phase_ends, )*
+                    _ => #num_tails,
+                }
+            }
+
+            fn header<'isheader>(this: &'isheader mut Self) -> &'isheader mut incrstruct::Header<Self::Tails, Self::Dirty> {
+                const {
+                    ::core::assert!(
+                        <<Self as incrstruct::internal::IncrStructBase>::Tails as incrstruct::TailBits>::BITS >= #num_tails,
+                        "the header has too few tail bits for the tail fields",
+                    )
+                };
+
+                &mut this.#header_name
+            }
+
+            #write_header_func
+        }
+    }
+}
+
+/// Struct-level options, given as `#[incrstruct(...)]`.
+#[derive(Default)]
+struct Options {
+    /// Return state errors instead of panicking.
+    no_panic: bool,
+
+    /// Borrowed fields are boxed, so the struct can move freely.
+    boxed: bool,
+
+    /// Head fields can be defaulted, for `incrstruct::Lazy`.
+    default: bool,
+
+    /// Head fields can be zeroed, for `new_box_zeroed`.
+    zeroed: bool,
+
+    /// The only head field is a memory map, for `new_box_from_path`.
+    mmap: bool,
+
+    /// Tail fields can also be initialized by async functions.
+    async_init: bool,
+
+    /// Tail fields can be initialized by closures, using a builder.
+    builder: bool,
+
+    /// Tail fields are private, with generated getters.
+    private_tails: bool,
+
+    /// The header is a `SyncHeader`, for `ensure_init_sync`.
+    sync: bool,
+
+    /// Serialize head fields, and deserialize by initializing tails.
+    serde: bool,
+
+    /// Clone by cloning head fields, and initializing tails.
+    clone: bool,
+
+    /// Implement `Debug`, only reading initialized fields.
+    debug: bool,
+
+    /// Generate a pin-project-style `project` function.
+    project: bool,
+
+    /// Traits implemented over head fields only.
+    derive_heads: Vec<syn::Ident>,
+
+    /// Convert to and from `yoke::Yoke`, with the head as the cart.
+    yoke: bool,
+
+    /// Track initialization by types, instead of a `Header`.
+    no_header: bool,
+
+    /// Implement `Send` for the struct, if all fields are `Send`.
+    unsafe_send: bool,
+
+    /// Implement `Sync` for the struct, if all fields are `Sync`.
+    unsafe_sync: bool,
+
+    /// The lifetime used for references to borrowed fields.
+    self_lifetime: Option<syn::Lifetime>,
+
+    /// The visibility of generated traits, helper types and
+    /// constructors.
+    vis: Option<syn::Visibility>,
+
+    /// The path to the incrstruct crate, if re-exported or renamed.
+    crate_path: Option<syn::Path>,
+}
+
+fn parse_options(attrs: &[syn::Attribute]) -> Result<Options, Error> {
+    let mut options = Options::default();
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("incrstruct"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("no_panic") {
+                options.no_panic = true;
+            } else if meta.path.is_ident("boxed") {
+                options.boxed = true;
+            } else if meta.path.is_ident("default") {
+                options.default = true;
+            } else if meta.path.is_ident("zeroed") {
+                if !cfg!(feature = "bytemuck") {
+                    return Err(meta.error("the zeroed option requires the bytemuck feature"));
+                }
+                options.zeroed = true;
+            } else if meta.path.is_ident("mmap") {
+                options.mmap = true;
+            } else if meta.path.is_ident("async_init") {
+                options.async_init = true;
+            } else if meta.path.is_ident("builder") {
+                options.builder = true;
+            } else if meta.path.is_ident("private_tails") {
+                options.private_tails = true;
+            } else if meta.path.is_ident("sync") {
+                options.sync = true;
+            } else if meta.path.is_ident("derive_heads") {
+                meta.parse_nested_meta(|meta| match meta.path.get_ident() {
+                    Some(name)
+                        if ["PartialEq", "Eq", "Hash"].contains(&name.to_string().as_str()) =>
+                    {
+                        options.derive_heads.push(name.clone());
+                        Ok(())
+                    }
+                    _ => Err(meta.error("derive_heads supports PartialEq, Eq and Hash")),
+                })?;
+            } else if meta.path.is_ident("debug") {
+                options.debug = true;
+            } else if meta.path.is_ident("project") {
+                options.project = true;
+            } else if meta.path.is_ident("clone") {
+                options.clone = true;
+            } else if meta.path.is_ident("serde") {
+                options.serde = true;
+            } else if meta.path.is_ident("yoke") {
+                options.yoke = true;
+            } else if meta.path.is_ident("no_header") {
+                options.no_header = true;
+            } else if meta.path.is_ident("unsafe_send") {
+                options.unsafe_send = true;
+            } else if meta.path.is_ident("unsafe_sync") {
+                options.unsafe_sync = true;
+            } else if meta.path.is_ident("self_lifetime") {
+                options.self_lifetime = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("crate") {
+                let lit: syn::LitStr = meta.value()?.parse()?;
+                options.crate_path = Some(lit.parse()?);
+            } else if meta.path.is_ident("vis") {
+                let lit: syn::LitStr = meta.value()?.parse()?;
+                options.vis = Some(lit.parse()?);
+            } else {
+                return Err(meta.error("unknown incrstruct option"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(options)
+}
+
+/// Derives constructors for a struct without tail fields, or header.
+/// The struct needs no pinning, but the same constructors, and Init
+/// trait, are generated, so the derive can be used uniformly.
+fn plain_struct(input: &DeriveInput, fields: Vec<&syn::Field>) -> Result<TokenStream, Error> {
+    let options = parse_options(&input.attrs)?;
+    let init_err = find_attribute(&input.attrs, "init_err")
+        .map(|attr| attr.parse_args_with(syn::Type::parse))
+        .transpose()?;
+    let result_err = if options.no_panic {
+        let err = init_err.map_or_else(
+            || quote_mixed! { ::core::convert::Infallible },
+            |err| quote_mixed! { #err },
+        );
+        Some(quote_mixed! { incrstruct::Error<#err> })
+    } else {
+        init_err.map(|err| quote_mixed! { #err })
+    };
+
+    // Heads are in declaration order, like for other structs.
+    let head_params = make_field_params(fields.as_slice(), None, false)?;
+    let head_args = make_field_args(fields.as_slice(), None, false);
+    let head_defaults = fields
+        .iter()
+        .map(|field| get_head_default(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    let ctor_head_params: Vec<&proc_macro2::TokenStream> = head_params
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(param, _)| param)
+        .collect();
+    let default_heads: Vec<proc_macro2::TokenStream> = head_args
+        .iter()
+        .zip(head_defaults.iter())
+        .filter_map(|(arg, default)| {
+            default
+                .as_ref()
+                .map(|expr| quote_mixed! { let #arg = #expr; })
+        })
+        .collect();
+
+    let mut containers = if options.boxed {
+        vec![(
+            quote_mixed! { new },
+            quote_mixed! { Self },
+            quote_mixed! { Self { #(#head_args),* } },
+        )]
+    } else {
+        Vec::new()
+    };
+    if cfg!(feature = "alloc") {
+        containers.push(if options.boxed {
+            (
+                quote_mixed! { new_box },
+                quote_mixed! { incrstruct::internal::Box<Self> },
+                quote_mixed! { incrstruct::internal::Box::new(Self { #(#head_args),* }) },
+            )
+        } else {
+            (
+                quote_mixed! { new_box },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> },
+                quote_mixed! { incrstruct::internal::Box::pin(Self { #(#head_args),* }) },
+            )
+        });
+        if !options.boxed {
+            containers.push((
+                quote_mixed! { new_rc },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Rc<Self>> },
+                quote_mixed! { incrstruct::internal::Rc::pin(Self { #(#head_args),* }) },
+            ));
+            containers.push((
+                quote_mixed! { new_arc },
+                quote_mixed! { ::core::pin::Pin<incrstruct::internal::Arc<Self>> },
+                quote_mixed! { incrstruct::internal::Arc::pin(Self { #(#head_args),* }) },
+            ));
+        }
+    }
+    let vis = options.vis.as_ref().unwrap_or(&input.vis);
+    let ctor_vis = match &options.vis {
+        Some(vis) => quote_mixed! { #vis },
+        None => quote_mixed! { pub },
+    };
+    let new_funcs = containers
+        .into_iter()
+        .map(|(name, ty, value)| match &result_err {
+            Some(err) => quote_mixed! {
+                #ctor_vis fn #name(#(#ctor_head_params),*) -> ::core::result::Result<#ty, #err> {
+                    #(#default_heads)*
+                    ::core::result::Result::Ok(#value)
+                }
+            },
+            None => quote_mixed! {
+                #ctor_vis fn #name(#(#ctor_head_params),*) -> #ty {
+                    #(#default_heads)*
+                    #value
+                }
+            },
+        });
+
+    let struct_name = &input.ident;
+    let init_trait_name = parse_init_trait(&input.attrs)?
+        .name
+        .unwrap_or_else(|| join_ident("", struct_name, "Init"));
+    let (generics_decls, generics_args, generics_where) = input.generics.split_for_impl();
+    let cfg_attrs: Vec<&syn::Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .collect();
+
+    let output = quote_mixed! {
+        #(#cfg_attrs)*
+        impl #generics_decls #struct_name #generics_args #generics_where {
+            #(#new_funcs)*
+        }
+
+        #(#cfg_attrs)*
+        #vis trait #init_trait_name #generics_decls #generics_where {}
+    };
+
+    Ok(with_crate_path(output, options.crate_path.as_ref()).into())
+}
+
+/// Derives constructors for a `no_header` struct, where the
+/// initialization state is tracked by types instead of a `Header`.
+/// `new_uninit` returns an `AStructUninit`, which `ensure_init`
+/// consumes. The `#[header]` field must be a `PhantomPinned`, which
+/// keeps the struct pinned, without taking up space.
+fn no_header_struct(
+    input: &DeriveInput,
+    mut fields: Vec<&syn::Field>,
+) -> Result<TokenStream, Error> {
+    let options = parse_options(&input.attrs)?;
+    for (set, name) in [
+        (options.no_panic, "no_panic"),
+        (options.boxed, "boxed"),
+        (options.default, "default"),
+        (options.zeroed, "zeroed"),
+        (options.mmap, "mmap"),
+        (options.async_init, "async_init"),
+        (options.builder, "builder"),
+        (options.private_tails, "private_tails"),
+        (options.sync, "sync"),
+        (options.serde, "serde"),
+        (options.clone, "clone"),
+        (options.debug, "debug"),
+        (options.project, "project"),
+        (!options.derive_heads.is_empty(), "derive_heads"),
+    ] {
+        if set {
+            return Err(Error::new_spanned(
+                &input.ident,
+                format!(
+                    "the no_header option can't be combined with the {} option",
+                    name
+                ),
+            ));
+        }
+    }
+    if let Some(attr) = find_attribute(&input.attrs, "init_args") {
+        return Err(Error::new_spanned(
+            attr,
+            "#[init_args] can't be combined with the no_header option",
+        ));
+    }
+    if !cfg!(feature = "alloc") {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "the no_header option requires the alloc feature",
+        ));
+    }
+    check_not_copy(&input.attrs)?;
+
+    let header = match fields.pop() {
+        Some(header) if has_attribute(&header.attrs, "header") => header,
+        Some(field) => {
+            return Err(Error::new_spanned(
+                field,
+                "missing #[header] attribute on last field",
+            ))
+        }
+        None => return Err(Error::new_spanned(&input.ident, "missing #[header] field")),
+    };
+    let header_name = header.ident.as_ref().unwrap();
+    fields.reverse();
+
+    let mut heads = find_phase(fields.as_slice(), false);
+    heads.reverse();
+    let tails = find_phase(fields.as_slice(), true);
+    for field in fields.iter() {
+        for name in ["lazy", "phase", "nested"] {
+            if let Some(attr) = find_attribute(&field.attrs, name) {
+                return Err(Error::new_spanned(
+                    attr,
+                    format!("#[{}] can't be combined with the no_header option", name),
+                ));
+            }
+        }
+    }
+    for tail in tails.iter() {
+        if let Some(name) = get_mut_borrows(tail)?.first() {
+            return Err(Error::new_spanned(
+                name,
+                "mutable borrows can't be combined with the no_header option",
+            ));
+        }
+    }
+
+    let head_params = make_field_params(heads.as_slice(), None, false)?;
+    let head_args = make_field_args(heads.as_slice(), None, false);
+    let tail_names = make_field_args(tails.as_slice(), None, false);
+    let head_defaults = heads
+        .iter()
+        .map(|field| get_head_default(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    let ctor_head_params: Vec<&proc_macro2::TokenStream> = head_params
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(param, _)| param)
+        .collect();
+    let ctor_head_args: Vec<&proc_macro2::TokenStream> = head_args
+        .iter()
+        .zip(head_defaults.iter())
+        .filter(|(_, default)| default.is_none())
+        .map(|(arg, _)| arg)
+        .collect();
+    let default_heads: Vec<proc_macro2::TokenStream> = head_args
+        .iter()
+        .zip(head_defaults.iter())
+        .filter_map(|(arg, default)| {
+            default
+                .as_ref()
+                .map(|expr| quote_mixed! { let #arg = #expr; })
+        })
+        .collect();
+
+    let vis = options.vis.as_ref().unwrap_or(&input.vis);
+    let ctor_vis = match &options.vis {
+        Some(vis) => quote_mixed! { #vis },
+        None => quote_mixed! { pub },
+    };
+    let self_lifetime = match &options.self_lifetime {
+        Some(lifetime) => Some(
+            input
+                .generics
+                .lifetimes()
+                .map(|param| &param.lifetime)
+                .find(|param| *param == lifetime)
+                .ok_or_else(|| {
+                    Error::new_spanned(lifetime, "lifetime is not a parameter of the struct")
+                })?,
+        ),
+        None => input
+            .generics
+            .lifetimes()
+            .next()
+            .map(|param| &param.lifetime),
+    };
+    let mut generics = input.generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        let type_params: Vec<syn::Ident> = input
+            .generics
+            .type_params()
+            .map(|param| param.ident.clone())
+            .collect();
+        let where_clause = generics.make_where_clause();
+
+        for ident in type_params {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #ident: #lifetime });
+        }
+    }
+    let (generics_decls, generics_args, generics_where) = generics.split_for_impl();
+    let mut trait_generics = generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        trait_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote! { Self: #lifetime });
+    }
+    let trait_where = &trait_generics.where_clause;
+
+    let struct_name = &input.ident;
+    let init_err = find_attribute(&input.attrs, "init_err")
+        .map(|attr| attr.parse_args_with(syn::Type::parse))
+        .transpose()?;
+    let box_type =
+        quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<#struct_name #generics_args>> };
+    let (ensure_init_type, new_box_type) = match &init_err {
+        Some(err) => (
+            quote_mixed! { ::core::result::Result<#box_type, #err> },
+            quote_mixed! { ::core::result::Result<::core::pin::Pin<incrstruct::internal::Box<Self>>, #err> },
+        ),
+        None => (
+            box_type.clone(),
+            quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> },
+        ),
+    };
+
+    let struct_name = &input.ident;
+    let init_trait = parse_init_trait(&input.attrs)?;
+    let init_trait_name = init_trait
+        .name
+        .unwrap_or_else(|| join_ident("", struct_name, "Init"));
+    let uninit_name = join_ident("", struct_name, "Uninit");
+    let (init_field_decls, init_field_args, _) = make_init_field_decls_and_args(
+        fields.as_slice(),
+        self_lifetime,
+        Some(&syn::Ident::new("r", proc_macro2::Span::mixed_site())),
+        init_err.as_ref(),
+        false,
+        &[],
+        &init_trait.fn_prefix,
+    )?;
+    let init_field_names = make_init_field_names(tails.as_slice(), &init_trait.fn_prefix);
+
+    // Tails are initialized in order. On error, the tails initialized
+    // so far are dropped in reverse order, and then the heads.
+    let tail_inits: Vec<proc_macro2::TokenStream> = (0..tails.len())
+        .map(|i| {
+            let tail_name = &tail_names[i];
+            let init_field_name = &init_field_names[i];
+            let init_field_args = &init_field_args[i];
+            let callee = quote_mixed! { <#struct_name #generics_args as #init_trait_name #generics_args>::#init_field_name };
+
+            match &init_err {
+                Some(_) => {
+                    let undo_tails = tail_names[..i].iter().rev();
+
+                    quote_mixed! {
+                        match #callee(#( #init_field_args ),*) {
+                            ::core::result::Result::Ok(v) => ::core::ptr::write(&mut r.#tail_name as *mut _, v),
+                            ::core::result::Result::Err(err) => {
+                                #(
+                                    ::core::ptr::drop_in_place(::core::ptr::addr_of_mut!((*p).#undo_tails));
+                                )*
+                                #(
+                                    ::core::ptr::drop_in_place(::core::ptr::addr_of_mut!((*p).#head_args));
+                                )*
+                                ::core::mem::drop(incrstruct::internal::Box::from_raw(p.cast::<::core::mem::MaybeUninit<#struct_name #generics_args>>()));
+                                return ::core::result::Result::Err(err);
+                            }
+                        }
+                    }
+                }
+                None => quote_mixed! {
+                    ::core::ptr::write(&mut r.#tail_name as *mut _, #callee(#( #init_field_args ),*));
+                },
+            }
+        })
+        .collect();
+    let pinned_box =
+        quote_mixed! { ::core::pin::Pin::from(incrstruct::internal::Box::from_raw(p)) };
+    let ok_box = match &init_err {
+        Some(_) => quote_mixed! { ::core::result::Result::Ok(#pinned_box) },
+        None => pinned_box,
+    };
+    let uninit_doc = format!(
+        "A [{}] with only head fields initialized, returned by `{}::new_uninit`. Dropping it drops the head fields.",
+        struct_name, struct_name,
+    );
+    let cfg_attrs: Vec<&syn::Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .collect();
+    let not_impl_guards = make_not_impl_guards(struct_name, &generics, &cfg_attrs, false);
+
+    let output = quote_mixed! {
+        #not_impl_guards
+
+        #(#cfg_attrs)*
+        impl #generics_decls #struct_name #generics_args #generics_where {
+            /// Creates a box with head fields initialized. Call
+            /// `ensure_init` on the result to initialize tail fields.
+            #ctor_vis fn new_uninit(#(#ctor_head_params),*) -> #uninit_name #generics_args {
+                #(#default_heads)*
+                let mut inner = incrstruct::internal::Box::new(::core::mem::MaybeUninit::<Self>::uninit());
+                let p = inner.as_mut_ptr();
+
+                // SAFETY: we only write each field once, so this
+                // overwrites uninitialized values.
+                unsafe {
+                    #(
+                        ::core::ptr::write(::core::ptr::addr_of_mut!((*p).#head_args), #head_args);
+                    )*
+                    ::core::ptr::write(::core::ptr::addr_of_mut!((*p).#header_name), ::core::marker::PhantomPinned);
+                }
+
+                #uninit_name { inner }
+            }
+
+            /// Creates a box with all fields initialized.
+            #ctor_vis fn new_box(#(#ctor_head_params),*) -> #new_box_type {
+                Self::new_uninit(#(#ctor_head_args),*).ensure_init()
+            }
+        }
+
+        #(#cfg_attrs)*
+        #[doc = #uninit_doc]
+        #vis struct #uninit_name #generics_decls #generics_where {
+            inner: incrstruct::internal::Box<::core::mem::MaybeUninit<#struct_name #generics_args>>,
+        }
+
+        #(#cfg_attrs)*
+        impl #generics_decls #uninit_name #generics_args #generics_where {
+            /// Initializes tail fields, and returns the pinned
+            /// struct. If this fails, the head fields are dropped. If
+            /// it panics, they are leaked.
+            #ctor_vis fn ensure_init(self) -> #ensure_init_type {
+                let inner = unsafe { ::core::ptr::read(&::core::mem::ManuallyDrop::new(self).inner) };
+                let p = incrstruct::internal::Box::into_raw(inner).cast::<#struct_name #generics_args>();
+
+                // SAFETY: the heads are initialized, and the allocation
+                // doesn't move, so the tails can borrow them.
+                unsafe {
+                    let r = &mut *p;
+                    let _: &::core::marker::PhantomPinned = &r.#header_name;
+
+                    #(#tail_inits)*
+
+                    #ok_box
+                }
+            }
+        }
+
+        #(#cfg_attrs)*
+        impl #generics_decls ::core::ops::Drop for #uninit_name #generics_args #generics_where {
+            fn drop(&mut self) {
+                let p = self.inner.as_mut_ptr();
+
+                // SAFETY: only the heads are initialized.
+                unsafe {
+                    #(
+                        ::core::ptr::drop_in_place(::core::ptr::addr_of_mut!((*p).#head_args));
+                    )*
+                }
+            }
+        }
+
+        // The parameters take the field types by reference, e.g.
+        // `&'a String`, even where a slice would do.
+        #(#cfg_attrs)*
+        #[allow(clippy::ptr_arg)]
+        #vis trait #init_trait_name #generics_decls #trait_where {
+            #(
+                #init_field_decls
+            )*
+        }
+    };
+
+    Ok(with_crate_path(output, options.crate_path.as_ref()).into())
+}
+
+/// Derives constructors for an enum, where each variant has its own
+/// head and tail fields, and `#[header]` field. The enum must be
+/// `#[repr(u8)]`, so the tag can be written before the fields are
+/// initialized. The tail fields of all variants are initialized in a
+/// single phase.
+fn incr_enum(input: &DeriveInput, data_enum: &syn::DataEnum) -> Result<TokenStream, Error> {
+    let options = parse_options(&input.attrs)?;
+    for (set, name) in [
+        (options.no_panic, "no_panic"),
+        (options.boxed, "boxed"),
+        (options.default, "default"),
+        (options.zeroed, "zeroed"),
+        (options.mmap, "mmap"),
+        (options.async_init, "async_init"),
+        (options.builder, "builder"),
+        (options.private_tails, "private_tails"),
+        (options.sync, "sync"),
+        (options.serde, "serde"),
+        (options.clone, "clone"),
+        (options.debug, "debug"),
+        (options.project, "project"),
+        (!options.derive_heads.is_empty(), "derive_heads"),
+        (options.yoke, "yoke"),
+        (options.no_header, "no_header"),
+        (options.unsafe_send, "unsafe_send"),
+        (options.unsafe_sync, "unsafe_sync"),
+    ] {
+        if set {
+            return Err(Error::new_spanned(
+                &input.ident,
+                format!("the {} option can't be used on enums", name),
+            ));
+        }
+    }
+    for name in ["init_args", "constructor"] {
+        if let Some(attr) = find_attribute(&input.attrs, name) {
+            return Err(Error::new_spanned(
+                attr,
+                format!("#[{}] can't be used on enums", name),
+            ));
+        }
+    }
+    check_not_copy(&input.attrs)?;
+
+    // The tag of a `#[repr(u8)]` enum is a `u8` at the start, in
+    // every variant, so it can be written on its own.
+    let is_repr_u8 = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+        .any(|attr| {
+            attr.parse_args_with(
+                syn::punctuated::Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated,
+            )
+            .is_ok_and(|reprs| reprs.iter().any(|repr| repr == "u8"))
+        });
+    if !is_repr_u8 {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "an IncrStruct enum must be #[repr(u8)], so its variant can be set before its fields are initialized",
+        ));
+    }
+
+    let cfg_attrs: Vec<&syn::Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .collect();
+    let enum_name = &input.ident;
+    let vis = &input.vis;
+    let ctor_vis = match &options.vis {
+        Some(vis) => quote_mixed! { #vis },
+        None => quote_mixed! { pub },
+    };
+    let (_, self_generics, _) = input.generics.split_for_impl();
+    let self_ty = quote_mixed! { #enum_name #self_generics };
+
+    let self_lifetime = match &options.self_lifetime {
+        Some(lifetime) => Some(
+            input
+                .generics
+                .lifetimes()
+                .map(|param| &param.lifetime)
+                .find(|param| *param == lifetime)
+                .ok_or_else(|| {
+                    Error::new_spanned(lifetime, "lifetime is not a parameter of the enum")
+                })?,
+        ),
+        None => input
+            .generics
+            .lifetimes()
+            .next()
+            .map(|param| &param.lifetime),
+    };
+    let mut generics = input.generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        for param in generics.type_params_mut() {
+            param
+                .bounds
+                .push(syn::TypeParamBound::Lifetime(lifetime.clone()));
+        }
+    }
+    let (generics_decls, generics_args, generics_where) = generics.split_for_impl();
+    let mut trait_generics = generics.clone();
+    if let Some(lifetime) = self_lifetime {
+        trait_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote! { Self: #lifetime });
+    }
+    let trait_where = &trait_generics.where_clause;
+
+    let init_err = find_attribute(&input.attrs, "init_err")
+        .map(|attr| attr.parse_args_with(syn::Type::parse))
+        .transpose()?;
+    let init_err_or_infallible = init_err.clone().unwrap_or(syn::Type::Verbatim(
+        quote_mixed! { ::core::convert::Infallible },
+    ));
+    let wrap_result = |ty: proc_macro2::TokenStream| match &init_err {
+        Some(err) => quote_mixed! { ::core::result::Result<#ty, #err> },
+        None => ty,
+    };
+    let init_unwrap = match &init_err {
+        Some(_) => quote_mixed! {},
+        None => quote_mixed! { .unwrap() },
+    };
+    let init_trait = parse_init_trait(&input.attrs)?;
+    let init_trait_name = init_trait
+        .name
+        .unwrap_or_else(|| join_ident("", enum_name, "Init"));
+
+    let mut init_field_decls = Vec::new();
+    let mut init_arms = Vec::new();
+    let mut drop_tail_arms = Vec::new();
+    let mut drop_head_arms = Vec::new();
+    let mut header_arms = Vec::new();
+    let mut header_ty = None;
+    let mut new_funcs = Vec::new();
+    let mut max_tails = 0;
+
+    for (tag, variant) in data_enum.variants.iter().enumerate() {
+        let variant_name = &variant.ident;
+        if let Some((_, expr)) = &variant.discriminant {
+            return Err(Error::new_spanned(
+                expr,
+                "IncrStruct enums can't have explicit discriminants",
+            ));
+        }
+        let named = match &variant.fields {
+            syn::Fields::Named(named) => named,
+            fields => {
+                return Err(Error::new_spanned(
+                    fields,
+                    "IncrStruct enum variants must have named fields, and a #[header] field",
+                ))
+            }
+        };
+        let own_fields: Vec<syn::Field> = named
+            .named
+            .iter()
+            .map(|field| {
+                Ok(syn::Field {
+                    ty: syn::parse2(replace_self(field.ty.to_token_stream(), &self_ty))?,
+                    ..field.clone()
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        for field in own_fields.iter() {
+            for attr in field.attrs.iter() {
+                let path = attr.path();
+                if [
+                    "covariant",
+                    "cyclic",
+                    "default",
+                    "head_err",
+                    "lazy",
+                    "nested",
+                    "not_covariant",
+                    "phase",
+                    "stable_deref",
+                ]
+                .iter()
+                .any(|name| path.is_ident(name))
+                {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "this attribute can't be used in enum variants",
+                    ));
+                }
+            }
+            if !get_mut_borrows(field)?.is_empty() {
+                return Err(Error::new_spanned(
+                    field,
+                    "mutable borrows can't be used in enum variants",
+                ));
+            }
+        }
+
+        let mut fields: Vec<&syn::Field> = own_fields.iter().collect();
+        let header = match fields.pop() {
+            Some(header) if has_attribute(&header.attrs, "header") => header,
+            Some(field) => {
+                return Err(Error::new_spanned(
+                    field,
+                    "missing #[header] attribute on last field",
+                ))
+            }
+            None => return Err(Error::new_spanned(variant, "missing #[header] field")),
+        };
+        let header_name = header.ident.as_ref().unwrap();
+
+        // Like in a struct, fields are initialized in reverse
+        // declaration order.
+        fields.reverse();
+        let mut heads = find_phase(fields.as_slice(), false);
+        heads.reverse();
+        let tails = find_phase(fields.as_slice(), true);
+        if let Some(tail) = tails.get(MAX_TAILS) {
+            return Err(Error::new_spanned(
+                tail,
+                format!("at most {} tail fields are supported", MAX_TAILS),
+            ));
+        }
+        max_tails = max_tails.max(tails.len());
+
+        let fn_prefix = format!(
+            "{}{}_",
+            init_trait.fn_prefix,
+            to_snake_case(&variant_name.unraw().to_string())
+        );
+        let (decls, init_field_args, _) = make_init_field_decls_and_args(
+            fields.as_slice(),
+            self_lifetime,
+            None,
+            init_err.as_ref(),
+            false,
+            &[],
+            &fn_prefix,
+        )?;
+        init_field_decls.extend(decls);
+        let init_field_names = make_init_field_names(tails.as_slice(), &fn_prefix);
+        let field_names: Vec<&syn::Ident> = own_fields
+            .iter()
+            .map(|field| field.ident.as_ref().unwrap())
+            .collect();
+        let head_names: Vec<&syn::Ident> = heads
+            .iter()
+            .map(|field| field.ident.as_ref().unwrap())
+            .collect();
+        let tail_names: Vec<&syn::Ident> = tails
+            .iter()
+            .map(|field| field.ident.as_ref().unwrap())
+            .collect();
+        let num_tails = tails.len();
+
+        // The match arms bind the fields by reference, so the init
+        // functions take the bindings directly.
+        let stmts: Vec<proc_macro2::TokenStream> = (0..num_tails)
+            .map(|i| {
+                let tail_name = tail_names[i];
+                let init_field_name = &init_field_names[i];
+                let init_field_args = &init_field_args[i];
+                let callee = quote_mixed! { <Self as #init_trait_name #generics_args>::#init_field_name };
+
+                match &init_err {
+                    Some(_) => quote_mixed! {
+                        match #callee(#( #init_field_args ),*) {
+                            ::core::result::Result::Ok(v) => {
+                                ::core::ptr::write(#tail_name as *mut _, v);
+                                incrstruct::internal::set_tail_inited(#header_name, #i);
+                                at += 1;
+                            }
+                            ::core::result::Result::Err(err) => {
+                                // SAFETY: we are undoing what we have
+                                // done, and any field references will be
+                                // dropped.
+                                <Self as incrstruct::internal::IncrStructBase>::drop_tail_in_place(&mut *this, 0..at);
+                                return ::core::result::Result::Err(err);
+                            }
+                        };
+                    },
+                    None => quote_mixed! {
+                        ::core::ptr::write(#tail_name as *mut _, #callee(#( #init_field_args ),*));
+                        incrstruct::internal::set_tail_inited(#header_name, #i);
+                    },
+                }
+            })
+            .collect();
+        let init_body = match &init_err {
+            Some(_) => quote_mixed! {
+                let mut at = 0;
+                #(#stmts)*
+                ::core::debug_assert_eq!(at, #num_tails);
+            },
+            None => quote_mixed! { #(#stmts)* },
+        };
+        init_arms.push(quote_mixed! {
+            Self::#variant_name { #(#field_names),* } => {
+                #init_body
+            }
+        });
+
+        let drop_tail_names: Vec<&syn::Ident> = tail_names.iter().rev().copied().collect();
+        let drop_tail_indices: Vec<usize> = (0..num_tails).rev().collect();
+        drop_tail_arms.push(quote_mixed! {
+            Self::#variant_name { #(#drop_tail_names,)* #header_name, .. } => {
+                #(
+                    if range.contains(&#drop_tail_indices) && incrstruct::internal::take_tail_inited(#header_name, #drop_tail_indices) {
+                        ::core::ptr::drop_in_place(#drop_tail_names);
+                    }
+                )*
+            }
+        });
+        drop_head_arms.push(quote_mixed! {
+            Self::#variant_name { #(#head_names,)* .. } => {
+                #(
+                    ::core::ptr::drop_in_place(#head_names);
+                )*
+            }
+        });
+        header_arms.push(quote_mixed! {
+            Self::#variant_name { #header_name, .. } => #header_name,
+        });
+        // The variants must use the same header type.
+        header_ty.get_or_insert_with(|| header.ty.clone());
+
+        if cfg!(feature = "alloc") {
+            let tag = tag as u8;
+            let fn_name = join_ident(
+                "new_box_",
+                &syn::Ident::new(
+                    &to_snake_case(&variant_name.unraw().to_string()),
+                    variant_name.span(),
+                ),
+                "",
+            );
+            let head_params = make_field_params(heads.as_slice(), None, false)?;
+            let head_refs: Vec<syn::Ident> = (0..heads.len())
+                .map(|i| syn::Ident::new(&format!("head{}", i), proc_macro2::Span::mixed_site()))
+                .collect();
+            let ret_type =
+                wrap_result(quote_mixed! { ::core::pin::Pin<incrstruct::internal::Box<Self>> });
+            let doc = format!(
+                "Creates a `Box` holding the `{}` variant, and initializes its tail fields.",
+                variant_name.unraw()
+            );
+
+            new_funcs.push(quote_mixed! {
+                #[doc = #doc]
+                #ctor_vis fn #fn_name(#(#head_params),*) -> #ret_type {
+                    // The variant is written in place, which the
+                    // dead_code lint doesn't see.
+                    let _ = |#(#field_names),*| Self::#variant_name { #(#field_names),* };
+
+                    // SAFETY: the closure writes the tag, selecting the
+                    // variant, and then all its head fields, and
+                    // nothing else.
+                    unsafe {
+                        incrstruct::internal::new_box(|out: &mut Self| {
+                            ::core::ptr::write(out as *mut Self as *mut u8, #tag);
+
+                            match out {
+                                Self::#variant_name { #(#head_names: #head_refs,)* .. } => {
+                                    #(
+                                        ::core::ptr::write(#head_refs, #head_names);
+                                    )*
+                                }
+                                #[allow(unreachable_patterns)]
+                                _ => {}
+                            }
+                        })
+                    } #init_unwrap
+                }
+            });
+        }
+    }
+
+    let force_init_type = wrap_result(quote_mixed! { () });
+    let not_impl_guards = make_not_impl_guards(enum_name, &generics, &cfg_attrs, false);
+    let header_ty = match header_ty {
+        Some(ty) => quote_mixed! { #ty },
+        None => quote_mixed! { incrstruct::Header },
+    };
+
+    let output = quote_mixed! {
+        #(#cfg_attrs)*
+        impl #generics_decls #enum_name #generics_args #generics_where {
+            #(#new_funcs)*
+
+            /// Drops the tail fields of the variant, and initializes
+            /// them again.
+            pub fn force_init(this: &mut Self) -> #force_init_type {
+                incrstruct::internal::force_init(this) #init_unwrap
+            }
+        }
+
+        #not_impl_guards
+
+        // The parameters take the field types by reference, e.g.
+        // `&'a String`, even where a slice would do.
+        #(#cfg_attrs)*
+        #[allow(clippy::ptr_arg)]
+        #vis trait #init_trait_name #generics_decls #trait_where {
+            #(
+                #init_field_decls
+            )*
+        }
+
+        #(#cfg_attrs)*
+        impl #generics_decls incrstruct::internal::IncrStructBase for #enum_name #generics_args #generics_where {
+            type Error = #init_err_or_infallible;
+            type Tails = <#header_ty as incrstruct::internal::HeaderBits>::Tails;
+            type Dirty = <#header_ty as incrstruct::internal::HeaderBits>::Dirty;
+
+            const PHASES: u8 = 1;
+
+            /// Drops a value previously created with `new_uninit`.
+            unsafe fn drop_uninit_in_place(this: &mut ::core::mem::MaybeUninit<Self>) -> ::core::result::Result<(), incrstruct::StateError> {
+                // SAFETY: we only drop head fields, and only once.
+                incrstruct::internal::drop_uninit_in_place(this, |this| unsafe {
+                    match this {
+                        #(#drop_head_arms)*
+                    }
+                })
+            }
+
+            /// Drops tail fields of the variant in `range`, in natural
+            /// drop order, causing a partially initialized enum.
+            ///
+            /// # Safety
+            ///
+            /// We only drop tail fields, and only once.
+            #[allow(unused_variables)]
+            unsafe fn drop_tail_in_place(this: &mut Self, range: ::core::ops::Range<usize>) {
+                match this {
+                    #(#drop_tail_arms)*
+                }
+            }
+
+            fn phase_end(phase: u8) -> usize {
+                match phase {
+                    0 => 0,
+                    _ => #max_tails,
+                }
+            }
+
+            fn header<'isheader>(this: &'isheader mut Self) -> &'isheader mut incrstruct::Header<Self::Tails, Self::Dirty> {
+                const {
+                    ::core::assert!(
+                        <<Self as incrstruct::internal::IncrStructBase>::Tails as incrstruct::TailBits>::BITS >= #max_tails,
+                        "the header has too few tail bits for the tail fields",
+                    )
+                };
+
+                match this {
+                    #(#header_arms)*
+                }
+            }
+        }
+
+        #(#cfg_attrs)*
+        impl #generics_decls incrstruct::internal::IncrStructInit for #enum_name #generics_args #generics_where {
+            // SAFETY: the tag is written before the heads, so the
+            // variant is known, and its tail fields only borrow later
+            // fields, in a DAG, so this always writes to uninitialized
+            // space.
+            #[allow(unused_variables)]
+            unsafe fn init_phase(this: *mut Self, phase: u8) -> ::core::result::Result<(), Self::Error> {
+                let r = &mut *this;
+
+                if phase == 1 {
+                    match r {
+                        #(#init_arms)*
+                    }
+                }
+
+                ::core::result::Result::Ok(())
+            }
+        }
     };
 
-    Ok(quote! {
-        impl #generics_decls #struct_name #generics_args #generics_where {
-            #(#new_funcs)*
+    Ok(with_crate_path(output, options.crate_path.as_ref()).into())
+}
 
-            pub fn force_init(this: &mut Self) -> #force_init_type {
-                incrstruct::internal::force_init(this) #init_unwrap
-            }
+/// Returns items that fail to compile if the struct implements
+/// `Unpin`, since it could then be moved out of a `Pin`, or `Copy`,
+/// since the copy would reference the original. The auto trait never
+/// applies, because of the `PhantomPinned` in the header, so this
+/// catches manual implementations. `check_not_copy` gives a better
+/// error for the common case of `#[derive(Copy)]`.
+///
+/// With `not_drop`, for the projection, a `Drop` impl fails too, since
+/// it could move a pinned field out of `&mut self`.
+fn make_not_impl_guards(
+    struct_name: &syn::Ident,
+    generics: &syn::Generics,
+    cfg_attrs: &[&syn::Attribute],
+    not_drop: bool,
+) -> proc_macro2::TokenStream {
+    let (generics_decls, generics_args, generics_where) = generics.split_for_impl();
+    // The blanket impl conflicts with this one if the struct is Drop.
+    let not_drop_guard = not_drop.then(|| {
+        quote_mixed! {
+            #[allow(dead_code)]
+            trait MustNotImplDrop {}
 
-            /// See [incrstruct::internal::new_uninit].
-            pub unsafe fn new_uninit(#(#head_params),*) -> core::mem::MaybeUninit<Self> {
-                // SAFETY: we only write each field once, so this
-                // overwrites uninitialized values.
-                incrstruct::internal::new_uninit::<Self, _>(|out| unsafe {
-                    #(
-                        core::ptr::write(&mut out.#head_args, #head_args);
-                    )*
-                })
+            #[allow(drop_bounds)]
+            impl<T: ::core::ops::Drop> MustNotImplDrop for T {}
+
+            impl #generics_decls MustNotImplDrop for #struct_name #generics_args #generics_where {}
+        }
+    });
+
+    quote_mixed! {
+        #(#cfg_attrs)*
+        const _: () = {
+            trait AmbiguousIfUnpin<A> {
+                fn some_item() {}
             }
 
-            /// See [incrstruct::internal::drop_uninit_in_place].
-            pub unsafe fn drop_uninit(mut this: core::mem::MaybeUninit<Self>) {
-                <Self as incrstruct::internal::IncrStructInit>::drop_uninit_in_place(&mut this)
+            impl<T: ?::core::marker::Sized> AmbiguousIfUnpin<()> for T {}
+
+            #[allow(dead_code)]
+            struct Invalid;
+
+            impl<T: ?::core::marker::Sized + ::core::marker::Unpin> AmbiguousIfUnpin<Invalid> for T {}
+
+            trait AmbiguousIfCopy<A> {
+                fn some_item() {}
             }
 
-            pub unsafe fn ensure_init(this: &mut core::mem::MaybeUninit<Self>) -> #ensure_init_type {
-                incrstruct::internal::ensure_init(this) #init_unwrap
+            impl<T: ?::core::marker::Sized> AmbiguousIfCopy<()> for T {}
+
+            impl<T: ?::core::marker::Sized + ::core::marker::Copy> AmbiguousIfCopy<Invalid> for T {}
+
+            // The parameter brings the implied lifetime bounds of the
+            // struct into scope.
+            #[allow(dead_code)]
+            fn assert_not_unpin_or_copy #generics_decls (_: &#struct_name #generics_args) #generics_where {
+                let _ = <#struct_name #generics_args as AmbiguousIfUnpin<_>>::some_item;
+                let _ = <#struct_name #generics_args as AmbiguousIfCopy<_>>::some_item;
             }
-        }
 
-        trait #init_trait_name #generics_decls #generics_where {
-            #(
-                #init_field_decls
-            )*
+            #not_drop_guard
+        };
+    }
+}
+
+/// Returns an error if `Copy` is derived. Copying the struct would
+/// leave the copy referencing the original.
+fn check_not_copy(attrs: &[syn::Attribute]) -> Result<(), Error> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("derive")) {
+        let paths = attr.parse_args_with(
+            syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated,
+        )?;
+
+        if let Some(path) = paths
+            .iter()
+            .find(|path| path.segments.last().is_some_and(|seg| seg.ident == "Copy"))
+        {
+            return Err(Error::new_spanned(
+                path,
+                "a self-referencing struct cannot be Copy",
+            ));
         }
+    }
 
-        impl #generics_decls incrstruct::internal::IncrStructInit for #struct_name #generics_args #generics_where {
-            type Error = #init_err_or_unit;
+    Ok(())
+}
 
-            // SAFETY: since we only support referencing earlier
-            // fields, in a DAG, this always writes to
-            // uninitialized space. The generated trait guarantees
-            // that init_field_X is not unsafe.
-            unsafe fn init(this: *mut Self) -> std::result::Result<(), Self::Error> {
-                let r = &mut *this;
+/// A named constructor, given as `#[constructor(name(param: Type, ...))]`.
+struct Constructor {
+    name: syn::Ident,
+    params: syn::punctuated::Punctuated<ConstructorParam, syn::Token![,]>,
+}
 
-                #init_field_calls
+impl Parse for Constructor {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        let content;
+        syn::parenthesized!(content in input);
 
-                Ok(())
-            }
+        Ok(Constructor {
+            name,
+            params: content.parse_terminated(ConstructorParam::parse, syn::Token![,])?,
+        })
+    }
+}
 
-            /// Drops a value previously created with `new_uninit`.
-            unsafe fn drop_uninit_in_place(this: &mut core::mem::MaybeUninit<Self>) {
-                // SAFETY: we only drop head fields, and only once.
-                incrstruct::internal::drop_uninit_in_place(this, |this| unsafe {
-                    #(
-                        core::ptr::drop_in_place(&mut this.#drop_head_names);
-                    )*
-                });
-            }
+/// A parameter of a named constructor, like `path: &Path`, or an
+/// init argument.
+struct ConstructorParam {
+    name: syn::Ident,
+    ty: syn::Type,
+}
 
-            /// Drops tail fields starting at `at`, in natural drop
-            /// order, causing a partially initialized struct.
-            ///
-            /// # Safety
-            ///
-            /// We only drop tail fields, and only once.
-            unsafe fn drop_tail_in_place(this: &mut Self, mut at: usize) {
-                #( if at == 0 { core::ptr::drop_in_place(&mut this.#drop_tail_names); } else { at -= 1; } )*
-            }
+impl Parse for ConstructorParam {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<syn::Token![:]>()?;
 
-            fn header<'isheader>(this: &'isheader mut Self) -> &'isheader mut incrstruct::Header {
-                &mut this.#header_name
+        Ok(ConstructorParam {
+            name,
+            ty: input.parse()?,
+        })
+    }
+}
+
+fn parse_constructors(attrs: &[syn::Attribute]) -> Result<Vec<Constructor>, Error> {
+    let mut out = Vec::new();
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("constructor"))
+    {
+        out.extend(attr.parse_args_with(
+            syn::punctuated::Punctuated::<Constructor, syn::Token![,]>::parse_terminated,
+        )?);
+    }
+
+    Ok(out)
+}
+
+/// Parses `#[init_args(name: Type, ...)]`.
+/// The names given in `#[init_trait(...)]`.
+struct InitTrait {
+    /// The name of the Init trait, instead of `AStructInit`.
+    name: Option<syn::Ident>,
+
+    /// The prefix of the trait functions, instead of `init_field_`.
+    fn_prefix: String,
+}
+
+fn parse_init_trait(attrs: &[syn::Attribute]) -> Result<InitTrait, Error> {
+    let mut out = InitTrait {
+        name: None,
+        fn_prefix: "init_field_".to_owned(),
+    };
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("init_trait"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                let lit: syn::LitStr = meta.value()?.parse()?;
+                out.name = Some(lit.parse()?);
+            } else if meta.path.is_ident("fn_prefix") {
+                let lit: syn::LitStr = meta.value()?.parse()?;
+                syn::parse_str::<syn::Ident>(&format!("{}x", lit.value()))
+                    .map_err(|_| Error::new_spanned(&lit, "invalid function prefix"))?;
+                out.fn_prefix = lit.value();
+            } else {
+                return Err(meta.error("unknown init_trait option"));
             }
-        }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(out)
+}
+
+fn parse_init_args(attrs: &[syn::Attribute]) -> Result<Vec<ConstructorParam>, Error> {
+    let mut out = Vec::new();
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("init_args"))
+    {
+        out.extend(attr.parse_args_with(
+            syn::punctuated::Punctuated::<ConstructorParam, syn::Token![,]>::parse_terminated,
+        )?);
     }
-    .into())
+
+    Ok(out)
 }
 
 /// Returns a list of function parameters, like how a function is declared.
 fn make_field_params(
     fields: &[&syn::Field],
     ref_lifetime: Option<&syn::Lifetime>,
-) -> Vec<proc_macro2::TokenStream> {
+    boxed: bool,
+) -> Result<Vec<proc_macro2::TokenStream>, Error> {
+    let types = make_field_types(fields, ref_lifetime, boxed)?;
+
+    Ok(fields
+        .iter()
+        .zip(types)
+        .map(|(field, ty)| {
+            let name = &field.ident;
+            quote_mixed! { #name: #ty }
+        })
+        .collect())
+}
+
+/// Returns a list of parameter types, like in a closure type. In
+/// boxed mode, references point to the contents of the boxes, or the
+/// deref target of `#[stable_deref]` fields.
+fn make_field_types(
+    fields: &[&syn::Field],
+    ref_lifetime: Option<&syn::Lifetime>,
+    boxed: bool,
+) -> Result<Vec<proc_macro2::TokenStream>, Error> {
     fields
         .iter()
         .map(|field| {
-            let name = &field.ident;
-            let ty = &field.ty;
             if let Some(ref ref_lifetime) = ref_lifetime {
-                quote! { #name: & #ref_lifetime #ty }.into()
+                if boxed && has_attribute(&field.attrs, "stable_deref") {
+                    let ty = &field.ty;
+                    return Ok(
+                        quote_mixed! { & #ref_lifetime <#ty as ::core::ops::Deref>::Target },
+                    );
+                }
+                let ty = if boxed {
+                    get_boxed_type(field)?
+                } else {
+                    &field.ty
+                };
+                Ok(quote_mixed! { & #ref_lifetime #ty })
             } else {
-                quote! { #name: #ty }.into()
+                let ty = &field.ty;
+                Ok(quote_mixed! { #ty })
             }
         })
         .collect()
@@ -262,66 +6085,251 @@ fn make_field_params(
 fn make_field_args(
     fields: &[&syn::Field],
     src: Option<&syn::Ident>,
+    boxed: bool,
 ) -> Vec<proc_macro2::TokenStream> {
     fields
         .iter()
         .map(|field| {
             let name = &field.ident;
             if let Some(src) = src {
-                quote! { &#src.#name }.into()
+                if boxed {
+                    quote_mixed! { &*#src.#name }
+                } else {
+                    quote_mixed! { &#src.#name }
+                }
             } else {
-                quote! { #name }.into()
+                quote_mixed! { #name }
             }
         })
         .collect()
 }
 
-fn make_init_field_names(fields: &[&syn::Field]) -> Vec<syn::Ident> {
+fn make_init_field_names(fields: &[&syn::Field], fn_prefix: &str) -> Vec<syn::Ident> {
     fields
         .iter()
-        .map(|field| {
-            let name = field.ident.as_ref().unwrap().to_string();
-
-            syn::Ident::new(
-                &("init_field_".to_string() + name.as_str()),
-                proc_macro2::Span::call_site(),
-            )
-        })
+        .map(|field| join_ident(fn_prefix, field.ident.as_ref().unwrap(), ""))
         .collect()
 }
 
+/// Returns the name given to a tuple field while deriving.
+fn tuple_field_ident(index: usize, span: proc_macro2::Span) -> syn::Ident {
+    syn::Ident::new(&format!("_{}", index), span)
+}
+
+/// Returns the index of a tuple field, given its name while deriving.
+fn tuple_field_index(ident: &syn::Ident) -> Option<usize> {
+    ident.to_string().strip_prefix('_')?.parse().ok()
+}
+
+/// Returns the tokens with accesses to tuple fields by name, like
+/// `this._1`, rewritten to use the index, like `this.1`.
+fn with_tuple_members(tokens: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let mut out = proc_macro2::TokenStream::new();
+    let mut after_dot = false;
+
+    for tt in tokens.into_iter() {
+        let next_after_dot =
+            matches!(&tt, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == '.');
+        match tt {
+            proc_macro2::TokenTree::Ident(ref ident) if after_dot => {
+                match tuple_field_index(ident) {
+                    Some(index) => {
+                        let mut lit = proc_macro2::Literal::usize_unsuffixed(index);
+                        lit.set_span(ident.span());
+                        out.extend([proc_macro2::TokenTree::Literal(lit)]);
+                    }
+                    None => out.extend([tt]),
+                }
+            }
+            proc_macro2::TokenTree::Group(group) => {
+                let mut new_group =
+                    proc_macro2::Group::new(group.delimiter(), with_tuple_members(group.stream()));
+                new_group.set_span(group.span());
+                out.extend([proc_macro2::TokenTree::Group(new_group)]);
+            }
+            _ => out.extend([tt]),
+        }
+        after_dot = next_after_dot;
+    }
+
+    out
+}
+
+/// Returns the tokens with paths starting with `incrstruct::`
+/// rewritten to start with the given crate path instead.
+fn with_crate_path(
+    tokens: proc_macro2::TokenStream,
+    crate_path: Option<&syn::Path>,
+) -> proc_macro2::TokenStream {
+    let crate_path = match crate_path {
+        Some(crate_path) => crate_path,
+        None => return tokens,
+    };
+    let mut out = proc_macro2::TokenStream::new();
+    let mut iter = tokens.into_iter().peekable();
+    // The number of consecutive colons just seen. A single colon is
+    // a bound or type ascription, while two make a path separator.
+    let mut colons = 0;
+    let is_colon = |tt: &proc_macro2::TokenTree| matches!(tt, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == ':');
+
+    while let Some(tt) = iter.next() {
+        let next_colons = if is_colon(&tt) { colons + 1 } else { 0 };
+        match tt {
+            // Paths like `other::incrstruct::X` are left alone.
+            proc_macro2::TokenTree::Ident(ref ident)
+                if ident == "incrstruct" && colons < 2 && iter.peek().is_some_and(is_colon) =>
+            {
+                crate_path.to_tokens(&mut out);
+            }
+            proc_macro2::TokenTree::Group(group) => {
+                let mut new_group = proc_macro2::Group::new(
+                    group.delimiter(),
+                    with_crate_path(group.stream(), Some(crate_path)),
+                );
+                new_group.set_span(group.span());
+                out.extend([proc_macro2::TokenTree::Group(new_group)]);
+            }
+            _ => out.extend([tt]),
+        }
+        colons = next_colons;
+    }
+
+    out
+}
+
+/// Returns the tokens with all occurrences of a lifetime replaced.
+fn replace_lifetime(
+    tokens: proc_macro2::TokenStream,
+    from: &syn::Lifetime,
+    to: &syn::Lifetime,
+) -> proc_macro2::TokenStream {
+    let mut out = proc_macro2::TokenStream::new();
+    let mut iter = tokens.into_iter().peekable();
+
+    while let Some(tt) = iter.next() {
+        match tt {
+            proc_macro2::TokenTree::Punct(ref punct) if punct.as_char() == '\'' => {
+                match iter.peek() {
+                    Some(proc_macro2::TokenTree::Ident(ident)) if *ident == from.ident => {
+                        iter.next();
+                        to.to_tokens(&mut out);
+                    }
+                    _ => out.extend([tt]),
+                }
+            }
+            proc_macro2::TokenTree::Group(group) => {
+                let mut new_group = proc_macro2::Group::new(
+                    group.delimiter(),
+                    replace_lifetime(group.stream(), from, to),
+                );
+                new_group.set_span(group.span());
+                out.extend([proc_macro2::TokenTree::Group(new_group)]);
+            }
+            _ => out.extend([tt]),
+        }
+    }
+
+    out
+}
+
+/// Returns the tokens with all occurrences of `Self` replaced.
+fn replace_self(
+    tokens: proc_macro2::TokenStream,
+    to: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut out = proc_macro2::TokenStream::new();
+
+    for tt in tokens {
+        match tt {
+            proc_macro2::TokenTree::Ident(ref ident) if ident == "Self" => {
+                out.extend(to.clone());
+            }
+            proc_macro2::TokenTree::Group(group) => {
+                let mut new_group =
+                    proc_macro2::Group::new(group.delimiter(), replace_self(group.stream(), to));
+                new_group.set_span(group.span());
+                out.extend([proc_macro2::TokenTree::Group(new_group)]);
+            }
+            _ => out.extend([tt]),
+        }
+    }
+
+    out
+}
+
+/// Converts a `CamelCase` variant name into `snake_case`, for
+/// function names.
+fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+
+    for c in name.chars() {
+        if c.is_uppercase() {
+            if !out.is_empty() && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Returns a new identifier with a prefix and suffix added. Raw
+/// identifiers, like `r#type`, are unescaped first, since the result
+/// is never a keyword. Tuple fields, like `_1`, lose the underscore
+/// after a prefix, as in `init_field_1`.
+fn join_ident(prefix: &str, ident: &syn::Ident, suffix: &str) -> syn::Ident {
+    let name = match tuple_field_index(ident) {
+        Some(index) if !prefix.is_empty() => index.to_string(),
+        _ => ident.unraw().to_string(),
+    };
+
+    syn::Ident::new(
+        &format!("{}{}{}", prefix, name, suffix),
+        proc_macro2::Span::call_site(),
+    )
+}
+
+/// The trait function declarations, the arguments to pass to them,
+/// and equivalent closure trait bounds.
+type InitFieldDecls = (
+    Vec<proc_macro2::TokenStream>,
+    Vec<Vec<proc_macro2::TokenStream>>,
+    Vec<proc_macro2::TokenStream>,
+);
+
+/// Returns the [InitFieldDecls] for all tail fields.
 fn make_init_field_decls_and_args(
     fields: &[&syn::Field],
     ref_lifetime: Option<&syn::Lifetime>,
     src: Option<&syn::Ident>,
     init_err: Option<&syn::Type>,
-) -> Result<
-    (
-        Vec<proc_macro2::TokenStream>,
-        Vec<Vec<proc_macro2::TokenStream>>,
-    ),
-    Error,
-> {
+    boxed: bool,
+    init_args: &[ConstructorParam],
+    fn_prefix: &str,
+) -> Result<InitFieldDecls, Error> {
     let mut decls = Vec::new();
     let mut args = Vec::new();
+    let mut fn_types = Vec::new();
 
     for (i, field) in fields.iter().enumerate() {
         if !has_attribute(&field.attrs, "borrows") {
             continue;
         }
 
+        // Lazy fields are initialized with the contents of the cell.
+        let ty = if has_attribute(&field.attrs, "lazy") {
+            get_lazy_type(field)?
+        } else {
+            &field.ty
+        };
         let ty = match &init_err {
-            Some(err) => {
-                let ty = &field.ty;
-                syn::Type::Verbatim(quote! { std::result::Result<#ty, #err> })
-            }
-            None => field.ty.clone(),
+            Some(err) => syn::Type::Verbatim(quote_mixed! { ::core::result::Result<#ty, #err> }),
+            None => ty.clone(),
         };
-        let name = field.ident.as_ref().unwrap().to_string();
-        let fn_name = syn::Ident::new(
-            &("init_field_".to_string() + name.as_str()),
-            proc_macro2::Span::call_site(),
-        );
+        let fn_name = join_ident(fn_prefix, field.ident.as_ref().unwrap(), "");
         let borrows = get_borrows(field)?;
         let param_fields = find_borrows_fields(&fields[..i], borrows).map_err(|missing| {
             let mut out: Option<Error> = None;
@@ -337,46 +6345,358 @@ fn make_init_field_decls_and_args(
 
             out.unwrap()
         })?;
-        let params = make_field_params(param_fields.as_slice(), ref_lifetime);
+        let mut params = make_field_params(param_fields.as_slice(), ref_lifetime, boxed)?;
+        let mut param_types = make_field_types(param_fields.as_slice(), ref_lifetime, boxed)?;
+        let mut field_args = make_field_args(param_fields.as_slice(), src, boxed);
+        // Mutably borrowed fields are passed as the contents of their
+        // Lent<T>, which nothing else can reach.
+        let mut_borrows = get_mut_borrows(field)?;
+        for (j, param_field) in param_fields.iter().enumerate() {
+            let name = param_field.ident.as_ref().unwrap();
+            if !mut_borrows.contains(name) {
+                continue;
+            }
+
+            let ty = get_lent_type(param_field)?;
+            let ref_ty = match ref_lifetime {
+                Some(ref_lifetime) => quote_mixed! { & #ref_lifetime mut #ty },
+                None => quote_mixed! { &mut #ty },
+            };
+            params[j] = quote_mixed! { #name: #ref_ty };
+            param_types[j] = ref_ty;
+            if let Some(src) = src {
+                field_args[j] = quote_mixed! {
+                    incrstruct::internal::lent_mut(::core::ptr::addr_of_mut!(#src.#name))
+                };
+            }
+        }
+        for arg in init_args.iter() {
+            let name = &arg.name;
+            let ty = &arg.ty;
+
+            params.push(quote_mixed! { #name: &#ty });
+            param_types.push(quote_mixed! { &#ty });
+            field_args.push(quote_mixed! { #name });
+        }
+
+        // An inline #[init(expr)] becomes the default body, so the trait
+        // doesn't need to implement the function.
+        decls.push(match find_attribute(&field.attrs, "init") {
+            Some(attr) => {
+                let expr = attr.parse_args::<syn::Expr>()?;
+                quote_mixed! {
+                    fn #fn_name(#( #params ),*) -> #ty {
+                        #![allow(unused_variables)]
+                        #expr
+                    }
+                }
+            }
+            None => quote_mixed! { fn #fn_name(#( #params ),*) -> #ty; },
+        });
+        args.push(field_args);
+        fn_types.push(quote_mixed! { ::core::ops::FnMut(#( #param_types ),*) -> #ty });
+    }
+
+    Ok((decls, args, fn_types))
+}
+
+/// Returns `T` given a field type of `OnceCell<T>` or `OnceLock<T>`.
+fn get_lazy_type(field: &syn::Field) -> Result<&syn::Type, Error> {
+    if let syn::Type::Path(syn::TypePath { qself: None, path }) = &field.ty {
+        if let Some(syn::PathSegment {
+            ident,
+            arguments: syn::PathArguments::AngleBracketed(args),
+        }) = path.segments.last()
+        {
+            if (ident == "OnceCell" || ident == "OnceLock") && args.args.len() == 1 {
+                if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
+                    return Ok(ty);
+                }
+            }
+        }
+    }
+
+    Err(Error::new_spanned(
+        &field.ty,
+        "#[lazy] fields must be of type OnceCell<T> or OnceLock<T>",
+    ))
+}
 
-        decls.push(quote! { fn #fn_name(#( #params ),*) -> #ty; }.into());
-        args.push(make_field_args(param_fields.as_slice(), src));
+/// Returns `T` for a field of type `AliasableBox<T>`, or an error.
+fn get_boxed_type(field: &syn::Field) -> Result<&syn::Type, Error> {
+    // A qualified path, like `<T as Trait>::AliasableBox<U>`, is not
+    // an AliasableBox.
+    if let syn::Type::Path(syn::TypePath { qself: None, path }) = &field.ty {
+        if let Some(syn::PathSegment {
+            ident,
+            arguments: syn::PathArguments::AngleBracketed(args),
+        }) = path.segments.last()
+        {
+            if ident == "AliasableBox" && args.args.len() == 1 {
+                if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
+                    return Ok(ty);
+                }
+            }
+        }
+    }
+
+    Err(Error::new_spanned(
+        &field.ty,
+        "in boxed mode, borrowed fields must be of type AliasableBox<T> or #[stable_deref]",
+    ))
+}
+
+/// Returns `T` for a field of type `Lent<T>`, or an error.
+fn get_lent_type(field: &syn::Field) -> Result<&syn::Type, Error> {
+    if let syn::Type::Path(syn::TypePath { qself: None, path }) = &field.ty {
+        if let Some(syn::PathSegment {
+            ident,
+            arguments: syn::PathArguments::AngleBracketed(args),
+        }) = path.segments.last()
+        {
+            if ident == "Lent" && args.args.len() == 1 {
+                if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
+                    return Ok(ty);
+                }
+            }
+        }
     }
 
-    Ok((decls, args))
+    Err(Error::new_spanned(
+        &field.ty,
+        "mutably borrowed fields must be of type Lent<T>",
+    ))
 }
 
 fn find_borrows_fields<'b>(
     fields: &'b [&syn::Field],
-    mut borrows: HashSet<syn::Ident>,
-) -> Result<Vec<&'b syn::Field>, HashSet<syn::Ident>> {
-    let out = fields
-        .iter()
-        .map(|field| *field)
-        .filter(|field| field.ident.is_some() && borrows.remove(field.ident.as_ref().unwrap()))
-        .collect();
+    borrows: Vec<syn::Ident>,
+) -> Result<Vec<&'b syn::Field>, Vec<syn::Ident>> {
+    let mut out = Vec::new();
+    let mut missing = Vec::new();
 
-    if borrows.is_empty() {
+    // Parameters are in the order the borrows are listed.
+    for name in borrows.into_iter() {
+        match fields
+            .iter()
+            .find(|field| field.ident.as_ref() == Some(&name))
+        {
+            Some(field) => out.push(*field),
+            None => missing.push(name),
+        }
+    }
+
+    if missing.is_empty() {
         Ok(out)
     } else {
-        Err(borrows)
+        Err(missing)
+    }
+}
+
+/// How a runtime constructor takes the head fields.
+enum HeadsArg {
+    /// A partial struct, from `new_uninit`.
+    Uninit,
+
+    /// A closure writing them directly into the allocation.
+    Writer,
+}
+
+/// A field name, or tuple index, in `#[borrows(...)]`, optionally
+/// preceded by `mut` for an exclusive borrow. Keywords are accepted
+/// without the `r#` prefix, and made raw to match the field.
+struct BorrowsArg {
+    mutability: Option<syn::Token![mut]>,
+    name: syn::Ident,
+}
+
+impl Parse for BorrowsArg {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mutability = input.parse()?;
+        // Tuple fields are borrowed by index.
+        if input.peek(syn::LitInt) {
+            let index: syn::LitInt = input.parse()?;
+            let name = tuple_field_ident(index.base10_parse()?, index.span());
+
+            return Ok(BorrowsArg { mutability, name });
+        }
+        let name = syn::Ident::parse_any(input)?;
+        let s = name.to_string();
+        // Path keywords can't be raw, and are never field names.
+        let is_keyword = syn::parse_str::<syn::Ident>(&s).is_err();
+        let is_path_keyword = ["self", "Self", "super", "crate", "_"].contains(&s.as_str());
+        let name = if is_keyword && !is_path_keyword {
+            syn::Ident::new_raw(&s, name.span())
+        } else {
+            name
+        };
+
+        Ok(BorrowsArg { mutability, name })
     }
 }
 
-fn get_borrows(field: &syn::Field) -> Result<HashSet<syn::Ident>, Error> {
+fn parse_borrows(field: &syn::Field) -> Result<Vec<BorrowsArg>, Error> {
     let attr = field
         .attrs
         .iter()
         .find(|attr| attr.path().is_ident("borrows"));
 
     if let Some(attr) = attr {
+        if let syn::Meta::Path(_) = attr.meta {
+            return Err(Error::new_spanned(
+                attr,
+                "use #[borrows()] for a tail field that borrows no fields",
+            ));
+        }
+
+        // An empty list is a computed tail, initialized without
+        // arguments. A trailing comma is allowed.
         let args = attr.parse_args_with(
-            syn::punctuated::Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated,
+            syn::punctuated::Punctuated::<BorrowsArg, syn::Token![,]>::parse_terminated,
         )?;
+        let mut out: Vec<BorrowsArg> = Vec::new();
+
+        for arg in args.into_iter() {
+            if out.iter().any(|other| other.name == arg.name) {
+                return Err(Error::new_spanned(arg.name, "field is already borrowed"));
+            }
+            out.push(arg);
+        }
 
-        Ok(HashSet::from_iter(args.into_iter()))
+        Ok(out)
     } else {
-        Ok(HashSet::new())
+        Ok(Vec::new())
+    }
+}
+
+fn get_borrows(field: &syn::Field) -> Result<Vec<syn::Ident>, Error> {
+    Ok(parse_borrows(field)?
+        .into_iter()
+        .map(|arg| arg.name)
+        .collect())
+}
+
+/// Returns the fields borrowed with `#[borrows(mut ...)]`.
+fn get_mut_borrows(field: &syn::Field) -> Result<Vec<syn::Ident>, Error> {
+    Ok(parse_borrows(field)?
+        .into_iter()
+        .filter(|arg| arg.mutability.is_some())
+        .map(|arg| arg.name)
+        .collect())
+}
+
+/// Checks that every field borrowed with `#[borrows(mut ...)]` is a
+/// `Lent<T>` head field, and not borrowed by any other tail. Tails
+/// borrowing mutably can't be borrowed themselves, since they are
+/// mutable through `with_myfield_mut`.
+fn check_mut_borrows(
+    heads: &[&syn::Field],
+    tails: &[&syn::Field],
+    boxed: bool,
+) -> Result<(), Error> {
+    for tail in tails.iter() {
+        for arg in parse_borrows(tail)?.iter() {
+            let (mutability, name) = match &arg.mutability {
+                Some(mutability) => (mutability, &arg.name),
+                None => continue,
+            };
+
+            if boxed {
+                return Err(Error::new_spanned(
+                    quote_mixed! { #mutability #name },
+                    "mutable borrows are not supported in boxed mode",
+                ));
+            }
+            if has_attribute(&tail.attrs, "lazy") {
+                return Err(Error::new_spanned(
+                    quote_mixed! { #mutability #name },
+                    "#[lazy] fields can't borrow mutably",
+                ));
+            }
+            let head = heads
+                .iter()
+                .find(|field| field.ident.as_ref() == Some(name))
+                .ok_or_else(|| {
+                    Error::new_spanned(
+                        quote_mixed! { #mutability #name },
+                        "only head fields can be borrowed mutably",
+                    )
+                })?;
+            if has_attribute(&head.attrs, "stable_deref") {
+                return Err(Error::new_spanned(
+                    quote_mixed! { #mutability #name },
+                    "#[stable_deref] fields can't be borrowed mutably",
+                ));
+            }
+            get_lent_type(head)?;
+
+            for other in tails.iter() {
+                if !core::ptr::eq(*other, *tail) && get_borrows(other)?.contains(name) {
+                    return Err(Error::new_spanned(
+                        other,
+                        format!(
+                            "field `{}` is mutably borrowed by another tail field",
+                            name.unraw()
+                        ),
+                    ));
+                }
+                if get_borrows(other)?.contains(tail.ident.as_ref().unwrap()) {
+                    return Err(Error::new_spanned(
+                        other,
+                        "tail fields that borrow mutably can't be borrowed",
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the initialization phase of the field, as given by
+/// `#[phase(n)]`. Head fields are always in phase zero, and tail
+/// fields default to phase one.
+fn get_init_phase(field: &syn::Field) -> Result<u8, Error> {
+    let is_tail = has_attribute(&field.attrs, "borrows");
+
+    match find_attribute(&field.attrs, "phase") {
+        Some(attr) if !is_tail => Err(Error::new_spanned(
+            attr,
+            "#[phase] can only be used on tail fields",
+        )),
+        Some(attr) => {
+            let lit = attr.parse_args::<syn::LitInt>()?;
+            let phase = lit.base10_parse::<u8>()?;
+
+            if phase == 0 {
+                Err(Error::new_spanned(lit, "phase must be at least 1"))
+            } else {
+                Ok(phase)
+            }
+        }
+        None => Ok(if is_tail { 1 } else { 0 }),
+    }
+}
+
+/// Returns the value of a head field with `#[default]`, or
+/// `#[default = expr]`, which is then not a constructor parameter.
+fn get_head_default(field: &syn::Field) -> Result<Option<syn::Expr>, Error> {
+    match find_attribute(&field.attrs, "default") {
+        Some(attr) if has_attribute(&field.attrs, "borrows") => Err(Error::new_spanned(
+            attr,
+            "#[default] can only be used on head fields",
+        )),
+        Some(attr) => match &attr.meta {
+            syn::Meta::Path(_) => Ok(Some(
+                syn::parse_quote! { ::core::default::Default::default() },
+            )),
+            syn::Meta::NameValue(meta) => Ok(Some(meta.value.clone())),
+            syn::Meta::List(_) => Err(Error::new_spanned(
+                attr,
+                "expected #[default] or #[default = expr]",
+            )),
+        },
+        None => Ok(None),
     }
 }
 
@@ -385,20 +6705,37 @@ fn get_borrows(field: &syn::Field) -> Result<HashSet<syn::Ident>, Error> {
 fn find_phase<'b>(fields: &'b [&syn::Field], borrows: bool) -> Vec<&'b syn::Field> {
     fields
         .iter()
-        .map(|field| *field)
+        .copied()
         .filter(|field| has_attribute(&field.attrs, "borrows") == borrows)
         .collect()
 }
 
-fn get_named_fields(input: &DeriveInput) -> Vec<&syn::Field> {
-    match &input.data {
-        syn::Data::Struct(data) => match &data.fields {
-            syn::Fields::Named(fields) => fields.named.iter().collect(),
-            syn::Fields::Unnamed(_) => Vec::new(),
-            syn::Fields::Unit => Vec::new(),
-        },
-        _ => Vec::new(),
+/// Returns true if `tokens` use the generic parameter.
+fn mentions_param(tokens: proc_macro2::TokenStream, param: &syn::GenericParam) -> bool {
+    let (ident, is_lifetime) = match param {
+        syn::GenericParam::Lifetime(param) => (&param.lifetime.ident, true),
+        syn::GenericParam::Type(param) => (&param.ident, false),
+        syn::GenericParam::Const(param) => (&param.ident, false),
+    };
+    let mut after_quote = false;
+
+    for token in tokens {
+        let found = match &token {
+            proc_macro2::TokenTree::Group(group) => mentions_param(group.stream(), param),
+            proc_macro2::TokenTree::Ident(token_ident) => {
+                token_ident == ident && after_quote == is_lifetime
+            }
+            _ => false,
+        };
+        if found {
+            return true;
+        }
+
+        after_quote =
+            matches!(&token, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == '\'');
     }
+
+    false
 }
 
 fn has_attribute(attrs: &[syn::Attribute], name: &str) -> bool {
@@ -0,0 +1,120 @@
+//! A read-only box that can be moved while its contents are borrowed.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+use alloc::boxed::Box;
+
+/// A heap allocation, like `Box<T>`, that can be moved while its
+/// contents are borrowed. Borrowed fields in boxed mode have this
+/// type.
+///
+/// Moving a `Box` asserts that it's the only pointer to its contents,
+/// which invalidates references the tail fields hold into it. This
+/// holds a raw pointer instead, like [Pair](crate::Pair) does for
+/// its owner. It only gives shared access to the contents, so they
+/// can't change under the tails. Use interior mutability, like
+/// `RefCell`, for contents that change.
+pub struct AliasableBox<T: ?Sized> {
+    ptr: NonNull<T>,
+    _owns: PhantomData<T>,
+}
+
+// SAFETY: it owns the contents, like a Box.
+unsafe impl<T: ?Sized + Send> Send for AliasableBox<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for AliasableBox<T> {}
+
+impl<T> AliasableBox<T> {
+    /// Moves the value into a new allocation.
+    pub fn new(v: T) -> Self {
+        Box::new(v).into()
+    }
+}
+
+impl<T: ?Sized> AliasableBox<T> {
+    /// Returns the contents as a `Box` again.
+    pub fn into_box(this: Self) -> Box<T> {
+        let this = ManuallyDrop::new(this);
+
+        // SAFETY: the pointer came from Box::into_raw, and is not
+        // dropped by `this`.
+        unsafe { Box::from_raw(this.ptr.as_ptr()) }
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for AliasableBox<T> {
+    fn from(v: Box<T>) -> Self {
+        Self {
+            // SAFETY: Box pointers are never null.
+            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(v)) },
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl From<&str> for AliasableBox<str> {
+    fn from(v: &str) -> Self {
+        Box::<str>::from(v).into()
+    }
+}
+
+impl<T: ?Sized> Deref for AliasableBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the allocation lives as long as self, and is only
+        // ever shared.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Drop for AliasableBox<T> {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from Box::into_raw.
+        drop(unsafe { Box::from_raw(self.ptr.as_ptr()) });
+    }
+}
+
+impl<T: Clone> Clone for AliasableBox<T> {
+    fn clone(&self) -> Self {
+        Self::new(T::clone(self))
+    }
+}
+
+impl Clone for AliasableBox<str> {
+    fn clone(&self) -> Self {
+        Self::from(&**self)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AliasableBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        T::fmt(self, f)
+    }
+}
+
+impl<T: Default> Default for AliasableBox<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized + serde::Serialize> serde::Serialize for AliasableBox<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        T::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: ?Sized> serde::Deserialize<'de> for AliasableBox<T>
+where
+    Box<T>: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Box::<T>::deserialize(deserializer).map(Self::from)
+    }
+}
@@ -0,0 +1,286 @@
+//! Arenas allocating many self-referencing structs in bulk.
+
+use core::cell::{Cell, RefCell};
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::internal::{expect_init, init_heads, try_ensure_init, IncrStructInit};
+use crate::Error;
+
+/// The number of structs allocated at a time by [IncrArena].
+const CHUNK_LEN: usize = 64;
+
+/// An arena that structs can be constructed in, using the generated
+/// `AStruct::new_in_arena(&arena, heads...)` function. Both
+/// initialization phases run in place, and the structs are dropped
+/// together with the arena.
+///
+/// Implemented by [IncrArena] and, with the `bumpalo` feature,
+/// [IncrBumpArena].
+// Like `bumpalo::Bump::alloc`, every call returns a new struct.
+#[allow(clippy::mut_from_ref)]
+pub trait IncrArenaAlloc<T: IncrStructInit> {
+    /// Constructs a struct in the arena, using `f` to write the head
+    /// fields in place. On error, the partial struct is dropped with
+    /// the arena.
+    ///
+    /// # Safety
+    ///
+    /// `f` must initialize all head fields, and only the head fields.
+    unsafe fn try_alloc_with<F: FnOnce(&mut T)>(
+        &self,
+        f: F,
+    ) -> Result<Pin<&mut T>, Error<T::Error>>;
+
+    /// Like [IncrArenaAlloc::try_alloc_with], but panics on state
+    /// errors.
+    ///
+    /// # Safety
+    ///
+    /// See [IncrArenaAlloc::try_alloc_with].
+    unsafe fn alloc_with<F: FnOnce(&mut T)>(&self, f: F) -> Result<Pin<&mut T>, T::Error> {
+        self.try_alloc_with(f)
+            .map_err(|err| expect_init(err, "new_in_arena"))
+    }
+}
+
+/// The structs constructed in an arena, which are dropped with it.
+struct Placed<T: IncrStructInit> {
+    slots: RefCell<Vec<*mut MaybeUninit<T>>>,
+}
+
+impl<T: IncrStructInit> Placed<T> {
+    const fn new() -> Self {
+        Self {
+            slots: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots.borrow().len()
+    }
+
+    /// Constructs a struct in `raw`, which must live as long as the
+    /// arena.
+    unsafe fn place<'a, F: FnOnce(&mut T)>(
+        &self,
+        raw: *mut MaybeUninit<T>,
+        f: F,
+    ) -> Result<Pin<&'a mut T>, Error<T::Error>> {
+        init_heads(&mut *raw, f);
+
+        // Registered before the tail fields are initialized, so a
+        // partial struct is also dropped. The borrow ends here, so
+        // `init_field_myfield` functions can use the arena.
+        self.slots.borrow_mut().push(raw);
+
+        // SAFETY: the slot never moves.
+        Ok(Pin::new_unchecked(try_ensure_init(&mut *raw)?))
+    }
+}
+
+impl<T: IncrStructInit> Drop for Placed<T> {
+    fn drop(&mut self) {
+        for &raw in self.slots.get_mut().iter() {
+            // SAFETY: the struct is either fully initialized, or only
+            // has head fields, which this drops.
+            unsafe {
+                if T::drop_uninit_in_place(&mut *raw).is_err() {
+                    (*raw).assume_init_drop();
+                }
+            }
+        }
+    }
+}
+
+/// An arena for self-referencing structs, allocating memory for many
+/// at a time. The structs never move, and are dropped with the arena.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{IncrArena, IncrStruct};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let arena = IncrArena::new();
+/// let my_a = AStruct::new_in_arena(&arena, RefCell::new(42));
+/// let my_b = AStruct::new_in_arena(&arena, RefCell::new(43));
+///
+/// assert_eq!(*my_a.b + *my_b.b, 85);
+/// ```
+pub struct IncrArena<T: IncrStructInit> {
+    // Declared first, so the structs are dropped before the memory
+    // is freed.
+    placed: Placed<T>,
+
+    chunks: Chunks<T>,
+}
+
+impl<T: IncrStructInit> IncrArena<T> {
+    /// Returns an empty arena, without allocating.
+    pub const fn new() -> Self {
+        Self {
+            placed: Placed::new(),
+            chunks: Chunks {
+                chunks: RefCell::new(Vec::new()),
+                used: Cell::new(CHUNK_LEN),
+            },
+        }
+    }
+
+    /// Returns the number of structs constructed in the arena.
+    pub fn len(&self) -> usize {
+        self.placed.len()
+    }
+
+    /// Returns whether no structs have been constructed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: IncrStructInit> Default for IncrArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: IncrStructInit> IncrArenaAlloc<T> for IncrArena<T> {
+    unsafe fn try_alloc_with<F: FnOnce(&mut T)>(
+        &self,
+        f: F,
+    ) -> Result<Pin<&mut T>, Error<T::Error>> {
+        self.placed.place(self.chunks.alloc(), f)
+    }
+}
+
+/// The memory of an [IncrArena].
+struct Chunks<T> {
+    chunks: RefCell<Vec<*mut [MaybeUninit<T>]>>,
+
+    // The number of slots used in the last chunk.
+    used: Cell<usize>,
+}
+
+impl<T> Chunks<T> {
+    /// Returns a new slot, which lives until the arena is dropped.
+    fn alloc(&self) -> *mut MaybeUninit<T> {
+        let mut chunks = self.chunks.borrow_mut();
+
+        if self.used.get() == CHUNK_LEN {
+            chunks.push(Box::into_raw(Box::new_uninit_slice(CHUNK_LEN)));
+            self.used.set(0);
+        }
+
+        let index = self.used.get();
+        self.used.set(index + 1);
+
+        // SAFETY: the index is within the last chunk.
+        unsafe { chunks[chunks.len() - 1].cast::<MaybeUninit<T>>().add(index) }
+    }
+}
+
+impl<T> Drop for Chunks<T> {
+    fn drop(&mut self) {
+        for &chunk in self.chunks.get_mut().iter() {
+            // SAFETY: the chunk came from Box::into_raw, and the
+            // structs in it have been dropped.
+            drop(unsafe { Box::from_raw(chunk) });
+        }
+    }
+}
+
+/// Like [IncrArena], but allocates from a `bumpalo::Bump`. The
+/// structs are dropped with this arena, and the memory is freed with
+/// the `Bump`.
+///
+/// The arena owns the `Bump`, so it can't be reset, and its memory
+/// reused, while the structs are pinned in it, even if the arena is
+/// leaked.
+#[cfg(feature = "bumpalo")]
+pub struct IncrBumpArena<T: IncrStructInit> {
+    // Declared first, so the structs are dropped before the memory
+    // is freed.
+    placed: Placed<T>,
+
+    bump: bumpalo::Bump,
+}
+
+#[cfg(feature = "bumpalo")]
+impl<T: IncrStructInit> IncrBumpArena<T> {
+    /// Returns an empty arena, with a new `Bump`.
+    pub fn new() -> Self {
+        Self::from_bump(bumpalo::Bump::new())
+    }
+
+    /// Returns an empty arena, allocating from `bump`.
+    pub const fn from_bump(bump: bumpalo::Bump) -> Self {
+        Self {
+            placed: Placed::new(),
+            bump,
+        }
+    }
+
+    /// Returns the `Bump`, e.g. to allocate other values in it.
+    pub fn bump(&self) -> &bumpalo::Bump {
+        &self.bump
+    }
+
+    /// Drops the structs, and returns the `Bump`, e.g. to reset it.
+    pub fn into_bump(self) -> bumpalo::Bump {
+        let Self { placed, bump } = self;
+        drop(placed);
+
+        bump
+    }
+
+    /// Returns the number of structs constructed in the arena.
+    pub fn len(&self) -> usize {
+        self.placed.len()
+    }
+
+    /// Returns whether no structs have been constructed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(feature = "bumpalo")]
+impl<T: IncrStructInit> Default for IncrBumpArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "bumpalo")]
+impl<T: IncrStructInit> IncrArenaAlloc<T> for IncrBumpArena<T> {
+    unsafe fn try_alloc_with<F: FnOnce(&mut T)>(
+        &self,
+        f: F,
+    ) -> Result<Pin<&mut T>, Error<T::Error>> {
+        let raw = self
+            .bump
+            .alloc_layout(core::alloc::Layout::new::<T>())
+            .cast::<MaybeUninit<T>>()
+            .as_ptr();
+
+        self.placed.place(raw, f)
+    }
+}
@@ -0,0 +1,242 @@
+//! Cells that pin their contents, for shared, mutable structs.
+
+use core::cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut, UnsafeCell};
+use core::fmt;
+use core::mem::MaybeUninit;
+#[cfg(feature = "std")]
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::addr_of_mut;
+
+#[cfg(feature = "std")]
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError};
+
+/// A `RefCell` that pins its contents. Created by the generated
+/// `new_rc_refcell`.
+///
+/// A `RefMut` of a plain `RefCell` allows moving the struct out, e.g.
+/// with `core::mem::swap`, which would leave its tail fields
+/// dangling. This only lends out `Pin<RefMut<T>>` once the cell is
+/// pinned, so the struct can only be mutated through `Pin<&mut T>`,
+/// unless it is `Unpin`.
+pub struct PinRefCell<T> {
+    borrow: RefCell<()>,
+    value: UnsafeCell<T>,
+}
+
+impl<T> PinRefCell<T> {
+    /// Creates a cell containing the value.
+    pub const fn new(v: T) -> Self {
+        Self {
+            borrow: RefCell::new(()),
+            value: UnsafeCell::new(v),
+        }
+    }
+
+    /// Returns the value. The cell isn't pinned, since it is moved.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Immutably borrows the value, like `RefCell::borrow`.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
+    }
+
+    /// Like [PinRefCell::borrow], but returns an error if the value
+    /// is mutably borrowed.
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        let value = &self.value;
+
+        // SAFETY: the guard tracks the shared borrow.
+        self.borrow
+            .try_borrow()
+            .map(|guard| Ref::map(guard, |_| unsafe { &*value.get() }))
+    }
+
+    /// Mutably borrows the pinned value, like `RefCell::borrow_mut`.
+    pub fn borrow_mut(self: Pin<&Self>) -> Pin<RefMut<'_, T>> {
+        self.try_borrow_mut().expect("already borrowed")
+    }
+
+    /// Like [PinRefCell::borrow_mut], but returns an error if the
+    /// value is borrowed.
+    pub fn try_borrow_mut(self: Pin<&Self>) -> Result<Pin<RefMut<'_, T>>, BorrowMutError> {
+        let this = self.get_ref();
+        let value = &this.value;
+
+        // SAFETY: the guard tracks the exclusive borrow, and the value
+        // is pinned, since the cell is.
+        this.borrow
+            .try_borrow_mut()
+            .map(|guard| unsafe { Pin::new_unchecked(RefMut::map(guard, |_| &mut *value.get())) })
+    }
+
+    /// Returns the pinned value, without borrow tracking, since the
+    /// cell is exclusively borrowed.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // SAFETY: the value is pinned, since the cell is.
+        unsafe { self.map_unchecked_mut(|this| this.value.get_mut()) }
+    }
+
+    /// Writes an unborrowed cell containing `v` into `this`, and
+    /// returns the value, so it can be initialized in place.
+    ///
+    /// # Safety
+    ///
+    /// `this` must be valid for writes, and stay allocated for `'a`.
+    pub(crate) unsafe fn write_uninit<'a>(
+        this: *mut Self,
+        v: MaybeUninit<T>,
+    ) -> &'a mut MaybeUninit<T> {
+        addr_of_mut!((*this).borrow).write(RefCell::new(()));
+        write_value(addr_of_mut!((*this).value), v)
+    }
+}
+
+impl<T> From<T> for PinRefCell<T> {
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T> fmt::Debug for PinRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PinRefCell(..)")
+    }
+}
+
+/// Writes a partial struct into the `UnsafeCell` of a cell.
+///
+/// # Safety
+///
+/// See [PinRefCell::write_uninit].
+unsafe fn write_value<'a, T>(
+    value: *mut UnsafeCell<T>,
+    v: MaybeUninit<T>,
+) -> &'a mut MaybeUninit<T> {
+    // SAFETY: `UnsafeCell<T>` has the same layout as `T`.
+    let value = UnsafeCell::raw_get(value) as *mut MaybeUninit<T>;
+    value.write(v);
+    &mut *value
+}
+
+/// Like [PinRefCell], but a `Mutex`. Created by the generated
+/// `new_arc_mutex`.
+#[cfg(feature = "std")]
+pub struct PinMutex<T> {
+    lock: Mutex<()>,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the value is only reachable while holding the lock, like in
+// a Mutex.
+#[cfg(feature = "std")]
+unsafe impl<T: Send> Sync for PinMutex<T> {}
+
+#[cfg(feature = "std")]
+impl<T> PinMutex<T> {
+    /// Creates a mutex containing the value.
+    pub const fn new(v: T) -> Self {
+        Self {
+            lock: Mutex::new(()),
+            value: UnsafeCell::new(v),
+        }
+    }
+
+    /// Returns the value. The mutex isn't pinned, since it is moved.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Locks the mutex, like `Mutex::lock`, and returns a guard to
+    /// the pinned value.
+    pub fn lock(self: Pin<&Self>) -> LockResult<Pin<PinMutexGuard<'_, T>>> {
+        let this = self.get_ref();
+        let wrap = |guard| {
+            // SAFETY: the value is pinned, since the mutex is.
+            unsafe {
+                Pin::new_unchecked(PinMutexGuard {
+                    _guard: guard,
+                    value: &this.value,
+                })
+            }
+        };
+
+        match this.lock.lock() {
+            Ok(guard) => Ok(wrap(guard)),
+            Err(err) => Err(PoisonError::new(wrap(err.into_inner()))),
+        }
+    }
+
+    /// Returns true if a thread panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.lock.is_poisoned()
+    }
+
+    /// Returns the pinned value, without locking, since the mutex is
+    /// exclusively borrowed.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // SAFETY: the value is pinned, since the mutex is.
+        unsafe { self.map_unchecked_mut(|this| this.value.get_mut()) }
+    }
+
+    /// Like [PinRefCell::write_uninit], but for an unlocked mutex.
+    ///
+    /// # Safety
+    ///
+    /// See [PinRefCell::write_uninit].
+    pub(crate) unsafe fn write_uninit<'a>(
+        this: *mut Self,
+        v: MaybeUninit<T>,
+    ) -> &'a mut MaybeUninit<T> {
+        addr_of_mut!((*this).lock).write(Mutex::new(()));
+        write_value(addr_of_mut!((*this).value), v)
+    }
+
+    /// Drops the mutex written by [PinMutex::write_uninit], but not
+    /// the value.
+    ///
+    /// # Safety
+    ///
+    /// `this` must have been written by `write_uninit`, and not be
+    /// used afterwards.
+    pub(crate) unsafe fn drop_uninit(this: *mut Self) {
+        core::ptr::drop_in_place(addr_of_mut!((*this).lock));
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for PinMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PinMutex(..)")
+    }
+}
+
+/// The guard returned by [PinMutex::lock]. It is always pinned, so
+/// the value can only be mutated through `Pin::as_mut`, unless it is
+/// `Unpin`.
+#[cfg(feature = "std")]
+pub struct PinMutexGuard<'a, T> {
+    _guard: MutexGuard<'a, ()>,
+    value: &'a UnsafeCell<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Deref for PinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the lock is held.
+        unsafe { &*self.value.get() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> DerefMut for PinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the lock is held, and the guard is only handed out
+        // pinned.
+        unsafe { &mut *self.value.get() }
+    }
+}
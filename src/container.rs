@@ -0,0 +1,162 @@
+//! Pluggable smart pointers for constructing self-referencing structs.
+
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+
+use crate::internal::{expect_init, IncrStructInit};
+use crate::Error;
+
+/// A smart pointer that a struct can be constructed in, in place,
+/// using the generated `AStruct::new_in::<P>` function. It is
+/// implemented for `Box`, `Rc` and `Arc` (and `triomphe::Arc` with
+/// the `triomphe` feature,) and can be implemented for custom
+/// pointers, e.g. into a pool.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use std::sync::Arc;
+/// use incrstruct::IncrStruct;
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let my_arc = AStruct::new_in::<Arc<_>>(RefCell::new(42));
+///
+/// assert_eq!(*my_arc.b, 42);
+/// ```
+///
+/// # Safety
+///
+/// The memory returned by `alloc_uninit` must not move, and must
+/// stay valid until it is passed to `assume_init_pin` or
+/// `dealloc_uninit`.
+pub unsafe trait IncrContainer<T>: Sized {
+    /// Allocates memory for a `T`, and returns an owning pointer to
+    /// it.
+    fn alloc_uninit() -> *mut MaybeUninit<T>;
+
+    /// Takes ownership of an initialized `T`, allocated by
+    /// `alloc_uninit`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `alloc_uninit`, and be fully
+    /// initialized.
+    unsafe fn assume_init_pin(ptr: *mut T) -> Pin<Self>;
+
+    /// Frees memory allocated by `alloc_uninit`, without dropping
+    /// the contents.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `alloc_uninit`.
+    unsafe fn dealloc_uninit(ptr: *mut MaybeUninit<T>);
+}
+
+unsafe impl<T> IncrContainer<T> for Box<T> {
+    fn alloc_uninit() -> *mut MaybeUninit<T> {
+        Box::into_raw(Box::new_uninit())
+    }
+
+    unsafe fn assume_init_pin(ptr: *mut T) -> Pin<Self> {
+        Pin::new_unchecked(Box::from_raw(ptr))
+    }
+
+    unsafe fn dealloc_uninit(ptr: *mut MaybeUninit<T>) {
+        _ = Box::from_raw(ptr);
+    }
+}
+
+unsafe impl<T> IncrContainer<T> for Rc<T> {
+    fn alloc_uninit() -> *mut MaybeUninit<T> {
+        Rc::into_raw(Rc::<T>::new_uninit()) as *mut _
+    }
+
+    unsafe fn assume_init_pin(ptr: *mut T) -> Pin<Self> {
+        Pin::new_unchecked(Rc::from_raw(ptr))
+    }
+
+    unsafe fn dealloc_uninit(ptr: *mut MaybeUninit<T>) {
+        _ = Rc::from_raw(ptr);
+    }
+}
+
+unsafe impl<T> IncrContainer<T> for Arc<T> {
+    fn alloc_uninit() -> *mut MaybeUninit<T> {
+        Arc::into_raw(Arc::<T>::new_uninit()) as *mut _
+    }
+
+    unsafe fn assume_init_pin(ptr: *mut T) -> Pin<Self> {
+        Pin::new_unchecked(Arc::from_raw(ptr))
+    }
+
+    unsafe fn dealloc_uninit(ptr: *mut MaybeUninit<T>) {
+        _ = Arc::from_raw(ptr);
+    }
+}
+
+#[cfg(feature = "triomphe")]
+unsafe impl<T> IncrContainer<T> for triomphe::Arc<T> {
+    fn alloc_uninit() -> *mut MaybeUninit<T> {
+        triomphe::Arc::into_raw(triomphe::Arc::new(MaybeUninit::<T>::uninit())) as *mut _
+    }
+
+    unsafe fn assume_init_pin(ptr: *mut T) -> Pin<Self> {
+        Pin::new_unchecked(triomphe::Arc::from_raw(ptr))
+    }
+
+    unsafe fn dealloc_uninit(ptr: *mut MaybeUninit<T>) {
+        _ = triomphe::Arc::from_raw(ptr);
+    }
+}
+
+/// A collection that a struct can be constructed in, using the
+/// generated `AStruct::emplace(&mut pool, heads...)` function. It is
+/// implemented by [IncrSlab](crate::IncrSlab), returning the key of
+/// the slot, and [PinnedVec](crate::PinnedVec), returning a reference
+/// to the new element.
+pub trait IncrEmplace<T: IncrStructInit> {
+    /// What `emplace` returns to identify the new struct.
+    type Key<'a>
+    where
+        Self: 'a;
+
+    /// Constructs a struct in the collection, using `f` to write the
+    /// head fields in place.
+    ///
+    /// # Safety
+    ///
+    /// `f` must initialize all head fields, and only the head fields.
+    unsafe fn try_emplace_with<F: FnOnce(&mut T)>(
+        &mut self,
+        f: F,
+    ) -> Result<Self::Key<'_>, Error<T::Error>>;
+
+    /// Like [IncrEmplace::try_emplace_with], but panics on state
+    /// errors.
+    ///
+    /// # Safety
+    ///
+    /// See [IncrEmplace::try_emplace_with].
+    unsafe fn emplace_with<F: FnOnce(&mut T)>(&mut self, f: F) -> Result<Self::Key<'_>, T::Error> {
+        self.try_emplace_with(f)
+            .map_err(|err| expect_init(err, "emplace"))
+    }
+}
@@ -1,226 +1,2280 @@
 //! Internal functions used by the derive macro.
 
+#[cfg(feature = "async")]
+use core::future::Future;
 use core::marker::PhantomPinned;
 use core::mem::MaybeUninit;
+use core::ops::Range;
 use core::pin::Pin;
 use core::ptr::drop_in_place;
-use std::rc::Rc;
 
-use crate::Header;
+#[cfg(feature = "alloc")]
+use crate::IncrContainer;
+use crate::{Address, AtomicU8, Error, Header, Ordering, State, StateError, SyncHeader, TailBits};
+
+/// Re-exported for auto-generated code, which can't assume the
+/// `alloc` crate is linked.
+#[cfg(feature = "alloc")]
+pub use alloc::{boxed::Box, rc::Rc, sync::Arc};
 
 /// A trait implemented by all structures using incrstruct. The
 /// implementation is auto-generated by the macros.
 ///
+/// Tail fields are initialized by [IncrStructInit] or, with
+/// `#[init_args]`, by [IncrStructInitWith].
+///
 /// Used by auto-generated code. This is not an external API.
-pub trait IncrStructInit: Sized {
+pub trait IncrStructBase: Sized {
     type Error;
 
-    /// Initializes all leaf fields, in dependency order. All head
-    /// fields have already been initialized, and all tail fields are
-    /// uninitialized. When this function returns, all tail fields of
-    /// the struct must have been initialized.
-    unsafe fn init(this: *mut Self) -> Result<(), Self::Error>;
+    /// The bitset recording which tail fields are initialized.
+    type Tails: TailBits;
+
+    /// The bitset recording which tail fields are dirty.
+    type Dirty: TailBits;
+
+    /// The number of tail initialization phases. Always at least one.
+    const PHASES: u8;
 
     /// Drops all head fields, going in normal drop order. It is only
-    /// called when all head fields are initialized.
-    unsafe fn drop_uninit_in_place(this: &mut MaybeUninit<Self>);
+    /// called when all head fields are initialized. Returns an error,
+    /// without dropping anything, if the struct is not `Uninited`.
+    ///
+    /// # Safety
+    ///
+    /// All head fields must be initialized, and must not be used
+    /// again.
+    unsafe fn drop_uninit_in_place(this: &mut MaybeUninit<Self>) -> Result<(), StateError>;
 
-    /// Drops all tail fields starting at `at`, going in normal drop
-    /// order. A zero drops all tail fields. It is only called when
-    /// the referenced tail fields have been initialized.
-    unsafe fn drop_tail_in_place(this: &mut Self, at: usize);
+    /// Drops the tail fields whose index, in initialization order,
+    /// is in `range`, going in normal drop order. Only fields marked
+    /// as initialized in the header are dropped.
+    ///
+    /// # Safety
+    ///
+    /// The header must mark exactly the initialized tail fields. The
+    /// dropped fields must not be used again before they are
+    /// initialized again.
+    unsafe fn drop_tail_in_place(this: &mut Self, range: Range<usize>);
+
+    /// Returns the number of tail fields in phases up to and
+    /// including `phase`. This is the end of the phase, as an index
+    /// in initialization order.
+    fn phase_end(phase: u8) -> usize;
 
     /// Returns a reference to the incrstruct header. This field
     /// should be last, so it's dropped last.
-    fn header<'b>(this: &'b mut Self) -> &'b mut Header;
+    fn header(this: &mut Self) -> &mut Header<Self::Tails, Self::Dirty>;
+
+    /// Writes a new header, for an uninitialized struct. Overridden
+    /// with `#[incrstruct(sync)]`, to also write the sync state.
+    ///
+    /// # Safety
+    ///
+    /// The old header isn't read or dropped, so the struct must not
+    /// be in use.
+    unsafe fn write_header(this: &mut Self) {
+        core::ptr::write(Self::header(this), Header::default());
+    }
 }
 
-/// Creates a `Box` from the given, partial struct. The function
-/// initializes all fields. The input is normally created using
-/// `T::new_uninit`.
+/// Implemented by the header types, to name their bitsets.
 ///
-/// Used by auto-generated code.
-pub fn new_box<T: IncrStructInit>(v: MaybeUninit<T>) -> Result<Pin<Box<T>>, T::Error> {
-    let raw = Box::into_raw(Box::new(v));
-    // SAFETY: we keep a pin until the Box is reassembled.
-    let _pinned_raw = unsafe { Pin::new_unchecked(&mut *raw) };
+/// Used by auto-generated code. This is not an external API.
+pub trait HeaderBits {
+    type Tails: TailBits;
+    type Dirty: TailBits;
+}
 
-    // SAFETY: we have taken ownership of the pointer to uninitialized Box data.
-    match unsafe { ensure_init(&mut *raw) } {
-        Ok(ptr) => {
-            // SAFETY: the data is fully initialized, and Box can take ownership.
-            Ok(unsafe { Pin::new_unchecked(Box::from_raw(ptr as *mut _)) })
-        }
-        Err(err) => {
-            // SAFETY: only head data is initialized.
-            unsafe {
-                T::drop_uninit_in_place(&mut *raw);
-                _ = Box::from_raw(raw);
-            }
-            Err(err)
-        }
+impl<T: TailBits, D: TailBits> HeaderBits for Header<T, D> {
+    type Tails = T;
+    type Dirty = D;
+}
+
+impl<T: TailBits, D: TailBits> HeaderBits for SyncHeader<T, D> {
+    type Tails = T;
+    type Dirty = D;
+}
+
+/// Implemented by the bitsets that have room for dirty tracking.
+///
+/// Used by auto-generated code. This is not an external API.
+pub trait DirtyBits: TailBits {}
+
+impl DirtyBits for u8 {}
+impl DirtyBits for u16 {}
+impl DirtyBits for u32 {}
+impl DirtyBits for u64 {}
+
+/// Implemented by structs whose tail fields can be initialized
+/// without arguments, i.e. without `#[init_args]`. Generic entry
+/// points, like `IncrVec` and `IncrArena`, require this.
+///
+/// Used by auto-generated code. This is not an external API.
+pub trait IncrStructInit: IncrStructBase {
+    /// Initializes all tail fields in the given phase, in dependency
+    /// order. All head fields, and the tail fields of earlier phases,
+    /// have already been initialized. When this function returns
+    /// successfully, all tail fields of the phase must have been
+    /// initialized. On error, none of them are.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to a pinned struct where all head fields,
+    /// and the tail fields of earlier phases, are initialized, and
+    /// the tail fields of `phase` are not.
+    unsafe fn init_phase(this: *mut Self, phase: u8) -> Result<(), Self::Error>;
+}
+
+/// Implemented instead of [IncrStructInit] by structs using
+/// `#[init_args]`. `Args` is a tuple of references to the arguments,
+/// in declaration order.
+///
+/// Used by auto-generated code. This is not an external API.
+pub trait IncrStructInitWith<Args>: IncrStructBase {
+    /// Like [IncrStructInit::init_phase], but passes `args` on to the
+    /// `init_field_myfield` functions.
+    ///
+    /// # Safety
+    ///
+    /// See [IncrStructInit::init_phase].
+    unsafe fn init_phase_with(this: *mut Self, phase: u8, args: Args) -> Result<(), Self::Error>;
+}
+
+/// Implemented by structs using `#[incrstruct(async_init)]`, whose
+/// tail fields are initialized by async functions.
+///
+/// Used by auto-generated code. This is not an external API.
+#[cfg(feature = "async")]
+pub trait IncrStructInitAsync: IncrStructInit {
+    /// Like [IncrStructInit::init_phase], but awaits each tail
+    /// field.
+    ///
+    /// # Safety
+    ///
+    /// See [IncrStructInit::init_phase].
+    unsafe fn init_phase_async(
+        this: &mut Self,
+        phase: u8,
+    ) -> impl Future<Output = Result<(), Self::Error>> + '_;
+}
+
+/// Implemented by structs using `#[incrstruct(default)]`, where all
+/// head fields implement `Default`.
+///
+/// Used by auto-generated code. This is not an external API.
+pub trait IncrStructDefault: IncrStructInit {
+    /// Writes default head fields, like the closure passed to
+    /// [new_box].
+    ///
+    /// # Safety
+    ///
+    /// The head fields of `out` are overwritten without being
+    /// dropped, so they must not be initialized.
+    unsafe fn write_default_heads(out: &mut Self);
+
+    /// Like `new_uninit`, but with default head fields.
+    ///
+    /// # Safety
+    ///
+    /// See [new_uninit].
+    unsafe fn new_uninit_default() -> MaybeUninit<Self> {
+        new_uninit(|out| Self::write_default_heads(out))
     }
 }
 
-/// Creates a `Rc` from the given, partial struct. The function
-/// initializes all fields. The input is normally created using
-/// `T::new_uninit`.
+/// Implemented by structs using `#[incrstruct(zeroed)]`.
+///
+/// Used by auto-generated code. This is not an external API.
+///
+/// # Safety
+///
+/// All head fields must be valid when zeroed, i.e. implement
+/// `bytemuck::Zeroable`.
+#[cfg(feature = "bytemuck")]
+pub unsafe trait IncrStructZeroed: IncrStructInit {}
+
+/// Creates a `Box` with all head fields zeroed, without writing
+/// them. This is faster than [new_box] for large heads.
 ///
 /// Used by auto-generated code.
-pub fn new_rc<T: IncrStructInit>(v: MaybeUninit<T>) -> Result<Pin<Rc<T>>, T::Error> {
-    let raw = Rc::into_raw(Rc::new(v)) as *mut _;
-    // SAFETY: we keep a pin until the Rc is reassembled.
-    let _pinned_raw = unsafe { Pin::new_unchecked(&mut *raw) };
+#[cfg(all(feature = "alloc", feature = "bytemuck"))]
+pub fn new_box_zeroed<T: IncrStructZeroed>() -> Result<Pin<Box<T>>, T::Error> {
+    try_new_box_zeroed().map_err(|err| expect_init(err, "new_box_zeroed"))
+}
 
-    // SAFETY: we have taken ownership of the pointer to uninitialized Box data.
-    match unsafe { ensure_init(&mut *raw) } {
-        Ok(ptr) => {
-            // SAFETY: the data is fully initialized, and Box can take ownership.
-            Ok(unsafe { Pin::new_unchecked(Rc::from_raw(ptr as *mut _)) })
-        }
-        Err(err) => {
-            // SAFETY: only head data is initialized.
-            unsafe {
-                T::drop_uninit_in_place(&mut *raw);
-                _ = Rc::from_raw(raw);
-            }
-            Err(err)
-        }
+/// Like [new_box_zeroed], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+#[cfg(all(feature = "alloc", feature = "bytemuck"))]
+pub fn try_new_box_zeroed<T: IncrStructZeroed>() -> Result<Pin<Box<T>>, Error<T::Error>> {
+    let raw = Box::into_raw(Box::<T>::new_zeroed());
+
+    // SAFETY: the allocation is a Box, like from `alloc_uninit`, and
+    // all head fields are Zeroable, so only the header needs to be
+    // written.
+    unsafe {
+        try_init_in_with_init::<Box<T>, T, _, _>(
+            raw,
+            |_| {},
+            |this, phase| T::init_phase(this, phase),
+        )
     }
 }
 
-/// Forces initialization of `this`, even if it was previously initialized.
+/// Opens the file at `path`, and maps it read-only.
 ///
-/// This is useful when a T has moved, and the self-referencing tail
-/// fields must be synchronized.
-///
-/// The caller is reponsible for keeping `this` pinned.
+/// Used by auto-generated code.
 ///
 /// # Safety
 ///
-/// This function is always safe to call, but only makes sense to call
-/// after you have used an unsafe function to move a pinned value
-/// somewhere else.
-pub fn force_init<T: IncrStructInit>(this: &mut T) -> Result<(), T::Error> {
-    match <T as IncrStructInit>::header(this) {
-        Header::Uninited => {}
-        // SAFETY: we are now making `this` back into a partially
-        // initialized struct, the same as Uninited.
-        Header::Inited(_) => unsafe {
-            T::drop_tail_in_place(this, 0);
-        },
-        Header::Initing => panic!("Recursive call to force_init"),
-    };
+/// See [memmap2::Mmap::map]. The file must not be modified or
+/// truncated while it is mapped.
+#[cfg(feature = "memmap2")]
+pub unsafe fn map_path(path: impl AsRef<std::path::Path>) -> std::io::Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
 
-    // SAFETY: tail fields are uninitialized.
-    unsafe { do_init(this) }
+    // SAFETY: forwarded to the caller.
+    unsafe { memmap2::Mmap::map(&file) }
 }
 
-/// Creates a partially initialized struct. The `f` function
-/// initializes all head fields, and only the head fields.
+/// Creates a `Box`, writing the head fields directly into the heap
+/// allocation, using `f`. The tail fields are then initialized in
+/// place, so the struct never goes through the stack, even if it is
+/// large.
 ///
 /// Used by auto-generated code.
 ///
 /// # Safety
 ///
-/// Calling this function yields a value that will not call
-/// destructors. Callers must use `drop_uninit` until a successful
-/// call to `ensure_init`. After `ensure_init`, the caller owns the
-/// `*mut Self`, and normal drop rules apply.
-pub unsafe fn new_uninit<T: IncrStructInit, F: FnOnce(&mut T)>(f: F) -> MaybeUninit<T> {
-    let mut out = MaybeUninit::<T>::uninit();
+/// `f` must initialize all head fields, and only the head fields. For
+/// an enum, it must first write the tag.
+#[cfg(feature = "alloc")]
+pub unsafe fn new_box<T: IncrStructInit, F: FnOnce(&mut T)>(f: F) -> Result<Pin<Box<T>>, T::Error> {
+    try_new_box(f).map_err(|err| expect_init(err, "new_box"))
+}
 
-    let this = &mut *out.as_mut_ptr();
-    core::ptr::write(<T as IncrStructInit>::header(this), Header::Uninited);
+/// Like [new_box], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_box<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<Box<T>>, Error<T::Error>> {
+    try_new_in(f)
+}
 
-    f(this);
+/// Creates a struct in the container `P`, writing the head fields
+/// directly into the allocation, using `f`, like [new_box].
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_in<P: IncrContainer<T>, T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<P>, T::Error> {
+    try_new_in(f).map_err(|err| expect_init(err, "new_in"))
+}
 
-    out
+/// Like [new_in], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_in<P: IncrContainer<T>, T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<P>, Error<T::Error>> {
+    try_new_in_with_init(f, |this, phase| T::init_phase(this, phase))
 }
 
-/// Finalizes a partially initialized struct. The returned reference
-/// is guaranteed to be the same as `this`, and is only returned as a
-/// type-safety convenience.
+/// Like [new_in], but calls `init` to initialize each phase, like
+/// [ensure_init_with].
+///
+/// Used by auto-generated code.
 ///
-/// If an error occurs, all tail fields are dropped before the
-/// function returns. Thus, it's safe to call `ensure_init` again. The
-/// caller is responsible for calling `drop_uninit` if required.
+/// # Safety
 ///
-/// The caller is responsible for pinning `this`.
+/// See [new_box] and [ensure_init_with].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_in_with_init<P, T, F, G>(f: F, init: G) -> Result<Pin<P>, T::Error>
+where
+    P: IncrContainer<T>,
+    T: IncrStructBase,
+    F: FnOnce(&mut T),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_new_in_with_init(f, init).map_err(|err| expect_init(err, "new_in_with_init"))
+}
+
+/// Like [new_in_with_init], but returns state errors instead of
+/// panicking.
 ///
 /// Used by auto-generated code.
 ///
 /// # Safety
 ///
-/// This function assumes head fields are initialized and that tail
-/// fields are not .
-pub unsafe fn ensure_init<T: IncrStructInit>(
-    this: &mut MaybeUninit<T>,
-) -> Result<&mut T, T::Error> {
-    let r = &mut *this.as_mut_ptr();
+/// See [new_in_with_init].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_in_with_init<P, T, F, G>(f: F, init: G) -> Result<Pin<P>, Error<T::Error>>
+where
+    P: IncrContainer<T>,
+    T: IncrStructBase,
+    F: FnOnce(&mut T),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_init_in_with_init::<P, T, F, G>(P::alloc_uninit(), f, init)
+}
 
-    match <T as IncrStructInit>::header(r) {
-        Header::Uninited => do_init(r)?,
-        _ => panic!("ensure_init called on already initialized struct"),
+/// Initializes the head fields of a new allocation, `raw`, using `f`,
+/// and then the tail fields, using `init`.
+///
+/// # Safety
+///
+/// `raw` must come from `P::alloc_uninit`. See [new_in_with_init].
+#[cfg(feature = "alloc")]
+unsafe fn try_init_in_with_init<P, T, F, G>(
+    raw: *mut MaybeUninit<T>,
+    f: F,
+    init: G,
+) -> Result<Pin<P>, Error<T::Error>>
+where
+    P: IncrContainer<T>,
+    T: IncrStructBase,
+    F: FnOnce(&mut T),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    // SAFETY: the allocation is new, so the pointer is unique.
+    init_heads(&mut *raw, f);
+
+    // Drops the partial struct on error, or if `init` panics.
+    let uninit = UninitGuard::<P, T> {
+        raw,
+        container: core::marker::PhantomData,
     };
 
-    Ok(this.assume_init_mut())
+    // SAFETY: we keep a pin until the container is reassembled.
+    let _pinned_raw = Pin::new_unchecked(&mut *raw);
+
+    let ptr = try_ensure_init_with(&mut *raw, init)? as *mut T;
+    core::mem::forget(uninit);
+
+    // SAFETY: the data is fully initialized.
+    Ok(P::assume_init_pin(ptr))
 }
 
-/// Drops a partially initialized struct. Tail fields are assumed to
-/// be uninitialized, while all head fields are assumed to be
-/// initialized.
+/// Like [new_in_with_init] for an `Rc`, but `f` also gets a weak
+/// pointer to the allocation, to store in a head field.
 ///
 /// Used by auto-generated code.
 ///
 /// # Safety
 ///
-/// It only drops head fields. This function panics if the struct is
-/// not `Uninited`.
-pub unsafe fn drop_uninit_in_place<T: IncrStructInit, F: FnOnce(&mut T)>(
-    this: &mut MaybeUninit<T>,
+/// See [new_in_with_init]. No tail field may borrow the weak pointer,
+/// since it could then be upgraded while the struct is partial.
+#[cfg(feature = "alloc")]
+pub unsafe fn new_rc_cyclic_with_init<T, F, G>(f: F, init: G) -> Result<Pin<Rc<T>>, T::Error>
+where
+    T: IncrStructBase,
+    F: FnOnce(&mut T, crate::PinRcWeak<T>),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_new_rc_cyclic_with_init(f, init).map_err(|err| expect_init(err, "new_rc_cyclic"))
+}
+
+/// Like [new_rc_cyclic_with_init], but returns state errors instead
+/// of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_rc_cyclic_with_init].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_rc_cyclic_with_init<T, F, G>(
     f: F,
-) {
-    let r = &mut *this.as_mut_ptr();
+    init: G,
+) -> Result<Pin<Rc<T>>, Error<T::Error>>
+where
+    T: IncrStructBase,
+    F: FnOnce(&mut T, crate::PinRcWeak<T>),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    let raw = <Rc<T> as IncrContainer<T>>::alloc_uninit();
+    // SAFETY: the allocation comes from an Rc<MaybeUninit<T>>, which
+    // has the same layout. The Rc is never dropped, since the
+    // allocation is still owned by `raw`.
+    let weak = crate::PinRcWeak(Rc::downgrade(&core::mem::ManuallyDrop::new(Rc::from_raw(
+        raw as *const T,
+    ))));
+
+    try_init_in_with_init::<Rc<T>, T, _, G>(raw, |this| f(this, weak), init)
+}
+
+/// Like [new_rc_cyclic_with_init], but for an `Arc`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_rc_cyclic_with_init].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_arc_cyclic_with_init<T, F, G>(f: F, init: G) -> Result<Pin<Arc<T>>, T::Error>
+where
+    T: IncrStructBase,
+    F: FnOnce(&mut T, crate::PinArcWeak<T>),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_new_arc_cyclic_with_init(f, init).map_err(|err| expect_init(err, "new_arc_cyclic"))
+}
+
+/// Like [new_arc_cyclic_with_init], but returns state errors instead
+/// of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_rc_cyclic_with_init].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_arc_cyclic_with_init<T, F, G>(
+    f: F,
+    init: G,
+) -> Result<Pin<Arc<T>>, Error<T::Error>>
+where
+    T: IncrStructBase,
+    F: FnOnce(&mut T, crate::PinArcWeak<T>),
+    G: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    let raw = <Arc<T> as IncrContainer<T>>::alloc_uninit();
+    // SAFETY: see try_new_rc_cyclic_with_init.
+    let weak = crate::PinArcWeak(Arc::downgrade(&core::mem::ManuallyDrop::new(
+        Arc::from_raw(raw as *const T),
+    )));
+
+    try_init_in_with_init::<Arc<T>, T, _, G>(raw, |this| f(this, weak), init)
+}
+
+/// Like [new_in], but awaits the tail fields, using
+/// [ensure_init_async]. If the returned future is dropped before it
+/// completes, the partial struct is dropped.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(all(feature = "alloc", feature = "async"))]
+pub async unsafe fn new_in_async<P, T, F>(f: F) -> Result<Pin<P>, T::Error>
+where
+    P: IncrContainer<T>,
+    T: IncrStructInitAsync,
+    F: FnOnce(&mut T),
+{
+    try_new_in_async(f)
+        .await
+        .map_err(|err| expect_init(err, "new_in_async"))
+}
+
+/// Like [new_in_async], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(all(feature = "alloc", feature = "async"))]
+pub async unsafe fn try_new_in_async<P, T, F>(f: F) -> Result<Pin<P>, Error<T::Error>>
+where
+    P: IncrContainer<T>,
+    T: IncrStructInitAsync,
+    F: FnOnce(&mut T),
+{
+    let raw = P::alloc_uninit();
+    // SAFETY: the allocation is new, so the pointer is unique.
+    init_heads(&mut *raw, f);
+
+    // Drops the partial struct on error, or if the future is dropped.
+    let uninit = UninitGuard::<P, T> {
+        raw,
+        container: core::marker::PhantomData,
+    };
+
+    // SAFETY: the allocation never moves.
+    let ptr = try_ensure_init_async(&mut *raw).await? as *mut T;
+    core::mem::forget(uninit);
+
+    Ok(P::assume_init_pin(ptr))
+}
+
+/// Owns a partial struct in a container, and drops it, unless
+/// forgotten.
+#[cfg(feature = "alloc")]
+struct UninitGuard<P: IncrContainer<T>, T: IncrStructBase> {
+    raw: *mut MaybeUninit<T>,
+    container: core::marker::PhantomData<P>,
+}
 
-    match <T as IncrStructInit>::header(r) {
-        Header::Uninited => {
-            f(r);
+// SAFETY: the guard owns the struct.
+#[cfg(feature = "alloc")]
+unsafe impl<P: IncrContainer<T>, T: IncrStructBase + Send> Send for UninitGuard<P, T> {}
 
-            drop_in_place(<T as IncrStructInit>::header(r));
+#[cfg(feature = "alloc")]
+impl<P: IncrContainer<T>, T: IncrStructBase> Drop for UninitGuard<P, T> {
+    fn drop(&mut self) {
+        // SAFETY: only head data, and completed phases, are
+        // initialized.
+        unsafe {
+            _ = T::drop_uninit_in_place(&mut *self.raw);
+            P::dealloc_uninit(self.raw);
         }
-        Header::Inited(_) => panic!("drop_uninit_in_place on initialized value"),
-        Header::Initing => panic!("drop_uninit_in_place during initialization"),
     }
 }
 
-/// Performs initialization of tail fields, without sanity checking.
+/// Creates a plain value from the given, partial struct. The
+/// function initializes all fields. The input is normally created
+/// using `T::new_uninit`.
 ///
-/// See [ensure_init] and [force_init].
-unsafe fn do_init<T: IncrStructInit>(this: &mut T) -> Result<(), T::Error> {
-    *<T as IncrStructInit>::header(this) = Header::Initing;
-
-    // If we panic in the middle of init(), data will
-    // leak without being dropped, even if
-    // drop_uninit_in_place is invoked later.
-    //
-    // It seems most things in the Rust standard library
-    // are not unwind-safe, e.g. unlocking mutexes on
-    // panic.
-    //
-    // SAFETY: the code above has made the struct partially
-    // initialized.
-
-    match T::init(this) {
-        Ok(_) => {
-            *<T as IncrStructInit>::header(this) = Header::Inited(PhantomPinned);
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// All fields borrowed by tail fields must be behind stable heap
+/// allocations, which moving the value doesn't assert unique access
+/// to, so the value can move without invalidating the tails. This is
+/// what `#[incrstruct(boxed)]` guarantees.
+pub unsafe fn new_movable<T: IncrStructInit>(v: MaybeUninit<T>) -> Result<T, T::Error> {
+    try_new_movable(v).map_err(|err| expect_init(err, "new_movable"))
+}
 
-            Ok(())
-        }
+/// Like [new_movable], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_movable].
+pub unsafe fn try_new_movable<T: IncrStructInit>(
+    mut v: MaybeUninit<T>,
+) -> Result<T, Error<T::Error>> {
+    match try_ensure_init(&mut v) {
+        Ok(_) => Ok(v.assume_init()),
         Err(err) => {
-            *<T as IncrStructInit>::header(this) = Header::Uninited;
+            // SAFETY: only head data is initialized.
+            _ = T::drop_uninit_in_place(&mut v);
+            Err(err)
+        }
+    }
+}
+
+/// Creates a `Box` from the given, partial struct, without pinning
+/// it. See [new_movable].
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_movable].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_movable_box<T: IncrStructInit>(v: MaybeUninit<T>) -> Result<Box<T>, T::Error> {
+    new_movable(v).map(Box::new)
+}
+
+/// Like [new_movable_box], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_movable].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_movable_box<T: IncrStructInit>(
+    v: MaybeUninit<T>,
+) -> Result<Box<T>, Error<T::Error>> {
+    try_new_movable(v).map(Box::new)
+}
+
+/// Creates a `Rc<PinRefCell<T>>` from the given, partial struct. The
+/// function initializes all fields, in place in the allocation. The
+/// input is normally created using `T::new_uninit`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// All head fields of `v` must be initialized.
+#[cfg(feature = "alloc")]
+pub unsafe fn new_rc_refcell<T: IncrStructInit>(
+    v: MaybeUninit<T>,
+) -> Result<Pin<Rc<crate::PinRefCell<T>>>, T::Error> {
+    try_new_rc_refcell(v).map_err(|err| expect_init(err, "new_rc_refcell"))
+}
+
+/// Like [new_rc_refcell], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_rc_refcell].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_rc_refcell<T: IncrStructInit>(
+    v: MaybeUninit<T>,
+) -> Result<Pin<Rc<crate::PinRefCell<T>>>, Error<T::Error>> {
+    let mut rc = Rc::<crate::PinRefCell<T>>::new_uninit();
+    // SAFETY: the Rc is newly created, so it is unique.
+    let cell = Rc::get_mut(&mut rc).unwrap_unchecked().as_mut_ptr();
+    let inner = crate::PinRefCell::write_uninit(cell, v);
+
+    // On error, the Rc frees the allocation without dropping the
+    // cell, which has nothing to drop.
+    try_init_cell(inner)?;
+
+    // SAFETY: the cell and the struct are fully initialized, and the
+    // struct has not moved since.
+    Ok(Pin::new_unchecked(rc.assume_init()))
+}
+
+/// Creates an `Arc<PinMutex<T>>` from the given, partial struct. The
+/// function initializes all fields, in place in the allocation. The
+/// input is normally created using `T::new_uninit`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_rc_refcell].
+#[cfg(feature = "std")]
+pub unsafe fn new_arc_mutex<T: IncrStructInit>(
+    v: MaybeUninit<T>,
+) -> Result<Pin<Arc<crate::PinMutex<T>>>, T::Error> {
+    try_new_arc_mutex(v).map_err(|err| expect_init(err, "new_arc_mutex"))
+}
+
+/// Like [new_arc_mutex], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_arc_mutex].
+#[cfg(feature = "std")]
+pub unsafe fn try_new_arc_mutex<T: IncrStructInit>(
+    v: MaybeUninit<T>,
+) -> Result<Pin<Arc<crate::PinMutex<T>>>, Error<T::Error>> {
+    let mut arc = Arc::<crate::PinMutex<T>>::new_uninit();
+    // SAFETY: the Arc is newly created, so it is unique.
+    let mutex = Arc::get_mut(&mut arc).unwrap_unchecked().as_mut_ptr();
+    let inner = crate::PinMutex::write_uninit(mutex, v);
+
+    if let Err(err) = try_init_cell(inner) {
+        crate::PinMutex::drop_uninit(mutex);
+        return Err(err);
+    }
+
+    // SAFETY: the mutex and the struct are fully initialized, and the
+    // struct has not moved since.
+    Ok(Pin::new_unchecked(arc.assume_init()))
+}
 
+/// Initializes the tail fields of a partial struct inside a cell. On
+/// error, the head fields are dropped, but the cell must be freed by
+/// the caller.
+///
+/// # Safety
+///
+/// `inner` must be the only reference, and must not move until the
+/// struct is dropped.
+#[cfg(feature = "alloc")]
+unsafe fn try_init_cell<T: IncrStructInit>(
+    inner: &mut MaybeUninit<T>,
+) -> Result<(), Error<T::Error>> {
+    match try_ensure_init(inner) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            // SAFETY: only head data is initialized.
+            _ = T::drop_uninit_in_place(inner);
             Err(err)
         }
     }
 }
+
+/// Creates an `Rc`, writing the head fields directly into the
+/// allocation, using `f`, like [new_box]. This backs the generated
+/// `AStruct::new_rc`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_rc_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<Rc<T>>, T::Error> {
+    try_new_rc_with(f).map_err(|err| expect_init(err, "new_rc_with"))
+}
+
+/// Like [new_rc_with], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_rc_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<Rc<T>>, Error<T::Error>> {
+    try_new_in(f)
+}
+
+/// Creates a boxed slice with one struct per item of `heads`, using
+/// `f` to write the head fields of each element in place. The tail
+/// fields are initialized in place, element by element. If an element
+/// fails, the elements before it are dropped, and so are the heads
+/// not yet used.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `f` must initialize all head fields, and only the head fields.
+#[cfg(feature = "alloc")]
+pub unsafe fn new_boxed_slice<T, H, I, F>(heads: I, f: F) -> Result<Pin<Box<[T]>>, T::Error>
+where
+    T: IncrStructInit,
+    I: IntoIterator<Item = H>,
+    F: FnMut(&mut T, H),
+{
+    try_new_boxed_slice(heads, f).map_err(|err| expect_init(err, "new_boxed_slice"))
+}
+
+/// Like [new_boxed_slice], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_boxed_slice].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_boxed_slice<T, H, I, F>(
+    heads: I,
+    mut f: F,
+) -> Result<Pin<Box<[T]>>, Error<T::Error>>
+where
+    T: IncrStructInit,
+    I: IntoIterator<Item = H>,
+    F: FnMut(&mut T, H),
+{
+    let heads: alloc::vec::Vec<H> = heads.into_iter().collect();
+
+    // Drops the initialized prefix, and frees the slice, on error, or
+    // if initialization panics.
+    let mut guard = SliceGuard {
+        raw: Box::into_raw(Box::<[T]>::new_uninit_slice(heads.len())),
+        len: 0,
+    };
+
+    for (i, h) in heads.into_iter().enumerate() {
+        let item = &mut *guard.raw.cast::<MaybeUninit<T>>().add(i);
+
+        init_heads(item, |out| f(out, h));
+        if let Err(err) = try_ensure_init(item) {
+            // SAFETY: only head data is initialized.
+            _ = T::drop_uninit_in_place(item);
+            return Err(err);
+        }
+        guard.len += 1;
+    }
+
+    let raw = guard.raw as *mut [T];
+    core::mem::forget(guard);
+
+    // SAFETY: all elements are fully initialized.
+    Ok(Pin::new_unchecked(Box::from_raw(raw)))
+}
+
+/// Owns a boxed slice, whose first `len` elements are initialized.
+#[cfg(feature = "alloc")]
+struct SliceGuard<T> {
+    raw: *mut [MaybeUninit<T>],
+    len: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Drop for SliceGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: the prefix is fully initialized, and the slice came
+        // from Box::into_raw.
+        unsafe {
+            drop_in_place(core::ptr::slice_from_raw_parts_mut(
+                self.raw.cast::<T>(),
+                self.len,
+            ));
+            drop(Box::from_raw(self.raw));
+        }
+    }
+}
+
+/// Creates an `Arc`, writing the head fields directly into the
+/// allocation, using `f`, like [new_box]. This backs the generated
+/// `AStruct::new_arc`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn new_arc_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<Arc<T>>, T::Error> {
+    try_new_arc_with(f).map_err(|err| expect_init(err, "new_arc_with"))
+}
+
+/// Like [new_arc_with], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_new_arc_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<Arc<T>>, Error<T::Error>> {
+    try_new_in(f)
+}
+
+/// Like [new_arc_with], but creates a `triomphe::Arc`, which has no
+/// weak count.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "triomphe")]
+pub unsafe fn new_triomphe_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<triomphe::Arc<T>>, T::Error> {
+    try_new_triomphe_with(f).map_err(|err| expect_init(err, "new_triomphe_with"))
+}
+
+/// Like [new_triomphe_with], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [new_box].
+#[cfg(feature = "triomphe")]
+pub unsafe fn try_new_triomphe_with<T: IncrStructInit, F: FnOnce(&mut T)>(
+    f: F,
+) -> Result<Pin<triomphe::Arc<T>>, Error<T::Error>> {
+    try_new_in(f)
+}
+
+/// Moves a boxed struct into a new `Rc`, initializing the tail
+/// fields again at the new location. If initialization fails, the
+/// head fields are dropped.
+///
+/// Used by auto-generated code.
+#[cfg(feature = "alloc")]
+pub fn box_into_rc<T: IncrStructInit>(this: Pin<Box<T>>) -> Result<Pin<Rc<T>>, T::Error> {
+    try_box_into_rc(this).map_err(|err| expect_init(err, "into_rc"))
+}
+
+/// Like [box_into_rc], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+#[cfg(feature = "alloc")]
+pub fn try_box_into_rc<T: IncrStructInit>(
+    this: Pin<Box<T>>,
+) -> Result<Pin<Rc<T>>, Error<T::Error>> {
+    try_box_into_in(this)
+}
+
+/// Moves a boxed struct into a new `Arc`, initializing the tail
+/// fields again at the new location. If initialization fails, the
+/// head fields are dropped.
+///
+/// Used by auto-generated code.
+#[cfg(feature = "alloc")]
+pub fn box_into_arc<T: IncrStructInit>(this: Pin<Box<T>>) -> Result<Pin<Arc<T>>, T::Error> {
+    try_box_into_arc(this).map_err(|err| expect_init(err, "into_arc"))
+}
+
+/// Like [box_into_arc], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+#[cfg(feature = "alloc")]
+pub fn try_box_into_arc<T: IncrStructInit>(
+    this: Pin<Box<T>>,
+) -> Result<Pin<Arc<T>>, Error<T::Error>> {
+    try_box_into_in(this)
+}
+
+/// Drops the tail fields of a boxed struct, and moves the remaining
+/// partial struct directly into a new allocation of `P`, where the
+/// tail fields are initialized again.
+#[cfg(feature = "alloc")]
+fn try_box_into_in<P: IncrContainer<T>, T: IncrStructInit>(
+    this: Pin<Box<T>>,
+) -> Result<Pin<P>, Error<T::Error>> {
+    // SAFETY: the tail fields are dropped before the value moves, so
+    // nothing references the old location.
+    let raw = Box::into_raw(unsafe { Pin::into_inner_unchecked(this) });
+    drop_tails(unsafe { &mut *raw }).map_err(Error::State)?;
+
+    let src = raw as *mut MaybeUninit<T>;
+    // SAFETY: the copy writes exactly the head fields, and the header
+    // is written again by `try_new_in`.
+    let out = unsafe {
+        try_new_in(|this: &mut T| {
+            core::ptr::copy_nonoverlapping(src, this as *mut T as *mut MaybeUninit<T>, 1)
+        })
+    };
+
+    // SAFETY: the head fields have moved out, so dropping the box only
+    // deallocates it.
+    drop(unsafe { Box::from_raw(src) });
+
+    out
+}
+
+/// Drops the tail fields of a boxed struct, and moves the remaining
+/// partial struct out of the box. This is the reverse of [new_box].
+#[cfg(feature = "alloc")]
+fn box_into_uninit<T: IncrStructBase>(this: Pin<Box<T>>) -> Result<MaybeUninit<T>, StateError> {
+    // SAFETY: the tail fields are dropped before the value moves, so
+    // nothing references the old location.
+    let raw = Box::into_raw(unsafe { Pin::into_inner_unchecked(this) });
+    drop_tails(unsafe { &mut *raw })?;
+
+    // SAFETY: MaybeUninit<T> has the same layout as T, and dropping
+    // the box only deallocates it.
+    Ok(*unsafe { Box::from_raw(raw as *mut MaybeUninit<T>) })
+}
+
+/// Drops all initialized tail fields, making `this` a partial struct
+/// with only head fields. On error, the tail fields are in an
+/// unknown state, and the struct should be leaked.
+pub(crate) fn drop_tails<T: IncrStructBase>(this: &mut T) -> Result<(), StateError> {
+    let end = match check_recoverable(&<T as IncrStructBase>::header(this).state) {
+        Ok(phase) => T::phase_end(phase),
+        Err(StateError::Inited) => T::phase_end(T::PHASES),
+        Err(err) => return Err(err),
+    };
+
+    // SAFETY: the tail fields of completed phases are initialized,
+    // and the header marks the rest as uninitialized.
+    unsafe { T::drop_tail_in_place(this, 0..end) };
+    <T as IncrStructBase>::header(this).state = State::Uninited;
+
+    Ok(())
+}
+
+/// Drops the tail fields of a boxed struct, and uses `f` to move the
+/// head fields out of it. This is the reverse of [new_box].
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `f` must move out all head fields, and nothing else.
+#[cfg(feature = "alloc")]
+pub unsafe fn box_into_heads<T: IncrStructBase, H, F: FnOnce(&mut T) -> H>(
+    this: Pin<Box<T>>,
+    f: F,
+) -> H {
+    try_box_into_heads(this, f).unwrap_or_else(|err| state_panic(err, "into_heads"))
+}
+
+/// Like [box_into_heads], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [box_into_heads].
+#[cfg(feature = "alloc")]
+pub unsafe fn try_box_into_heads<T: IncrStructBase, H, F: FnOnce(&mut T) -> H>(
+    this: Pin<Box<T>>,
+    f: F,
+) -> Result<H, StateError> {
+    let mut uninit = box_into_uninit(this)?;
+
+    // SAFETY: only head fields are initialized, and `f` moves them
+    // out, so dropping the MaybeUninit drops nothing.
+    Ok(f(&mut *uninit.as_mut_ptr()))
+}
+
+/// Drops the tail fields of a movable struct, in boxed mode, and uses
+/// `f` to move the head fields out of it.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `f` must move out all head fields, and nothing else.
+pub unsafe fn movable_into_heads<T: IncrStructBase, H, F: FnOnce(&mut T) -> H>(this: T, f: F) -> H {
+    try_movable_into_heads(this, f).unwrap_or_else(|err| state_panic(err, "into_heads"))
+}
+
+/// Like [movable_into_heads], but returns state errors instead of
+/// panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [movable_into_heads].
+pub unsafe fn try_movable_into_heads<T: IncrStructBase, H, F: FnOnce(&mut T) -> H>(
+    this: T,
+    f: F,
+) -> Result<H, StateError> {
+    // The struct is never dropped as a whole, since `f` moves the
+    // head fields out.
+    let mut this = core::mem::ManuallyDrop::new(this);
+    drop_tails(&mut *this)?;
+
+    Ok(f(&mut *this))
+}
+
+/// Drops the tail fields of `this`, moves the head fields out with
+/// `read`, calls `f` with them, moves them back with `write`, and
+/// initializes the tail fields again, using `init`.
+///
+/// The `backup` function is called first, and may return a copy of the
+/// head fields. If initialization fails, the changed head fields are
+/// dropped, the copy is moved in instead, the tail fields are
+/// initialized from it again, and the error is returned.
+///
+/// If `f` panics, the head fields are still moved back. If `f`, or
+/// initialization, panics, or initialization fails without a copy, or
+/// fails again from the copy, the process aborts, since the tail
+/// fields are dropped, and `this` can be neither used nor dropped.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `read` must move out all head fields, and nothing else. `write`
+/// must move them back. The `init` function has the same
+/// requirements as [IncrStructBase::init_phase].
+pub unsafe fn with_heads_mut<T: IncrStructBase, H, R, B, G, W, F, I>(
+    this: &mut T,
+    backup: B,
+    read: G,
+    write: W,
+    f: F,
+    init: I,
+) -> Result<R, T::Error>
+where
+    B: FnOnce(&T) -> Option<H>,
+    G: FnMut(&mut T) -> H,
+    W: FnMut(&mut T, H),
+    F: FnOnce(&mut H) -> R,
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_with_heads_mut(this, backup, read, write, f, init)
+        .map_err(|err| expect_init(err, "with_heads_mut"))
+}
+
+/// Like [with_heads_mut], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [with_heads_mut].
+pub unsafe fn try_with_heads_mut<T: IncrStructBase, H, R, B, G, W, F, I>(
+    this: &mut T,
+    backup: B,
+    mut read: G,
+    mut write: W,
+    f: F,
+    mut init: I,
+) -> Result<R, Error<T::Error>>
+where
+    B: FnOnce(&T) -> Option<H>,
+    G: FnMut(&mut T) -> H,
+    W: FnMut(&mut T, H),
+    F: FnOnce(&mut H) -> R,
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    /// Moves the head fields back, even if `f` panics.
+    struct HeadsGuard<'a, T, H, W: FnMut(&mut T, H)> {
+        this: &'a mut T,
+        heads: core::mem::ManuallyDrop<H>,
+        write: &'a mut W,
+    }
+
+    impl<T, H, W: FnMut(&mut T, H)> Drop for HeadsGuard<'_, T, H, W> {
+        fn drop(&mut self) {
+            // SAFETY: the heads are only taken here, once.
+            let heads = unsafe { core::mem::ManuallyDrop::take(&mut self.heads) };
+
+            (self.write)(self.this, heads);
+        }
+    }
+
+    // The copy is made while the struct is intact, so a panic in
+    // `backup` needs no abort.
+    let backup = backup(this);
+    let abort = AbortGuard("with_heads_mut");
+    if let Err(err) = drop_tails(this) {
+        // Nothing was dropped.
+        abort.disarm();
+
+        return Err(Error::State(err));
+    }
+
+    let heads = read(this);
+    let mut guard = HeadsGuard {
+        this: &mut *this,
+        heads: core::mem::ManuallyDrop::new(heads),
+        write: &mut write,
+    };
+    let ret = f(&mut guard.heads);
+    drop(guard);
+
+    // SAFETY: tail fields are uninitialized, and the header is
+    // Uninited.
+    let Err(err) = do_init_with(this, 0, T::PHASES, &mut init) else {
+        abort.disarm();
+
+        return Ok(ret);
+    };
+    let Some(old) = backup else {
+        abort_poisoned("with_heads_mut");
+    };
+
+    // The failed phase has been undone, and the struct is poisoned,
+    // which drop_tails accepts.
+    _ = drop_tails(this);
+    drop(read(this));
+    write(this, old);
+
+    // SAFETY: as above.
+    abort.finish(do_init_with(this, 0, T::PHASES, &mut init));
+
+    Err(Error::Init(err))
+}
+
+/// Re-initializes some tail fields of `this`, keeping their old values
+/// until the new ones are initialized.
+///
+/// The `build` function initializes new values, outside of `this`, for
+/// the tail fields only borrowing fields that stay unchanged. The `swap`
+/// function drops the other tail fields, may then change the fields
+/// they borrow, moves the new values in, and returns what it replaced.
+/// The `refresh` function initializes the dropped tail fields again. If
+/// that fails, `rollback` drops what it initialized, and moves the old
+/// values back, and `refresh` initializes the tail fields from them
+/// again. The init error is then returned, with `this` as it was
+/// before the call, except the tail fields in the `dirty` mask are
+/// marked as dirty.
+///
+/// If `build` fails, or panics, nothing has changed. If anything else
+/// panics, or `refresh` fails again after `rollback`, the process
+/// aborts, since the tail fields are dropped, and `this` can be neither
+/// used nor dropped.
+///
+/// If `this` isn't fully initialized, all tail fields are dropped,
+/// `swap` is called without new values, and `init` initializes all
+/// phases instead. Errors are then returned like [force_init].
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `swap` must only drop tail fields marked as initialized, and
+/// `refresh` must initialize exactly those tail fields again. The
+/// `rollback` function must drop the ones `refresh` marked as
+/// initialized. The `init` function has the same requirements as
+/// [IncrStructBase::init_phase].
+pub unsafe fn refresh_with<T: IncrStructBase, N, O, B, S, F, R, I>(
+    this: &mut T,
+    dirty: u64,
+    build: B,
+    swap: S,
+    refresh: F,
+    rollback: R,
+    init: I,
+) -> Result<(), T::Error>
+where
+    B: FnOnce(*const T) -> Result<N, T::Error>,
+    S: FnOnce(&mut T, Option<N>) -> O,
+    F: FnMut(*mut T) -> Result<(), T::Error>,
+    R: FnOnce(&mut T, O),
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_refresh_with(this, dirty, build, swap, refresh, rollback, init)
+        .map_err(|err| expect_init(err, "refresh_with"))
+}
+
+/// Like [refresh_with], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [refresh_with].
+pub unsafe fn try_refresh_with<T: IncrStructBase, N, O, B, S, F, R, I>(
+    this: &mut T,
+    dirty: u64,
+    build: B,
+    swap: S,
+    mut refresh: F,
+    rollback: R,
+    init: I,
+) -> Result<(), Error<T::Error>>
+where
+    B: FnOnce(*const T) -> Result<N, T::Error>,
+    S: FnOnce(&mut T, Option<N>) -> O,
+    F: FnMut(*mut T) -> Result<(), T::Error>,
+    R: FnOnce(&mut T, O),
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    match check_recoverable(&<T as IncrStructBase>::header(this).state) {
+        Err(StateError::Inited) => {}
+        Err(err) => return Err(Error::State(err)),
+        Ok(_) => {
+            drop_tails(this).map_err(Error::State)?;
+            let old = swap(this, None);
+
+            // SAFETY: tail fields are uninitialized.
+            let result = do_init_with(this, 0, T::PHASES, init);
+            drop(old);
+
+            return result.map_err(Error::Init);
+        }
+    }
+
+    let new = match build(this) {
+        Ok(new) => new,
+        Err(err) => {
+            mark_tails_dirty(<T as IncrStructBase>::header(this), dirty);
+
+            return Err(Error::Init(err));
+        }
+    };
+
+    let abort = AbortGuard("refresh_with");
+    <T as IncrStructBase>::header(this).state = State::Initing;
+    let old = swap(this, Some(new));
+    let result = match refresh(this) {
+        Ok(()) => {
+            abort.disarm();
+
+            Ok(old)
+        }
+        Err(err) => {
+            rollback(this, old);
+            abort.finish(refresh(this));
+            mark_tails_dirty(<T as IncrStructBase>::header(this), dirty);
+
+            Err(Error::Init(err))
+        }
+    };
+    <T as IncrStructBase>::header(this).state =
+        phase_state(T::PHASES, T::PHASES, Address::of(this));
+
+    // The replaced values are dropped once `this` is intact again.
+    result.map(drop)
+}
+
+/// Like [refresh_with], but for the tail fields marked as dirty,
+/// which are all clean afterwards. If initialization fails, they stay
+/// dirty.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [refresh_with]. The closures must only use the tail fields
+/// marked as dirty.
+pub unsafe fn recompute_with<T: IncrStructBase, N, O, B, S, F, R, I>(
+    this: &mut T,
+    build: B,
+    swap: S,
+    refresh: F,
+    rollback: R,
+    init: I,
+) -> Result<(), T::Error>
+where
+    B: FnOnce(*const T) -> Result<N, T::Error>,
+    S: FnOnce(&mut T, Option<N>) -> O,
+    F: FnMut(*mut T) -> Result<(), T::Error>,
+    R: FnOnce(&mut T, O),
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_recompute_with(this, build, swap, refresh, rollback, init)
+        .map_err(|err| expect_init(err, "recompute_with"))
+}
+
+/// Like [recompute_with], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [recompute_with].
+pub unsafe fn try_recompute_with<T: IncrStructBase, N, O, B, S, F, R, I>(
+    this: &mut T,
+    build: B,
+    swap: S,
+    refresh: F,
+    rollback: R,
+    init: I,
+) -> Result<(), Error<T::Error>>
+where
+    B: FnOnce(*const T) -> Result<N, T::Error>,
+    S: FnOnce(&mut T, Option<N>) -> O,
+    F: FnMut(*mut T) -> Result<(), T::Error>,
+    R: FnOnce(&mut T, O),
+    I: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_refresh_with(this, 0, build, swap, refresh, rollback, init)?;
+
+    // Either the dirty fields were initialized again, or all tail
+    // fields were.
+    <T as IncrStructBase>::header(this).dirty = TailBits::EMPTY;
+
+    Ok(())
+}
+
+/// Forces initialization of `this`, even if it was previously initialized.
+///
+/// This is useful when a T has moved, and the self-referencing tail
+/// fields must be synchronized. The tail fields are dropped first,
+/// so what they borrowed, e.g. the old place, must still be valid.
+///
+/// The caller is reponsible for keeping `this` pinned.
+///
+/// # Safety
+///
+/// This function is always safe to call, but only makes sense to call
+/// after you have used an unsafe function to move a pinned value
+/// somewhere else.
+pub fn force_init<T: IncrStructInit>(this: &mut T) -> Result<(), T::Error> {
+    try_force_init(this).map_err(|err| expect_init(err, "force_init"))
+}
+
+/// Like [force_init], but returns state errors instead of panicking.
+pub fn try_force_init<T: IncrStructInit>(this: &mut T) -> Result<(), Error<T::Error>> {
+    // SAFETY: init_phase is the reference implementation.
+    unsafe { try_force_init_with(this, |this, phase| T::init_phase(this, phase)) }
+}
+
+/// Like [force_init], but calls `init` to initialize each phase, like
+/// [ensure_init_with].
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// The `init` function has the same requirements as
+/// [IncrStructBase::init_phase].
+pub unsafe fn force_init_with<T: IncrStructBase, F>(this: &mut T, init: F) -> Result<(), T::Error>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_force_init_with(this, init).map_err(|err| expect_init(err, "force_init_with"))
+}
+
+/// Like [force_init_with], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [force_init_with].
+pub unsafe fn try_force_init_with<T: IncrStructBase, F>(
+    this: &mut T,
+    init: F,
+) -> Result<(), Error<T::Error>>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    let end = match check_recoverable(&<T as IncrStructBase>::header(this).state) {
+        Ok(phase) => T::phase_end(phase),
+        Err(StateError::Inited) => T::phase_end(T::PHASES),
+        Err(err) => return Err(Error::State(err)),
+    };
+
+    // SAFETY: we are now making `this` back into a partially
+    // initialized struct, the same as Uninited.
+    T::drop_tail_in_place(this, 0..end);
+
+    // SAFETY: tail fields are uninitialized.
+    do_init_with(this, 0, T::PHASES, init).map_err(Error::Init)
+}
+
+/// Like [force_init_with], but for a movable struct, which safe code
+/// can use while it's fully initialized. If initializing it then
+/// fails, or panics, the process aborts, since the tail fields are
+/// dropped, and `this` can be neither used nor dropped. Only a struct
+/// that wasn't fully initialized gets the init error back.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [force_init_with].
+pub unsafe fn force_init_movable_with<T: IncrStructBase, F>(
+    this: &mut T,
+    init: F,
+) -> Result<(), T::Error>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_force_init_movable_with(this, init)
+        .map_err(|err| expect_init(err, "force_init_movable_with"))
+}
+
+/// Like [force_init_movable_with], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [force_init_with].
+pub unsafe fn try_force_init_movable_with<T: IncrStructBase, F>(
+    this: &mut T,
+    init: F,
+) -> Result<(), Error<T::Error>>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    if !matches!(<T as IncrStructBase>::header(this).state, State::Inited(..)) {
+        return try_force_init_with(this, init);
+    }
+
+    let abort = AbortGuard("force_init");
+    let result = try_force_init_with(this, init);
+    abort.finish(result);
+
+    Ok(())
+}
+
+/// Initializes a `#[nested]` head field again, like [force_init],
+/// before the tail fields of the enclosing struct. The nested init
+/// error is converted using `From`.
+///
+/// Used by auto-generated code.
+pub fn force_init_nested<T: IncrStructInit, E: From<T::Error>>(this: &mut T) -> Result<(), E> {
+    try_force_init(this).map_err(|err| E::from(expect_init(err, "force_init")))
+}
+
+/// Creates a partially initialized struct. The `f` function
+/// initializes all head fields, and only the head fields.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// Calling this function yields a value that will not call
+/// destructors. Callers must use `drop_uninit` until a successful
+/// call to `ensure_init`. After `ensure_init`, the caller owns the
+/// `*mut Self`, and normal drop rules apply.
+pub unsafe fn new_uninit<T: IncrStructBase, F: FnOnce(&mut T)>(f: F) -> MaybeUninit<T> {
+    let mut out = MaybeUninit::<T>::uninit();
+    init_heads(&mut out, f);
+
+    out
+}
+
+/// Uses `f` to write all head fields, in place, and then writes the
+/// header. For an enum, `f` also writes the tag, so the header is
+/// found in the right variant.
+///
+/// # Safety
+///
+/// `f` must initialize all head fields, and only the head fields.
+pub(crate) unsafe fn init_heads<T: IncrStructBase, F: FnOnce(&mut T)>(
+    out: &mut MaybeUninit<T>,
+    f: F,
+) {
+    let this = &mut *out.as_mut_ptr();
+    f(this);
+
+    T::write_header(this);
+}
+
+/// Initializes a struct in caller-provided memory, e.g. in a
+/// memory-mapped region, or from a custom allocator. The head fields
+/// are written using `f`, and then the tail fields are initialized.
+/// On error, the head fields are dropped again, so `out` is left
+/// uninitialized.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `out` must be valid for writes, and properly aligned. The memory
+/// must not be reused or moved until the struct has been dropped in
+/// place. `f` must initialize all head fields, and only the head
+/// fields.
+pub unsafe fn init_at<'a, T: IncrStructInit, F: FnOnce(&mut T)>(
+    out: *mut T,
+    f: F,
+) -> Result<Pin<&'a mut T>, T::Error> {
+    try_init_at(out, f).map_err(|err| expect_init(err, "init_at"))
+}
+
+/// Like [init_at], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [init_at].
+pub unsafe fn try_init_at<'a, T: IncrStructInit, F: FnOnce(&mut T)>(
+    out: *mut T,
+    f: F,
+) -> Result<Pin<&'a mut T>, Error<T::Error>> {
+    let out = &mut *out.cast::<MaybeUninit<T>>();
+
+    init_heads(out, f);
+    if let Err(err) = try_ensure_init(out) {
+        // SAFETY: only head fields are initialized.
+        _ = T::drop_uninit_in_place(out);
+        return Err(err);
+    }
+
+    // SAFETY: the struct is fully initialized, and the caller
+    // promised not to move it.
+    Ok(Pin::new_unchecked(out.assume_init_mut()))
+}
+
+/// Finalizes a partially initialized struct. The returned reference
+/// is guaranteed to be the same as `this`, and is only returned as a
+/// type-safety convenience. Phases that have already been completed,
+/// using [ensure_init_phase], are not run again.
+///
+/// If an error occurs, all tail fields of the failing phase are
+/// dropped before the function returns, and the header is poisoned.
+/// Calling `ensure_init` again fails with [StateError::Poisoned], but
+/// `force_init` and `drop_uninit` still work. The caller is
+/// responsible for calling `drop_uninit` if required.
+///
+/// The caller is responsible for pinning `this`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// This function assumes head fields are initialized and that tail
+/// fields are not .
+pub unsafe fn ensure_init<T: IncrStructInit>(
+    this: &mut MaybeUninit<T>,
+) -> Result<&mut T, T::Error> {
+    try_ensure_init(this).map_err(|err| expect_init(err, "ensure_init"))
+}
+
+/// Like [ensure_init], but returns state errors instead of panicking.
+///
+/// # Safety
+///
+/// See [ensure_init].
+pub unsafe fn try_ensure_init<T: IncrStructInit>(
+    this: &mut MaybeUninit<T>,
+) -> Result<&mut T, Error<T::Error>> {
+    let r = &mut *this.as_mut_ptr();
+
+    let from = check_uninited(&<T as IncrStructBase>::header(r).state).map_err(Error::State)?;
+    do_init(r, from, T::PHASES).map_err(Error::Init)?;
+
+    Ok(this.assume_init_mut())
+}
+
+/// Like [ensure_init], but takes and returns pinned references. Since
+/// `this` is already pinned, the caller can't move the struct
+/// afterwards by mistake.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// All head fields must be initialized, and no tail fields. Pinning
+/// a `MaybeUninit` doesn't drop the struct, so the caller must drop
+/// it in place, e.g. with `drop_in_place` on the returned pointer,
+/// before the pinned storage is deallocated or reused. Otherwise,
+/// borrowers of the tails may observe the storage dying under them.
+pub unsafe fn ensure_init_pin<T: IncrStructInit>(
+    this: Pin<&mut MaybeUninit<T>>,
+) -> Result<Pin<&mut T>, T::Error> {
+    try_ensure_init_pin(this).map_err(|err| expect_init(err, "ensure_init_pin"))
+}
+
+/// Like [ensure_init_pin], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [ensure_init_pin].
+pub unsafe fn try_ensure_init_pin<T: IncrStructInit>(
+    this: Pin<&mut MaybeUninit<T>>,
+) -> Result<Pin<&mut T>, Error<T::Error>> {
+    // SAFETY: the struct is initialized in place, and stays pinned.
+    Ok(Pin::new_unchecked(try_ensure_init(
+        this.get_unchecked_mut(),
+    )?))
+}
+
+/// Like [ensure_init], but can be called from many threads at
+/// once. The first caller initializes the struct, while the others
+/// wait for it to finish, and then return the initialized struct. If
+/// initialization fails, the other callers see a poisoned struct.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [ensure_init]. Additionally, `this` must be valid for writes,
+/// e.g. from `Arc::as_ptr`, `header` must point into it, and no
+/// references to the struct may exist until this function has
+/// returned successfully in some thread.
+pub unsafe fn ensure_init_sync<'a, T: IncrStructInit>(
+    this: *const MaybeUninit<T>,
+    header: *const SyncHeader<T::Tails, T::Dirty>,
+) -> Result<&'a T, T::Error> {
+    try_ensure_init_sync(this, header).map_err(|err| expect_init(err, "ensure_init_sync"))
+}
+
+/// Like [ensure_init_sync], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [ensure_init_sync].
+pub unsafe fn try_ensure_init_sync<'a, T: IncrStructInit>(
+    this: *const MaybeUninit<T>,
+    header: *const SyncHeader<T::Tails, T::Dirty>,
+) -> Result<&'a T, Error<T::Error>> {
+    // Only the state is accessed through a shared reference, and it
+    // is atomic.
+    let state = &*core::ptr::addr_of!((*header).state);
+
+    loop {
+        // The fast path, once some thread has published the
+        // initialized struct. Only shared references are formed from
+        // here on, since other threads may be reading it.
+        match state.compare_exchange_weak(
+            SYNC_UNLOCKED,
+            SYNC_LOCKED,
+            Ordering::Acquire,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => break,
+            Err(SYNC_INITED) => return Ok(&*(this as *const T)),
+            Err(_) => {}
+        }
+
+        #[cfg(loom)]
+        loom::thread::yield_now();
+        #[cfg(all(not(loom), feature = "std"))]
+        std::thread::yield_now();
+        #[cfg(all(not(loom), not(feature = "std")))]
+        core::hint::spin_loop();
+    }
+
+    // If initialization panics, the phase guard has already poisoned
+    // the struct when this is dropped, and the next thread sees that.
+    let unlock = SyncUnlock(state);
+
+    // Nothing has been published yet, so this thread has exclusive
+    // access.
+    match try_ensure_init(&mut *(this as *mut MaybeUninit<T>)) {
+        Ok(_) | Err(Error::State(StateError::Inited)) => {
+            core::mem::forget(unlock);
+            state.store(SYNC_INITED, Ordering::Release);
+
+            Ok(&*(this as *const T))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// The states of `SyncHeader::state`. Only the thread moving it from
+// `SYNC_UNLOCKED` to `SYNC_LOCKED` accesses the inner header, until it
+// moves it on. `SYNC_INITED` is final.
+const SYNC_UNLOCKED: u8 = 0;
+const SYNC_LOCKED: u8 = 1;
+const SYNC_INITED: u8 = 2;
+
+/// Unlocks a [SyncHeader] when dropped, unless it was initialized.
+struct SyncUnlock<'a>(&'a AtomicU8);
+
+impl Drop for SyncUnlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(SYNC_UNLOCKED, Ordering::Release);
+    }
+}
+
+/// Like [ensure_init], but calls `init` instead of
+/// [IncrStructBase::init_phase] to initialize each phase. This allows
+/// initializing tail fields with closures, e.g. to stub them in
+/// tests. On error, `init` must drop the tail fields of the failing
+/// phase, just like `init_phase`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [ensure_init]. The `init` function has the same requirements
+/// as [IncrStructBase::init_phase].
+pub unsafe fn ensure_init_with<T: IncrStructBase, F>(
+    this: &mut MaybeUninit<T>,
+    init: F,
+) -> Result<&mut T, T::Error>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    try_ensure_init_with(this, init).map_err(|err| expect_init(err, "ensure_init_with"))
+}
+
+/// Like [ensure_init_with], but returns state errors instead of panicking.
+///
+/// # Safety
+///
+/// See [ensure_init_with].
+pub unsafe fn try_ensure_init_with<T: IncrStructBase, F>(
+    this: &mut MaybeUninit<T>,
+    init: F,
+) -> Result<&mut T, Error<T::Error>>
+where
+    F: FnMut(*mut T, u8) -> Result<(), T::Error>,
+{
+    let r = &mut *this.as_mut_ptr();
+
+    let from = check_uninited(&<T as IncrStructBase>::header(r).state).map_err(Error::State)?;
+    do_init_with(r, from, T::PHASES, init).map_err(Error::Init)?;
+
+    Ok(this.assume_init_mut())
+}
+
+/// Like [ensure_init], but awaits
+/// [IncrStructInitAsync::init_phase_async] for each phase. If the
+/// returned future is dropped before it completes, the tail fields
+/// of the interrupted phase are dropped and the header is poisoned,
+/// so the struct can still be dropped with `drop_uninit`.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [ensure_init].
+#[cfg(feature = "async")]
+pub async unsafe fn ensure_init_async<T: IncrStructInitAsync>(
+    this: &mut MaybeUninit<T>,
+) -> Result<&mut T, T::Error> {
+    try_ensure_init_async(this)
+        .await
+        .map_err(|err| expect_init(err, "ensure_init_async"))
+}
+
+/// Like [ensure_init_async], but returns state errors instead of
+/// panicking.
+///
+/// # Safety
+///
+/// See [ensure_init].
+#[cfg(feature = "async")]
+pub async unsafe fn try_ensure_init_async<T: IncrStructInitAsync>(
+    this: &mut MaybeUninit<T>,
+) -> Result<&mut T, Error<T::Error>> {
+    let r = &mut *this.as_mut_ptr();
+
+    let from = check_uninited(&<T as IncrStructBase>::header(r).state).map_err(Error::State)?;
+    do_init_async(r, from, T::PHASES)
+        .await
+        .map_err(Error::Init)?;
+
+    Ok(this.assume_init_mut())
+}
+
+/// Runs all initialization phases up to and including `phase`, that
+/// haven't already been completed. Does nothing if the struct is
+/// already fully initialized. Phase zero is the head fields, so it's
+/// always complete.
+///
+/// If an error occurs, the tail fields of the failing phase are
+/// dropped, and earlier phases remain initialized.
+///
+/// The caller is responsible for pinning `this` once the first tail
+/// phase has run.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// This function assumes head fields are initialized, and that the
+/// header correctly describes which tail phases are.
+pub unsafe fn ensure_init_phase<T: IncrStructInit>(
+    this: &mut MaybeUninit<T>,
+    phase: u8,
+) -> Result<(), T::Error> {
+    try_ensure_init_phase(this, phase).map_err(|err| expect_init(err, "ensure_init_phase"))
+}
+
+/// Like [ensure_init_phase], but returns state errors instead of panicking.
+///
+/// # Safety
+///
+/// See [ensure_init_phase].
+pub unsafe fn try_ensure_init_phase<T: IncrStructInit>(
+    this: &mut MaybeUninit<T>,
+    phase: u8,
+) -> Result<(), Error<T::Error>> {
+    let r = &mut *this.as_mut_ptr();
+
+    let from = match check_uninited(&<T as IncrStructBase>::header(r).state) {
+        Ok(from) => from,
+        Err(StateError::Inited) => return Ok(()),
+        Err(err) => return Err(Error::State(err)),
+    };
+
+    do_init(r, from, phase.min(T::PHASES)).map_err(Error::Init)
+}
+
+/// Drops a partially initialized struct. All head fields, and the
+/// tail fields of completed phases, are assumed to be initialized.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// The `f` function must only drop head fields. This function
+/// returns an error, and drops nothing, if the struct is fully
+/// initialized, or being initialized.
+pub unsafe fn drop_uninit_in_place<T: IncrStructBase, F: FnOnce(&mut T)>(
+    this: &mut MaybeUninit<T>,
+    f: F,
+) -> Result<(), StateError> {
+    let r = &mut *this.as_mut_ptr();
+
+    let phase = check_recoverable(&<T as IncrStructBase>::header(r).state)?;
+
+    T::drop_tail_in_place(r, 0..T::phase_end(phase));
+    f(r);
+
+    drop_in_place(<T as IncrStructBase>::header(r));
+
+    Ok(())
+}
+
+/// Drops a value previously created with `new_uninit`. This function
+/// panics if the struct is fully initialized, or being initialized.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [drop_uninit_in_place].
+pub unsafe fn drop_uninit<T: IncrStructBase>(this: MaybeUninit<T>) {
+    if let Err(err) = try_drop_uninit(this) {
+        state_panic(err, "drop_uninit")
+    }
+}
+
+/// Like [drop_uninit], but returns state errors instead of panicking.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// See [drop_uninit_in_place].
+pub unsafe fn try_drop_uninit<T: IncrStructBase>(
+    mut this: MaybeUninit<T>,
+) -> Result<(), StateError> {
+    T::drop_uninit_in_place(&mut this)
+}
+
+/// Performs initialization of tail fields in phases after `from`, up
+/// to and including `to`, without sanity checking.
+///
+/// See [ensure_init] and [force_init].
+unsafe fn do_init<T: IncrStructInit>(this: &mut T, from: u8, to: u8) -> Result<(), T::Error> {
+    do_init_with(this, from, to, |this, phase| T::init_phase(this, phase))
+}
+
+/// Like [do_init], but calls `init` to initialize each phase.
+unsafe fn do_init_with<T: IncrStructBase>(
+    this: &mut T,
+    from: u8,
+    to: u8,
+    mut init: impl FnMut(*mut T, u8) -> Result<(), T::Error>,
+) -> Result<(), T::Error> {
+    for phase in from + 1..=to {
+        <T as IncrStructBase>::header(this).state = State::Initing;
+
+        // If `init` panics, the tail fields of the phase initialized
+        // so far are dropped while unwinding, and the struct is
+        // poisoned in the previous phase, so `drop_uninit` can drop
+        // the rest.
+        let guard = PhaseGuard {
+            this: this as *mut T,
+            phase,
+        };
+        // SAFETY: the code above has made the struct partially
+        // initialized.
+        let result = init(this, phase);
+        core::mem::forget(guard);
+
+        match result {
+            Ok(_) => {
+                <T as IncrStructBase>::header(this).state =
+                    phase_state(phase, T::PHASES, Address::of(this));
+            }
+            Err(err) => {
+                <T as IncrStructBase>::header(this).state = State::Poisoned(phase - 1);
+
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [do_init], but awaits each phase.
+#[cfg(feature = "async")]
+async unsafe fn do_init_async<T: IncrStructInitAsync>(
+    this: &mut T,
+    from: u8,
+    to: u8,
+) -> Result<(), T::Error> {
+    for phase in from + 1..=to {
+        <T as IncrStructBase>::header(this).state = State::Initing;
+
+        let guard = PhaseGuard {
+            this: this as *mut T,
+            phase,
+        };
+        let result = T::init_phase_async(this, phase).await;
+        core::mem::forget(guard);
+
+        match result {
+            Ok(_) => {
+                <T as IncrStructBase>::header(this).state =
+                    phase_state(phase, T::PHASES, Address::of(this));
+            }
+            Err(err) => {
+                <T as IncrStructBase>::header(this).state = State::Poisoned(phase - 1);
+
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Undoes an interrupted phase, if initialization panics, or the
+/// async future is dropped while awaiting it. Tail fields of the
+/// phase that were initialized are dropped, and the struct is left
+/// poisoned in the previous phase.
+struct PhaseGuard<T: IncrStructBase> {
+    this: *mut T,
+    phase: u8,
+}
+
+// SAFETY: the pointer is only used the same way as the `&mut T` it
+// was created from.
+unsafe impl<T: IncrStructBase + Send> Send for PhaseGuard<T> {}
+
+impl<T: IncrStructBase> Drop for PhaseGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: the phase has been abandoned, so nothing else
+        // references the struct.
+        let r = unsafe { &mut *self.this };
+
+        // SAFETY: only the fields marked initialized are dropped.
+        unsafe { T::drop_tail_in_place(r, T::phase_end(self.phase - 1)..T::phase_end(self.phase)) };
+        <T as IncrStructBase>::header(r).state = State::Poisoned(self.phase - 1);
+    }
+}
+
+/// Returns the header state describing that phases up to and including
+/// `phase` have been completed.
+#[cfg_attr(all(feature = "no_panic", not(debug_assertions)), no_panic::no_panic)]
+fn phase_state(phase: u8, phases: u8, addr: Address) -> State {
+    if phase == 0 {
+        State::Uninited
+    } else if phase < phases {
+        State::InitedPhase(phase)
+    } else {
+        State::Inited(PhantomPinned, addr)
+    }
+}
+
+/// Returns the number of completed tail phases, or an error if the
+/// struct is fully initialized, being initialized, or poisoned.
+///
+/// This and [check_recoverable] are the only places state violations
+/// are detected, so they are what the `no_panic` feature verifies.
+#[cfg_attr(all(feature = "no_panic", not(debug_assertions)), no_panic::no_panic)]
+fn check_uninited(state: &State) -> Result<u8, StateError> {
+    match state {
+        State::Poisoned(_) => Err(StateError::Poisoned),
+        _ => check_recoverable(state),
+    }
+}
+
+/// Like [check_uninited], but accepts a poisoned struct, for the
+/// functions that drop tail fields, and may start over.
+#[cfg_attr(all(feature = "no_panic", not(debug_assertions)), no_panic::no_panic)]
+fn check_recoverable(state: &State) -> Result<u8, StateError> {
+    match *state {
+        State::Uninited => Ok(0),
+        State::InitedPhase(phase) | State::Poisoned(phase) => Ok(phase),
+        State::Initing => Err(StateError::Initing),
+        State::Inited(..) => Err(StateError::Inited),
+    }
+}
+
+/// Marks the tail field with the given index, in initialization
+/// order, as initialized.
+///
+/// Used by auto-generated code.
+pub fn set_tail_inited<T: TailBits, D>(header: &mut Header<T, D>, index: usize) {
+    header.tails.insert(1 << index);
+}
+
+/// Marks the tail field with the given index, in initialization
+/// order, as uninitialized. Returns whether it was initialized, and
+/// thus should be dropped.
+///
+/// Used by auto-generated code.
+pub fn take_tail_inited<T: TailBits, D>(header: &mut Header<T, D>, index: usize) -> bool {
+    header.tails.remove(index)
+}
+
+/// Returns whether the tail field with the given index, in
+/// initialization order, is initialized.
+///
+/// Used by auto-generated code.
+pub fn is_tail_inited<T: TailBits, D>(header: &Header<T, D>, index: usize) -> bool {
+    header.tails.contains(index)
+}
+
+/// Formats a partial struct using a function, for the
+/// `debug_uninit` function generated with `#[incrstruct(debug)]`. It's
+/// a named type, rather than an `impl Debug`, so it can borrow the
+/// struct without capturing its other lifetimes.
+///
+/// Used by auto-generated code. This is not an external API.
+pub struct DebugUninitRef<'a, T> {
+    value: &'a MaybeUninit<T>,
+    fmt: fn(&MaybeUninit<T>, &mut core::fmt::Formatter<'_>) -> core::fmt::Result,
+}
+
+impl<'a, T> DebugUninitRef<'a, T> {
+    /// Returns a `Debug` view of `value`, formatted by `fmt`.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be in a state `fmt` can format.
+    pub unsafe fn new(
+        value: &'a MaybeUninit<T>,
+        fmt: fn(&MaybeUninit<T>, &mut core::fmt::Formatter<'_>) -> core::fmt::Result,
+    ) -> Self {
+        Self { value, fmt }
+    }
+}
+
+impl<T> core::fmt::Debug for DebugUninitRef<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        (self.fmt)(self.value, f)
+    }
+}
+
+/// Stands in for a tail field that isn't initialized, in generated
+/// `Debug` output.
+///
+/// Used by auto-generated code. This is not an external API.
+pub struct DebugUninit;
+
+impl core::fmt::Debug for DebugUninit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("<uninit>")
+    }
+}
+
+/// Marks the tail fields in `mask`, with bit `i` being the tail field
+/// with index `i` in initialization order, as dirty.
+///
+/// Used by auto-generated code.
+pub fn mark_tails_dirty<T, D: TailBits>(header: &mut Header<T, D>, mask: u64) {
+    header.dirty.insert(mask);
+}
+
+/// Returns whether the tail field with the given index, in
+/// initialization order, is dirty.
+///
+/// Used by auto-generated code.
+pub fn is_tail_dirty<T, D: TailBits>(header: &Header<T, D>, index: usize) -> bool {
+    header.dirty.contains(index)
+}
+
+/// Returns the value of a head field borrowed with
+/// `#[borrows(mut myfield)]`, for the tail field borrowing it.
+///
+/// Used by auto-generated code.
+///
+/// # Safety
+///
+/// `this` must be valid, and no other reference to the value may
+/// exist for `'a`.
+pub unsafe fn lent_mut<'a, T>(this: *mut crate::Lent<T>) -> &'a mut T {
+    &mut (*this).0
+}
+
+/// Debug-asserts that a fully initialized struct hasn't moved since
+/// it was initialized, which would leave its tail fields
+/// dangling. This only detects moves with the `move_check` feature.
+///
+/// Used by auto-generated code.
+pub fn debug_assert_not_moved<T: IncrStructBase>(this: &T, header: &Header<T::Tails, T::Dirty>) {
+    if let State::Inited(_, addr) = &header.state {
+        debug_assert!(
+            addr.matches(this),
+            "struct has moved since it was initialized; call force_init after moving it"
+        );
+    }
+}
+
+/// Owns a struct on the stack, for [incr_pin](crate::incr_pin). It
+/// drops the struct at the end of the scope, whether or not
+/// initialization succeeded.
+///
+/// Used by auto-generated code. This is not an external API.
+pub struct StackSlot<T: IncrStructInit> {
+    value: MaybeUninit<T>,
+    inited: bool,
+}
+
+impl<T: IncrStructInit> StackSlot<T> {
+    /// Takes ownership of a partial struct, normally created using
+    /// `T::new_uninit`.
+    pub fn new(value: MaybeUninit<T>) -> Self {
+        Self {
+            value,
+            inited: false,
+        }
+    }
+
+    /// Initializes all tail fields, and pins the struct.
+    ///
+    /// # Safety
+    ///
+    /// The slot must never move again, even if this fails.
+    pub unsafe fn init(&mut self) -> Result<Pin<&mut T>, T::Error> {
+        let r = ensure_init(&mut self.value)?;
+        self.inited = true;
+
+        Ok(Pin::new_unchecked(r))
+    }
+}
+
+impl<T: IncrStructInit> Drop for StackSlot<T> {
+    fn drop(&mut self) {
+        if self.inited {
+            // SAFETY: the struct is fully initialized.
+            unsafe { self.value.assume_init_drop() };
+        } else {
+            // SAFETY: only head fields, and completed phases, are
+            // initialized.
+            _ = unsafe { T::drop_uninit_in_place(&mut self.value) };
+        }
+    }
+}
+
+/// Unwraps the initialization error, or panics on a state error. This
+/// implements the panicking variants of the `try_` functions.
+pub(crate) fn expect_init<E>(err: Error<E>, func: &str) -> E {
+    match err {
+        Error::Init(err) => err,
+        Error::State(err) => state_panic(err, func),
+    }
+}
+
+fn state_panic(err: StateError, func: &str) -> ! {
+    match err {
+        StateError::Initing => panic!(
+            "{} called re-entrantly, on a struct that is being initialized",
+            func
+        ),
+        err => panic!("{} called on a struct that is {}", func, err),
+    }
+}
+
+/// Aborts the process unless disarmed, when re-initializing a live
+/// struct. If that fails, or panics, its tail fields are dropped, but
+/// the caller still holds the struct, and would use, or drop, them
+/// again.
+struct AbortGuard(&'static str);
+
+impl AbortGuard {
+    /// Disarms the guard, or aborts if initialization failed.
+    fn finish<R, E>(self, result: Result<R, E>) -> R {
+        match result {
+            Ok(v) => {
+                self.disarm();
+
+                v
+            }
+            Err(_) => abort_poisoned(self.0),
+        }
+    }
+
+    /// Disarms the guard.
+    fn disarm(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        abort_poisoned(self.0);
+    }
+}
+
+/// Aborts the process. A panic can't unwind out of an `extern "C"`
+/// function, so callers know this doesn't unwind, which is what the
+/// `no_panic` feature checks.
+#[cold]
+#[allow(improper_ctypes_definitions)]
+extern "C" fn abort_poisoned(func: &str) -> ! {
+    #[cfg(feature = "std")]
+    {
+        let _ = func;
+        std::process::abort()
+    }
+
+    // Without std, the panic aborts, since it can't unwind.
+    #[cfg(not(feature = "std"))]
+    panic!(
+        "{} failed to initialize a struct that can be neither used nor dropped",
+        func
+    )
+}
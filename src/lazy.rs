@@ -0,0 +1,394 @@
+//! Lazily initialized, self-referencing structs.
+
+use core::cell::{Cell, UnsafeCell};
+use core::mem::{forget, MaybeUninit};
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::drop_in_place;
+use std::boxed::Box;
+use std::sync::{Once, OnceLock};
+
+use crate::internal::{ensure_init, new_box, IncrStructDefault, IncrStructInit};
+use crate::UninitGuard;
+
+/// A struct that is created on first use, from default head
+/// fields. Unlike the struct itself, this implements `Default`, so it
+/// can be used where that is required. The struct must use
+/// `#[incrstruct(default)]`, and all head fields must implement
+/// `Default`.
+///
+/// The value is boxed, so `Lazy` can be moved freely, even after
+/// initialization.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{IncrStruct, Lazy};
+///
+/// #[derive(IncrStruct)]
+/// #[incrstruct(default)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let my_lazy: Lazy<AStruct> = Default::default();
+///
+/// assert_eq!(*my_lazy.b, 0);
+/// ```
+pub struct Lazy<T> {
+    inner: OnceLock<Pin<Box<T>>>,
+}
+
+impl<T: IncrStructDefault> Lazy<T> {
+    /// Returns the value, creating it if needed. Panics if
+    /// initialization fails.
+    pub fn get(this: &Self) -> Pin<&T> {
+        this.inner
+            .get_or_init(|| {
+                // SAFETY: write_default_heads writes all head fields.
+                match unsafe { new_box(|out| T::write_default_heads(out)) } {
+                    Ok(v) => v,
+                    Err(_) => panic!("lazy initialization failed"),
+                }
+            })
+            .as_ref()
+    }
+}
+
+impl<T> Default for Lazy<T> {
+    fn default() -> Self {
+        Self {
+            inner: OnceLock::new(),
+        }
+    }
+}
+
+impl<T: IncrStructDefault> Deref for Lazy<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::get(self).get_ref()
+    }
+}
+
+/// A struct that is constructed in place, in static storage, on first
+/// use. Since a static never moves, neither does the struct, so it
+/// never needs `force_init`. This is useful for singletons, like
+/// interners and registries.
+///
+/// The head fields come from a function, normally calling
+/// `AStruct::new_uninit`, or from `#[incrstruct(default)]`.
+///
+/// ```rust
+/// use incrstruct::{IncrStruct, StaticLazy};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: &'a str,
+///     a: String,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a String) -> &'a str {
+///         a.as_str()
+///     }
+/// }
+///
+/// // SAFETY: new_uninit initializes all head fields.
+/// static MY_STATIC: StaticLazy<AStruct<'static>> =
+///     unsafe { StaticLazy::new(|| AStruct::new_uninit("hello".to_string())) };
+///
+/// assert_eq!(MY_STATIC.get().b, "hello");
+/// ```
+///
+/// If initialization fails, the head fields are dropped, and this and
+/// later calls to `get` panic.
+pub struct StaticLazy<T> {
+    once: Once,
+    init: fn() -> MaybeUninit<T>,
+
+    // Only written by the thread running `once`.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: the value is only written once, before any shared
+// references to it exist, and may be constructed in any thread.
+unsafe impl<T: Send + Sync> Sync for StaticLazy<T> {}
+
+impl<T: IncrStructInit> StaticLazy<T> {
+    /// Returns a cell that will construct the struct from the head
+    /// fields returned by `init`.
+    ///
+    /// # Safety
+    ///
+    /// `init` must return a struct with all head fields initialized,
+    /// and no tail fields, like `new_uninit`.
+    pub const unsafe fn new(init: fn() -> MaybeUninit<T>) -> Self {
+        Self {
+            once: Once::new(),
+            init,
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, creating it if needed. Panics if
+    /// initialization fails.
+    pub fn get(&'static self) -> Pin<&'static T> {
+        self.once.call_once(|| {
+            // SAFETY: this is the only thread accessing the value.
+            let value = unsafe { &mut *self.value.get() };
+
+            *value = (self.init)();
+            // SAFETY: the caller of new promised head fields are
+            // initialized, and the value is never moved, since it's
+            // borrowed forever.
+            if unsafe { ensure_init(value) }.is_err() {
+                _ = unsafe { T::drop_uninit_in_place(value) };
+                panic!("lazy initialization failed");
+            }
+        });
+
+        // SAFETY: the value is fully initialized.
+        unsafe { Pin::new_unchecked((*self.value.get()).assume_init_ref()) }
+    }
+}
+
+impl<T: IncrStructDefault> StaticLazy<T> {
+    /// Like [StaticLazy::new], but with default head fields, using
+    /// `#[incrstruct(default)]`.
+    pub const fn new_default() -> Self {
+        // SAFETY: new_uninit_default initializes all head fields.
+        unsafe { Self::new(new_uninit_default::<T>) }
+    }
+}
+
+fn new_uninit_default<T: IncrStructDefault>() -> MaybeUninit<T> {
+    // SAFETY: the struct is only ever initialized by StaticLazy::get.
+    unsafe { T::new_uninit_default() }
+}
+
+/// Like `std::sync::OnceLock`, but constructs the struct in place, in
+/// the lock itself, which must be in a `static`. The struct never
+/// moves, so it never needs `force_init`. Unlike [StaticLazy], the
+/// head fields are given on first use.
+///
+/// `get_or_init` takes a closure returning the head fields, from the
+/// generated `AStruct::new_uninit_guard`, and initializes the tail
+/// fields in the lock.
+///
+/// ```rust
+/// use incrstruct::{IncrStruct, PinnedOnceLock};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: &'a str,
+///     a: String,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a String) -> &'a str {
+///         a.as_str()
+///     }
+/// }
+///
+/// static MY_LOCK: PinnedOnceLock<AStruct<'static>> = PinnedOnceLock::new();
+///
+/// let name = "hello".to_string();
+/// let my_value = MY_LOCK.get_or_init(|| AStruct::new_uninit_guard(name));
+///
+/// assert_eq!(my_value.b, "hello");
+/// ```
+pub struct PinnedOnceLock<T> {
+    once: Once,
+
+    // Only written by the thread running `once`.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: see StaticLazy.
+unsafe impl<T: Send + Sync> Sync for PinnedOnceLock<T> {}
+
+impl<T: IncrStructInit> PinnedOnceLock<T> {
+    /// Returns an empty lock.
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, if it has been constructed.
+    pub fn get(&self) -> Option<Pin<&T>> {
+        // SAFETY: the value was constructed by get_or_init, through a
+        // static reference, so it can never move.
+        self.once
+            .is_completed()
+            .then(|| unsafe { Pin::new_unchecked((*self.value.get()).assume_init_ref()) })
+    }
+
+    /// Returns the value, constructing it from the head fields `f`
+    /// returns if needed. If `f` panics, or initialization fails, this
+    /// panics, and so do later calls.
+    pub fn get_or_init<F>(&'static self, f: F) -> Pin<&'static T>
+    where
+        F: FnOnce() -> UninitGuard<T>,
+    {
+        self.once.call_once(|| {
+            // SAFETY: this is the only thread accessing the value.
+            let slot = unsafe { &mut *self.value.get() };
+
+            // SAFETY: the slot is static, so it's never reused.
+            init_in_place(unsafe { f().try_ensure_init_in(slot) }.ok());
+        });
+
+        self.get().unwrap()
+    }
+}
+
+impl<T: IncrStructInit> Default for PinnedOnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Leaves a value constructed in place there, without dropping it.
+/// Panics if initialization failed.
+fn init_in_place<T: IncrStructInit>(value: Option<crate::InPlace<'_, T>>) {
+    forget(value.expect("the pinned value failed to initialize"));
+}
+
+/// Constructs pinned thread-local structs on first use. Each thread
+/// gets its own struct, which lives in thread-local storage, and
+/// never moves. The initializer returns the head fields, like in
+/// [PinnedOnceLock::get_or_init].
+///
+/// ```rust
+/// use incrstruct::{pinned_thread_local, IncrStruct};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: &'a str,
+///     a: String,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a String) -> &'a str {
+///         a.as_str()
+///     }
+/// }
+///
+/// pinned_thread_local! {
+///     static MY_LOCAL: AStruct<'static> = AStruct::new_uninit_guard("hello".to_string());
+/// }
+///
+/// MY_LOCAL.with(|v| assert_eq!(v.get().b, "hello"));
+/// ```
+#[macro_export]
+macro_rules! pinned_thread_local {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        ::std::thread_local! {
+            $(#[$attr])*
+            $vis static $name: $crate::PinnedThreadLocal<$ty> = {
+                // Outside the unsafe block, so `$init` is checked as
+                // safe code.
+                let init: fn() -> $crate::UninitGuard<$ty> = || $init;
+
+                // SAFETY: thread-local storage never moves.
+                unsafe { $crate::PinnedThreadLocal::new(init) }
+            };
+        }
+        $crate::pinned_thread_local!($($rest)*);
+    };
+    () => {};
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum SlotState {
+    Uninited,
+    Initing,
+    Inited,
+}
+
+/// The thread-local cell created by [pinned_thread_local]. It
+/// constructs the struct in place on first use, and drops it with the
+/// thread.
+pub struct PinnedThreadLocal<T: IncrStructInit> {
+    init: fn() -> UninitGuard<T>,
+    state: Cell<SlotState>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T: IncrStructInit> PinnedThreadLocal<T> {
+    /// Returns a cell that constructs the struct using `init`.
+    ///
+    /// # Safety
+    ///
+    /// The cell must never move after `get` has been called, e.g.
+    /// because it's in thread-local storage.
+    pub const unsafe fn new(init: fn() -> UninitGuard<T>) -> Self {
+        Self {
+            init,
+            state: Cell::new(SlotState::Uninited),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, constructing it if needed. Panics if called
+    /// while constructing it, or after that panicked.
+    pub fn get(&self) -> Pin<&T> {
+        match self.state.get() {
+            SlotState::Inited => {}
+            SlotState::Uninited => {
+                self.state.set(SlotState::Initing);
+
+                // SAFETY: no references to the value exist before it's
+                // constructed.
+                let slot = unsafe { &mut *self.value.get() };
+
+                // SAFETY: the slot is only reused after the drop
+                // below.
+                init_in_place(unsafe { (self.init)().try_ensure_init_in(slot) }.ok());
+                self.state.set(SlotState::Inited);
+            }
+            SlotState::Initing => {
+                panic!("pinned thread-local used while constructing it, or after that failed")
+            }
+        }
+
+        // SAFETY: the value is fully initialized, and the caller of
+        // new promised the cell never moves.
+        unsafe { Pin::new_unchecked((*self.value.get()).assume_init_ref()) }
+    }
+}
+
+impl<T: IncrStructInit> Drop for PinnedThreadLocal<T> {
+    fn drop(&mut self) {
+        if self.state.get() == SlotState::Inited {
+            // SAFETY: the value is fully initialized, and is dropped
+            // in place.
+            unsafe { drop_in_place(self.value.get_mut().as_mut_ptr()) };
+        }
+    }
+}
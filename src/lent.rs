@@ -0,0 +1,46 @@
+//! Head fields lent exclusively to a tail field.
+
+use core::fmt;
+
+/// A head field borrowed mutably by a tail field, with
+/// `#[borrows(mut myfield)]`.
+///
+/// The tail holds the only reference to the value, so this gives no
+/// access to it through a shared reference, unlike a plain field,
+/// which stays readable through the struct. The value is available
+/// again once the tails are dropped, e.g. in `with_heads_mut` or
+/// after `into_heads`.
+#[derive(Default)]
+#[repr(transparent)]
+pub struct Lent<T>(pub(crate) T);
+
+impl<T> Lent<T> {
+    /// Wraps a value, to be lent to a tail field.
+    pub const fn new(v: T) -> Self {
+        Self(v)
+    }
+
+    /// Returns the value.
+    pub fn into_inner(this: Self) -> T {
+        this.0
+    }
+
+    /// Returns a mutable reference to the value. This requires
+    /// exclusive access to the struct, with no tail field borrowing
+    /// it, like in `with_heads_mut`.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Lent<T> {
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T> fmt::Debug for Lent<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Lent(..)")
+    }
+}
@@ -19,10 +19,10 @@
 //!   these structs directly in a `Vec`, which later has to reallocate
 //!   to grow. As long as you use `Vec<Pin<Box<MyStruct>>>`, like what the
 //!   high-level API provides with e.g. [new_box], it is safe.
-//!    - We could provide a special `Vec` (and other in-line containers)
-//!      that runs `force_init`. It could be eager or lazy, though the
-//!      lazy case would be complicated by borrowed slices. This would
-//!      clean up the heap usage (and cache utilization) further.
+//!    - [IncrVec] stores the structs inline, and initializes the tail
+//!      fields again whenever it has to move elements. This cleans up
+//!      the heap usage (and cache utilization) further. Other in-line
+//!      containers could do the same.
 //!
 //! # How To Define a Self-Referencing Struct
 //!
@@ -67,11 +67,50 @@
 //!
 //! You will likely always want a lifetime parameter, so you can refer
 //! back to it in tail fields. The first declared lifetime parameter
-//! is used for the `init_field_myfield` arguments.
+//! is used for the `init_field_myfield` arguments. If the struct has
+//! several lifetime parameters, use e.g.
+//! `#[incrstruct(self_lifetime = 'this)]` on the struct to select
+//! another one. The same lifetime should be used in all tail types,
+//! including nested ones like `Ref<'a, Ref<'a, T>>` and
+//! `Box<dyn Trait<'a> + 'a>`. Type and const
+//! parameters are forwarded to the Init trait, as in
+//! `impl<'a, const N: usize> BufInit<'a, N> for Buf<'a, N>`.
+//!
+//! If the names collide with existing items, use e.g.
+//! `#[init_trait(name = "AStructFns", fn_prefix = "build_")]` on the
+//! struct, to implement `AStructFns` with `build_myfield` functions
+//! instead.
 //!
 //! Unlike Ouroboros, you can only borrow from fields later in the
-//! struct (to enforce a sane drop order,) and only immutable
-//! references are allowed.
+//! struct (to enforce a sane drop order.) Borrows are immutable,
+//! unless written as `#[borrows(mut a)]`, see [Mutable
+//! Borrows](#mutable-borrows).
+//!
+//! The init function takes its parameters in the order they are
+//! listed in `#[borrows]`, so reordering fields in the struct doesn't
+//! change the signature. Listing a field twice is an error.
+//!
+//! An empty `#[borrows()]` makes a computed tail field, which is
+//! initialized without arguments. A trailing comma in the list is
+//! allowed. Fields with raw identifiers, like `r#type`, can be
+//! borrowed as `#[borrows(type)]`, and generated names drop the
+//! prefix, as in `init_field_type`.
+//!
+//! For one-liners, the initializer can be given inline, as in
+//! `#[borrows(a)] #[init(a.borrow())]`. The expression becomes the
+//! default body of `init_field_myfield`, with the borrowed fields in
+//! scope, so the trait doesn't need to implement it. With
+//! `#[init_err]`, it evaluates to a `Result`.
+//!
+//! Tuple structs work the same way, borrowing by index, like
+//! `#[borrows(1)]`, with the header as the last element. The trait
+//! functions are then named by index, like `init_field_0`.
+//!
+//! A struct without tail fields needs no header. Deriving
+//! `IncrStruct` on it still generates `new_box`, `new_rc` and
+//! `new_arc`, and an empty Init trait, so the derive can be used
+//! uniformly. Since nothing is self-referencing, the struct isn't
+//! `!Unpin`, and none of the other functions are generated.
 //!
 //! Lastly, you implement initialization functions in an
 //! auto-generated trait, named like the struct with `Init`
@@ -102,6 +141,7 @@
 //! `Rc` value:
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! # use std::cell::{Ref, RefCell};
 //! # use incrstruct::IncrStruct;
 //! # #[derive(IncrStruct)]
@@ -119,21 +159,151 @@
 //! # }
 //! let my_box = AStruct::new_box(RefCell::new(42));
 //! let my_rc = AStruct::new_rc(RefCell::new(42));
+//! let my_arc = AStruct::new_arc(RefCell::new(42));
 //!
 //! assert_eq!(*my_box.a.borrow(), *my_box.b);
 //! assert_eq!(*my_rc.a.borrow(), *my_rc.b);
+//! assert_eq!(*my_arc.a.borrow(), *my_arc.b);
+//! # }
 //! ```
 //!
+//! `new_arc` returns a `Pin<Arc<AStruct>>`, which can be shared
+//! across threads if the struct is `Send` and `Sync`.
+//!
+//! Without an allocator, [incr_pin] constructs the struct on the
+//! stack, and binds a pinned reference to it, like
+//! `incr_pin!(let my_value = AStruct::new(RefCell::new(42)))`.
+//! To construct it in memory you provide, e.g. a memory-mapped region
+//! or a custom allocator, the unsafe
+//! `AStruct::new_in_place(&mut slot, ...)` returns an [InPlace]
+//! guard, which drops the struct, and must not be leaked. The unsafe
+//! `AStruct::init_at(ptr, ...)` works on a raw pointer.
+//!
+//! `new_in::<P>` constructs the struct in any smart pointer `P`
+//! implementing [IncrContainer], e.g. `AStruct::new_in::<Box<_>>`,
+//! or a custom pointer into a pool.
+//!
+//! `AStruct::new_boxed_slice(heads)` creates a `Pin<Box<[AStruct]>>`
+//! from an iterator of head field tuples, initializing each element in
+//! place.
+//!
+//! [IncrArena] allocates memory for many structs at a time, and
+//! `AStruct::new_in_arena(&arena, ...)` constructs the struct in it,
+//! returning a pinned reference that lives as long as the arena. With
+//! the `bumpalo` feature, [IncrBumpArena] does the same in a
+//! `bumpalo::Bump` it owns.
+//!
+//! For many structs without one allocation each,
+//! `AStruct::emplace(&mut slab, ...)` constructs the struct in place,
+//! in a free slot of an [IncrSlab], and returns the key of the slot.
+//! The same function appends a boxed struct to a [PinnedVec], and
+//! returns a reference to it. Both implement [IncrEmplace].
+//!
+//! With the `triomphe` feature enabled, `new_triomphe` returns a
+//! [`triomphe::Arc`](https://docs.rs/triomphe), which has no weak
+//! reference count.
+//!
+//! If you later need shared ownership, `AStruct::into_rc` and
+//! `AStruct::into_arc` move a `Pin<Box<AStruct>>` into a new
+//! allocation, initializing the tail fields again.
+//!
 //! These are safe, since they return a
 //! [`Pin<>`](https://doc.rust-lang.org/std/pin/struct.Pin.html), and
 //! thus the value cannot be moved. If you do move the value (using
 //! unsafe code,) the self-references will still be pointing to the
-//! old place, so you need to run `AStruct::force_init`:
+//! old place, so you need to run `AStruct::force_init`. It drops the
+//! old tail fields first, so the old place must still be valid:
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! use core::mem::MaybeUninit;
+//! use core::pin::Pin;
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! # #[derive(IncrStruct)]
+//! # struct AStruct<'a> {
+//! #     #[borrows(a)]
+//! #     b: Ref<'a, i32>,
+//! #     a: RefCell<i32>,
+//! #     #[header]
+//! #     hdr: incrstruct::Header,
+//! # }
+//! # impl<'a> AStructInit<'a> for AStruct<'a> {
+//! #     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//! #         a.borrow()
+//! #     }
+//! # }
+//!
+//! let my_box = AStruct::new_box(RefCell::new(42));
+//! // Keeps the old place, without dropping the value in it.
+//! let old_place: Box<MaybeUninit<AStruct>> =
+//!     unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(my_box)).cast()) };
+//! let mut taken_value = unsafe { old_place.assume_init_read() };
+//!
+//! //assert_eq!(*taken_value.a.borrow(), *taken_value.b);  // UNSOUND!
+//!
+//! AStruct::force_init(&mut taken_value);
+//!
+//! assert_eq!(*taken_value.a.borrow(), *taken_value.b);  // Good
+//! # }
+//! ```
+//!
+//! For mutable shared values, `new_rc_refcell` returns a
+//! `Pin<Rc<PinRefCell<AStruct>>>`, and, with the `std` feature,
+//! `new_arc_mutex` returns a `Pin<Arc<PinMutex<AStruct>>>`. Unlike
+//! `RefCell` and `Mutex`, these pin their contents, so
+//! `my_rc.as_ref().borrow_mut()` returns a `Pin<RefMut<AStruct>>`,
+//! and the struct can't be moved out of the cell.
+//!
+//! If you really want to make a mess, you can use the low-level API,
+//! which gives you control over each initialization phase
+//! separately. This is useful e.g. in creating other wrappers that
+//! aren't supported directly. Take a look at the [new_box]
+//! function. Note that you must always use `Pin<>` to wrap your smart
+//! pointer, ensuring the value cannot be moved by Rust.
+//!
+//! `AStruct::ensure_init_pin` is a safer alternative to
+//! `ensure_init`. It takes a `Pin<&mut MaybeUninit<AStruct>>`, e.g.
+//! from `core::pin::pin!`, and returns a `Pin<&mut AStruct>`, so the
+//! struct can't be moved by mistake. You still promise that the head
+//! fields are initialized, and that the struct is dropped in place
+//! before the pinned storage goes away, since `MaybeUninit` never
+//! drops its contents:
+//!
+//! ```rust
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! # #[derive(IncrStruct)]
+//! # struct AStruct<'a> {
+//! #     #[borrows(a)]
+//! #     b: Ref<'a, i32>,
+//! #     a: RefCell<i32>,
+//! #     #[header]
+//! #     hdr: incrstruct::Header,
+//! # }
+//! # impl<'a> AStructInit<'a> for AStruct<'a> {
+//! #     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//! #         a.borrow()
+//! #     }
+//! # }
+//! let my_a = core::pin::pin!(unsafe { AStruct::new_uninit(RefCell::new(42)) });
+//! let my_a = unsafe { AStruct::ensure_init_pin(my_a) };
+//!
+//! assert_eq!(*my_a.b, 42);
+//! # unsafe { core::ptr::drop_in_place(my_a.get_unchecked_mut()) };
+//! ```
+//!
+//! # Trait Objects
+//!
+//! `Pin<Box<AStruct>>` coerces to `Pin<Box<dyn ATrait>>` like any
+//! other box, as long as the type is known at the coercion site. No
+//! unsafe code, or unpinning, is needed. The same goes for `Pin<Rc<>>`
+//! and `Pin<Arc<>>`:
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! use core::pin::Pin;
 //! # use std::cell::{Ref, RefCell};
-//! # use std::rc::Rc;
 //! # use incrstruct::IncrStruct;
 //! # #[derive(IncrStruct)]
 //! # struct AStruct<'a> {
@@ -148,49 +318,1049 @@
 //! #         a.borrow()
 //! #     }
 //! # }
+//! trait Value {
+//!     fn value(&self) -> i32;
+//! }
+//!
+//! impl<'a> Value for AStruct<'a> {
+//!     fn value(&self) -> i32 {
+//!         *self.b
+//!     }
+//! }
+//!
+//! let my_dyn: Pin<Box<dyn Value>> = AStruct::new_box(RefCell::new(42));
+//!
+//! assert_eq!(my_dyn.value(), 42);
+//! # }
+//! ```
+//!
+//! # Named Constructors
+//!
+//! The generated `new_box`, `new_rc` and `new_arc` functions take
+//! the head fields as arguments, in declaration order. Often, there
+//! are other ways to construct a value. Using
+//! `#[constructor(from_path(path: &Path))]` on the struct, a
+//! `from_path` function is added to the Init trait, which maps the
+//! arguments to a tuple of head fields (in the same order as in
+//! `new_box`.) Then `new_box_from_path`, `new_rc_from_path` and
+//! `new_arc_from_path` are generated. If `#[init_err]` is used, the
+//! mapping function returns a `Result`, just like
+//! `init_field_myfield`.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! #[constructor(from_str(s: &str))]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn from_str(s: &str) -> (RefCell<i32>,) {
+//!         (RefCell::new(s.parse().unwrap()),)
+//!     }
+//!
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let my_box = AStruct::new_box_from_str("42");
+//!
+//! assert_eq!(*my_box.b, 42);
+//! # }
+//! ```
+//!
+//! Head values often come from fallible conversions, like opening a
+//! file, that fail differently than the tail fields. With
+//! `#[head_err(MyError)]`, the mapping functions return
+//! `Result<_, MyError>`, and so do the generated constructors. An
+//! `#[init_err]` error, or [Error] in no-panic mode, is converted
+//! using `From`.
+//!
+//! With many head fields, the positional arguments of `new_box` are
+//! easy to mix up. An `AStructHeads` struct, with the head fields, is
+//! also generated, together with `new_box_from_heads`,
+//! `new_rc_from_heads` and `new_arc_from_heads`, which take it
+//! instead.
+//!
+//! The reverse is `AStruct::into_heads`, which takes a `Pin<Box<Self>>`,
+//! drops the tail fields, and returns the `AStructHeads`. In boxed mode,
+//! it takes `Self` instead.
+//!
+//! Deriving `Clone` would copy the tail fields, which still borrow
+//! from the original struct. Instead, `#[incrstruct(clone)]`
+//! generates `clone_box`, `clone_rc` and `clone_arc`, which clone the
+//! head fields, and construct a new struct from them, initializing
+//! the tail fields again. All head fields must implement `Clone`. In
+//! boxed mode, the struct implements `Clone` itself, unless it has an
+//! initialization error, in which case `try_clone` is generated.
+//!
+//! Similarly, `#[incrstruct(derive_heads(PartialEq, Eq, Hash))]`
+//! implements the listed traits by comparing, or hashing, only the
+//! head fields. The tail fields are derived from them, and comparing
+//! borrowed views, e.g. by address, is meaningless.
+//!
+//! To change head fields after construction, use
+//! `AStruct::with_heads_mut(this, f)`. It drops the tail fields, calls
+//! `f` with `&mut AStructHeads`, and initializes the tail fields again
+//! before returning what `f` returned. In boxed mode, it takes `&mut
+//! Self` instead of `Pin<&mut Self>`. With `#[init_err]`, it returns
+//! `Result<R, E>`, and requires the head fields to implement `Clone`.
+//! They are copied before calling `f`, and if initializing the tail
+//! fields from the changed ones fails, the copy is moved back, the
+//! tail fields are initialized from it again, and the error is
+//! returned. The struct is then as it was before the call. In no-panic
+//! mode, it returns `Result<R, Error<E>>`.
+//!
+//! If `f` panics, or initializing the tail fields panics, or fails
+//! again from the copy, the process aborts, since the tail fields are
+//! already dropped, and the struct can be neither used nor dropped.
+//!
+//! To change a single head field, `AStruct::set_myhead(this, myhead)`
+//! is cheaper. It only initializes the tail fields that borrow
+//! `myhead` again, directly or through other tail fields, following
+//! the `#[borrows]` attributes. Other tail fields are left as is.
+//! Similarly, `AStruct::refresh_mytail(this)` initializes `mytail`,
+//! and the tail fields borrowing it, again. This is useful when a tail
+//! field depends on something outside the struct, like a `Cell` head
+//! field.
+//!
+//! Like `with_heads_mut`, these functions take a struct that is in
+//! use, and return `Result<(), E>` with `#[init_err]`, or
+//! `Result<(), Error<E>>` in no-panic mode. If an `init_field_myfield`
+//! function fails, `set_myhead` moves the old value back, initializes
+//! the tail fields from it again, and returns the error, so the head
+//! field needn't implement `Clone`. `refresh_mytail` initializes the
+//! new value of `mytail` before dropping the old one, so if that fails,
+//! or panics, nothing has changed. If a tail field borrowing it fails,
+//! the old value is moved back, and the tail fields borrowing it are
+//! initialized from it again. Either way, the tail fields are then
+//! marked as dirty, for a later `recompute`. A `mytail` borrowing a
+//! head field mutably is dropped first instead, since the old value
+//! holds the borrow, so it can only be initialized again from the same
+//! fields.
+//!
+//! If initializing from the old value fails too, or anything else
+//! panics, the process aborts, since the dropped tail fields could
+//! otherwise be read, or dropped again.
+//!
+//! # Cyclic References
+//!
+//! A private head field of type [PinRcWeak]`<Self>`, marked
+//! `#[cyclic(rc)]`, can point back to the struct itself, like with
+//! `Rc::new_cyclic`. The generated `AStruct::new_rc_cyclic` takes the
+//! other head fields, and fills in the weak pointer before
+//! initializing the tail fields. Similarly, `#[cyclic(arc)]` on a
+//! [PinArcWeak]`<Self>` generates `new_arc_cyclic`. Tail fields can't
+//! borrow the weak pointer, since it could then be upgraded before
+//! the struct is complete.
+//!
+//! Upgrading the weak pointer returns a `Pin<Rc<Self>>`, so the struct
+//! can't be moved out of it. The field must be private, so it can't be
+//! replaced with a pointer to something else.
+//!
+//! # Init Arguments
+//!
+//! Values only needed during initialization, like configuration,
+//! don't have to be stored in the struct. Using `#[init_args(config:
+//! Config)]` on the struct, every `init_field_myfield` function takes
+//! an extra `config: &Config` parameter, last. The generated
+//! constructors, `ensure_init`, `ensure_init_with` and `force_init`
+//! take the same parameters after the head fields, and pass them on.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! struct Config {
+//!     scale: i32,
+//! }
+//!
+//! #[derive(IncrStruct)]
+//! #[init_args(config: Config)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: (Ref<'a, i32>, i32),
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>, config: &Config) -> (Ref<'a, i32>, i32) {
+//!         (a.borrow(), config.scale)
+//!     }
+//! }
+//!
+//! let my_box = AStruct::new_box(RefCell::new(42), &Config { scale: 2 });
+//!
+//! assert_eq!(*my_box.b.0 * my_box.b.1, 84);
+//! # }
+//! ```
+//!
+//! Since the runtime can't provide the arguments, `into_rc`,
+//! `into_arc` and `ensure_init_phase` aren't generated, and the
+//! option can't be combined with `boxed`, `default`, `zeroed` or
+//! `async_init`. Generic code, like [IncrArena], [IncrVec] and
+//! `par_force_init`, requires a struct without `#[init_args]`, so
+//! using it is a compile error:
+//!
+//! ```rust,compile_fail
+//! # use incrstruct::IncrStruct;
+//! # struct Config;
+//! #[derive(IncrStruct)]
+//! #[init_args(config: Config)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: &'a i32,
+//!     a: i32,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a i32, _config: &Config) -> &'a i32 {
+//!         a
+//!     }
+//! }
+//!
+//! let arena = incrstruct::IncrArena::<AStruct>::new();
+//! ```
+//!
+//! # Handling Failures
+//!
+//! Using the `#[init_err(AnError)]` attribute on the struct, the
+//! `init_field_myfield` functions are expected to return a `Result<T,
+//! AnError>` instead of the plain value. If any tail field
+//! initialization fails, the previously initialized tail fields are
+//! dropped before `ensure_init` returns. This also causes relevant
+//! generated functions on your struct to return a corresponding
+//! `Result`:
+//!
+//! - `new_box -> Result<Pin<Box<AStruct>>, AnError>`
+//! - `new_rc -> Result<Pin<Rc<AStruct>>, AnError>`
+//! - `new_arc -> Result<Pin<Arc<AStruct>>, AnError>`
+//! - `ensure_init -> Result<&mut AStruct, AnError>`
+//! - `force_init -> Result<(), AnError>`
+//!
+//! A failed (or panicking) initialization poisons the header. Further
+//! calls to `ensure_init` then fail with `StateError::Poisoned`, while
+//! `force_init` and `drop_uninit` still work. Use
+//! `Header::is_poisoned` to check for this state. Only these
+//! functions check the header. Field access doesn't, but a poisoned
+//! struct is only reachable through unsafe code, like the
+//! `MaybeUninit` passed to `ensure_init`, since functions on a struct
+//! in use abort instead of poisoning it. More generally,
+//! [Header::state] returns the [InitState], and
+//! `AStruct::is_initialized(&my_a)` whether all tail fields are
+//! initialized, e.g. after a failed `force_init`.
+//!
+//! If you are using the unsafe `new_uninit`, and `ensure_init` fails,
+//! remember to run `drop_uninit` to stop memory leaks.
+//!
+//! # No-Panic Mode
+//!
+//! By default, calling e.g. `ensure_init` on a struct that is already
+//! initialized, or calling `ensure_init` or `force_init` re-entrantly
+//! from an `init_field_myfield` function, panics. Using the
+//! `#[incrstruct(no_panic)]` attribute on the struct, these state
+//! violations are instead returned as [Error::State], and all
+//! generated functions return a `Result<_, incrstruct::Error<AnError>>`:
+//!
+//! - `new_box -> Result<Pin<Box<AStruct>>, Error<AnError>>`
+//! - `new_rc -> Result<Pin<Rc<AStruct>>, Error<AnError>>`
+//! - `new_arc -> Result<Pin<Arc<AStruct>>, Error<AnError>>`
+//! - `ensure_init -> Result<&mut AStruct, Error<AnError>>`
+//! - `force_init -> Result<(), Error<AnError>>`
+//! - `drop_uninit -> Result<(), StateError>`
+//!
+//! Without `#[init_err]`, the error type is `Error<Infallible>`, like
+//! the `Error` of the Init trait. No debug assertions are generated in
+//! this mode.
+//!
+//! The guarantee is that the generated functions of a no-panic
+//! struct don't panic. They only call the runtime functions that
+//! return state violations, not the ones that panic on them.
+//! Re-initializing a live struct, with `with_heads_mut`,
+//! `set_myfield`, `refresh_myfield`, `recompute`, or `force_init` in
+//! boxed mode, still aborts the process if restoring the old values
+//! fails too, or anything panics, since the struct could then be
+//! neither used nor dropped. An abort doesn't unwind, so it's not a
+//! panic to callers.
+//!
+//! Enabling the `no_panic` cargo feature verifies this with the
+//! [no-panic](https://docs.rs/no-panic) crate, for the runtime
+//! functions that check and update the header state. The generic code
+//! can only be checked once it's instantiated, so the tests also
+//! verify a no-panic struct, going through `new_uninit`,
+//! `ensure_init`, `force_init`, `with_heads_mut`, `set_myfield`,
+//! `refresh_myfield`, `recompute` and `drop_uninit`. No-panic only
+//! works in optimized builds, so run `cargo test --release --features
+//! no_panic`. Other builds skip the checks. Not covered are:
+//!
+//! - your own `init_field_myfield` functions, and the `Clone` and
+//!   `Drop` impls of your fields, and
+//! - allocation, which aborts on failure, like `Box::new`.
+//!
+//! Apply `#[no_panic]` to your own functions that create or
+//! re-initialize a struct, to verify them together with your init
+//! functions.
+//!
+//! # Mutable Borrows
+//!
+//! A tail field can hold the only reference to a head field, with
+//! `#[borrows(mut a)]`. The head must have type [Lent]`<T>`, and its
+//! init function gets a `&'a mut T`. The derive macro checks that no
+//! other tail borrows the head, and `Lent` gives no access to it
+//! through the struct, so the reference is never aliased. The tail
+//! can't be borrowed by other tails, and can be mutated with the
+//! generated `AStruct::with_myfield_mut(Pin<&mut Self>, f)`. This
+//! doesn't work in boxed mode, or with `#[lazy]` tails.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use incrstruct::{IncrStruct, Lent};
+//! #[derive(IncrStruct)]
+//! struct AStruct<'a> {
+//!     #[borrows(mut buf)]
+//!     halves: (&'a mut [u8], &'a mut [u8]),
+//!     buf: Lent<[u8; 4]>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_halves(buf: &'a mut [u8; 4]) -> (&'a mut [u8], &'a mut [u8]) {
+//!         buf.split_at_mut(2)
+//!     }
+//! }
+//!
+//! let mut my_a = AStruct::new_box(Lent::new([1, 2, 3, 4]));
+//! AStruct::with_halves_mut(my_a.as_mut(), |halves| halves.1[0] = 5);
+//!
+//! assert_eq!(*my_a.halves.1, [5, 4]);
+//! # }
+//! ```
+//!
+//! # Mutable Heads Through Token Cells
+//!
+//! When a head is borrowed by several tails, or must stay readable,
+//! tails hold immutable references to it, so mutating the head
+//! usually means wrapping it in a `RefCell`, and keeping a `Ref` in
+//! the tail. A token-cell crate, like
+//! [qcell](https://docs.rs/qcell), avoids the runtime borrow
+//! checks: the head is wrapped in e.g. a `TCell` or `LCell`, and the
+//! tail holds a reference to the cell. Whoever holds the owner token
+//! can then mutate the head contents through a shared reference,
+//! without invalidating the tails. See the example below.
+//!
+//! # Initialization Phases
+//!
+//! Sometimes, a tail field can't be computed until the struct has
+//! been registered somewhere, or some other external event has
+//! happened. Tail fields can be split into numbered phases using
+//! `#[phase(n)]`. Tails without the attribute are in phase one, and
+//! head fields are (implicitly) in phase zero.
+//!
+//! A tail field can only borrow from fields in the same, or earlier,
+//! phases. To keep the drop order sane, fields in later phases must
+//! come before fields in earlier phases in the struct.
+//!
+//! If there is more than one phase, an unsafe
+//! `ensure_init_phase(this, n)` function is generated. It runs all
+//! phases up to, and including, `n` that haven't already run. The
+//! normal `ensure_init`, `new_box` and `new_rc` functions run all
+//! remaining phases. If a phase fails, only its own tail fields are
+//! dropped, and `drop_uninit` takes care of the rest.
+//!
+//! While phases are pending, the unsafe `AStruct::try_myfield(&a)`
+//! accessors return `Some(&myfield)` for tail fields that have been
+//! initialized, and `None` otherwise. This lets early tails be used
+//! before the whole struct is ready.
+//!
+//! ```rust
+//! use std::cell::{Ref, RefCell};
+//! use incrstruct::IncrStruct;
+//!
+//! #[derive(IncrStruct)]
+//! struct AStruct<'a> {
+//!     #[borrows(b, id)]         // Only computable after an ID is
+//!     #[phase(2)]               // assigned.
+//!     c: (i32, &'a Ref<'a, i32>),
+//!
+//!     #[borrows(a)]             // Implicitly in phase one.
+//!     b: Ref<'a, i32>,
+//!
+//!     a: RefCell<i32>,
+//!     id: i32,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_c(b: &'a Ref<'a, i32>, id: &'a i32) -> (i32, &'a Ref<'a, i32>) {
+//!         (*id, b)
+//!     }
+//!
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let mut my_a = unsafe { AStruct::new_uninit(RefCell::new(42), 0) };
+//!
+//! unsafe { AStruct::ensure_init_phase(&mut my_a, 1) };
+//!
+//! // Assign an ID, and complete initialization.
+//! unsafe { (*my_a.as_mut_ptr()).id = 4711 };
+//! let my_a = unsafe { AStruct::ensure_init(&mut my_a) };
+//!
+//! assert_eq!(my_a.c.0, 4711);
+//! # unsafe { core::ptr::drop_in_place(my_a) };
+//! ```
+//!
+//! # Unwind Safety
+//!
+//! [Header] is always `UnwindSafe` and `RefUnwindSafe`, so a derived
+//! struct is as unwind-safe as its head and tail fields are. A
+//! `RefCell` head makes the struct `!RefUnwindSafe`, just like it
+//! would in any other struct.
+//!
+//! Note that `&mut AStruct` is never `UnwindSafe`. This matters for
+//! `force_init`: if an `init_field_myfield` function panics, the
+//! struct is left with some tail fields initialized, and dropping it
+//! normally would drop uninitialized fields. Don't wrap `force_init`
+//! in `AssertUnwindSafe` unless you also leak the value (e.g. with
+//! `core::mem::forget`) when a panic is caught.
+//!
+//! Panics during initialization are otherwise cleaned up. The tail
+//! fields of the interrupted phase are dropped while unwinding, and
+//! the header is poisoned in the previous phase, so `drop_uninit` still
+//! works after a panicking `ensure_init`. The `new_box` and `new_rc`
+//! functions also drop the head fields, so they are safe to use with
+//! `catch_unwind`.
+//!
+//! # Boxed Mode
+//!
+//! If ergonomics matter more than avoiding allocations, use
+//! `#[incrstruct(boxed)]` on the struct. All borrowed fields must then
+//! be of type [AliasableBox<T>](AliasableBox), and the
+//! `init_field_myfield` functions get `&'a T`, pointing into the heap.
+//! Since moving the struct doesn't move the borrowed data, the
+//! generated `new` and `new_box` functions return `AStruct` and
+//! `Box<AStruct>`, without `Pin`. A plain `Box<T>` doesn't work, since
+//! moving it asserts unique access to its contents, which invalidates
+//! the tails borrowing them.
+//!
+//! Replacing or mutating a borrowed field would leave the tails
+//! dangling, so borrowed fields must be private, and read-only
+//! getters, like `AStruct::a(&my_a)`, are generated for them.
+//! `AliasableBox` only gives shared access to its contents. Tail
+//! fields must be private too, like with `private_tails`, since moving
+//! one out of a `&mut AStruct`, e.g. with `mem::replace`, would let it
+//! outlive what it borrows.
+//!
+//! Safe code holds the struct while `force_init` re-initializes it, so
+//! if an `init_field_myfield` function then fails, or panics, the
+//! process aborts, like with `with_heads_mut`.
+//!
+//! Other pointer types work too, by marking the head field with
+//! `#[stable_deref]`. The field type must implement [StableDeref],
+//! like `Rc<T>` and `Arc<T>` do, and `init_field_myfield` gets a
+//! reference to its deref target, e.g. `&'a str` for an `Rc<str>`. Boxed mode is
+//! enabled automatically for a struct with `#[stable_deref]` fields,
+//! if all borrowed fields are `AliasableBox<T>` or `#[stable_deref]`.
+//!
+//! # Typestate Mode
+//!
+//! A struct that is always created with `new_box`, and never
+//! re-initialized with `force_init`, doesn't need runtime state. With
+//! `#[incrstruct(no_header)]`, the `#[header]` field is a
+//! `PhantomPinned`, taking up no space, and the initialization state is
+//! tracked by types instead. `AStruct::new_uninit(...)` returns an
+//! `AStructUninit`, owning a box with only the head fields
+//! initialized, and `ensure_init` consumes it, returning
+//! `Pin<Box<AStruct>>`. Dropping an `AStructUninit` drops the head
+//! fields. The mode needs the `alloc` feature, and doesn't support
+//! phases, lazy tails, init arguments, or the other options.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! #[incrstruct(no_header)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: std::marker::PhantomPinned,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let my_a = AStruct::new_uninit(RefCell::new(42)).ensure_init();
+//! assert_eq!(*my_a.b, 42);
+//! # }
+//! ```
+//!
+//! # Nested Structs
+//!
+//! A head field can itself be an incremental struct, stored inline.
+//! Mark it `#[nested]`, and it's initialized again, like with
+//! `force_init`, before the tail fields of the enclosing struct, both
+//! in `ensure_init` and in `force_init`. This fixes its tail fields
+//! after it moved into the enclosing struct, and lets tails borrow
+//! from it. Its init error must convert into the enclosing init
+//! error, using `From`.
+//!
+//! # Detecting Moves
+//!
+//! Enabling the `move_check` cargo feature records the address of the
+//! struct in the header when it's fully initialized. Calling
+//! `AStruct::debug_assert_not_moved(&my_a)` then panics in debug
+//! builds if the struct has moved since, pointing at a missing
+//! `force_init` before any dangling reference is used. The generated
+//! tail accessors, e.g. `AStruct::with_b`, lazy tail getters, and the
+//! getters of `#[incrstruct(private_tails)]`, make the same check, so
+//! a moved struct is caught on access. Without the feature, the check
+//! does nothing, and the header doesn't grow.
+//!
+//! # Debugging
+//!
+//! A derived `Debug` would read tail fields that may not be
+//! initialized. `#[incrstruct(debug)]` implements `Debug` by printing
+//! the head fields, the tail fields the header marks as initialized,
+//! `<uninit>` for the others, and the header state. For a partial
+//! struct, e.g. from `new_uninit`, or after a failed `ensure_init`,
+//! the unsafe `AStruct::debug_uninit(&my_a)` returns the same view.
+//! All fields must implement `Debug`.
+//!
+//! # Default Values
+//!
+//! Head fields with `#[default]` are initialized with
+//! `Default::default()`, and those with `#[default = expr]` with the
+//! expression. They are left out of the arguments of the constructors,
+//! `new_uninit`, and the tuples returned by named constructors. They
+//! are still part of `AStructHeads`, so `new_box_from_heads` can set
+//! them.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::RefCell;
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! struct AStruct<'a> {
+//!     #[borrows(a, scale)]
+//!     b: i32,
+//!
+//!     a: RefCell<i32>,
+//!     #[default = 2]
+//!     scale: i32,
+//! #   #[default]
+//! #   _ph: std::marker::PhantomData<&'a ()>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>, scale: &'a i32) -> i32 {
+//!         *a.borrow() * scale
+//!     }
+//! }
+//!
+//! let my_box = AStruct::new_box(RefCell::new(21));
+//!
+//! assert_eq!(my_box.b, 42);
+//! # }
+//! ```
+//!
+//! A struct can't implement `Default`, since its tail fields must be
+//! initialized in place. Using `#[incrstruct(default)]` on a struct
+//! where all head fields without `#[default]` implement `Default`
+//! allows it to be used with [Lazy], which implements `Default`, and
+//! creates a boxed value on first use. It also generates
+//! `new_box_default()`, `new_rc_default()` and `new_arc_default()`,
+//! like the normal constructors but with default head fields, and
+//! `default_box()`, which returns the same type as `new_box()`. The
+//! latter needs a struct without `#[init_args]`, and isn't generated
+//! in no-panic mode.
+//!
+//! For singletons in a `static`, [StaticLazy] constructs the struct
+//! in place, in static storage, on first use. The head fields come
+//! from a function, or from `#[incrstruct(default)]` using
+//! `StaticLazy::new_default()`. The struct never moves, so no
+//! allocation or `force_init` is needed.
+//!
+//! [PinnedOnceLock] does the same, but takes the head fields on first
+//! use, and [pinned_thread_local] constructs one struct per thread,
+//! in thread-local storage. Their initializers return the head fields,
+//! using the generated `AStruct::new_uninit_guard`, so no unsafe code
+//! is needed.
+//!
+//! # Async Initialization
+//!
+//! With the `async` cargo feature, `#[incrstruct(async_init)]`
+//! generates a second trait, `AStructInitAsync`, with an `async fn`
+//! for each tail field, and the constructors `new_box_async`,
+//! `new_rc_async` and `new_arc_async`, as well as
+//! `ensure_init_async`. Phases are awaited in order. If the future is
+//! dropped before it completes, the tail fields initialized so far
+//! are dropped. The struct must still implement `AStructInit` for the
+//! synchronous functions, like `force_init`.
+//!
+//! # Zeroed Heads
+//!
+//! With the `bytemuck` cargo feature, `#[incrstruct(zeroed)]` on a
+//! struct whose head fields all implement `bytemuck::Zeroable`
+//! generates `AStruct::new_box_zeroed()`. It allocates zeroed memory,
+//! and only runs the tail initialization, so large buffers aren't
+//! written twice. Without the feature, the option is a compile
+//! error.
+//!
+//! # Memory-Mapped Files
+//!
+//! With the `memmap2` cargo feature, `#[incrstruct(mmap)]` on a
+//! struct with a single head field of type `memmap2::Mmap` generates
+//! `AStruct::new_box_from_path(path)`. It opens and maps the file,
+//! and then initializes the tail fields, which can parse views
+//! directly into the mapped bytes. The mapped bytes never move, but
+//! the function is unsafe, like `Mmap::map`, since the file must not
+//! be modified while it is mapped.
+//!
+//! Without `#[init_err]`, it returns a `std::io::Result`. Otherwise,
+//! the error type must implement `From<std::io::Error>`. With
+//! `no_panic`, `#[init_err]` is required.
+//!
+//! # Serde
+//!
+//! With the `serde` cargo feature, `#[incrstruct(serde)]` implements
+//! `Serialize` by writing only the head fields. The tail fields are
+//! derived from them, so they are never serialized. `AStructHeads`
+//! implements `Deserialize`, and `AStruct::deserialize_box` reads the
+//! head fields, and constructs a `Pin<Box<AStruct>>` using
+//! `new_box_from_heads`. It can be used with
+//! `#[serde(deserialize_with = "AStruct::deserialize_box")]`. In
+//! boxed mode, the struct can move, so it implements `Deserialize`
+//! itself. Initialization errors become custom deserialization
+//! errors. This can't be combined with `#[init_args]`.
+//!
+//! ```rust
+//! # #[cfg(feature = "serde")] {
+//! use std::cell::{Ref, RefCell};
+//! use incrstruct::IncrStruct;
+//!
+//! #[derive(IncrStruct)]
+//! #[incrstruct(serde)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let my_box = AStruct::new_box(RefCell::new(42));
+//! let json = serde_json::to_string(&*my_box).unwrap();
+//!
+//! assert_eq!(json, r#"{"a":42}"#);
+//!
+//! let mut de = serde_json::Deserializer::from_str(&json);
+//! let my_box = AStruct::deserialize_box(&mut de).unwrap();
+//!
+//! assert_eq!(*my_box.b, 42);
+//! # }
+//! ```
+//!
+//! # Yoke
+//!
+//! With the `yoke` cargo feature, `#[incrstruct(yoke)]` converts a
+//! struct with one head field and one tail field to and from
+//! [yoke](https://docs.rs/yoke). The head field is the cart, and must
+//! be `#[stable_deref]`, and the tail field is the
+//! yokeable, with the struct lifetime replaced by `'static`.
+//! `AStruct::into_yoke(my_a)` returns a `Yoke`, and
+//! `AStruct::from_yoke(yoke)` takes its cart back. Both initialize
+//! the tail field again.
+//!
+//! # Pinned Init
+//!
+//! With the `pinned_init` cargo feature, `AStruct::pin_init(heads...)`
+//! returns an `impl PinInit<AStruct, E>`, for allocators and macros
+//! speaking the [pinned-init](https://docs.rs/pinned-init) protocol,
+//! like `stack_pin_init!`. The error type is the `#[init_err]` type, or
+//! `Infallible`. It's the same as `AStruct::init_at`, so on error,
+//! the head fields are dropped, and the slot is left uninitialized.
+//! It's not generated in boxed mode, or with `#[init_args]`.
+//!
+//! # Thread-Safe Initialization
+//!
+//! With `#[incrstruct(sync)]`, the `#[header]` field must have type
+//! [SyncHeader] instead of `Header`, and the unsafe
+//! `AStruct::ensure_init_sync(ptr)` is generated. It works like
+//! `ensure_init`, but takes a raw pointer, e.g. from `Arc::as_ptr` on
+//! an `Arc<MaybeUninit<AStruct>>`, and can be called from many
+//! threads at once. The first caller initializes the tail fields,
+//! while the others wait, and then get the same reference. Once
+//! initialized, a call is a single atomic load, and only ever forms
+//! shared references to the struct. If
+//! initialization fails, the struct is poisoned, and later callers
+//! see that instead. As with `new_uninit`, dropping the struct is up
+//! to the caller.
+//!
+//! When built with `--cfg loom`, [SyncHeader] uses the atomics of the
+//! [loom](https://docs.rs/loom) crate, so the synchronization can be
+//! model checked, both in this crate and in code embedding it.
+//!
+//! # Send and Sync
+//!
+//! `#[incrstruct(unsafe_send)]` and `#[incrstruct(unsafe_sync)]`
+//! implement the `Send` and `Sync` traits explicitly, together with
+//! compile-time assertions that every head and tail field type
+//! implements them. If a field later suppresses the auto trait, e.g.
+//! a `Ref` tail, this fails at the struct definition, instead of at
+//! some distant use site.
+//!
+//! # Stubbing Tails
+//!
+//! For tests, the low-level `AStruct::ensure_init_with` function
+//! initializes the tail fields using closures, instead of the Init
+//! trait. There is one closure per tail field, in initialization
+//! order (i.e. reverse declaration order,) taking the same arguments
+//! as `init_field_myfield`. This allows creating a value with stubbed
+//! tails without defining a second struct.
+//!
+//! # Builders
+//!
+//! When initialization needs to capture local state, implementing the
+//! Init trait is awkward. With `#[incrstruct(builder)]`,
+//! `AStruct::builder(heads...)` returns an `AStructBuilder`, which
+//! has a `myfield_builder` method per tail field, taking a closure
+//! with the same arguments as `init_field_myfield`. Once all closures
+//! are set, `build()` creates a `Pin<Box<AStruct>>` in place. With
+//! `#[init_err]`, or in no-panic mode, the names are
+//! `AStructTryBuilder`, `try_builder` and `try_build` instead, and
+//! the closures return a `Result`. The struct must still implement
+//! the Init trait for the other functions.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! #[incrstruct(builder)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: (Ref<'a, i32>, &'a str),
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//! # impl<'a> AStructInit<'a> for AStruct<'a> {
+//! #     fn init_field_b(a: &'a RefCell<i32>) -> (Ref<'a, i32>, &'a str) {
+//! #         todo!()
+//! #     }
+//! # }
+//!
+//! let name = String::from("answer");
+//! let my_box = AStruct::builder(RefCell::new(42))
+//!     .b_builder(|a| (a.borrow(), name.as_str()))
+//!     .build();
+//!
+//! assert_eq!(*my_box.b.0, 42);
+//! assert_eq!(my_box.b.1, "answer");
+//! # }
+//! ```
+//!
+//! # Field Ordering
+//!
+//! Porting a large struct means ordering its fields so every field
+//! comes before the fields it borrows. Instead of the derive, the
+//! `#[incrstruct::incrstruct]` attribute macro can do that. It sorts
+//! the fields, keeping the original order where possible, moves the
+//! `#[header]` field last, and then derives `IncrStruct`. If there is
+//! no `#[header]` field, a private `hdr` [Header] field is added, with
+//! the smallest bitsets that fit the tail fields. Options are given as
+//! arguments, e.g.
+//! `#[incrstruct::incrstruct(no_panic)]`. Note that the order of head
+//! fields decides the order of constructor arguments.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! #[incrstruct::incrstruct]
+//! struct AStruct<'a> {
+//!     a: RefCell<i32>,
+//!
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let my_box = AStruct::new_box(RefCell::new(42));
+//!
+//! assert_eq!(*my_box.b, 42);
+//! # }
+//! ```
+//!
+//! # One-Shot Construction
+//!
+//! If all tail fields are in the same phase, and only borrow head
+//! fields, `AStruct::new_box_with(heads..., f)` is also generated. It
+//! calls `f` once, with references to the head fields, in the same
+//! order as the arguments, and `f` returns a tuple of all tail
+//! values, in initialization order. With `#[init_err]`, `f` returns
+//! a `Result`. This avoids using the Init trait for simple cases,
+//! though the struct must still implement it.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! # #[derive(IncrStruct)]
+//! # struct AStruct<'a> {
+//! #     #[borrows(a)]
+//! #     b: Ref<'a, i32>,
+//! #     a: RefCell<i32>,
+//! #     #[header]
+//! #     hdr: incrstruct::Header,
+//! # }
+//! # impl<'a> AStructInit<'a> for AStruct<'a> {
+//! #     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//! #         todo!()
+//! #     }
+//! # }
+//! let my_box = AStruct::new_box_with(RefCell::new(42), |a| (a.borrow(),));
+//!
+//! assert_eq!(*my_box.b, 42);
+//! # }
+//! ```
+//!
+//! # Lazy Tails
+//!
+//! A tail field marked `#[lazy]` must have type `OnceCell<T>` or
+//! `OnceLock<T>`. It starts out empty, and `init_field_myfield`
+//! returns a `T`, which is only computed the first time the generated
+//! accessor, `AStruct::myfield(&a)`, is called. With `#[init_err]`,
+//! the accessor returns a `Result`, and leaves the cell empty on
+//! failure. Lazy tails have no closure in `ensure_init_with`.
+//!
+//! # Field Accessors
+//!
+//! Reading a tail field directly gives a type using the struct
+//! lifetime, like `&Ref<'a, i32>`, which then shows up in the
+//! signatures of any function it's passed to. Marking a tail field
+//! `#[covariant]` generates `AStruct::borrow_myfield(&a)`, which
+//! returns `&'this Ref<'this, i32>` instead, where `'this` is the
+//! borrow of the struct. This only compiles if the field type is
+//! covariant in the lifetime. `#[not_covariant]` documents that a
+//! field isn't, and generates no accessor.
+//!
+//! For any tail field, `AStruct::with_myfield(&a, |b| ...)` calls the
+//! closure with a reference to the field, and returns its result. The
+//! closure must work for any lifetime in place of the struct
+//! lifetime, so nothing borrowed from the field can escape it.
+//! Similarly, `AStruct::with_all(&a, |fields| ...)` passes an
+//! `AStructBorrowedFields`, holding a reference to each field except
+//! the header, which can be destructured in the closure parameters.
+//!
+//! Like pin-project, with `#[incrstruct(project)]`,
+//! `my_a.as_mut().project()` returns an `AStructProjection`, with a
+//! `Pin<&mut T>` for each head field no tail borrows, and a shared
+//! reference to every other field. This is what implementing `Future`
+//! or `Stream` on the struct needs, without unsafe code.
+//!
+//! As with pin-project, the struct can then not implement `Drop`,
+//! since `drop` gets `&mut self`, and could move a pinned field out.
+//! Put the drop logic in a field type instead. A manual `Unpin`
+//! implementation is already rejected for every struct.
+//!
+//! ```compile_fail
+//! # use std::cell::{Ref, RefCell};
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! #[incrstruct(project)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//! # impl<'a> AStructInit<'a> for AStruct<'a> {
+//! #    fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//! #        a.borrow()
+//! #    }
+//! # }
 //!
-//! let my_rc = AStruct::new_rc(RefCell::new(42));
-//! let mut taken_value = Rc::into_inner(unsafe { Pin::into_inner_unchecked(my_rc) }).unwrap();
-//!
-//! //assert_eq!(*taken_value.a.borrow(), *taken_value.b);  // UNSOUND!
-//!
-//! AStruct::force_init(&mut taken_value);
-//!
-//! assert_eq!(*taken_value.a.borrow(), *taken_value.b);  // Good
+//! impl Drop for AStruct<'_> {
+//!     fn drop(&mut self) {}
+//! }
 //! ```
 //!
-//! If you really want to make a mess, you can use the low-level API,
-//! which gives you control over each initialization phase
-//! separately. This is useful e.g. in creating
-//! `Pin<Rc<RefCell<AStruct>>>` or other wrappers that aren't
-//! supported directly. Take a look at the [new_box] function. Note
-//! that you must always use `Pin<>` to wrap your smart pointer,
-//! ensuring the value cannot be moved by Rust.
+//! Public tail fields can be replaced, e.g. with `mem::swap` on a
+//! boxed-mode struct, leaving other tails dangling. With
+//! `#[incrstruct(private_tails)]`, all tail fields must be private,
+//! and a read-only getter, `AStruct::myfield(&a)`, is generated for
+//! each of them. Boxed mode implies it.
+//!
+//! # Visibility and Paths
+//!
+//! The generated `AStructInit` trait and helper types, like
+//! `AStructHeads`, have the same visibility as the struct, so a public
+//! struct can be implemented in, and re-exported from, another
+//! module. Constructors, and the layout functions, are public. Use
+//! `#[incrstruct(vis = "pub(crate)")]` to give all of them another
+//! visibility.
+//!
+//! The generated code refers to this crate as `incrstruct`. If it's
+//! renamed, or re-exported from another crate, use e.g.
+//! `#[incrstruct(crate = "my_facade::incrstruct")]` to give the path
+//! to use instead, like with serde. Other paths in the generated code
+//! are absolute, like `::core::ptr::write`, so shadowing modules named
+//! `core` or `std` don't interfere. Together with `crate =
+//! "::incrstruct"`, this allows deriving in `#[no_implicit_prelude]`
+//! modules.
+//!
+//! # Dirty Tracking
+//!
+//! When head fields change through interior mutability, like a
+//! `Cell`, several changes can be batched.
+//! `AStruct::mark_myhead_dirty(this)` marks the tail fields borrowing
+//! `myhead`, directly or through other tail fields, as dirty, in the
+//! [Header]. `AStruct::recompute(this)` then initializes only the
+//! dirty tail fields again, in initialization order, and marks them as
+//! clean. If nothing is dirty, it does nothing. Like `refresh_mytail`,
+//! a dirty tail field only borrowing clean fields is initialized before
+//! the old value is dropped. If initialization fails, the old values
+//! are kept, the error returned, and the tail fields stay dirty. It
+//! aborts if the tail fields borrowing them fail again from the old
+//! values, or anything else panics. These functions need a header
+//! with dirty bits, so they can't be called with e.g.
+//! `Header<u8, ()>`.
+//!
+//! # Parallel Re-initialization
+//!
+//! With the `rayon` cargo feature, [ParForceInit] adds
+//! `par_force_init()` to vectors of `Pin<Box<AStruct>>`, [PinnedVec]
+//! and [IncrSlab], which re-initializes all elements in parallel. The
+//! elements that fail are dropped, and removed, and their errors
+//! returned. The other elements are kept.
+//!
+//! # Layout Introspection
+//!
+//! The derived struct has `const fn heads_size()`, `tails_size()` and
+//! `header_offset()` functions, and an `AStruct::DESCRIPTOR` constant
+//! of type [Descriptor] that collects them together with the size,
+//! alignment and number of phases. These can be used at compile time,
+//! e.g. to size an arena holding many values.
+//!
+//! # Owner and Dependent Pairs
+//!
+//! For the common case of one owned value, and one value borrowing
+//! from it, [Pair] needs no derive. `Pair::new(owner, |o| ...)` boxes
+//! the owner, and creates the dependent from a reference to it.
+//! `owner()` returns the owner, and `with_dependent(|o, d| ...)` calls
+//! a closure with both. The dependent type is named by its `'static`
+//! version, like `Pair<String, &'static str>`, through the
+//! [Dependent] trait. `Pair::try_new` takes a fallible closure.
+//!
+//! # Enums
+//!
+//! The derive macro also works on enums with named fields, where each
+//! variant has its own head fields, tail fields and `#[header]` field.
+//! The enum must be `#[repr(u8)]`, or `#[repr(C, u8)]`, so the tag can
+//! be written before the fields. There is a `new_box_<variant>`
+//! constructor per variant, taking its head fields, and the init
+//! functions are named like `init_field_<variant>_<field>`. Options,
+//! phases and field attributes other than `#[borrows]`, `#[init]` and
+//! `#[header]` aren't supported.
 //!
-//! # Handling Failures
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! use incrstruct::IncrStruct;
 //!
-//! Using the `#[init_err(AnError)]` attribute on the struct, the
-//! `init_field_myfield` functions are expected to return a `Result<T,
-//! AnError>` instead of the plain value. If any tail field
-//! initialization fails, the previously initialized tail fields are
-//! dropped before `ensure_init` returns. This also causes relevant
-//! generated functions on your struct to return a corresponding
-//! `Result`:
+//! #[derive(IncrStruct)]
+//! #[repr(u8)]
+//! enum View<'a> {
+//!     Words {
+//!         #[borrows(text)]
+//!         words: Vec<&'a str>,
+//!         text: String,
+//!
+//!         #[header]
+//!         hdr: incrstruct::Header,
+//!     },
+//!     Lines {
+//!         #[borrows(text)]
+//!         #[init(text.lines().collect())]
+//!         lines: Vec<&'a str>,
+//!         text: String,
+//!
+//!         #[header]
+//!         hdr: incrstruct::Header,
+//!     },
+//! }
 //!
-//! - `new_box -> Result<Pin<Box<AStruct>>, AnError>`
-//! - `new_rc -> Result<Pin<Rc<AStruct>>, AnError>`
-//! - `ensure_init -> Result<&mut AStruct, AnError>`
-//! - `force_init -> Result<(), AnError>`
+//! impl<'a> ViewInit<'a> for View<'a> {
+//!     fn init_field_words_words(text: &'a String) -> Vec<&'a str> {
+//!         text.split(' ').collect()
+//!     }
+//! }
+//!
+//! let view = View::new_box_words("hello world".to_owned());
+//! if let View::Words { words, .. } = &*view {
+//!     assert_eq!(words[1], "world");
+//! }
+//! # }
+//! ```
 //!
-//! Note that Rust is generally not panic-tolerant, and no attempts to
-//! drop are made if a field initialization function panics.
+//! # `no_std` Support
 //!
-//! If you are using the unsafe `new_uninit`, and `ensure_init` fails,
-//! remember to run `drop_uninit` to stop memory leaks.
+//! The crate is `no_std`. The default `std` feature enables [Lazy],
+//! [StaticLazy], and the features that depend on other crates. The
+//! `alloc` feature, enabled by `std`, enables the `Box`, `Rc` and
+//! `Arc` constructors.
+//! Without either, the struct can still be constructed using
+//! [incr_pin], or `new_uninit` and `ensure_init`.
 //!
 //! # Examples
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! use std::cell::{Ref, RefCell};
 //! use incrstruct::IncrStruct;
 //!
@@ -227,16 +1397,135 @@
 //! let my_a = AStruct::new_box(RefCell::new(42));
 //!
 //! assert_eq!(*my_a.a.borrow(), *my_a.b);
+//! # }
+//! ```
+//!
+//! ## No-Panic Mode
+//!
+//! ```rust
+//! use std::cell::{Ref, RefCell};
+//! use incrstruct::IncrStruct;
+//!
+//! #[derive(IncrStruct)]
+//! #[incrstruct(no_panic)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: Ref<'a, i32>,
+//!     a: RefCell<i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+//!         a.borrow()
+//!     }
+//! }
+//!
+//! let mut my_a = unsafe { AStruct::new_uninit(RefCell::new(42)) };
+//!
+//! unsafe { AStruct::ensure_init(&mut my_a) }.unwrap();
+//!
+//! assert_eq!(
+//!     unsafe { AStruct::ensure_init(&mut my_a) }.err(),
+//!     Some(incrstruct::Error::State(incrstruct::StateError::Inited)),
+//! );
+//! # unsafe { core::ptr::drop_in_place(my_a.as_mut_ptr()) };
+//! ```
+//!
+//! ## Token Cells
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! use qcell::{TCell, TCellOwner};
+//! use incrstruct::IncrStruct;
+//!
+//! struct Marker;
+//!
+//! #[derive(IncrStruct)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]                  // The tail references the cell,
+//!     b: &'a TCell<Marker, i32>,     // not the value inside it.
+//!
+//!     a: TCell<Marker, i32>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a TCell<Marker, i32>) -> &'a TCell<Marker, i32> {
+//!         a
+//!     }
+//! }
+//!
+//! let mut owner = TCellOwner::<Marker>::new();
+//! let my_a = AStruct::new_box(TCell::new(42));
+//!
+//! *owner.rw(&my_a.a) += 1;
+//!
+//! assert_eq!(*owner.ro(my_a.b), 43);
+//! # }
+//! ```
+//!
+//! ## Trees
+//!
+//! Head fields can own other values of the same type, e.g. as
+//! `Vec<Pin<Box<Self>>>` or `Option<Pin<Box<Self>>>`, and tails can
+//! borrow from them. Since the children are pinned, and dropped after
+//! the tails of their parent, trees are simply built bottom-up:
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! use core::pin::Pin;
+//! use incrstruct::IncrStruct;
+//!
+//! #[derive(IncrStruct)]
+//! struct Node<'a> {
+//!     #[borrows(children)]
+//!     child_values: Vec<&'a i32>,
+//!
+//!     value: i32,
+//!     children: Vec<Pin<Box<Self>>>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'a> NodeInit<'a> for Node<'a> {
+//!     fn init_field_child_values(children: &'a Vec<Pin<Box<Self>>>) -> Vec<&'a i32> {
+//!         children.iter().map(|child| &child.value).collect()
+//!     }
+//! }
+//!
+//! let root = Node::new_box(
+//!     1,
+//!     vec![Node::new_box(2, Vec::new()), Node::new_box(3, Vec::new())],
+//! );
+//!
+//! assert_eq!(root.child_values, vec![&2, &3]);
+//! # }
 //! ```
 //!
+//! For trees built from another description, e.g. a parse tree,
+//! [build_tree] and [try_build_tree] build the nodes bottom-up,
+//! without recursion.
+//!
 //! ## Generics And Lifetimes
 //!
 //! Generic parameters are forwarded to the generated Init trait.
 //!
 //! The struct's first declared lifetime is used to set the lifetime
-//! of the argument references in `init_field_myfield`.
+//! of the argument references in `init_field_myfield`. All type
+//! parameters, and `Self`, are required to outlive it, so the
+//! generated trait and functions have `where T: 'b, Self: 'b` clauses.
+//! If the struct fields don't already imply `T: 'b`, e.g. when only
+//! an associated type like `T::Target` is borrowed, the Init trait
+//! implementation needs the bound too.
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! use std::cell::{Ref, RefCell};
 //! use std::fmt::Debug;
 //! use incrstruct::IncrStruct;
@@ -260,11 +1549,60 @@
 //! let my_box = AStruct::new_box(RefCell::new(42));
 //!
 //! assert_eq!(*my_box.a.borrow(), *my_box.b);
+//! # }
+//! ```
+//!
+//! Field types are copied verbatim into the generated signatures, so
+//! tails can use associated types, including generic associated
+//! types, like `<V as View>::Output<'b>`. A generic associated type
+//! with a `where Self: 'b` clause needs `V: 'b` on the struct itself.
+//!
+//! ```rust
+//! # #[cfg(feature = "alloc")] {
+//! use incrstruct::IncrStruct;
+//!
+//! trait View {
+//!     type Output<'v> where Self: 'v;
+//!
+//!     fn view<'v>(data: &'v [u8]) -> Self::Output<'v>;
+//! }
+//!
+//! struct Words;
+//!
+//! impl View for Words {
+//!     type Output<'v> = Vec<&'v [u8]>;
+//!
+//!     fn view<'v>(data: &'v [u8]) -> Self::Output<'v> {
+//!         data.split(|b| *b == b' ').collect()
+//!     }
+//! }
+//!
+//! #[derive(IncrStruct)]
+//! struct AStruct<'b, V: View + 'b> {
+//!     #[borrows(data)]
+//!     view: <V as View>::Output<'b>,
+//!     data: Vec<u8>,
+//!
+//!     #[header]
+//!     hdr: incrstruct::Header,
+//! }
+//!
+//! impl<'b, V: View + 'b> AStructInit<'b, V> for AStruct<'b, V> {
+//!     fn init_field_view(data: &'b Vec<u8>) -> <V as View>::Output<'b> {
+//!         V::view(data)
+//!     }
+//! }
+//!
+//! let my_box = AStruct::<Words>::new_box(b"hello world".to_vec());
+//!
+//! assert_eq!(my_box.view.len(), 2);
+//! # }
 //! ```
 //!
 //! ## Handling Failures
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! use std::cell::{Ref, RefCell};
 //! use incrstruct::IncrStruct;
 //!
@@ -295,14 +1633,21 @@
 //! let result = AStruct::new_box(RefCell::new(42));
 //!
 //! assert_eq!(result.unwrap_err(), AnError::Failed);
+//! # }
 //! ```
 //!
 //! # How It Works
 //!
 //! The `IncrStruct` derive macro creates a two-phase initialization
-//! scheme where `new_uninit` initializes all head fields, and `init`
-//! initializes all tail fields. `init` is also called whenever
-//! `force_init` is called.
+//! scheme where `new_uninit` initializes all head fields, and
+//! `init_phase` initializes all tail fields. `init_phase` is also
+//! called whenever `force_init` is called. With `#[phase(n)]`, the
+//! second phase is split up, and `init_phase` is called once per
+//! phase.
+//!
+//! `new_box`, `new_rc` and `new_arc` allocate first, and write the
+//! head fields directly into the allocation, so even very large
+//! structs never exist on the stack as a whole.
 //!
 //! The header field keeps track of whether the tail fields have been
 //! initialized or not. As long as the tail fields are invalid, we
@@ -313,10 +1658,14 @@
 //! functions in order.
 //!
 //! To support initialization functions that can fail, the generated
-//! `init` function keeps track of which field it is initializing, and
-//! calls the generated `drop_tail_in_place` for the previous
-//! ones. There is no concept of partially initialized tail fields;
-//! it's all or nothing after the second phase returns.
+//! `init_phase` function keeps track of which field it is
+//! initializing, and calls the generated `drop_tail_in_place` for the
+//! previous ones. There is no concept of partially initialized tail
+//! phases; it's all or nothing after each phase returns. The header
+//! records the last completed phase, and also which individual tail
+//! fields are initialized, so only those are ever dropped. This
+//! limits a struct to [Header::MAX_TAILS] tail fields, which is 64
+//! with the default `u64` bitsets.
 //!
 //! A generated associated function called `AStruct::drop_uninit` must
 //! be used to drop the `MaybeUninit<AStruct>` if the second phase
@@ -324,6 +1673,42 @@
 //! (but then you shouldn't have a `MaybeUninit<AStruct>` reference to
 //! it anyway.)
 //!
+//! Since the header contains a `PhantomPinned`, the struct is never
+//! `Unpin`. The derive macro makes a manual `Unpin` implementation
+//! fail to compile, and rejects `#[derive(Copy)]`, since either would
+//! allow the self-references to outlive what they point to. A manual
+//! `Copy` implementation also fails to compile, even where all fields
+//! are `Copy`:
+//!
+//! ```compile_fail
+//! # use incrstruct::IncrStruct;
+//! #[derive(IncrStruct)]
+//! #[incrstruct(no_header)]
+//! struct AStruct<'a> {
+//!     #[borrows(a)]
+//!     b: &'a i32,
+//!
+//!     a: i32,
+//!
+//!     #[header]
+//!     hdr: core::marker::PhantomPinned,
+//! }
+//!
+//! impl<'a> AStructInit<'a> for AStruct<'a> {
+//!     fn init_field_b(a: &'a i32) -> &'a i32 {
+//!         a
+//!     }
+//! }
+//!
+//! impl Clone for AStruct<'_> {
+//!     fn clone(&self) -> Self {
+//!         *self
+//!     }
+//! }
+//!
+//! impl Copy for AStruct<'_> {}
+//! ```
+//!
 //! # Design Considerations
 //!
 //! If we narrow down the scope on self-referencing structs, we may be
@@ -342,44 +1727,584 @@
 //!   `new_rc` functions, making the need for unsafe functions
 //!   and re-initialization limited in practice.
 //!
+//! Enums are supported, if they are `#[repr(u8)]`. A primitive
+//! representation gives the enum a defined layout, so the tag can be
+//! written in place, selecting the variant, before its head fields.
+//! The header is then found in that variant, and the tail fields are
+//! initialized in one phase.
+//!
 //! And here is a wish list:
 //!
 //! * [x] Don't `Box` individual field values. Use a derive macro, not
-//!       rewriting what the user has defined. WYSIWYG.
+//!   rewriting what the user has defined. WYSIWYG.
 //! * [x] Initialization can fail, and `Results` are handled properly
-//!       to drop already initialized fields.
+//!   to drop already initialized fields.
 //! * [x] Generics shouldn't be a problem.
 //! * [x] Enforce sound ordering of fields so that the natural drop order
-//!       makes sense w.r.t. dependencies.
+//!   makes sense w.r.t. dependencies.
 //! * [x] Moving an initialized struct is impossible. Moving partially
-//!       initialized structs works.
+//!   initialized structs works.
 //! * [ ] Since `&mut` is exclusive, it would be ideal if self-referential
-//!       structs could only grab immutable references. (Since a single
-//!       `&mut self` would imply that nothing else in the program can
-//!       grab a reference. If, additionally, external users of the struct
-//!       were unable to acquire a `&mut`, there would be no changes to
-//!       Rust borrow semantics.
+//!   structs could only grab immutable references. (Since a single
+//!   `&mut self` would imply that nothing else in the program can
+//!   grab a reference. If, additionally, external users of the struct
+//!   were unable to acquire a `&mut`, there would be no changes to
+//!   Rust borrow semantics.
 
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::fmt;
 use core::marker::PhantomPinned;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use core::panic::{RefUnwindSafe, UnwindSafe};
 
+pub use incrstruct_derive::incrstruct;
 pub use incrstruct_derive::IncrStruct;
 
 pub mod internal;
 
+// The atomics of `SyncHeader`, which are modeled by loom when
+// testing with `--cfg loom`.
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "alloc")]
+mod aliasable;
+#[cfg(feature = "alloc")]
+pub use aliasable::AliasableBox;
+#[cfg(feature = "alloc")]
+mod container;
+#[cfg(feature = "alloc")]
+pub use container::{IncrContainer, IncrEmplace};
+#[cfg(feature = "alloc")]
+mod arena;
+#[cfg(feature = "bumpalo")]
+pub use arena::IncrBumpArena;
+#[cfg(feature = "alloc")]
+pub use arena::{IncrArena, IncrArenaAlloc};
+#[cfg(feature = "alloc")]
+mod pair;
+#[cfg(feature = "alloc")]
+pub use pair::{Dependent, Pair};
+
+#[cfg(feature = "alloc")]
+mod pinned_vec;
+#[cfg(feature = "alloc")]
+pub use pinned_vec::PinnedVec;
+#[cfg(feature = "alloc")]
+mod slab;
+#[cfg(feature = "alloc")]
+pub use slab::IncrSlab;
+#[cfg(feature = "alloc")]
+mod cell;
+#[cfg(feature = "alloc")]
+pub use cell::PinRefCell;
+#[cfg(feature = "std")]
+pub use cell::{PinMutex, PinMutexGuard};
+#[cfg(feature = "alloc")]
+mod weak;
+#[cfg(feature = "alloc")]
+pub use weak::{PinArcWeak, PinRcWeak};
+#[cfg(feature = "alloc")]
+mod tree;
+#[cfg(feature = "alloc")]
+pub use tree::{build_tree, try_build_tree};
+#[cfg(feature = "alloc")]
+mod vec;
+#[cfg(feature = "alloc")]
+pub use vec::IncrVec;
+#[cfg(feature = "std")]
+mod lazy;
+#[cfg(feature = "std")]
+pub use lazy::{Lazy, PinnedOnceLock, PinnedThreadLocal, StaticLazy};
+
+mod stack;
+pub use stack::{InPlace, UninitGuard};
+mod lent;
+pub use lent::Lent;
+
+#[cfg(feature = "arc_swap")]
+mod swap;
+#[cfg(feature = "arc_swap")]
+pub use swap::{IncrArcSwap, IncrGuard};
+
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "rayon")]
+pub use par::ParForceInit;
+
+#[cfg(feature = "bumpalo")]
+pub use bumpalo;
+
+#[cfg(feature = "bytemuck")]
+pub use bytemuck;
+
+#[cfg(feature = "memmap2")]
+pub use memmap2;
+#[cfg(feature = "pinned_init")]
+pub use pinned_init;
+
+#[cfg(feature = "serde")]
+pub use serde;
+
+#[cfg(feature = "triomphe")]
+pub use triomphe;
+
+#[cfg(feature = "yoke")]
+pub use yoke;
+
+/// The runtime state of an incremental struct. Add one to the struct,
+/// marked `#[header]`.
+///
+/// The header holds the initialization state, and two bitsets, with
+/// one bit per tail field: `T` records which tail fields are
+/// initialized, for partial drops, and `D` which are dirty, for
+/// `recompute`. They are `u64` by default, which supports the
+/// maximum of 64 tail fields. Use a smaller [TailBits] type, with at
+/// least one bit per tail field, to shrink the header, and `()` for
+/// `D` if the struct doesn't use dirty tracking:
+///
+/// ```rust
+/// # use incrstruct::IncrStruct;
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: &'a i32,
+///     a: i32,
+///
+///     #[header]
+///     hdr: incrstruct::Header<u8, ()>,
+/// }
+/// # impl<'a> AStructInit<'a> for AStruct<'a> {
+/// #     fn init_field_b(a: &'a i32) -> &'a i32 {
+/// #         a
+/// #     }
+/// # }
+///
+/// use core::mem::size_of;
+///
+/// assert!(size_of::<incrstruct::Header<u8, ()>>() < size_of::<incrstruct::Header>());
+/// ```
+///
+/// A bitset that is too small for the tail fields is a compile
+/// error, and so is calling `mark_myfield_dirty` or `recompute` without
+/// dirty bits:
+///
+/// ```rust,compile_fail
+/// # use incrstruct::IncrStruct;
+/// # #[derive(IncrStruct)]
+/// # struct AStruct<'a> {
+/// #     #[borrows(a)]
+/// #     b: &'a i32,
+/// #     a: i32,
+/// #
+/// #     #[header]
+/// #     hdr: incrstruct::Header<u8, ()>,
+/// # }
+/// # impl<'a> AStructInit<'a> for AStruct<'a> {
+/// #     fn init_field_b(a: &'a i32) -> &'a i32 {
+/// #         a
+/// #     }
+/// # }
+/// # #[cfg(feature = "alloc")] {
+/// let mut a = AStruct::new_box(42);
+///
+/// AStruct::recompute(a.as_mut());
+/// # }
+/// # #[cfg(not(feature = "alloc"))]
+/// # compile_error!("");
+/// ```
+///
+/// The state itself is two bytes, and has unused values,
+/// so `Option<Header>`, and enums wrapping structs, don't grow
+/// because of it. The `move_check` feature adds the address of the
+/// struct.
+#[derive(Clone, Debug)]
+pub struct Header<T = u64, D = T> {
+    pub(crate) state: State,
+
+    // Bit `i` is set if the tail field with index `i`, in
+    // initialization order, is initialized. This is what
+    // `drop_tail_in_place` uses to decide what to drop.
+    pub(crate) tails: T,
+
+    // Bit `i` is set if the tail field with index `i` must be
+    // initialized again by `recompute`, because a field it borrows
+    // was marked dirty.
+    pub(crate) dirty: D,
+}
+
+// The header must keep a niche, and the state must stay small, see
+// its documentation.
+const _: () = assert!(size_of::<Option<Header>>() == size_of::<Header>());
+#[cfg(not(feature = "move_check"))]
+const _: () = assert!(size_of::<Option<Header<u8, ()>>>() == 3);
+
+impl<T: TailBits, D> Header<T, D> {
+    /// The maximum number of tail fields in a struct using this
+    /// header.
+    pub const MAX_TAILS: usize = T::BITS;
+}
+
+impl<T, D> Header<T, D> {
+    /// Returns the initialization state of the struct.
+    pub fn state(&self) -> InitState {
+        match self.state {
+            State::Uninited => InitState::Uninited,
+            State::InitedPhase(phase) => InitState::InitedPhase(phase),
+            State::Initing => InitState::Initing,
+            State::Poisoned(_) => InitState::Poisoned,
+            State::Inited(..) => InitState::Inited,
+        }
+    }
+
+    /// Returns whether all tail fields are initialized.
+    pub fn is_inited(&self) -> bool {
+        matches!(self.state, State::Inited(..))
+    }
+
+    /// Returns whether no tail fields are initialized, like after
+    /// `new_uninit`.
+    pub fn is_uninited(&self) -> bool {
+        matches!(self.state, State::Uninited)
+    }
+
+    /// Returns whether initialization failed, or panicked, leaving
+    /// the struct partially initialized. `ensure_init` rejects a
+    /// poisoned struct, while functions dropping the tail fields
+    /// first, like `force_init` and `drop_uninit`, accept it. Field
+    /// access isn't checked.
+    pub fn is_poisoned(&self) -> bool {
+        matches!(self.state, State::Poisoned(_))
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A bitset with one bit per tail field, for the [Header]. It's
+/// implemented for the unsigned integers up to `u64`, and for `()`,
+/// which has no bits.
+pub trait TailBits: sealed::Sealed + Copy + fmt::Debug {
+    /// The number of bits.
+    const BITS: usize;
+
+    /// The empty set.
+    #[doc(hidden)]
+    const EMPTY: Self;
+
+    /// Sets the bits that are set in `mask`.
+    #[doc(hidden)]
+    fn insert(&mut self, mask: u64);
+
+    /// Clears bit `index`, and returns whether it was set.
+    #[doc(hidden)]
+    fn remove(&mut self, index: usize) -> bool;
+
+    /// Returns whether bit `index` is set.
+    #[doc(hidden)]
+    fn contains(&self, index: usize) -> bool;
+}
+
+macro_rules! impl_tail_bits {
+    ($($ty:ty),*) => {
+        $(
+            impl sealed::Sealed for $ty {}
+
+            impl TailBits for $ty {
+                const BITS: usize = <$ty>::BITS as usize;
+                const EMPTY: Self = 0;
+
+                fn insert(&mut self, mask: u64) {
+                    *self |= mask as $ty;
+                }
+
+                fn remove(&mut self, index: usize) -> bool {
+                    let was = self.contains(index);
+                    *self &= !(1 << index);
+                    was
+                }
+
+                fn contains(&self, index: usize) -> bool {
+                    *self & (1 << index) != 0
+                }
+            }
+        )*
+    };
+}
+
+impl_tail_bits!(u8, u16, u32, u64);
+
+impl sealed::Sealed for () {}
+
+impl TailBits for () {
+    const BITS: usize = 0;
+    const EMPTY: Self = ();
+
+    fn insert(&mut self, _mask: u64) {}
+
+    fn remove(&mut self, _index: usize) -> bool {
+        false
+    }
+
+    fn contains(&self, _index: usize) -> bool {
+        false
+    }
+}
+
+/// The initialization state of a struct, as returned by
+/// [Header::state].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum InitState {
+    /// All head fields are initialized, and no tail fields.
+    Uninited,
+
+    /// The tail fields of phases up to and including this one are
+    /// initialized, using `ensure_init_phase`.
+    InitedPhase(u8),
+
+    /// The tail fields are being initialized.
+    Initing,
+
+    /// Initializing the tail fields failed, or panicked.
+    Poisoned,
+
+    /// All fields are initialized.
+    Inited,
+}
+
+impl<T: TailBits, D: TailBits> Default for Header<T, D> {
+    /// Returns a header for an uninitialized struct.
+    fn default() -> Self {
+        Self {
+            state: State::Uninited,
+            tails: T::EMPTY,
+            dirty: D::EMPTY,
+        }
+    }
+}
+
+/// A [Header] that also allows threads to race to initialize the
+/// struct, using the `ensure_init_sync` function generated with
+/// `#[incrstruct(sync)]`. The first caller initializes the struct,
+/// while the others wait for it to finish. After that, the state is
+/// read with a single atomic load.
+///
+/// It dereferences to the inner [Header], with the same bitsets.
+#[derive(Debug)]
+pub struct SyncHeader<T = u64, D = T> {
+    // One of the `SYNC_*` states in `internal`. The inner header is
+    // only accessed by the thread that moved this to `SYNC_LOCKED`,
+    // and never mutably once it is `SYNC_INITED`.
+    pub(crate) state: AtomicU8,
+
+    header: Header<T, D>,
+}
+
+impl<T: TailBits, D: TailBits> Default for SyncHeader<T, D> {
+    fn default() -> Self {
+        Self {
+            state: AtomicU8::default(),
+            header: Header::default(),
+        }
+    }
+}
+
+impl<T, D> Deref for SyncHeader<T, D> {
+    type Target = Header<T, D>;
+
+    fn deref(&self) -> &Header<T, D> {
+        &self.header
+    }
+}
+
+impl<T, D> DerefMut for SyncHeader<T, D> {
+    fn deref_mut(&mut self) -> &mut Header<T, D> {
+        &mut self.header
+    }
+}
+
 #[derive(Clone, Debug)]
-pub enum Header {
+pub(crate) enum State {
     // All head fields are initialized, and no tail fields are. The
     // struct is wrapped in `MaybeUninit<>` and `drop_uninit` must be
     // called manually to drop head fields.
     Uninited,
 
+    // All head fields, and the tail fields of phases up to and
+    // including this one, are initialized. Only used with
+    // `#[phase(n)]`, and never for the last phase.
+    InitedPhase(u8),
+
     // All head fields are initialized, and some tail fields may be
     // initialized. This is used to check for undue recursive calls to
     // `ensure_init`.
     Initing,
 
+    // Like `InitedPhase`, or `Uninited` for zero, but initializing the
+    // next phase failed or panicked. `ensure_init` refuses to try
+    // again, but `force_init` and `drop_uninit` still work.
+    Poisoned(u8),
+
     // All fields are initialized, and the struct is not allowed to
     // move. (Where in the enum the `PhantomPinned` is located doesn't
-    // matter, but it only matters for this variant.)
-    Inited(PhantomPinned),
+    // matter, but it only matters for this variant.) The address is
+    // where the struct was initialized.
+    Inited(PhantomPinned, Address),
+}
+
+// The header has no interior mutability, and is only changed through
+// `&mut`, so a caught panic can't expose a broken header through a
+// shared reference. This is implied by the fields, but stated
+// explicitly so it doesn't change by accident.
+impl<T: TailBits, D: TailBits> UnwindSafe for Header<T, D> {}
+impl<T: TailBits, D: TailBits> RefUnwindSafe for Header<T, D> {}
+
+/// The address of an initialized struct, used to detect moves. Only
+/// recorded with the `move_check` feature, and zero-sized otherwise.
+#[derive(Clone, Debug)]
+pub struct Address {
+    #[cfg(feature = "move_check")]
+    addr: usize,
+}
+
+impl Address {
+    /// Returns the address of the struct.
+    #[cfg_attr(not(feature = "move_check"), allow(unused_variables))]
+    pub(crate) fn of<T>(this: *const T) -> Self {
+        Self {
+            #[cfg(feature = "move_check")]
+            addr: this as usize,
+        }
+    }
+
+    /// Returns false if the struct is known to be at a different
+    /// address than when it was initialized.
+    #[cfg(feature = "move_check")]
+    pub(crate) fn matches<T>(&self, this: *const T) -> bool {
+        self.addr == this as usize
+    }
+
+    #[cfg(not(feature = "move_check"))]
+    pub(crate) fn matches<T>(&self, _this: *const T) -> bool {
+        true
+    }
+}
+
+/// Describes the memory layout of a struct using `IncrStruct`. It's
+/// available at compile time as `AStruct::DESCRIPTOR`, e.g. for
+/// capacity planning of arenas.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Descriptor {
+    /// The size of the struct, like `size_of`.
+    pub size: usize,
+
+    /// The alignment of the struct, like `align_of`.
+    pub align: usize,
+
+    /// The sum of the sizes of all head fields, excluding padding.
+    pub heads_size: usize,
+
+    /// The sum of the sizes of all tail fields, excluding padding.
+    pub tails_size: usize,
+
+    /// The offset of the header field.
+    pub header_offset: usize,
+
+    /// The number of tail initialization phases.
+    pub phases: u8,
+}
+
+/// A pointer type whose target doesn't move when the pointer itself
+/// is moved, and can't be changed through it. Head fields marked
+/// `#[stable_deref]` must implement it.
+///
+/// Owners that can be mutated, like `String` and `Vec<T>`, don't
+/// implement it, since e.g. `push` may reallocate under the tails.
+/// Neither does `Box<T>`, since moving it asserts unique access to
+/// the target. Use [AliasableBox], e.g. `AliasableBox<str>`, instead.
+///
+/// # Safety
+///
+/// `deref` must return the same address for as long as the value
+/// isn't dropped, even if the value is moved. Moving the value must
+/// not invalidate shared references to the target, and the target
+/// must not be mutable through a shared reference to the value,
+/// except through interior mutability.
+pub unsafe trait StableDeref: core::ops::Deref {}
+
+unsafe impl<T: ?Sized> StableDeref for &T {}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> StableDeref for AliasableBox<T> {}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> StableDeref for alloc::rc::Rc<T> {}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> StableDeref for alloc::sync::Arc<T> {}
+
+/// The struct was in the wrong initialization state for the
+/// requested operation.
+///
+/// Returned by the generated functions in no-panic mode, where the
+/// default mode would panic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum StateError {
+    /// The tail fields are already initialized.
+    Inited,
+
+    /// The tail fields are being initialized, i.e. this was a
+    /// recursive call from an `init_field_myfield` function.
+    Initing,
+
+    /// An earlier initialization failed, or panicked. Use `force_init`
+    /// to start over, or `drop_uninit`.
+    Poisoned,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Inited => write!(f, "already initialized"),
+            StateError::Initing => write!(f, "being initialized"),
+            StateError::Poisoned => write!(f, "poisoned by a failed initialization"),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+/// The error returned by the generated functions in no-panic
+/// mode. The `E` is the `#[init_err]` type, or `Infallible` if none
+/// was given.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<E> {
+    /// The struct was in the wrong state for the call.
+    State(StateError),
+
+    /// An `init_field_myfield` function failed.
+    Init(E),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::State(err) => write!(f, "struct is {}", err),
+            Error::Init(err) => err.fmt(f),
+        }
+    }
 }
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for Error<E> {}
@@ -0,0 +1,193 @@
+//! A generic owner and dependent pair, for simple cases.
+
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ptr::NonNull;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Maps a lifetime to a type borrowing from an owner, like the tail
+/// field type of a derived struct, with the struct lifetime as a
+/// parameter. The implementing type is the `'static` version, so
+/// `&'static str` means `&'a str`. Implement it for own types with a
+/// lifetime parameter, like `type Of<'a> = MyView<'a>`.
+pub trait Dependent {
+    /// The type, borrowing for `'a`.
+    type Of<'a>;
+}
+
+impl<T: ?Sized + 'static> Dependent for &'static T {
+    type Of<'a> = &'a T;
+}
+
+impl<D: Dependent> Dependent for Option<D> {
+    type Of<'a> = Option<D::Of<'a>>;
+}
+
+impl<D: Dependent> Dependent for Vec<D> {
+    type Of<'a> = Vec<D::Of<'a>>;
+}
+
+impl<A: Dependent, B: Dependent> Dependent for (A, B) {
+    type Of<'a> = (A::Of<'a>, B::Of<'a>);
+}
+
+/// An owner, and a dependent value borrowing from it. This is the
+/// common "own a `String`, keep a parsed view of it" shape, without
+/// needing a derive. The owner is boxed, like in boxed mode, so the
+/// pair can move freely. The dependent is dropped before the owner.
+///
+/// ```rust
+/// use incrstruct::Pair;
+///
+/// let pair: Pair<String, Vec<&'static str>> =
+///     Pair::new("hello new world".to_string(), |s| s.split(' ').collect());
+///
+/// assert_eq!(pair.owner(), "hello new world");
+/// assert_eq!(pair.with_dependent(|_, words| words.len()), 3);
+/// ```
+pub struct Pair<O, D: Dependent> {
+    // The lifetime is a lie. It's only exposed for the duration of a
+    // borrow of the pair.
+    dependent: ManuallyDrop<D::Of<'static>>,
+
+    // A raw pointer, so moving the pair doesn't assert uniqueness of
+    // the owner while the dependent borrows it.
+    owner: NonNull<O>,
+    _owner: PhantomData<O>,
+}
+
+impl<O, D: Dependent> Pair<O, D> {
+    /// Moves the owner into a box, and creates the dependent using
+    /// `f`.
+    pub fn new<F>(owner: O, f: F) -> Self
+    where
+        F: for<'a> FnOnce(&'a O) -> D::Of<'a>,
+    {
+        match Self::try_new(owner, |owner| Ok::<_, core::convert::Infallible>(f(owner))) {
+            Ok(pair) => pair,
+            Err(err) => match err {},
+        }
+    }
+
+    /// Like [Pair::new], but `f` may fail. On error, the owner is
+    /// dropped.
+    pub fn try_new<E, F>(owner: O, f: F) -> Result<Self, E>
+    where
+        F: for<'a> FnOnce(&'a O) -> Result<D::Of<'a>, E>,
+    {
+        Self::try_new_or_recover(owner, f).map_err(|(_, err)| err)
+    }
+
+    /// Like [Pair::try_new], but returns the owner along with the
+    /// error.
+    pub fn try_new_or_recover<E, F>(owner: O, f: F) -> Result<Self, (O, E)>
+    where
+        F: for<'a> FnOnce(&'a O) -> Result<D::Of<'a>, E>,
+    {
+        // If `f` panics, the guard frees the owner.
+        let guard = OwnerGuard(NonNull::from(Box::leak(Box::new(owner))));
+
+        // SAFETY: the owner is only freed by the guard, or the pair,
+        // after the dependent is dropped.
+        match f(unsafe { guard.0.as_ref() }) {
+            Ok(dependent) => {
+                let owner = guard.0;
+                core::mem::forget(guard);
+
+                Ok(Self {
+                    // SAFETY: only the lifetime changes.
+                    dependent: ManuallyDrop::new(unsafe { extend_lifetime::<D>(dependent) }),
+                    owner,
+                    _owner: PhantomData,
+                })
+            }
+            Err(err) => Err((guard.into_inner(), err)),
+        }
+    }
+
+    /// Returns a reference to the owner.
+    pub fn owner(&self) -> &O {
+        // SAFETY: the owner lives until the pair is dropped.
+        unsafe { self.owner.as_ref() }
+    }
+
+    /// Calls `f` with references to the owner and the dependent, and
+    /// returns its result. Like `with_myfield` on a derived struct,
+    /// nothing borrowed from the dependent can escape the closure.
+    pub fn with_dependent<'b, R, F>(&'b self, f: F) -> R
+    where
+        F: for<'a> FnOnce(&'a O, &'a D::Of<'a>) -> R,
+    {
+        // SAFETY: the owner outlives `'b`, and only the lifetime
+        // changes.
+        let dependent =
+            unsafe { &*(&*self.dependent as *const D::Of<'static>).cast::<D::Of<'b>>() };
+
+        f(self.owner(), dependent)
+    }
+
+    /// Drops the dependent, and returns the owner.
+    pub fn into_owner(self) -> O {
+        let mut this = ManuallyDrop::new(self);
+
+        // SAFETY: the dependent is dropped first, and neither is used
+        // again.
+        unsafe {
+            ManuallyDrop::drop(&mut this.dependent);
+
+            OwnerGuard(this.owner).into_inner()
+        }
+    }
+}
+
+impl<O, D: Dependent> Drop for Pair<O, D> {
+    fn drop(&mut self) {
+        // SAFETY: the dependent borrows from the owner, so it's
+        // dropped first.
+        unsafe { ManuallyDrop::drop(&mut self.dependent) };
+        drop(OwnerGuard(self.owner));
+    }
+}
+
+// SAFETY: the dependent may hold references to the owner, so sending
+// the pair shares the owner with another thread.
+unsafe impl<O: Send + Sync, D: Dependent> Send for Pair<O, D> where D::Of<'static>: Send {}
+
+// SAFETY: only shared references are handed out.
+unsafe impl<O: Sync, D: Dependent> Sync for Pair<O, D> where D::Of<'static>: Sync {}
+
+/// Frees a leaked, boxed owner when dropped.
+struct OwnerGuard<O>(NonNull<O>);
+
+impl<O> OwnerGuard<O> {
+    /// Returns the owner, freeing the box.
+    fn into_inner(self) -> O {
+        let owner = self.0;
+        core::mem::forget(self);
+
+        // SAFETY: the pointer came from `Box::leak`, and is only
+        // freed once.
+        *unsafe { Box::from_raw(owner.as_ptr()) }
+    }
+}
+
+impl<O> Drop for OwnerGuard<O> {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `Box::leak`, and is only
+        // freed once.
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+/// Changes the lifetime of a dependent to `'static`.
+///
+/// # Safety
+///
+/// The value must not be used after what it borrows is dropped.
+unsafe fn extend_lifetime<'a, D: Dependent>(v: D::Of<'a>) -> D::Of<'static> {
+    let v = ManuallyDrop::new(v);
+
+    (&*v as *const D::Of<'a>).cast::<D::Of<'static>>().read()
+}
@@ -0,0 +1,195 @@
+//! Parallel re-initialization of many self-referencing structs.
+
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::pin::Pin;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use rayon::prelude::*;
+
+use crate::internal::{expect_init, try_force_init, IncrStructBase, IncrStructInit};
+use crate::{Error, IncrSlab, PinnedVec};
+
+/// Re-initializes all elements of a container in parallel, using
+/// [rayon]. This is useful after deserializing, or relocating, a
+/// large number of elements, where sequential re-initialization
+/// would dominate.
+///
+/// ```rust
+/// use incrstruct::{IncrStruct, ParForceInit};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: &'a i32,
+///     a: i32,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a i32) -> &'a i32 {
+///         a
+///     }
+/// }
+///
+/// let mut v: Vec<_> = (0..1000).map(AStruct::new_box).collect();
+///
+/// v.par_force_init().unwrap();
+///
+/// assert_eq!(*v[42].b, 42);
+/// ```
+///
+/// Errors are collected, together with the index, or key, of the
+/// failing element, in order. An element that fails has already
+/// dropped its tail fields, so it's dropped, and removed from the
+/// container, but all other elements are kept. If an element panics,
+/// all elements are leaked instead, and the container is left empty.
+pub trait ParForceInit {
+    /// The init error type of the elements.
+    type Error;
+
+    /// Calls `force_init` on every element. Panics on state errors.
+    fn par_force_init(&mut self) -> Result<(), Vec<(usize, Self::Error)>> {
+        self.try_par_force_init().map_err(|errs| {
+            errs.into_iter()
+                .map(|(i, err)| (i, expect_init(err, "par_force_init")))
+                .collect()
+        })
+    }
+
+    /// Like [ParForceInit::par_force_init], but returns state errors
+    /// instead of panicking.
+    #[allow(clippy::type_complexity)]
+    fn try_par_force_init(&mut self) -> Result<(), Vec<(usize, Error<Self::Error>)>>;
+}
+
+impl<T> ParForceInit for Vec<Pin<Box<T>>>
+where
+    T: IncrStructInit + Send,
+    T::Error: Send,
+{
+    type Error = T::Error;
+
+    fn try_par_force_init(&mut self) -> Result<(), Vec<(usize, Error<Self::Error>)>> {
+        // An element that panics is left with uninitialized tail
+        // fields, so nothing is dropped while unwinding.
+        let mut items = ManuallyDrop::new(core::mem::take(self));
+
+        let errs: Vec<(usize, Error<T::Error>)> = items
+            .par_iter_mut()
+            .enumerate()
+            // SAFETY: force_init keeps the value in place.
+            .filter_map(|(i, item)| {
+                try_force_init(unsafe { item.as_mut().get_unchecked_mut() })
+                    .err()
+                    .map(|err| (i, err))
+            })
+            .collect();
+
+        let mut failed = errs.iter().filter_map(|(i, err)| match err {
+            Error::Init(_) => Some(*i),
+            // Nothing was dropped.
+            Error::State(_) => None,
+        });
+        let mut next_failed = failed.next();
+
+        *self = ManuallyDrop::into_inner(items)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                if next_failed != Some(i) {
+                    return Some(item);
+                }
+
+                next_failed = failed.next();
+                drop_failed(item);
+
+                None
+            })
+            .collect();
+
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+}
+
+impl<T> ParForceInit for PinnedVec<T>
+where
+    T: IncrStructInit + Send,
+    T::Error: Send,
+{
+    type Error = T::Error;
+
+    fn try_par_force_init(&mut self) -> Result<(), Vec<(usize, Error<Self::Error>)>> {
+        let mut items = core::mem::take(self).into_inner();
+        let result = items.try_par_force_init();
+        *self = items.into();
+
+        result
+    }
+}
+
+impl<T> ParForceInit for IncrSlab<T>
+where
+    T: IncrStructInit + Send,
+    T::Error: Send,
+{
+    type Error = T::Error;
+
+    /// Failed elements are dropped, and their slots freed. The other
+    /// elements keep their keys.
+    fn try_par_force_init(&mut self) -> Result<(), Vec<(usize, Error<Self::Error>)>> {
+        // The slots are marked free while initializing, so an element
+        // that panics is leaked, together with the rest.
+        let slots = self.take_occupied();
+
+        let errs: Vec<(usize, Error<T::Error>)> = slots
+            .into_par_iter()
+            .filter_map(|(key, slot)| {
+                // SAFETY: the slot was occupied, and force_init keeps
+                // the value in place.
+                let result = try_force_init(unsafe { slot.value.assume_init_mut() });
+
+                match result {
+                    // SAFETY: the header marks the initialized tail
+                    // fields of the poisoned struct.
+                    Err(Error::Init(_)) => _ = unsafe { T::drop_uninit_in_place(&mut slot.value) },
+                    // A state error drops nothing.
+                    _ => slot.occupied = true,
+                }
+
+                result.err().map(|err| (key, err))
+            })
+            .collect();
+
+        self.free_failed(errs.iter().filter_map(|(key, err)| match err {
+            Error::Init(_) => Some(*key),
+            Error::State(_) => None,
+        }));
+
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+}
+
+/// Drops an element whose `force_init` failed. Its tail fields are
+/// partially initialized, so it has to be dropped like an
+/// uninitialized struct.
+fn drop_failed<T: IncrStructBase>(item: Pin<Box<T>>) {
+    // SAFETY: the value is dropped in place, and MaybeUninit<T> has
+    // the same layout as T.
+    let raw = Box::into_raw(unsafe { Pin::into_inner_unchecked(item) });
+    let mut item = unsafe { Box::from_raw(raw as *mut MaybeUninit<T>) };
+
+    // SAFETY: the header marks the initialized tail fields of the
+    // poisoned struct.
+    _ = unsafe { T::drop_uninit_in_place(&mut item) };
+}
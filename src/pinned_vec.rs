@@ -0,0 +1,141 @@
+//! A vector of individually boxed self-referencing structs.
+
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::internal::{try_new_box, IncrStructInit};
+use crate::{Error, IncrEmplace};
+
+/// A thin wrapper around `Vec<Pin<Box<T>>>`. Each struct has its own
+/// allocation, so elements never move when the vector grows, or
+/// elements are removed. Unlike [IncrVec](crate::IncrVec), this never
+/// has to initialize tail fields again.
+///
+/// Use the generated `AStruct::emplace(&mut pinned_vec, heads...)` to
+/// construct a struct at the end. It returns a reference to it.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{IncrStruct, PinnedVec};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let mut my_vec = PinnedVec::new();
+///
+/// for i in 0..10 {
+///     AStruct::emplace(&mut my_vec, RefCell::new(i));
+/// }
+/// my_vec.retain(|v| *v.b % 2 == 0);
+///
+/// assert_eq!(my_vec.iter().map(|v| *v.b).sum::<i32>(), 20);
+/// ```
+pub struct PinnedVec<T> {
+    items: Vec<Pin<Box<T>>>,
+}
+
+impl<T> PinnedVec<T> {
+    /// Returns an empty vector, without allocating.
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Appends a boxed struct, and returns a reference to it.
+    pub fn push(&mut self, v: Pin<Box<T>>) -> &T {
+        self.items.push(v);
+
+        &self.items[self.items.len() - 1]
+    }
+
+    /// Returns the element at `index`, if any.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index).map(|item| &**item)
+    }
+
+    /// Returns the element at `index` mutably, if any.
+    pub fn get_mut(&mut self, index: usize) -> Option<Pin<&mut T>> {
+        self.items.get_mut(index).map(|item| item.as_mut())
+    }
+
+    /// Returns an iterator over all elements, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.items.iter().map(|item| &**item)
+    }
+
+    /// Returns an iterator over all elements mutably, in order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = Pin<&mut T>> + '_ {
+        self.items.iter_mut().map(|item| item.as_mut())
+    }
+
+    /// Removes the element at `index`, and returns it, still pinned.
+    /// The elements after it keep their addresses. Panics if `index`
+    /// is out of bounds, like `Vec::remove`.
+    pub fn remove(&mut self, index: usize) -> Pin<Box<T>> {
+        self.items.remove(index)
+    }
+
+    /// Drops all elements for which `f` returns false. The other
+    /// elements keep their addresses.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        self.items.retain(|item| f(item))
+    }
+
+    /// Drops all elements.
+    pub fn clear(&mut self) {
+        self.items.clear()
+    }
+
+    /// Returns the underlying vector.
+    pub fn into_inner(self) -> Vec<Pin<Box<T>>> {
+        self.items
+    }
+}
+
+impl<T> Default for PinnedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<Vec<Pin<Box<T>>>> for PinnedVec<T> {
+    fn from(items: Vec<Pin<Box<T>>>) -> Self {
+        Self { items }
+    }
+}
+
+impl<T: IncrStructInit> IncrEmplace<T> for PinnedVec<T> {
+    type Key<'a>
+        = &'a T
+    where
+        Self: 'a;
+
+    /// Constructs a struct in a new allocation, and appends it.
+    unsafe fn try_emplace_with<F: FnOnce(&mut T)>(&mut self, f: F) -> Result<&T, Error<T::Error>> {
+        Ok(self.push(try_new_box(f)?))
+    }
+}
@@ -0,0 +1,240 @@
+//! A pool of pinned slots for self-referencing structs.
+
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::internal::{init_heads, try_ensure_init, IncrStructInit};
+use crate::{Error, IncrEmplace};
+
+/// The number of slots allocated at a time.
+const CHUNK_LEN: usize = 64;
+
+pub(crate) struct Slot<T> {
+    pub(crate) value: MaybeUninit<T>,
+    pub(crate) occupied: bool,
+}
+
+/// A slab allocator for self-referencing structs. Slots are allocated
+/// in fixed-size chunks, which never move, so the structs are
+/// constructed in place, and keep their addresses until removed. Freed
+/// slots are reused by later insertions.
+///
+/// Use the generated `AStruct::emplace(slab, heads...)` to construct a
+/// struct in a free slot. It returns the key of the slot.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{IncrSlab, IncrStruct};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let mut my_slab = IncrSlab::new();
+/// let key = AStruct::emplace(&mut my_slab, RefCell::new(42));
+///
+/// assert_eq!(*my_slab.get(key).unwrap().b, 42);
+///
+/// my_slab.remove(key);
+/// ```
+pub struct IncrSlab<T: IncrStructInit> {
+    chunks: Vec<Box<[Slot<T>]>>,
+
+    // The keys of slots that aren't occupied, with the next one last.
+    free: Vec<usize>,
+
+    len: usize,
+}
+
+impl<T: IncrStructInit> IncrSlab<T> {
+    /// Returns an empty slab, without allocating.
+    pub const fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of occupied slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no slots are occupied.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the struct in slot `key`, if occupied.
+    pub fn get(&self, key: usize) -> Option<Pin<&T>> {
+        let slot = self.chunks.get(key / CHUNK_LEN)?.get(key % CHUNK_LEN)?;
+
+        // SAFETY: an occupied slot is fully initialized, and chunks
+        // never move.
+        slot.occupied
+            .then(|| unsafe { Pin::new_unchecked(slot.value.assume_init_ref()) })
+    }
+
+    /// Returns the struct in slot `key` mutably, if occupied.
+    pub fn get_mut(&mut self, key: usize) -> Option<Pin<&mut T>> {
+        let slot = self
+            .chunks
+            .get_mut(key / CHUNK_LEN)?
+            .get_mut(key % CHUNK_LEN)?;
+
+        // SAFETY: see get.
+        slot.occupied
+            .then(|| unsafe { Pin::new_unchecked(slot.value.assume_init_mut()) })
+    }
+
+    /// Drops the struct in slot `key`, and frees the slot. Returns
+    /// false if the slot wasn't occupied.
+    pub fn remove(&mut self, key: usize) -> bool {
+        let Some(slot) = self
+            .chunks
+            .get_mut(key / CHUNK_LEN)
+            .and_then(|chunk| chunk.get_mut(key % CHUNK_LEN))
+        else {
+            return false;
+        };
+        if !slot.occupied {
+            return false;
+        }
+
+        // The slot is marked free first, so the struct is leaked if
+        // its drop panics.
+        slot.occupied = false;
+        self.len -= 1;
+
+        // SAFETY: the slot was fully initialized, and is dropped in
+        // place.
+        unsafe { slot.value.assume_init_drop() };
+        self.free.push(key);
+
+        true
+    }
+
+    /// Marks all occupied slots as free, without dropping their
+    /// structs, and returns them with their keys. The caller marks
+    /// them occupied again, and calls [IncrSlab::free_failed] for the
+    /// rest.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn take_occupied(&mut self) -> Vec<(usize, &mut Slot<T>)> {
+        self.len = 0;
+
+        self.chunks
+            .iter_mut()
+            .flat_map(|chunk| chunk.iter_mut())
+            .enumerate()
+            .filter(|(_, slot)| slot.occupied)
+            .map(|(key, slot)| {
+                slot.occupied = false;
+
+                (key, slot)
+            })
+            .collect()
+    }
+
+    /// Frees the slots with the given keys, whose structs have been
+    /// dropped, after [IncrSlab::take_occupied].
+    #[cfg(feature = "rayon")]
+    pub(crate) fn free_failed(&mut self, keys: impl Iterator<Item = usize>) {
+        self.free.extend(keys);
+        self.len = self
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .filter(|slot| slot.occupied)
+            .count();
+    }
+
+    /// Allocates a new chunk, and returns the key of its first slot.
+    /// The other slots are added to the free list.
+    fn grow(&mut self) -> usize {
+        let base = self.chunks.len() * CHUNK_LEN;
+
+        self.chunks.push(
+            (0..CHUNK_LEN)
+                .map(|_| Slot {
+                    value: MaybeUninit::uninit(),
+                    occupied: false,
+                })
+                .collect(),
+        );
+        self.free.extend((base + 1..base + CHUNK_LEN).rev());
+
+        base
+    }
+}
+
+impl<T: IncrStructInit> IncrEmplace<T> for IncrSlab<T> {
+    type Key<'a>
+        = usize
+    where
+        Self: 'a;
+
+    /// Constructs a struct in a free slot, and returns its key. On
+    /// error, the slot is freed again.
+    unsafe fn try_emplace_with<F: FnOnce(&mut T)>(
+        &mut self,
+        f: F,
+    ) -> Result<usize, Error<T::Error>> {
+        let key = match self.free.pop() {
+            Some(key) => key,
+            None => self.grow(),
+        };
+        let slot = &mut self.chunks[key / CHUNK_LEN][key % CHUNK_LEN];
+
+        // If initialization panics, the key is lost, and the slot
+        // leaked.
+        init_heads(&mut slot.value, f);
+        match try_ensure_init(&mut slot.value) {
+            Ok(_) => {
+                slot.occupied = true;
+                self.len += 1;
+
+                Ok(key)
+            }
+            Err(err) => {
+                // SAFETY: only head fields are initialized.
+                _ = T::drop_uninit_in_place(&mut slot.value);
+                self.free.push(key);
+
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T: IncrStructInit> Default for IncrSlab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: IncrStructInit> Drop for IncrSlab<T> {
+    fn drop(&mut self) {
+        for slot in self.chunks.iter_mut().flat_map(|chunk| chunk.iter_mut()) {
+            if slot.occupied {
+                // SAFETY: the slot is fully initialized.
+                unsafe { slot.value.assume_init_drop() };
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Allocation-free construction of self-referencing structs.
+
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::drop_in_place;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+use crate::internal::{expect_init, try_ensure_init, try_init_at, IncrStructInit};
+use crate::Error;
+
+/// Constructs a struct on the stack, and binds a pinned mutable
+/// reference to it. The struct is dropped at the end of the
+/// enclosing scope. This doesn't need an allocator.
+///
+/// The struct must not have an init error type, unless the
+/// statement ends with `?`, which returns the error from the
+/// enclosing function.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{incr_pin, IncrStruct};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// incr_pin!(let my_value = AStruct::new(RefCell::new(42)));
+///
+/// assert_eq!(*my_value.b, 42);
+/// ```
+///
+/// Use `<AStruct<T>>::new(...)` for a generic struct.
+#[macro_export]
+macro_rules! incr_pin {
+    (let $name:ident = $ty:ident :: new($($arg:expr),* $(,)?) ?) => {
+        $crate::incr_pin!(@slot slot, $ty, $($arg),*);
+        // SAFETY: the slot is shadowed, so it can't move.
+        let $name = unsafe { slot.init() }?;
+    };
+    (let $name:ident = <$ty:ty> :: new($($arg:expr),* $(,)?) ?) => {
+        $crate::incr_pin!(@slot slot, $ty, $($arg),*);
+        // SAFETY: the slot is shadowed, so it can't move.
+        let $name = unsafe { slot.init() }?;
+    };
+    (let $name:ident = $ty:ident :: new($($arg:expr),* $(,)?)) => {
+        $crate::incr_pin!(@slot slot, $ty, $($arg),*);
+        // SAFETY: the slot is shadowed, so it can't move.
+        let $name = match unsafe { slot.init() } {
+            Ok(v) => v,
+            Err(err) => match err {},
+        };
+    };
+    (let $name:ident = <$ty:ty> :: new($($arg:expr),* $(,)?)) => {
+        $crate::incr_pin!(@slot slot, $ty, $($arg),*);
+        // SAFETY: the slot is shadowed, so it can't move.
+        let $name = match unsafe { slot.init() } {
+            Ok(v) => v,
+            Err(err) => match err {},
+        };
+    };
+    (@slot $slot:ident, $ty:ty, $($arg:expr),*) => {
+        // SAFETY: the slot takes care of dropping the partial struct.
+        let mut $slot = $crate::internal::StackSlot::<$ty>::new(unsafe { <$ty>::new_uninit($($arg),*) });
+    };
+}
+
+/// Owns a struct constructed in borrowed memory, using the unsafe,
+/// generated `AStruct::new_in_place(&mut slot, heads...)` function,
+/// and drops it when the guard is dropped. The memory can be anywhere, e.g. in
+/// a static buffer, or a memory-mapped region, as long as it's a
+/// `MaybeUninit<AStruct>`. For raw pointers, the generated
+/// `AStruct::init_at(ptr, heads...)` is the unsafe equivalent.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use std::mem::MaybeUninit;
+/// use incrstruct::IncrStruct;
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let mut slot = MaybeUninit::uninit();
+/// // SAFETY: the guard is dropped before the slot.
+/// let my_value = unsafe { AStruct::new_in_place(&mut slot, RefCell::new(42)) };
+///
+/// assert_eq!(*my_value.b, 42);
+/// ```
+///
+/// The struct is pinned, and `Pin` promises it's dropped before its
+/// memory is reused. Leaking the guard, e.g. with `mem::forget`,
+/// skips the drop, but ends the borrow of the slot, so that's why
+/// constructing it is unsafe. Use [incr_pin](crate::incr_pin), which
+/// owns the slot, for a safe alternative on the stack.
+pub struct InPlace<'a, T: IncrStructInit> {
+    value: Pin<&'a mut T>,
+}
+
+impl<'a, T: IncrStructInit> InPlace<'a, T> {
+    /// Constructs a struct in `slot`, using `f` to write the head
+    /// fields.
+    ///
+    /// Used by auto-generated code.
+    ///
+    /// # Safety
+    ///
+    /// `f` must initialize all head fields, and only the head fields.
+    ///
+    /// The returned guard must not be leaked, unless the slot is
+    /// never reused, e.g. because it's in a `static`. Otherwise, the
+    /// pinned struct wouldn't be dropped before its memory is reused.
+    pub unsafe fn new_with<F: FnOnce(&mut T)>(
+        slot: &'a mut MaybeUninit<T>,
+        f: F,
+    ) -> Result<Self, T::Error> {
+        Self::try_new_with(slot, f).map_err(|err| expect_init(err, "new_in_place"))
+    }
+
+    /// Like [InPlace::new_with], but returns state errors instead of
+    /// panicking.
+    ///
+    /// Used by auto-generated code.
+    ///
+    /// # Safety
+    ///
+    /// See [InPlace::new_with].
+    pub unsafe fn try_new_with<F: FnOnce(&mut T)>(
+        slot: &'a mut MaybeUninit<T>,
+        f: F,
+    ) -> Result<Self, Error<T::Error>> {
+        // SAFETY: the slot is borrowed until the guard drops the
+        // struct.
+        try_init_at(slot.as_mut_ptr(), f).map(|value| Self { value })
+    }
+
+    /// Returns a pinned mutable reference to the struct.
+    pub fn as_mut(&mut self) -> Pin<&mut T> {
+        self.value.as_mut()
+    }
+}
+
+impl<T: IncrStructInit> Deref for InPlace<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: IncrStructInit> Drop for InPlace<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: the struct is fully initialized, and is dropped in
+        // place.
+        unsafe { drop_in_place(self.value.as_mut().get_unchecked_mut()) };
+    }
+}
+
+/// Owns a struct with only head fields initialized, as returned by
+/// the generated `AStruct::new_uninit_guard(heads...)` function, and
+/// drops the head fields when the guard is dropped. Unlike the
+/// `MaybeUninit<AStruct>` returned by `new_uninit`, nothing leaks if
+/// the second phase is skipped, or fails. The struct doesn't borrow
+/// from itself yet, so the guard can move freely.
+///
+/// `ensure_init` consumes the guard, and moves the struct into a box
+/// before initializing the tail fields. The unsafe `ensure_init_in`
+/// uses caller-provided memory instead, like `new_in_place`.
+///
+/// ```rust
+/// # #[cfg(feature = "alloc")] {
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::IncrStruct;
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let guard = AStruct::new_uninit_guard(RefCell::new(42));
+/// let my_value = guard.ensure_init().unwrap();
+///
+/// assert_eq!(*my_value.b, 42);
+/// # }
+/// ```
+pub struct UninitGuard<T: IncrStructInit> {
+    value: MaybeUninit<T>,
+}
+
+impl<T: IncrStructInit> UninitGuard<T> {
+    /// Takes ownership of a partial struct.
+    ///
+    /// Used by auto-generated code.
+    ///
+    /// # Safety
+    ///
+    /// The value must have been created using `T::new_uninit`, and
+    /// its tail fields must not be initialized.
+    pub unsafe fn new(value: MaybeUninit<T>) -> Self {
+        Self { value }
+    }
+
+    /// Returns the partial struct, without dropping it. The caller is
+    /// responsible for calling `drop_uninit`, as after `new_uninit`.
+    pub fn into_inner(self) -> MaybeUninit<T> {
+        // SAFETY: the guard is forgotten, so the value is only owned
+        // by the caller.
+        unsafe { (&ManuallyDrop::new(self).value as *const MaybeUninit<T>).read() }
+    }
+
+    /// Initializes the tail fields in a new box. On error, the head
+    /// fields are dropped.
+    #[cfg(feature = "alloc")]
+    pub fn ensure_init(self) -> Result<Pin<Box<T>>, T::Error> {
+        self.try_ensure_init()
+            .map_err(|err| expect_init(err, "ensure_init"))
+    }
+
+    /// Like [UninitGuard::ensure_init], but returns state errors
+    /// instead of panicking.
+    #[cfg(feature = "alloc")]
+    pub fn try_ensure_init(self) -> Result<Pin<Box<T>>, Error<T::Error>> {
+        let mut value = Box::new(self.into_inner());
+
+        // SAFETY: the box is never moved out of, and a failed struct
+        // only has head fields, and completed phases, to drop.
+        unsafe {
+            if let Err(err) = try_ensure_init(&mut value) {
+                _ = T::drop_uninit_in_place(&mut value);
+
+                return Err(err);
+            }
+
+            Ok(Pin::new_unchecked(Box::from_raw(
+                Box::into_raw(value).cast::<T>(),
+            )))
+        }
+    }
+
+    /// Initializes the tail fields in `slot`. On error, the head fields
+    /// are dropped.
+    ///
+    /// # Safety
+    ///
+    /// See [InPlace::new_with]: the returned guard must not be leaked,
+    /// unless the slot is never reused.
+    pub unsafe fn ensure_init_in(
+        self,
+        slot: &mut MaybeUninit<T>,
+    ) -> Result<InPlace<'_, T>, T::Error> {
+        self.try_ensure_init_in(slot)
+            .map_err(|err| expect_init(err, "ensure_init_in"))
+    }
+
+    /// Like [UninitGuard::ensure_init_in], but returns state errors
+    /// instead of panicking.
+    ///
+    /// # Safety
+    ///
+    /// See [UninitGuard::ensure_init_in].
+    pub unsafe fn try_ensure_init_in(
+        self,
+        slot: &mut MaybeUninit<T>,
+    ) -> Result<InPlace<'_, T>, Error<T::Error>> {
+        *slot = self.into_inner();
+
+        // SAFETY: the slot is borrowed until the guard drops the
+        // struct, and a failed struct only has head fields, and
+        // completed phases, to drop.
+        unsafe {
+            if let Err(err) = try_ensure_init(slot) {
+                _ = T::drop_uninit_in_place(slot);
+
+                return Err(err);
+            }
+
+            Ok(InPlace {
+                value: Pin::new_unchecked(slot.assume_init_mut()),
+            })
+        }
+    }
+}
+
+impl<T: IncrStructInit> Drop for UninitGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: only head fields are initialized.
+        _ = unsafe { T::drop_uninit_in_place(&mut self.value) };
+    }
+}
@@ -0,0 +1,93 @@
+//! Atomic replacement of shared, self-referencing structs.
+
+use core::ops::Deref;
+use core::pin::Pin;
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+
+/// An atomically replaceable `Pin<Arc<T>>`, based on
+/// [arc_swap::ArcSwap]. This is useful e.g. for configuration that
+/// is reloaded while other threads read it.
+///
+/// Unlike `ArcSwap<T>`, this never hands out an unpinned `Arc<T>`,
+/// so the value can't be moved out of its allocation, e.g. with
+/// `Arc::try_unwrap`.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use incrstruct::IncrArcSwap;
+///
+/// let config = IncrArcSwap::new(Arc::pin(42));
+///
+/// config.store(Arc::pin(43));
+///
+/// assert_eq!(*config.load(), 43);
+/// ```
+pub struct IncrArcSwap<T> {
+    inner: ArcSwap<T>,
+}
+
+impl<T> IncrArcSwap<T> {
+    /// Creates a new swap, holding `v`.
+    pub fn new(v: Pin<Arc<T>>) -> Self {
+        // SAFETY: the Arc is only ever handed out pinned.
+        Self {
+            inner: ArcSwap::new(unsafe { Pin::into_inner_unchecked(v) }),
+        }
+    }
+
+    /// Returns a temporary reference to the current value. This is
+    /// cheaper than [IncrArcSwap::load_full], but the guard should
+    /// not be held for long.
+    pub fn load(&self) -> IncrGuard<T> {
+        IncrGuard(self.inner.load())
+    }
+
+    /// Returns the current value.
+    pub fn load_full(&self) -> Pin<Arc<T>> {
+        // SAFETY: the value has been pinned since it was stored.
+        unsafe { Pin::new_unchecked(self.inner.load_full()) }
+    }
+
+    /// Replaces the current value with `v`.
+    pub fn store(&self, v: Pin<Arc<T>>) {
+        // SAFETY: the Arc is only ever handed out pinned.
+        self.inner.store(unsafe { Pin::into_inner_unchecked(v) })
+    }
+
+    /// Replaces the current value with `v`, returning the previous
+    /// value.
+    pub fn swap(&self, v: Pin<Arc<T>>) -> Pin<Arc<T>> {
+        // SAFETY: the Arcs are only ever handed out pinned.
+        unsafe { Pin::new_unchecked(self.inner.swap(Pin::into_inner_unchecked(v))) }
+    }
+
+    /// Replaces the current value with a new one, computed from the
+    /// current value by `f`, and returns the previous value. If
+    /// another thread replaces the value concurrently, `f` is called
+    /// again. See [arc_swap::ArcSwapAny::rcu].
+    pub fn rcu<F>(&self, mut f: F) -> Pin<Arc<T>>
+    where
+        F: FnMut(&T) -> Pin<Arc<T>>,
+    {
+        // SAFETY: the Arcs are only ever handed out pinned.
+        unsafe {
+            Pin::new_unchecked(
+                self.inner
+                    .rcu(|current| Pin::into_inner_unchecked(f(current))),
+            )
+        }
+    }
+}
+
+/// A temporary reference to the value of an [IncrArcSwap].
+pub struct IncrGuard<T>(Guard<Arc<T>>);
+
+impl<T> Deref for IncrGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,109 @@
+//! Bottom-up construction of trees of self-referencing structs.
+
+use alloc::vec::Vec;
+
+/// Builds a tree bottom-up, e.g. of structs owning their children as
+/// `Vec<Pin<Box<Self>>>` heads. `children` takes the descriptions of
+/// the children out of a node description, and `build` creates a
+/// node from its description and its children, which are already
+/// built, in the same order. A node is built after all its children,
+/// so tails of the node can borrow them.
+///
+/// This doesn't recurse, so deep trees don't overflow the stack.
+///
+/// ```rust
+/// use core::pin::Pin;
+/// use incrstruct::IncrStruct;
+///
+/// #[derive(IncrStruct)]
+/// struct Node<'a> {
+///     #[borrows(children)]
+///     child_values: Vec<&'a i32>,
+///
+///     value: i32,
+///     children: Vec<Pin<Box<Self>>>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> NodeInit<'a> for Node<'a> {
+///     fn init_field_child_values(children: &'a Vec<Pin<Box<Self>>>) -> Vec<&'a i32> {
+///         children.iter().map(|child| &child.value).collect()
+///     }
+/// }
+///
+/// // A value, and the children.
+/// struct Desc(i32, Vec<Desc>);
+///
+/// let root = incrstruct::build_tree(
+///     Desc(1, vec![Desc(2, Vec::new()), Desc(3, Vec::new())]),
+///     |desc| core::mem::take(&mut desc.1),
+///     |desc, children| Node::new_box(desc.0, children),
+/// );
+///
+/// assert_eq!(root.child_values, vec![&2, &3]);
+/// ```
+pub fn build_tree<S, N>(
+    root: S,
+    children: impl FnMut(&mut S) -> Vec<S>,
+    mut build: impl FnMut(S, Vec<N>) -> N,
+) -> N {
+    match try_build_tree(root, children, |desc, children| {
+        Ok::<_, core::convert::Infallible>(build(desc, children))
+    }) {
+        Ok(node) => node,
+        Err(err) => match err {},
+    }
+}
+
+/// Like [build_tree], but stops at the first error `build` returns.
+/// The nodes built so far, and the remaining descriptions, are
+/// dropped.
+pub fn try_build_tree<S, N, E>(
+    mut root: S,
+    mut children: impl FnMut(&mut S) -> Vec<S>,
+    mut build: impl FnMut(S, Vec<N>) -> Result<N, E>,
+) -> Result<N, E> {
+    let pending = children(&mut root).into_iter();
+    let mut stack = Vec::from([Frame {
+        desc: root,
+        built: Vec::with_capacity(pending.len()),
+        pending,
+    }]);
+
+    loop {
+        // The stack is only empty after the root is built, which
+        // returns.
+        let top = stack.last_mut().unwrap();
+
+        if let Some(mut desc) = top.pending.next() {
+            let pending = children(&mut desc).into_iter();
+            stack.push(Frame {
+                desc,
+                built: Vec::with_capacity(pending.len()),
+                pending,
+            });
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let node = build(frame.desc, frame.built)?;
+
+        match stack.last_mut() {
+            Some(parent) => parent.built.push(node),
+            None => return Ok(node),
+        }
+    }
+}
+
+/// A node being built by [try_build_tree].
+struct Frame<S, N> {
+    desc: S,
+
+    // Descriptions of children not yet built.
+    pending: alloc::vec::IntoIter<S>,
+
+    // Children already built, in order.
+    built: Vec<N>,
+}
@@ -0,0 +1,274 @@
+//! A growable vector storing self-referencing structs inline.
+
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+
+use alloc::vec::Vec;
+
+use crate::internal::{drop_tails, expect_init, try_ensure_init, IncrStructInit};
+
+/// A `Vec`-like container that stores structs inline, instead of
+/// boxing each one. When elements have to move, because the vector
+/// grows, or an element is removed, their tail fields are dropped
+/// before the move, and initialized again afterwards. Elements are
+/// only handed out pinned, so they can't be moved otherwise.
+///
+/// ```rust
+/// use std::cell::{Ref, RefCell};
+/// use incrstruct::{IncrStruct, IncrVec};
+///
+/// #[derive(IncrStruct)]
+/// struct AStruct<'a> {
+///     #[borrows(a)]
+///     b: Ref<'a, i32>,
+///     a: RefCell<i32>,
+///
+///     #[header]
+///     hdr: incrstruct::Header,
+/// }
+///
+/// impl<'a> AStructInit<'a> for AStruct<'a> {
+///     fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+///         a.borrow()
+///     }
+/// }
+///
+/// let mut my_vec = IncrVec::new();
+///
+/// for i in 0..10 {
+///     unsafe { my_vec.push(AStruct::new_uninit(RefCell::new(i))) };
+/// }
+///
+/// assert_eq!(*my_vec.get(9).unwrap().b, 9);
+/// ```
+///
+/// If a tail field fails to initialize again after a move, the
+/// element is dropped and removed, and the error is returned. If it
+/// panics, that element, and all elements after it, are dropped and
+/// removed, so only fully initialized elements remain.
+pub struct IncrVec<T: IncrStructInit> {
+    // All elements are fully initialized, except while moving them.
+    items: Vec<MaybeUninit<T>>,
+}
+
+impl<T: IncrStructInit> IncrVec<T> {
+    /// Returns an empty vector, without allocating.
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// Returns an empty vector with room for `capacity` elements.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the number of elements that fit without moving them.
+    pub fn capacity(&self) -> usize {
+        self.items.capacity()
+    }
+
+    /// Returns the element at `index`, if any.
+    pub fn get(&self, index: usize) -> Option<Pin<&T>> {
+        // SAFETY: the element is fully initialized, and is never moved
+        // without being initialized again.
+        self.items
+            .get(index)
+            .map(|item| unsafe { Pin::new_unchecked(item.assume_init_ref()) })
+    }
+
+    /// Returns the element at `index` mutably, if any.
+    pub fn get_mut(&mut self, index: usize) -> Option<Pin<&mut T>> {
+        // SAFETY: see get.
+        self.items
+            .get_mut(index)
+            .map(|item| unsafe { Pin::new_unchecked(item.assume_init_mut()) })
+    }
+
+    /// Returns an iterator over all elements, in order.
+    pub fn iter(&self) -> impl Iterator<Item = Pin<&T>> + '_ {
+        // SAFETY: see get.
+        self.items
+            .iter()
+            .map(|item| unsafe { Pin::new_unchecked(item.assume_init_ref()) })
+    }
+
+    /// Makes room for at least `additional` more elements. If this
+    /// reallocates, all elements are initialized again.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), T::Error> {
+        if self.items.capacity() - self.items.len() >= additional {
+            return Ok(());
+        }
+
+        self.relocate(0, |items| items.reserve(additional))
+    }
+
+    /// Appends a partial struct, normally created using
+    /// `T::new_uninit`, and initializes its tail fields. On error, the
+    /// struct is dropped. Errors from initializing existing elements
+    /// again, if the vector had to grow, are also returned.
+    ///
+    /// # Safety
+    ///
+    /// All head fields of `v` must be initialized, and no tail fields.
+    pub unsafe fn push(&mut self, mut v: MaybeUninit<T>) -> Result<Pin<&mut T>, T::Error> {
+        if let Err(err) = self.reserve(1) {
+            _ = T::drop_uninit_in_place(&mut v);
+            return Err(err);
+        }
+
+        self.items.push(v);
+        let index = self.items.len() - 1;
+        if let Err(err) = try_ensure_init(&mut self.items[index]) {
+            // SAFETY: only head fields are initialized.
+            _ = T::drop_uninit_in_place(&mut self.items[index]);
+            self.items.pop();
+            return Err(expect_init(err, "IncrVec::push"));
+        }
+
+        Ok(self.get_mut(index).unwrap())
+    }
+
+    /// Drops the element at `index`, and shifts all elements after it
+    /// to the left, initializing them again. Panics if `index` is out
+    /// of bounds, like `Vec::remove`.
+    pub fn remove(&mut self, index: usize) -> Result<(), T::Error> {
+        assert!(
+            index < self.items.len(),
+            "IncrVec::remove index out of bounds"
+        );
+
+        let mut removed = None;
+        let res = self.relocate(index, |items| removed = Some(items.remove(index)));
+
+        if let Some(mut removed) = removed {
+            // SAFETY: the tail fields were dropped by relocate.
+            _ = unsafe { T::drop_uninit_in_place(&mut removed) };
+        }
+
+        res
+    }
+
+    /// Drops all elements after the first `len`.
+    pub fn truncate(&mut self, len: usize) {
+        while self.items.len() > len {
+            let last = self.items.len() - 1;
+
+            // SAFETY: the element is fully initialized, and is dropped
+            // in place. It's excluded first, so it's leaked if its
+            // drop panics.
+            unsafe {
+                self.items.set_len(last);
+                self.items
+                    .as_mut_ptr()
+                    .add(last)
+                    .cast::<T>()
+                    .drop_in_place();
+            }
+        }
+    }
+
+    /// Drops all elements.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Drops the tail fields of the elements from `index`, runs `f`,
+    /// which may move them, and initializes them again. Elements that
+    /// fail to initialize are dropped, and removed. The first error
+    /// is returned.
+    fn relocate<F: FnOnce(&mut Vec<MaybeUninit<T>>)>(
+        &mut self,
+        index: usize,
+        f: F,
+    ) -> Result<(), T::Error> {
+        let mut guard = RelocateGuard {
+            items: &mut self.items,
+            inited: index,
+        };
+        for item in guard.items[index..].iter_mut() {
+            // SAFETY: the element is fully initialized, and its tail
+            // fields are dropped before it moves.
+            _ = drop_tails(unsafe { item.assume_init_mut() });
+        }
+
+        f(guard.items);
+
+        let mut res = Ok(());
+        while guard.inited < guard.items.len() {
+            let i = guard.inited;
+            // SAFETY: only head fields are initialized, and the
+            // element won't move again until its tails are dropped.
+            match unsafe { try_ensure_init(&mut guard.items[i]) } {
+                Ok(_) => guard.inited += 1,
+                Err(err) => {
+                    // SAFETY: only head fields are initialized.
+                    _ = unsafe { T::drop_uninit_in_place(&mut guard.items[i]) };
+                    guard.items.remove(i);
+
+                    if res.is_ok() {
+                        res = Err(expect_init(err, "IncrVec"));
+                    }
+                }
+            }
+        }
+
+        res
+    }
+}
+
+/// Drops and removes the elements that weren't initialized again, if
+/// [IncrVec::relocate] unwinds, so the vector only hands out fully
+/// initialized elements.
+struct RelocateGuard<'a, T: IncrStructInit> {
+    items: &'a mut Vec<MaybeUninit<T>>,
+
+    // Elements before this index are fully initialized.
+    inited: usize,
+}
+
+impl<T: IncrStructInit> Drop for RelocateGuard<'_, T> {
+    fn drop(&mut self) {
+        for item in self.items[self.inited..].iter_mut() {
+            drop_item(item);
+        }
+        self.items.truncate(self.inited);
+    }
+}
+
+impl<T: IncrStructInit> Default for IncrVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: IncrStructInit> Drop for IncrVec<T> {
+    fn drop(&mut self) {
+        for item in self.items.iter_mut() {
+            drop_item(item);
+        }
+    }
+}
+
+/// Drops an element that is either fully initialized, or, after a
+/// panic, only has head fields, and tail fields of completed phases.
+fn drop_item<T: IncrStructInit>(item: &mut MaybeUninit<T>) {
+    // SAFETY: drop_uninit_in_place drops nothing if the element is
+    // fully initialized.
+    unsafe {
+        if T::drop_uninit_in_place(item).is_err() {
+            item.assume_init_drop();
+        }
+    }
+}
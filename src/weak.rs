@@ -0,0 +1,88 @@
+//! Weak pointers to pinned structs, for `#[cyclic]` head fields.
+
+use core::fmt;
+use core::pin::Pin;
+
+use alloc::rc::{self, Rc};
+use alloc::sync::{self, Arc};
+
+/// A weak pointer to a struct in a `Pin<Rc<T>>`, stored in a
+/// `#[cyclic(rc)]` head field.
+///
+/// Unlike `std::rc::Weak`, upgrading it returns a pinned pointer, so
+/// the struct can't be moved out, e.g. with `Rc::try_unwrap`, which
+/// would leave its tail fields dangling.
+pub struct PinRcWeak<T>(pub(crate) rc::Weak<T>);
+
+impl<T> PinRcWeak<T> {
+    /// Creates a weak pointer to a pinned `Rc`.
+    pub fn downgrade(this: &Pin<Rc<T>>) -> Self {
+        // SAFETY: the pointer is only upgraded into pins again.
+        Self(Rc::downgrade(unsafe {
+            &*(this as *const Pin<Rc<T>> as *const Rc<T>)
+        }))
+    }
+
+    /// Returns a strong pointer, unless the struct has been dropped.
+    pub fn upgrade(&self) -> Option<Pin<Rc<T>>> {
+        // SAFETY: the struct was pinned when this was created, and
+        // all strong pointers to it are pins.
+        self.0.upgrade().map(|rc| unsafe { Pin::new_unchecked(rc) })
+    }
+
+    /// Returns the number of strong pointers to the struct.
+    pub fn strong_count(&self) -> usize {
+        self.0.strong_count()
+    }
+}
+
+impl<T> Clone for PinRcWeak<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for PinRcWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(PinRcWeak)")
+    }
+}
+
+/// Like [PinRcWeak], but for a `Pin<Arc<T>>`, stored in a
+/// `#[cyclic(arc)]` head field.
+pub struct PinArcWeak<T>(pub(crate) sync::Weak<T>);
+
+impl<T> PinArcWeak<T> {
+    /// Creates a weak pointer to a pinned `Arc`.
+    pub fn downgrade(this: &Pin<Arc<T>>) -> Self {
+        // SAFETY: the pointer is only upgraded into pins again.
+        Self(Arc::downgrade(unsafe {
+            &*(this as *const Pin<Arc<T>> as *const Arc<T>)
+        }))
+    }
+
+    /// Returns a strong pointer, unless the struct has been dropped.
+    pub fn upgrade(&self) -> Option<Pin<Arc<T>>> {
+        // SAFETY: see PinRcWeak::upgrade.
+        self.0
+            .upgrade()
+            .map(|arc| unsafe { Pin::new_unchecked(arc) })
+    }
+
+    /// Returns the number of strong pointers to the struct.
+    pub fn strong_count(&self) -> usize {
+        self.0.strong_count()
+    }
+}
+
+impl<T> Clone for PinArcWeak<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for PinArcWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(PinArcWeak)")
+    }
+}
@@ -1,8 +1,9 @@
 use core::cell::{Ref, RefCell};
+#[cfg(feature = "alloc")]
 use core::pin::Pin;
 use core::ptr::drop_in_place;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod simple {
     use super::*;
 
@@ -26,14 +27,14 @@ mod simple {
             b
         }
 
-        fn init_field_b(_head2: &'a i64, head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+        fn init_field_b(head1: &'a RefCell<i32>, _head2: &'a i64) -> Ref<'a, i32> {
             head1.borrow()
         }
     }
 
     #[test]
     fn new_uninit_works() {
-        let mut a = unsafe { AStruct::new_uninit(4711, RefCell::new(42)) };
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
         let aref = unsafe { AStruct::ensure_init(&mut a) };
 
         assert_eq!(*aref.head1.borrow(), 42);
@@ -43,9 +44,23 @@ mod simple {
         unsafe { drop_in_place(aref) };
     }
 
+    #[test]
+    fn ensure_init_with_works() {
+        let stub = 17;
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+        let aref = unsafe {
+            AStruct::ensure_init_with(&mut a, |head1, _head2| head1.borrow(), |_b| &stub)
+        };
+
+        assert_eq!(*aref.b, 42);
+        assert_eq!(*aref.c, 17);
+
+        unsafe { drop_in_place(aref) };
+    }
+
     #[test]
     fn drop_uninit_in_place_works() {
-        let a = unsafe { AStruct::new_uninit(4711, RefCell::new(42)) };
+        let a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
 
         assert_eq!(*(unsafe { &*a.as_ptr() }).head1.borrow(), 42);
 
@@ -54,7 +69,7 @@ mod simple {
 
     #[test]
     fn new_box_works() {
-        let a = AStruct::new_box(4711, RefCell::new(42));
+        let a = AStruct::new_box(RefCell::new(42), 4711);
 
         assert_eq!(*a.head1.borrow(), 42);
         assert_eq!(*a.b, 42);
@@ -63,17 +78,100 @@ mod simple {
 
     #[test]
     fn new_rc_works() {
-        let a = AStruct::new_rc(4711, RefCell::new(42));
+        let a = AStruct::new_rc(RefCell::new(42), 4711);
+
+        assert_eq!(*a.head1.borrow(), 42);
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+    #[test]
+    fn new_arc_works() {
+        let a = AStruct::new_arc(RefCell::new(42), 4711);
+
+        assert_eq!(*a.head1.borrow(), 42);
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn new_box_from_heads_works() {
+        let a = AStruct::new_box_from_heads(AStructHeads {
+            head1: RefCell::new(42),
+            head2: 4711,
+        });
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.head2, 4711);
+    }
+
+    #[test]
+    fn into_heads_works() {
+        let a = AStruct::new_box(RefCell::new(42), 4711);
+        let heads = AStruct::into_heads(a);
+
+        assert_eq!(heads.head2, 4711);
+        // The tail borrows were dropped.
+        *heads.head1.borrow_mut() = 43;
+        assert_eq!(*heads.head1.borrow(), 43);
+    }
+
+    #[test]
+    fn with_heads_mut_works() {
+        let mut a = AStruct::new_box(RefCell::new(42), 4711);
+        let ret = AStruct::with_heads_mut(a.as_mut(), |heads| {
+            *heads.head1.borrow_mut() = 43;
+            heads.head2 = 17;
+
+            "done"
+        });
+
+        assert_eq!(ret, "done");
+        assert_eq!(a.head2, 17);
+        assert_eq!(*a.b, 43);
+        assert_eq!(*a.c, 43);
+    }
+
+    #[test]
+    fn set_head_works() {
+        let mut a = AStruct::new_box(RefCell::new(42), 4711);
+
+        AStruct::set_head1(a.as_mut(), RefCell::new(43));
+        assert_eq!(*a.b, 43);
+        assert_eq!(*a.c, 43);
+
+        AStruct::set_head2(a.as_mut(), 17);
+        assert_eq!(a.head2, 17);
+        assert_eq!(*a.c, 43);
+    }
+
+    #[test]
+    fn incr_pin_works() {
+        incrstruct::incr_pin!(let a = AStruct::new(RefCell::new(42), 4711));
 
         assert_eq!(*a.head1.borrow(), 42);
         assert_eq!(*a.b, 42);
         assert_eq!(*a.c, 42);
     }
 
+    #[test]
+    fn new_in_works() {
+        let a = AStruct::new_in::<Box<_>>(RefCell::new(42), 4711);
+        let b = AStruct::new_in::<std::rc::Rc<_>>(RefCell::new(43), 4711);
+        let c = AStruct::new_in::<std::sync::Arc<_>>(RefCell::new(44), 4711);
+
+        assert_eq!(*a.c, 42);
+        assert_eq!(*b.c, 43);
+        assert_eq!(*c.c, 44);
+    }
+
     #[test]
     fn force_init_works() {
-        let a = AStruct::new_box(4711, RefCell::new(42));
-        let mut b = *unsafe { Pin::into_inner_unchecked(a) };
+        let a = AStruct::new_box(RefCell::new(42), 4711);
+        // The tails still borrow the old place, so it's kept until
+        // force_init has dropped them.
+        let old: Box<core::mem::MaybeUninit<AStruct>> =
+            unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(a)).cast()) };
+        let mut b = unsafe { old.assume_init_read() };
         let _bpin = unsafe { Pin::new_unchecked(&mut b) };
 
         AStruct::force_init(&mut b);
@@ -81,10 +179,350 @@ mod simple {
         assert_eq!(*b.head1.borrow(), 42);
         assert_eq!(*b.b, 42);
         assert_eq!(*b.c, 42);
+
+        AStruct::debug_assert_not_moved(&b);
+    }
+
+    #[test]
+    fn into_rc_works() {
+        let a = AStruct::into_rc(AStruct::new_box(RefCell::new(42), 4711));
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+
+        AStruct::debug_assert_not_moved(&a);
+    }
+
+    #[test]
+    fn into_arc_works() {
+        let a = AStruct::into_arc(AStruct::new_box(RefCell::new(42), 4711));
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn debug_assert_not_moved_works() {
+        let a = AStruct::new_box(RefCell::new(42), 4711);
+
+        AStruct::debug_assert_not_moved(&a);
+    }
+
+    #[cfg(all(feature = "move_check", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "struct has moved")]
+    fn debug_assert_not_moved_panics() {
+        let a = AStruct::new_box(RefCell::new(42), 4711);
+        // The tails are dangling, so never drop it.
+        let b = core::mem::ManuallyDrop::new(*unsafe { Pin::into_inner_unchecked(a) });
+
+        AStruct::debug_assert_not_moved(&b);
+    }
+
+    #[cfg(all(feature = "move_check", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "struct has moved")]
+    fn tail_accessor_panics_if_moved() {
+        let a = AStruct::new_box(RefCell::new(42), 4711);
+        // The tails are dangling, so never drop it.
+        let b = core::mem::ManuallyDrop::new(*unsafe { Pin::into_inner_unchecked(a) });
+
+        AStruct::with_c(&b, |_c| ());
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
+mod reinit {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static DROPS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    struct Counted(i32);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.set(DROPS.get() + 1);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        c: &'a i32,
+
+        #[borrows(a)]
+        b: Counted,
+
+        a: Cell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const A_FAIL: i32 = -1;
+    const A_PANIC: i32 = -2;
+    const C_FAIL: i32 = -3;
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Counted) -> Result<&'a i32, Error> {
+            if b.0 == C_FAIL {
+                return Err(Error::Failed);
+            }
+
+            Ok(&b.0)
+        }
+
+        fn init_field_b(a: &'a Cell<i32>) -> Result<Counted, Error> {
+            match a.get() {
+                A_FAIL => Err(Error::Failed),
+                A_PANIC => panic!("init_field_b"),
+                a => Ok(Counted(a)),
+            }
+        }
+    }
+
+    /// Runs `f` in a child process, running the `test` test again,
+    /// and checks that it aborts. The tail fields are dropped when
+    /// re-initializing a live struct, so returning, or unwinding,
+    /// would drop them again.
+    fn assert_aborts(test: &str, f: impl FnOnce()) {
+        if std::env::var_os("INCRSTRUCT_REINIT_CHILD").is_some() {
+            f();
+
+            return;
+        }
+
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", &format!("reinit::{}", test), "--test-threads=1"])
+            .env("INCRSTRUCT_REINIT_CHILD", "1")
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success(), "{} didn't abort", test);
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            assert_eq!(output.status.signal(), Some(6), "{} didn't abort", test);
+        }
+    }
+
+    /// Drops `a`, and checks that the tail field was dropped once
+    /// more, as expected if nothing was dropped twice.
+    fn drop_checked(a: Pin<Box<AStruct<'_>>>, drops: usize) {
+        drop(a);
+        assert_eq!(DROPS.get(), drops + 1);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(boxed)]
+    #[init_err(Error)]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        b: Counted,
+
+        a: incrstruct::AliasableBox<Cell<i32>>,
+        #[default]
+        _ph: core::marker::PhantomData<&'a ()>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a Cell<i32>) -> Result<Counted, Error> {
+            AStruct::init_field_b(a)
+        }
+    }
+
+    #[test]
+    fn with_heads_mut_drops_tails_once() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        AStruct::with_heads_mut(a.as_mut(), |heads| heads.a.set(43)).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn with_heads_mut_restores_heads_on_failure() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        assert_eq!(
+            AStruct::with_heads_mut(a.as_mut(), |heads| heads.a.set(A_FAIL)),
+            Err(Error::Failed)
+        );
+        assert_eq!(a.a.get(), 42);
+        assert_eq!(*a.c, 42);
+        // Only the old tail was dropped, since the new one failed.
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn with_heads_mut_aborts_on_panic() {
+        assert_aborts("with_heads_mut_aborts_on_panic", || {
+            let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+            _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                AStruct::with_heads_mut(a.as_mut(), |_| panic!("f"))
+            }));
+            drop_checked(a, 1);
+        });
+    }
+
+    #[test]
+    fn set_head_drops_tails_once() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        AStruct::set_a(a.as_mut(), Cell::new(43)).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn set_head_restores_head_on_failure() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        assert_eq!(
+            AStruct::set_a(a.as_mut(), Cell::new(A_FAIL)),
+            Err(Error::Failed)
+        );
+        assert_eq!(a.a.get(), 42);
+        assert_eq!(*a.c, 42);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn set_head_aborts_on_panic() {
+        assert_aborts("set_head_aborts_on_panic", || {
+            let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+            _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                _ = AStruct::set_a(a.as_mut(), Cell::new(A_PANIC));
+            }));
+            drop_checked(a, 1);
+        });
+    }
+
+    #[test]
+    fn refresh_drops_tails_once() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(43);
+        AStruct::refresh_b(a.as_mut()).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn refresh_keeps_old_value_on_failure() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(A_FAIL);
+        assert_eq!(AStruct::refresh_b(a.as_mut()), Err(Error::Failed));
+        assert_eq!(*a.c, 42);
+        assert_eq!(DROPS.get(), 0);
+
+        // The tails were marked as dirty.
+        a.a.set(43);
+        AStruct::recompute(a.as_mut()).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn refresh_unwinds_on_panic() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(A_PANIC);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            _ = AStruct::refresh_b(a.as_mut());
+        }))
+        .is_err());
+        assert_eq!(*a.c, 42);
+
+        drop_checked(a, 0);
+    }
+
+    #[test]
+    fn recompute_drops_tails_once() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(43);
+        AStruct::mark_a_dirty(a.as_mut());
+        AStruct::recompute(a.as_mut()).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn boxed_force_init_aborts_on_failure() {
+        assert_aborts("boxed_force_init_aborts_on_failure", || {
+            let mut a = BStruct::new(incrstruct::AliasableBox::new(Cell::new(42))).unwrap();
+
+            BStruct::a(&a).set(A_FAIL);
+            _ = BStruct::force_init(&mut a);
+            drop(a);
+        });
+    }
+
+    #[test]
+    fn recompute_keeps_dirty_on_failure() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(A_FAIL);
+        AStruct::mark_a_dirty(a.as_mut());
+        assert_eq!(AStruct::recompute(a.as_mut()), Err(Error::Failed));
+        assert_eq!(*a.c, 42);
+        assert_eq!(DROPS.get(), 0);
+
+        a.a.set(43);
+        AStruct::recompute(a.as_mut()).unwrap();
+        assert_eq!(*a.c, 43);
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+
+    #[test]
+    fn recompute_restores_old_value_if_borrower_fails() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(C_FAIL);
+        AStruct::mark_a_dirty(a.as_mut());
+        assert_eq!(AStruct::recompute(a.as_mut()), Err(Error::Failed));
+        assert_eq!(*a.c, 42);
+        // The new value of b was dropped.
+        assert_eq!(DROPS.get(), 1);
+
+        drop_checked(a, 1);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
 mod init_err {
     use super::*;
 
@@ -112,7 +550,7 @@ mod init_err {
     const HEAD2_FAIL: i64 = 18;
 
     impl<'a> AStructInit<'a> for AStruct<'a> {
-        fn init_field_c(head2: &'a i64, b: &'a Ref<'a, i32>) -> Result<&'a i32, Error> {
+        fn init_field_c(b: &'a Ref<'a, i32>, head2: &'a i64) -> Result<&'a i32, Error> {
             if *head2 == HEAD2_FAIL {
                 Err(Error::Failed)
             } else {
@@ -127,7 +565,7 @@ mod init_err {
 
     #[test]
     fn new_uninit_works() {
-        let mut a = unsafe { AStruct::new_uninit(4711, RefCell::new(42)) };
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
         let aref = unsafe { AStruct::ensure_init(&mut a).unwrap() };
 
         assert_eq!(*aref.head1.borrow(), 42);
@@ -137,9 +575,46 @@ mod init_err {
         unsafe { drop_in_place(aref) };
     }
 
+    #[test]
+    fn ensure_init_with_fails_gracefully() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+        let res = unsafe {
+            AStruct::ensure_init_with(
+                &mut a,
+                |head1| Ok(head1.borrow()),
+                |_b, _head2| Err(Error::Failed),
+            )
+        };
+
+        assert_eq!(res.unwrap_err(), Error::Failed);
+
+        // The b field was dropped, so this can be borrowed mutably.
+        *unsafe { &*a.as_ptr() }.head1.borrow_mut() = 43;
+
+        unsafe { AStruct::drop_uninit(a) };
+    }
+
+    #[test]
+    #[should_panic(expected = "ensure_init called re-entrantly")]
+    fn reentrant_ensure_init_panics() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+        let ap: *mut core::mem::MaybeUninit<AStruct> = &mut a;
+
+        let _ = unsafe {
+            AStruct::ensure_init_with(
+                &mut a,
+                |head1| {
+                    let _ = AStruct::ensure_init(&mut *ap);
+                    Ok(head1.borrow())
+                },
+                |b, _head2| Ok(b),
+            )
+        };
+    }
+
     #[test]
     fn drop_uninit_in_place_works() {
-        let a = unsafe { AStruct::new_uninit(4711, RefCell::new(42)) };
+        let a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
 
         assert_eq!(*(unsafe { &*a.as_ptr() }).head1.borrow(), 42);
 
@@ -148,7 +623,7 @@ mod init_err {
 
     #[test]
     fn new_box_works() {
-        let a = AStruct::new_box(4711, RefCell::new(42)).unwrap();
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
 
         assert_eq!(*a.head1.borrow(), 42);
         assert_eq!(*a.b, 42);
@@ -157,7 +632,7 @@ mod init_err {
 
     #[test]
     fn new_rc_works() {
-        let a = AStruct::new_rc(4711, RefCell::new(42)).unwrap();
+        let a = AStruct::new_rc(RefCell::new(42), 4711).unwrap();
 
         assert_eq!(*a.head1.borrow(), 42);
         assert_eq!(*a.b, 42);
@@ -166,8 +641,12 @@ mod init_err {
 
     #[test]
     fn force_init_works() {
-        let a = AStruct::new_box(4711, RefCell::new(42)).unwrap();
-        let mut b = *unsafe { Pin::into_inner_unchecked(a) };
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+        // The tails still borrow the old place, so it's kept until
+        // force_init has dropped them.
+        let old: Box<core::mem::MaybeUninit<AStruct>> =
+            unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(a)).cast()) };
+        let mut b = unsafe { old.assume_init_read() };
         let _bpin = unsafe { Pin::new_unchecked(&mut b) };
 
         AStruct::force_init(&mut b).unwrap();
@@ -180,15 +659,52 @@ mod init_err {
     #[test]
     fn new_box_fails_gracefully() {
         assert_eq!(
-            AStruct::new_box(HEAD2_FAIL, RefCell::new(42)).unwrap_err(),
+            AStruct::new_box(RefCell::new(42), HEAD2_FAIL).unwrap_err(),
+            Error::Failed
+        );
+    }
+
+    #[test]
+    fn incr_pin_fails_gracefully() {
+        fn pin(head2: i64) -> Result<i32, Error> {
+            incrstruct::incr_pin!(let a = AStruct::new(RefCell::new(42), head2)?);
+
+            Ok(*a.c)
+        }
+
+        assert_eq!(pin(4711), Ok(42));
+        assert_eq!(pin(HEAD2_FAIL), Err(Error::Failed));
+    }
+
+    #[test]
+    fn new_in_fails_gracefully() {
+        assert_eq!(
+            AStruct::new_in::<std::rc::Rc<_>>(RefCell::new(42), HEAD2_FAIL).unwrap_err(),
+            Error::Failed
+        );
+    }
+
+    #[test]
+    fn into_rc_fails_gracefully() {
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+        let mut b = unsafe { Pin::into_inner_unchecked(a) };
+
+        b.head2 = HEAD2_FAIL;
+
+        assert_eq!(
+            AStruct::into_rc(unsafe { Pin::new_unchecked(b) }).unwrap_err(),
             Error::Failed
         );
     }
 
     #[test]
     fn second_force_init_fails_gracefully() {
-        let a = AStruct::new_box(4711, RefCell::new(42)).unwrap();
-        let mut b = *unsafe { Pin::into_inner_unchecked(a) };
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+        // The tails still borrow the old place, so it's kept until
+        // force_init has dropped them.
+        let old: Box<core::mem::MaybeUninit<AStruct>> =
+            unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(a)).cast()) };
+        let mut b = unsafe { old.assume_init_read() };
         let _bpin = unsafe { Pin::new_unchecked(&mut b) };
 
         AStruct::force_init(&mut b).unwrap();
@@ -198,3 +714,4420 @@ mod init_err {
         assert_eq!(AStruct::force_init(&mut b).unwrap_err(), Error::Failed);
     }
 }
+
+#[cfg(all(test, feature = "alloc"))]
+mod no_panic {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[incrstruct(no_panic)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(head1, head2)]
+        pub b: &'a i32,
+
+        pub head1: RefCell<i32>,
+        pub head2: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const HEAD2_FAIL: i64 = 18;
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(_head1: &'a RefCell<i32>, head2: &'a i64) -> Result<&'a i32, Error> {
+            if *head2 == HEAD2_FAIL {
+                Err(Error::Failed)
+            } else {
+                Ok(&42)
+            }
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn new_box_fails_gracefully() {
+        assert_eq!(
+            AStruct::new_box(RefCell::new(42), HEAD2_FAIL).unwrap_err(),
+            incrstruct::Error::Init(Error::Failed)
+        );
+    }
+
+    #[test]
+    fn second_ensure_init_fails_gracefully() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+
+        unsafe { AStruct::ensure_init(&mut a) }.unwrap();
+
+        assert_eq!(
+            unsafe { AStruct::ensure_init(&mut a) }.unwrap_err(),
+            incrstruct::Error::State(incrstruct::StateError::Inited)
+        );
+
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    }
+
+    #[test]
+    fn ensure_init_after_failure_is_poisoned() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), HEAD2_FAIL) };
+
+        assert_eq!(
+            unsafe { AStruct::ensure_init(&mut a) }.unwrap_err(),
+            incrstruct::Error::Init(Error::Failed)
+        );
+        assert!(unsafe { &*a.as_ptr() }.hdr.is_poisoned());
+        assert_eq!(
+            unsafe { AStruct::ensure_init(&mut a) }.unwrap_err(),
+            incrstruct::Error::State(incrstruct::StateError::Poisoned)
+        );
+
+        unsafe { AStruct::drop_uninit(a) }.unwrap();
+    }
+
+    #[test]
+    fn init_state_queries() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), HEAD2_FAIL) };
+        let hdr = unsafe { &(*a.as_ptr()).hdr };
+
+        assert!(hdr.is_uninited());
+        assert_eq!(hdr.state(), incrstruct::InitState::Uninited);
+
+        assert!(unsafe { AStruct::ensure_init(&mut a) }.is_err());
+
+        let hdr = unsafe { &(*a.as_ptr()).hdr };
+
+        assert!(!hdr.is_uninited());
+        assert!(!hdr.is_inited());
+        assert_eq!(hdr.state(), incrstruct::InitState::Poisoned);
+
+        unsafe { AStruct::drop_uninit(a) }.unwrap();
+
+        let b = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+
+        assert!(AStruct::is_initialized(&b));
+        assert_eq!(b.hdr.state(), incrstruct::InitState::Inited);
+    }
+
+    #[test]
+    fn reentrant_ensure_init_fails_gracefully() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+        let ap: *mut core::mem::MaybeUninit<AStruct> = &mut a;
+        let mut inner = None;
+
+        unsafe {
+            AStruct::ensure_init_with(&mut a, |_head1, _head2| {
+                inner = Some(AStruct::ensure_init(&mut *ap).map(|_| ()));
+                Ok(&42)
+            })
+        }
+        .unwrap();
+
+        assert_eq!(
+            inner,
+            Some(Err(incrstruct::Error::State(
+                incrstruct::StateError::Initing
+            )))
+        );
+
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    }
+
+    #[test]
+    fn drop_uninit_fails_gracefully() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+
+        unsafe { AStruct::ensure_init(&mut a) }.unwrap();
+
+        // The value is leaked, since it isn't dropped.
+        assert_eq!(
+            unsafe { AStruct::drop_uninit(a) }.unwrap_err(),
+            incrstruct::StateError::Inited
+        );
+    }
+
+    #[test]
+    fn force_init_works() {
+        let a = AStruct::new_box(RefCell::new(42), 4711).unwrap();
+        // The tails still borrow the old place, so it's kept until
+        // force_init has dropped them.
+        let old: Box<core::mem::MaybeUninit<AStruct>> =
+            unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(a)).cast()) };
+        let mut b = unsafe { old.assume_init_read() };
+        let _bpin = unsafe { Pin::new_unchecked(&mut b) };
+
+        AStruct::force_init(&mut b).unwrap();
+
+        assert_eq!(*b.b, 42);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(no_panic)]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: Box<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a Box<i32>) -> &'a i32 {
+            a
+        }
+    }
+
+    #[test]
+    fn default_error_is_infallible() {
+        let b: Result<_, incrstruct::Error<core::convert::Infallible>> =
+            BStruct::new_box(Box::new(42));
+
+        assert_eq!(*b.unwrap().b, 42);
+    }
+
+    // A concrete entry point, so the no_panic feature verifies the
+    // generated code, and the runtime functions it calls. No-panic
+    // only works in optimized builds, and the function must not be
+    // inlined, or it would be checked for the constant arguments.
+    #[cfg(all(feature = "no_panic", not(debug_assertions)))]
+    #[::no_panic::no_panic]
+    #[inline(never)]
+    fn init_and_drop(head2: i64) -> Result<i32, incrstruct::Error<Error>> {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), head2) };
+
+        match unsafe { AStruct::ensure_init(&mut a) } {
+            Ok(r) => {
+                let b = *r.b;
+                AStruct::force_init(r)?;
+                let again = unsafe { AStruct::ensure_init(&mut a) }.map(|_| ());
+                unsafe { drop_in_place(a.as_mut_ptr()) };
+                again.map(|_| b)
+            }
+            Err(err) => {
+                unsafe { AStruct::drop_uninit(a) }.map_err(incrstruct::Error::State)?;
+                Err(err)
+            }
+        }
+    }
+
+    // Re-initializing can only abort, when the rollback fails too, so
+    // the entry points are verified as well.
+    #[cfg(all(feature = "no_panic", not(debug_assertions)))]
+    #[::no_panic::no_panic]
+    #[inline(never)]
+    fn reinit(head2: i64) -> Result<i32, incrstruct::Error<Error>> {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42), 4711) };
+        let result = match unsafe { AStruct::ensure_init(&mut a) } {
+            Ok(r) => {
+                let mut r = unsafe { Pin::new_unchecked(r) };
+
+                AStruct::with_heads_mut(r.as_mut(), |heads| heads.head2 = head2)
+                    .and_then(|_| AStruct::set_head2(r.as_mut(), head2))
+                    .and_then(|_| AStruct::refresh_b(r.as_mut()))
+                    .and_then(|_| AStruct::recompute(r.as_mut()))
+                    .map(|_| *r.b)
+            }
+            Err(err) => Err(err),
+        };
+
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+
+        result
+    }
+
+    #[cfg(all(feature = "no_panic", not(debug_assertions)))]
+    #[test]
+    fn no_panic_verified() {
+        assert_eq!(
+            init_and_drop(core::hint::black_box(4711)),
+            Err(incrstruct::Error::State(incrstruct::StateError::Inited))
+        );
+        assert_eq!(
+            init_and_drop(core::hint::black_box(HEAD2_FAIL)),
+            Err(incrstruct::Error::Init(Error::Failed))
+        );
+        assert_eq!(reinit(core::hint::black_box(4711)), Ok(42));
+        assert_eq!(
+            reinit(core::hint::black_box(HEAD2_FAIL)),
+            Err(incrstruct::Error::Init(Error::Failed))
+        );
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod unwind_safe {
+    use core::panic::{RefUnwindSafe, UnwindSafe};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(head)]
+        pub b: &'a i32,
+
+        pub head: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(head: &'a i32) -> &'a i32 {
+            head
+        }
+    }
+
+    fn assert_unwind_safe<T: UnwindSafe + RefUnwindSafe>() {}
+
+    #[test]
+    fn header_is_unwind_safe() {
+        assert_unwind_safe::<incrstruct::Header>();
+    }
+
+    #[test]
+    fn struct_is_unwind_safe() {
+        assert_unwind_safe::<AStruct>();
+    }
+
+    #[test]
+    fn catch_unwind_works() {
+        let a = AStruct::new_box(42);
+
+        assert_eq!(std::panic::catch_unwind(|| *a.b).unwrap(), 42);
+    }
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted;
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct BStruct<'a> {
+        #[borrows(head)]
+        pub c: &'a Counted,
+
+        #[borrows(head)]
+        pub b: Counted,
+
+        pub head: Counted,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_c(_head: &'a Counted) -> &'a Counted {
+            panic!("init failed")
+        }
+
+        fn init_field_b(_head: &'a Counted) -> Counted {
+            Counted
+        }
+    }
+
+    #[test]
+    fn panicking_init_drops_fields() {
+        assert!(std::panic::catch_unwind(|| BStruct::new_box(Counted)).is_err());
+
+        // The head, and the tail initialized before the panic.
+        assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod token_cell {
+    use qcell::{LCell, LCellOwner, TCell, TCellOwner};
+
+    struct Marker;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct TStruct<'a> {
+        #[borrows(head)]
+        pub b: &'a TCell<Marker, Vec<i32>>,
+
+        pub head: TCell<Marker, Vec<i32>>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> TStructInit<'a> for TStruct<'a> {
+        fn init_field_b(head: &'a TCell<Marker, Vec<i32>>) -> &'a TCell<Marker, Vec<i32>> {
+            head
+        }
+    }
+
+    #[test]
+    fn tcell_works() {
+        let mut owner = TCellOwner::<Marker>::new();
+        let a = TStruct::new_box(TCell::new(vec![42]));
+
+        owner.rw(&a.head).push(43);
+
+        assert_eq!(owner.ro(a.b).as_slice(), &[42, 43]);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct LStruct<'a, 'id> {
+        #[borrows(head)]
+        pub b: &'a LCell<'id, i32>,
+
+        pub head: LCell<'id, i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a, 'id> LStructInit<'a, 'id> for LStruct<'a, 'id> {
+        fn init_field_b(head: &'a LCell<'id, i32>) -> &'a LCell<'id, i32> {
+            head
+        }
+    }
+
+    #[test]
+    fn lcell_works() {
+        LCellOwner::scope(|mut owner| {
+            let a = LStruct::new_box(LCell::new(42));
+
+            *owner.rw(&a.head) += 1;
+
+            assert_eq!(*owner.ro(a.b), 43);
+        });
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod hygiene {
+    // Field names matching local variables in the generated code.
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(out, this)]
+        pub at: &'a i32,
+
+        #[borrows(r)]
+        pub err: &'a i32,
+
+        pub out: i32,
+        pub this: i32,
+        pub r: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_at(_out: &'a i32, this: &'a i32) -> &'a i32 {
+            this
+        }
+
+        fn init_field_err(r: &'a i32) -> &'a i32 {
+            r
+        }
+    }
+
+    #[test]
+    fn field_names_work() {
+        let a = AStruct::new_box(1, 2, 3);
+
+        assert_eq!(a.out, 1);
+        assert_eq!(a.this, 2);
+        assert_eq!(a.r, 3);
+        assert_eq!(*a.at, 2);
+        assert_eq!(*a.err, 3);
+    }
+
+    #[test]
+    fn struct_in_fn_works() {
+        #[derive(incrstruct::IncrStruct)]
+        struct Local<'a> {
+            #[borrows(head)]
+            pub b: &'a i32,
+
+            pub head: i32,
+
+            #[header]
+            hdr: incrstruct::Header,
+        }
+
+        impl<'a> LocalInit<'a> for Local<'a> {
+            fn init_field_b(head: &'a i32) -> &'a i32 {
+                head
+            }
+        }
+
+        let a = Local::new_box(42);
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn struct_in_block_works() {
+        let a = {
+            #[derive(incrstruct::IncrStruct)]
+            struct Local<'a> {
+                #[borrows(head)]
+                pub b: &'a i32,
+
+                pub head: i32,
+
+                #[header]
+                hdr: incrstruct::Header,
+            }
+
+            impl<'a> LocalInit<'a> for Local<'a> {
+                fn init_field_b(head: &'a i32) -> &'a i32 {
+                    head
+                }
+            }
+
+            let a = Local::new_rc(42);
+
+            *a.b
+        };
+
+        assert_eq!(a, 42);
+    }
+
+    mod nested {
+        pub mod inner {
+            #[derive(incrstruct::IncrStruct)]
+            pub struct AStruct<'a> {
+                #[borrows(head)]
+                pub b: &'a i32,
+
+                pub head: i32,
+
+                #[header]
+                hdr: incrstruct::Header,
+            }
+
+            impl<'a> AStructInit<'a> for AStruct<'a> {
+                fn init_field_b(head: &'a i32) -> &'a i32 {
+                    head
+                }
+            }
+        }
+
+        #[test]
+        fn struct_in_nested_module_works() {
+            let a = inner::AStruct::new_box(42);
+
+            assert_eq!(*a.b, 42);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod phases {
+    use super::*;
+    use std::rc::Rc;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b, head2)]
+        #[phase(2)]
+        pub c: &'a i32,
+
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: Rc<RefCell<i32>>,
+        pub head2: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const HEAD2_FAIL: i64 = 18;
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>, head2: &'a i64) -> Result<&'a i32, Error> {
+            if *head2 == HEAD2_FAIL {
+                Err(Error::Failed)
+            } else {
+                Ok(b)
+            }
+        }
+
+        fn init_field_b(head1: &'a Rc<RefCell<i32>>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    #[test]
+    fn ensure_init_phase_works() {
+        let head1 = Rc::new(RefCell::new(42));
+        let mut a = unsafe { AStruct::new_uninit(head1.clone(), 4711) };
+
+        unsafe { AStruct::ensure_init_phase(&mut a, 1) }.unwrap();
+
+        assert_eq!(*unsafe { &*a.as_ptr() }.b, 42);
+        assert!(head1.try_borrow_mut().is_err());
+
+        // Already done.
+        unsafe { AStruct::ensure_init_phase(&mut a, 1) }.unwrap();
+
+        let aref = unsafe { AStruct::ensure_init(&mut a) }.unwrap();
+
+        assert_eq!(*aref.c, 42);
+
+        unsafe { drop_in_place(aref) };
+
+        assert!(head1.try_borrow_mut().is_ok());
+        assert_eq!(Rc::strong_count(&head1), 1);
+    }
+
+    #[test]
+    fn try_tail_works() {
+        let mut a = unsafe { AStruct::new_uninit(Rc::new(RefCell::new(42)), 4711) };
+
+        assert!(unsafe { AStruct::try_b(&a) }.is_none());
+
+        unsafe { AStruct::ensure_init_phase(&mut a, 1) }.unwrap();
+
+        assert_eq!(**unsafe { AStruct::try_b(&a) }.unwrap(), 42);
+        assert!(unsafe { AStruct::try_c(&a) }.is_none());
+
+        unsafe { AStruct::ensure_init_phase(&mut a, 2) }.unwrap();
+
+        assert_eq!(**unsafe { AStruct::try_c(&a) }.unwrap(), 42);
+
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    }
+
+    #[test]
+    fn drop_uninit_drops_completed_phases() {
+        let head1 = Rc::new(RefCell::new(42));
+        let mut a = unsafe { AStruct::new_uninit(head1.clone(), 4711) };
+
+        unsafe { AStruct::ensure_init_phase(&mut a, 1) }.unwrap();
+        unsafe { AStruct::drop_uninit(a) };
+
+        assert!(head1.try_borrow_mut().is_ok());
+        assert_eq!(Rc::strong_count(&head1), 1);
+    }
+
+    #[test]
+    fn failed_phase_keeps_earlier_phases() {
+        let head1 = Rc::new(RefCell::new(42));
+        let mut a = unsafe { AStruct::new_uninit(head1.clone(), HEAD2_FAIL) };
+
+        assert_eq!(
+            unsafe { AStruct::ensure_init(&mut a) }.unwrap_err(),
+            Error::Failed
+        );
+        assert!(head1.try_borrow_mut().is_err());
+
+        unsafe { AStruct::drop_uninit(a) };
+
+        assert!(head1.try_borrow_mut().is_ok());
+        assert_eq!(Rc::strong_count(&head1), 1);
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(Rc::new(RefCell::new(42)), 4711).unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn new_box_fails_gracefully() {
+        let head1 = Rc::new(RefCell::new(42));
+
+        assert_eq!(
+            AStruct::new_box(head1.clone(), HEAD2_FAIL).unwrap_err(),
+            Error::Failed
+        );
+        assert_eq!(Rc::strong_count(&head1), 1);
+    }
+
+    #[test]
+    fn force_init_works() {
+        let a = AStruct::new_box(Rc::new(RefCell::new(42)), 4711).unwrap();
+        // The tails still borrow the old place, so it's kept until
+        // force_init has dropped them.
+        let old: Box<core::mem::MaybeUninit<AStruct>> =
+            unsafe { Box::from_raw(Box::into_raw(Pin::into_inner_unchecked(a)).cast()) };
+        let mut b = unsafe { old.assume_init_read() };
+        let _bpin = unsafe { Pin::new_unchecked(&mut b) };
+
+        AStruct::force_init(&mut b).unwrap();
+
+        assert_eq!(*b.b, 42);
+        assert_eq!(*b.c, 42);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct BStruct<'a> {
+        #[borrows(c, input)]
+        #[phase(3)]
+        pub d: (i32, &'a i32),
+
+        #[borrows(b)]
+        #[phase(2)]
+        pub c: &'a i32,
+
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+        pub input: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_d(c: &'a &'a i32, input: &'a i32) -> (i32, &'a i32) {
+            (*input, c)
+        }
+
+        fn init_field_c(b: &'a Ref<'a, i32>) -> &'a i32 {
+            b
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+            head1.borrow()
+        }
+    }
+
+    #[test]
+    fn three_phases_work() {
+        let mut a = unsafe { BStruct::new_uninit(RefCell::new(42), 0) };
+
+        unsafe { BStruct::ensure_init_phase(&mut a, 2) };
+
+        assert_eq!(**unsafe { BStruct::try_c(&a) }.unwrap(), 42);
+        assert!(unsafe { BStruct::try_d(&a) }.is_none());
+
+        // The last phase waits for input.
+        unsafe { (*a.as_mut_ptr()).input = 4711 };
+        let aref = unsafe { BStruct::ensure_init(&mut a) };
+
+        assert_eq!(aref.d, (4711, &42));
+
+        unsafe { drop_in_place(aref) };
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod constructor {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[constructor(parse(s: &str))]
+    #[constructor(from_pair(head1: i32, head2: i64))]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+        pub head2: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn parse(s: &str) -> Result<(RefCell<i32>, i64), Error> {
+            match s.parse() {
+                Ok(v) => Ok((RefCell::new(v), 4711)),
+                Err(_) => Err(Error::Failed),
+            }
+        }
+
+        fn from_pair(head1: i32, head2: i64) -> Result<(RefCell<i32>, i64), Error> {
+            Ok((RefCell::new(head1), head2))
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    #[test]
+    fn new_box_constructor_works() {
+        let a = AStruct::new_box_parse("42").unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.head2, 4711);
+    }
+
+    #[test]
+    fn new_rc_constructor_works() {
+        let a = AStruct::new_rc_from_pair(42, 4711).unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.head2, 4711);
+    }
+
+    #[test]
+    fn constructor_fails_gracefully() {
+        assert_eq!(AStruct::new_box_parse("x").unwrap_err(), Error::Failed);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod init_args {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    struct Config {
+        offset: i32,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[init_args(config: Config)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(head1)]
+        pub b: (Ref<'a, i32>, i32),
+
+        pub head1: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(
+            head1: &'a RefCell<i32>,
+            config: &Config,
+        ) -> Result<(Ref<'a, i32>, i32), Error> {
+            if config.offset < 0 {
+                return Err(Error::Failed);
+            }
+
+            Ok((head1.borrow(), *head1.borrow() + config.offset))
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(42), &Config { offset: 1 }).unwrap();
+
+        assert_eq!(*a.b.0, 42);
+        assert_eq!(a.b.1, 43);
+    }
+
+    #[test]
+    fn new_box_fails_gracefully() {
+        assert_eq!(
+            AStruct::new_box(RefCell::new(42), &Config { offset: -1 }).unwrap_err(),
+            Error::Failed
+        );
+    }
+
+    #[test]
+    fn ensure_init_works() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42)) };
+        let aref = unsafe { AStruct::ensure_init(&mut a, &Config { offset: 2 }) }.unwrap();
+
+        assert_eq!(aref.b.1, 44);
+
+        unsafe { drop_in_place(aref) };
+    }
+
+    #[test]
+    fn force_init_works() {
+        let a = AStruct::new_box(RefCell::new(42), &Config { offset: 1 }).unwrap();
+        let mut b = unsafe { Pin::into_inner_unchecked(a) };
+
+        AStruct::force_init(&mut b, &Config { offset: 3 }).unwrap();
+
+        assert_eq!(b.b.1, 45);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod builder {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[incrstruct(builder)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        pub c: i32,
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> Result<i32, Error> {
+            Ok(**b)
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    #[test]
+    fn try_build_works() {
+        let offset = 1;
+        let a = AStruct::try_builder(RefCell::new(42))
+            .c_builder(|b| Ok(**b + offset))
+            .b_builder(|head1| Ok(head1.borrow()))
+            .try_build()
+            .unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.c, 43);
+    }
+
+    #[test]
+    fn try_build_fails_gracefully() {
+        assert_eq!(
+            AStruct::try_builder(RefCell::new(42))
+                .b_builder(|head1| Ok(head1.borrow()))
+                .c_builder(|_| Err(Error::Failed))
+                .try_build()
+                .unwrap_err(),
+            Error::Failed
+        );
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod new_box_with {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(head2)]
+        pub c: i64,
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+        pub head2: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(head2: &'a i64) -> Result<i64, Error> {
+            Ok(*head2)
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    #[test]
+    fn new_box_with_works() {
+        let a = AStruct::new_box_with(RefCell::new(42), 4711, |head1, head2| {
+            Ok((head1.borrow(), *head2 + 1))
+        })
+        .unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.c, 4712);
+    }
+
+    #[test]
+    fn new_box_with_fails_gracefully() {
+        assert_eq!(
+            AStruct::new_box_with(RefCell::new(42), 4711, |_, _| Err(Error::Failed)).unwrap_err(),
+            Error::Failed
+        );
+    }
+}
+
+#[cfg(all(test, feature = "triomphe"))]
+mod triomphe_arc {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+            head1.borrow()
+        }
+    }
+
+    #[test]
+    fn new_triomphe_works() {
+        let a = AStruct::new_triomphe(RefCell::new(42));
+        let b = a.clone();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*b.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "arc_swap"))]
+mod arc_swap {
+    use incrstruct::IncrArcSwap;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(head)]
+        pub b: &'a str,
+
+        pub head: String,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(head: &'a String) -> &'a str {
+            head.trim()
+        }
+    }
+
+    #[test]
+    fn store_works() {
+        let swap = IncrArcSwap::new(AStruct::new_arc(" a ".to_string()));
+        let old = swap.load_full();
+
+        swap.store(AStruct::new_arc(" b ".to_string()));
+
+        assert_eq!(old.b, "a");
+        assert_eq!(swap.load().b, "b");
+    }
+
+    #[test]
+    fn rcu_works() {
+        let swap = IncrArcSwap::new(AStruct::new_arc(" a ".to_string()));
+
+        let old = swap.rcu(|current| AStruct::new_arc(current.head.clone() + "b "));
+
+        assert_eq!(old.b, "a");
+        assert_eq!(swap.load().b, "a b");
+    }
+}
+
+#[cfg(test)]
+mod layout {
+    use super::*;
+    use core::mem::{align_of, offset_of, size_of};
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+        pub head2: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+            head1.borrow()
+        }
+    }
+
+    const HEADS_SIZE: usize = AStruct::heads_size();
+
+    #[test]
+    fn sizes_work() {
+        assert_eq!(HEADS_SIZE, size_of::<RefCell<i32>>() + size_of::<i64>());
+        assert_eq!(AStruct::tails_size(), size_of::<Ref<i32>>());
+        assert_eq!(AStruct::header_offset(), offset_of!(AStruct, hdr));
+    }
+
+    #[test]
+    fn descriptor_works() {
+        assert_eq!(
+            AStruct::DESCRIPTOR,
+            incrstruct::Descriptor {
+                size: size_of::<AStruct>(),
+                align: align_of::<AStruct>(),
+                heads_size: HEADS_SIZE,
+                tails_size: size_of::<Ref<i32>>(),
+                header_offset: offset_of!(AStruct, hdr),
+                phases: 1,
+            }
+        );
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod nested_lifetimes {
+    use super::*;
+
+    trait AView<'a> {
+        fn get(&self) -> &'a i32;
+    }
+
+    struct AnIntView<'a>(&'a i32);
+
+    impl<'a> AView<'a> for AnIntView<'a> {
+        fn get(&self) -> &'a i32 {
+            self.0
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(self_lifetime = 'a)]
+    struct AStruct<'x, 'a> {
+        #[borrows(b)]
+        pub c: Box<dyn AView<'a> + 'a>,
+
+        #[borrows(a)]
+        pub b: Ref<'a, Ref<'x, i32>>,
+
+        pub a: RefCell<Ref<'x, i32>>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'x, 'a> AStructInit<'x, 'a> for AStruct<'x, 'a> {
+        fn init_field_c(b: &'a Ref<'a, Ref<'x, i32>>) -> Box<dyn AView<'a> + 'a> {
+            Box::new(AnIntView(b))
+        }
+
+        fn init_field_b(a: &'a RefCell<Ref<'x, i32>>) -> Ref<'a, Ref<'x, i32>> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let outer = RefCell::new(42);
+        let my_a = AStruct::new_box(RefCell::new(outer.borrow()));
+
+        assert_eq!(**my_a.b, 42);
+        assert_eq!(*my_a.c.get(), 42);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(self_lifetime = 'this)]
+    struct BStruct<'x, 'this> {
+        #[borrows(a)]
+        pub b: Ref<'this, Ref<'x, i32>>,
+
+        pub a: RefCell<Ref<'x, i32>>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'x, 'this> BStructInit<'x, 'this> for BStruct<'x, 'this> {
+        fn init_field_b(a: &'this RefCell<Ref<'x, i32>>) -> Ref<'this, Ref<'x, i32>> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn self_lifetime_works() {
+        let outer = RefCell::new(42);
+        let my_b = BStruct::new_box(RefCell::new(outer.borrow()));
+
+        assert_eq!(**my_b.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod outlives {
+    use core::ops::Deref;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a, T: Deref> {
+        #[borrows(a)]
+        pub b: Option<&'a T::Target>,
+
+        pub a: T,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a, T: Deref + 'a> AStructInit<'a, T> for AStruct<'a, T> {
+        fn init_field_b(a: &'a T) -> Option<&'a T::Target> {
+            Some(a.deref())
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let my_a = AStruct::new_box(Box::new(42));
+
+        assert_eq!(my_a.b, Some(&42));
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod const_generics {
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(builder)]
+    struct Buf<'a, const N: usize> {
+        #[borrows(data)]
+        pub view: &'a [u8],
+
+        pub data: [u8; N],
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a, const N: usize> BufInit<'a, N> for Buf<'a, N> {
+        fn init_field_view(data: &'a [u8; N]) -> &'a [u8] {
+            &data[1..]
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let my_buf = Buf::new_box([1, 2, 3]);
+
+        assert_eq!(my_buf.view, &[2, 3]);
+        assert_eq!(Buf::<3>::heads_size(), 3);
+    }
+
+    #[test]
+    fn new_box_from_heads_works() {
+        let my_buf = Buf::new_box_from_heads(BufHeads { data: [1, 2] });
+
+        assert_eq!(my_buf.view, &[2]);
+    }
+
+    #[test]
+    fn builder_works() {
+        let my_buf = Buf::builder([1, 2, 3])
+            .view_builder(|data| &data[2..])
+            .build();
+
+        assert_eq!(my_buf.view, &[3]);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod borrows_list {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a, b)]
+        pub c: (Ref<'a, i32>, &'a i32),
+
+        #[borrows()]
+        pub b: i32,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(a: &'a RefCell<i32>, b: &'a i32) -> (Ref<'a, i32>, &'a i32) {
+            (a.borrow(), b)
+        }
+
+        fn init_field_b() -> i32 {
+            43
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let my_a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*my_a.c.0, 42);
+        assert_eq!(*my_a.c.1, 43);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod identifiers {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[constructor(r#try(v: i32))]
+    struct AStruct<'a> {
+        #[borrows(r#type)]
+        pub größe: Ref<'a, i32>,
+
+        #[borrows(r#type)]
+        pub r#match: Ref<'a, i32>,
+
+        pub r#type: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn r#try(v: i32) -> (RefCell<i32>,) {
+            (RefCell::new(v),)
+        }
+
+        fn init_field_größe(r#type: &'a RefCell<i32>) -> Ref<'a, i32> {
+            r#type.borrow()
+        }
+
+        fn init_field_match(r#type: &'a RefCell<i32>) -> Ref<'a, i32> {
+            r#type.borrow()
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let my_a = AStruct::new_box_try(42);
+
+        assert_eq!(*my_a.größe, 42);
+        assert_eq!(*my_a.r#match, 42);
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(builder)]
+    struct BStruct<'a> {
+        #[borrows(type)]
+        pub r#fn: Ref<'a, i32>,
+
+        pub r#type: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_fn(r#type: &'a RefCell<i32>) -> Ref<'a, i32> {
+            r#type.borrow()
+        }
+    }
+
+    #[test]
+    fn keyword_borrows_work() {
+        let my_b = BStruct::builder(RefCell::new(42))
+            .fn_builder(|r#type| r#type.borrow())
+            .build();
+
+        assert_eq!(*my_b.r#fn, 42);
+        assert_eq!(BStruct::with_fn(&my_b, |r#fn| **r#fn), 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tuple {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a>(
+        #[borrows(1)] pub Ref<'a, i32>,
+        pub RefCell<i32>,
+        #[header] incrstruct::Header,
+    );
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_0(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let my_a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*my_a.0, 42);
+        assert_eq!(*my_a.1.borrow(), 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod boxed {
+    use super::*;
+
+    use incrstruct::AliasableBox;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(boxed)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        c: &'a i32,
+
+        #[borrows(a)]
+        b: AliasableBox<Ref<'a, i32>>,
+
+        a: AliasableBox<RefCell<i32>>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> &'a i32 {
+            b
+        }
+
+        fn init_field_b(a: &'a RefCell<i32>) -> AliasableBox<Ref<'a, i32>> {
+            AliasableBox::new(a.borrow())
+        }
+    }
+
+    #[test]
+    fn new_works() {
+        let a = AStruct::new(AliasableBox::new(RefCell::new(42)));
+        let moved = [a];
+
+        assert_eq!(**moved[0].b, 42);
+        assert_eq!(*moved[0].c, 42);
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = *AStruct::new_box(AliasableBox::new(RefCell::new(42)));
+
+        assert_eq!(**a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn getters_work() {
+        let a = AStruct::new(AliasableBox::new(RefCell::new(42)));
+
+        assert_eq!(***AStruct::b(&a), 42);
+        assert!(AStruct::a(&a).try_borrow_mut().is_err());
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod stable_deref {
+    use incrstruct::AliasableBox;
+    use std::rc::Rc;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a, b)]
+        c: (&'a str, &'a [i32]),
+
+        #[stable_deref]
+        b: AliasableBox<[i32]>,
+
+        #[stable_deref]
+        a: Rc<str>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(a: &'a str, b: &'a [i32]) -> (&'a str, &'a [i32]) {
+            (a, b)
+        }
+    }
+
+    #[test]
+    fn new_works() {
+        let a = AStruct::new(vec![42].into_boxed_slice().into(), "hello".into());
+        let moved = [a];
+
+        assert_eq!(moved[0].c, ("hello", &[42][..]));
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod covariant {
+    use super::*;
+    use core::cell::Cell;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        #[not_covariant]
+        pub c: Cell<&'a i32>,
+
+        #[borrows(a)]
+        #[covariant]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> Cell<&'a i32> {
+            Cell::new(b)
+        }
+
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    fn deref_b<'x>(b: &'x Ref<'x, i32>) -> i32 {
+        **b
+    }
+
+    #[test]
+    fn borrow_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(deref_b(AStruct::borrow_b(&a)), 42);
+        assert_eq!(*a.c.get(), 42);
+    }
+
+    #[test]
+    fn with_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(AStruct::with_b(&a, |b| **b + 1), 43);
+        assert_eq!(AStruct::with_c(&a, |c| *c.get()), 42);
+    }
+
+    #[test]
+    fn with_all_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        let sum = AStruct::with_all(&a, |AStructBorrowedFields { a, b, c }| {
+            *a.borrow() + **b + *c.get()
+        });
+
+        assert_eq!(sum, 126);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod private_tails {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(private_tails)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn getter_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(**AStruct::b(&a), 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod visibility {
+    use super::*;
+
+    mod inner {
+        use super::*;
+
+        #[derive(incrstruct::IncrStruct)]
+        #[incrstruct(vis = "pub(crate)")]
+        pub struct AStruct<'a> {
+            #[borrows(a)]
+            pub b: Ref<'a, i32>,
+
+            pub a: RefCell<i32>,
+
+            #[header]
+            hdr: incrstruct::Header,
+        }
+    }
+
+    use inner::{AStruct, AStructInit};
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn vis_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(AStruct::DESCRIPTOR.phases, 1);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod init_trait {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_trait(name = "AStructFns", fn_prefix = "build_")]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructFns<'a> for AStruct<'a> {
+        fn build_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn renamed_trait_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod crate_path {
+    use super::*;
+
+    // Shadows the crate, so only rewritten paths resolve.
+    mod incrstruct {}
+
+    mod facade {
+        pub use ::incrstruct;
+    }
+
+    #[derive(::incrstruct::IncrStruct)]
+    #[incrstruct(crate = "self::facade::incrstruct")]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: facade::incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    // Generic structs get bounds, and where clauses, naming the crate.
+    #[derive(::incrstruct::IncrStruct)]
+    #[incrstruct(crate = "self::facade::incrstruct")]
+    struct BStruct<'a, T> {
+        #[borrows(a)]
+        pub b: &'a T,
+
+        pub a: T,
+
+        #[header]
+        hdr: facade::incrstruct::Header,
+    }
+
+    impl<'a, T: 'a> BStructInit<'a, T> for BStruct<'a, T> {
+        fn init_field_b(a: &'a T) -> &'a T {
+            a
+        }
+    }
+
+    #[test]
+    fn crate_path_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn crate_path_with_generics_works() {
+        let a = BStruct::new_box(42);
+
+        assert_eq!(*a.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod no_prelude {
+    #[no_implicit_prelude]
+    mod inner {
+        #[derive(::incrstruct::IncrStruct)]
+        #[incrstruct(crate = "::incrstruct")]
+        pub struct AStruct<'a> {
+            #[borrows(a)]
+            pub b: ::core::cell::Ref<'a, i32>,
+
+            pub a: ::core::cell::RefCell<i32>,
+
+            #[header]
+            hdr: ::incrstruct::Header,
+        }
+
+        impl<'a> AStructInit<'a> for AStruct<'a> {
+            fn init_field_b(a: &'a ::core::cell::RefCell<i32>) -> ::core::cell::Ref<'a, i32> {
+                a.borrow()
+            }
+        }
+
+        #[derive(Debug)]
+        pub struct Error;
+
+        #[derive(::incrstruct::IncrStruct)]
+        #[incrstruct(crate = "::incrstruct")]
+        #[init_err(Error)]
+        pub struct BStruct<'a> {
+            #[borrows(a)]
+            pub b: &'a i32,
+
+            pub a: i32,
+
+            #[header]
+            hdr: ::incrstruct::Header,
+        }
+
+        impl<'a> BStructInit<'a> for BStruct<'a> {
+            fn init_field_b(a: &'a i32) -> ::core::result::Result<&'a i32, Error> {
+                ::core::result::Result::Ok(a)
+            }
+        }
+    }
+
+    #[test]
+    fn no_implicit_prelude_works() {
+        let a = inner::AStruct::new_box(core::cell::RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn no_implicit_prelude_with_init_err_works() {
+        let b = inner::BStruct::new_box(42).unwrap();
+
+        assert_eq!(*b.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod dyn_coercion {
+    use super::*;
+    use std::rc::Rc;
+
+    trait Value {
+        fn value(&self) -> i32;
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    impl<'a> Value for AStruct<'a> {
+        fn value(&self) -> i32 {
+            *self.b
+        }
+    }
+
+    #[test]
+    fn box_coercion_works() {
+        let values: Vec<Pin<Box<dyn Value>>> = vec![
+            AStruct::new_box(RefCell::new(42)),
+            AStruct::new_box(RefCell::new(43)),
+        ];
+
+        assert_eq!(values[0].value(), 42);
+        assert_eq!(values[1].value(), 43);
+    }
+
+    #[test]
+    fn rc_coercion_works() {
+        let value: Pin<Rc<dyn Value>> = AStruct::new_rc(RefCell::new(42));
+
+        assert_eq!(value.value(), 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod cfg_gated {
+    use super::*;
+
+    #[cfg(any())]
+    #[derive(incrstruct::IncrStruct)]
+    struct Disabled<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[cfg(test)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    #[cfg(test)]
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tree {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct Node<'a> {
+        #[borrows(children)]
+        pub child_values: Vec<&'a i32>,
+
+        pub value: i32,
+        pub children: Vec<Pin<Box<Self>>>,
+        pub parent: Option<Pin<Box<Self>>>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> NodeInit<'a> for Node<'a> {
+        fn init_field_child_values(children: &'a Vec<Pin<Box<Self>>>) -> Vec<&'a i32> {
+            children.iter().map(|child| &child.value).collect()
+        }
+    }
+
+    fn leaf<'a>(value: i32) -> Pin<Box<Node<'a>>> {
+        Node::new_box(value, Vec::new(), None)
+    }
+
+    #[test]
+    fn bottom_up_works() {
+        let root = Node::new_box(
+            1,
+            vec![leaf(2), Node::new_box(3, vec![leaf(4)], None)],
+            None,
+        );
+
+        assert_eq!(root.child_values, vec![&2, &3]);
+        assert_eq!(root.children[1].child_values, vec![&4]);
+    }
+
+    #[test]
+    fn option_works() {
+        let child = Node::new_box(2, Vec::new(), Some(leaf(1)));
+
+        assert_eq!(child.parent.as_ref().unwrap().value, 1);
+    }
+
+    struct Desc(i32, Vec<Desc>);
+
+    #[test]
+    fn build_tree_works() {
+        let root = incrstruct::build_tree(
+            Desc(
+                1,
+                vec![Desc(2, Vec::new()), Desc(3, vec![Desc(4, Vec::new())])],
+            ),
+            |desc| core::mem::take(&mut desc.1),
+            |desc, children| Node::new_box(desc.0, children, None),
+        );
+
+        assert_eq!(root.child_values, vec![&2, &3]);
+        assert_eq!(root.children[1].child_values, vec![&4]);
+    }
+
+    #[test]
+    fn try_build_tree_fails() {
+        let res = incrstruct::try_build_tree(
+            Desc(1, vec![Desc(2, Vec::new()), Desc(-1, Vec::new())]),
+            |desc| core::mem::take(&mut desc.1),
+            |desc, children| {
+                if desc.0 < 0 {
+                    Err(desc.0)
+                } else {
+                    Ok(Node::new_box(desc.0, children, None))
+                }
+            },
+        );
+
+        assert_eq!(res.err(), Some(-1));
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod enums {
+    use std::rc::Rc;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct Error;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    #[repr(u8)]
+    enum AEnum<'a> {
+        Word {
+            #[borrows(text)]
+            first: &'a str,
+
+            text: String,
+
+            #[header]
+            hdr: incrstruct::Header,
+        },
+        Counted {
+            #[borrows(c)]
+            d: &'a (),
+
+            #[borrows(rc, fail)]
+            c: Rc<()>,
+
+            rc: Rc<()>,
+            fail: bool,
+
+            #[header]
+            hdr: incrstruct::Header,
+        },
+        Empty {
+            #[header]
+            hdr: incrstruct::Header,
+        },
+    }
+
+    impl<'a> AEnumInit<'a> for AEnum<'a> {
+        fn init_field_word_first(text: &'a String) -> Result<&'a str, Error> {
+            Ok(text.split(' ').next().unwrap())
+        }
+
+        fn init_field_counted_d(c: &'a Rc<()>) -> Result<&'a (), Error> {
+            Ok(c)
+        }
+
+        fn init_field_counted_c(rc: &'a Rc<()>, fail: &'a bool) -> Result<Rc<()>, Error> {
+            if *fail {
+                Err(Error)
+            } else {
+                Ok(rc.clone())
+            }
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AEnum::new_box_word("hello world".to_string()).unwrap();
+
+        match &*a {
+            AEnum::Word { first, text, .. } => {
+                assert_eq!(*first, "hello");
+                assert_eq!(text, "hello world");
+            }
+            _ => panic!("wrong variant"),
+        }
+
+        let a = AEnum::new_box_empty().unwrap();
+
+        assert!(matches!(&*a, AEnum::Empty { .. }));
+    }
+
+    #[test]
+    fn new_box_drops_tails_once() {
+        let rc = Rc::new(());
+        let a = AEnum::new_box_counted(rc.clone(), false).unwrap();
+
+        assert_eq!(Rc::strong_count(&rc), 3);
+        drop(a);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn new_box_fails_gracefully() {
+        let rc = Rc::new(());
+        let res = AEnum::new_box_counted(rc.clone(), true);
+
+        assert_eq!(res.err(), Some(Error));
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn force_init_works() {
+        let rc = Rc::new(());
+        let mut a = AEnum::new_box_counted(rc.clone(), false).unwrap();
+
+        // SAFETY: the fields are not borrowed.
+        let r = unsafe { a.as_mut().get_unchecked_mut() };
+        AEnum::force_init(r).unwrap();
+
+        assert_eq!(Rc::strong_count(&rc), 3);
+        drop(a);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod lazy {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(default)]
+    struct AStruct<'a> {
+        #[borrows(head1, head2)]
+        pub b: (Ref<'a, i32>, &'a String),
+
+        pub head1: RefCell<i32>,
+        pub head2: String,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(head1: &'a RefCell<i32>, head2: &'a String) -> (Ref<'a, i32>, &'a String) {
+            (head1.borrow(), head2)
+        }
+    }
+
+    #[test]
+    fn deref_works() {
+        let a: incrstruct::Lazy<AStruct> = Default::default();
+        let moved = vec![a];
+
+        assert_eq!(*moved[0].b.0, 0);
+        assert_eq!(moved[0].b.1, "");
+
+        // Moving after initialization is fine, since it's boxed.
+        let moved_again = moved.into_iter().next().unwrap();
+
+        assert_eq!(*moved_again.b.0, 0);
+    }
+
+    #[test]
+    fn new_box_default_works() {
+        let a = AStruct::new_box_default();
+
+        assert_eq!(*a.b.0, 0);
+        assert_eq!(a.b.1, "");
+    }
+
+    #[test]
+    fn new_rc_default_works() {
+        let a = AStruct::new_rc_default();
+
+        assert_eq!(*a.b.0, 0);
+    }
+
+    #[test]
+    fn default_box_works() {
+        let a = AStruct::default_box();
+
+        assert_eq!(a.b.1, "");
+    }
+}
+
+#[cfg(all(test, feature = "bytemuck"))]
+mod zeroed {
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(zeroed)]
+    struct AStruct<'a> {
+        #[borrows(buf)]
+        pub sum: &'a [u8],
+
+        pub buf: [u8; 4096],
+        pub len: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_sum(buf: &'a [u8; 4096]) -> &'a [u8] {
+            &buf[..16]
+        }
+    }
+
+    #[test]
+    fn new_box_zeroed_works() {
+        let a = AStruct::new_box_zeroed();
+
+        assert_eq!(a.len, 0);
+        assert_eq!(a.sum, &[0; 16]);
+    }
+}
+
+#[cfg(all(test, feature = "memmap2"))]
+mod mmap {
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(mmap)]
+    struct AStruct<'a> {
+        #[borrows(map)]
+        pub lines: Vec<&'a [u8]>,
+
+        pub map: incrstruct::memmap2::Mmap,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_lines(map: &'a incrstruct::memmap2::Mmap) -> Vec<&'a [u8]> {
+            map.split(|b| *b == b'\n').collect()
+        }
+    }
+
+    #[test]
+    fn new_box_from_path_works() {
+        // Unique per process, so concurrent test runs don't clash.
+        let path =
+            std::env::temp_dir().join(format!("incrstruct_mmap_test_{}.txt", std::process::id()));
+        std::fs::write(&path, b"hello\nworld").unwrap();
+
+        // SAFETY: the file is not modified while mapped.
+        let a = unsafe { AStruct::new_box_from_path(&path) }.unwrap();
+
+        assert_eq!(a.lines, vec![&b"hello"[..], &b"world"[..]]);
+
+        drop(a);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod projections {
+    trait View {
+        type Output<'v>
+        where
+            Self: 'v;
+
+        fn view<'v>(data: &'v [u8]) -> Self::Output<'v>;
+    }
+
+    struct Words;
+
+    impl View for Words {
+        type Output<'v> = Vec<&'v [u8]>;
+
+        fn view<'v>(data: &'v [u8]) -> Self::Output<'v> {
+            data.split(|b| *b == b' ').collect()
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a, V: View + 'a> {
+        #[borrows(data)]
+        pub view: <V as View>::Output<'a>,
+
+        pub data: Vec<u8>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a, V: View + 'a> AStructInit<'a, V> for AStruct<'a, V> {
+        fn init_field_view(data: &'a Vec<u8>) -> <V as View>::Output<'a> {
+            V::view(data)
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::<Words>::new_box(b"hello world".to_vec());
+
+        assert_eq!(a.view, vec![&b"hello"[..], &b"world"[..]]);
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod par {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use incrstruct::ParForceInit;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: &'a i32,
+
+        pub a: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a i32) -> Result<&'a i32, Error> {
+            if *a < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(a)
+            }
+        }
+    }
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted;
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct BStruct<'a> {
+        #[borrows(b)]
+        pub c: &'a Counted,
+
+        #[borrows(a)]
+        pub b: Counted,
+
+        pub a: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_c(b: &'a Counted) -> Result<&'a Counted, Error> {
+            Ok(b)
+        }
+
+        fn init_field_b(a: &'a i32) -> Result<Counted, Error> {
+            if *a < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(Counted)
+            }
+        }
+    }
+
+    #[test]
+    fn par_force_init_works() {
+        let mut v: Vec<_> = (0..100).map(|i| AStruct::new_box(i).unwrap()).collect();
+
+        v.par_force_init().unwrap();
+
+        assert!(v.iter().enumerate().all(|(i, a)| *a.b == i as i32));
+    }
+
+    #[test]
+    fn par_force_init_collects_errors() {
+        let mut v: Vec<_> = (0..100).map(|i| AStruct::new_box(i).unwrap()).collect();
+        for i in [3, 42] {
+            // SAFETY: the value is not moved.
+            unsafe { v[i].as_mut().get_unchecked_mut() }.a = -1;
+        }
+
+        assert_eq!(
+            v.par_force_init().err(),
+            Some(vec![(3, Error::Failed), (42, Error::Failed)])
+        );
+        assert_eq!(v.len(), 98);
+        assert!(v.iter().all(|a| *a.b == a.a && a.a >= 0));
+    }
+
+    #[test]
+    fn par_force_init_drops_failed_once() {
+        let mut v: Vec<_> = (0..10).map(|i| BStruct::new_box(i).unwrap()).collect();
+        for i in [3, 7] {
+            // SAFETY: the value is not moved.
+            unsafe { v[i].as_mut().get_unchecked_mut() }.a = -1;
+        }
+
+        assert_eq!(
+            v.par_force_init().err(),
+            Some(vec![(3, Error::Failed), (7, Error::Failed)])
+        );
+        // Every tail field was dropped by force_init, and the failed
+        // elements were dropped without initializing theirs again.
+        assert_eq!(DROPS.load(Ordering::Relaxed), 10);
+
+        drop(v);
+
+        assert_eq!(DROPS.load(Ordering::Relaxed), 18);
+    }
+
+    #[test]
+    fn par_force_init_pinned_vec() {
+        let mut v = incrstruct::PinnedVec::new();
+        for i in 0..10 {
+            AStruct::emplace(&mut v, i).unwrap();
+        }
+        // SAFETY: the value is not moved.
+        unsafe { v.get_mut(4).unwrap().get_unchecked_mut() }.a = -1;
+
+        assert_eq!(v.par_force_init().err(), Some(vec![(4, Error::Failed)]));
+        assert_eq!(
+            v.iter().map(|a| *a.b).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 5, 6, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn par_force_init_slab() {
+        let mut slab = incrstruct::IncrSlab::new();
+        let keys: Vec<usize> = (0..100)
+            .map(|i| AStruct::emplace(&mut slab, i).unwrap())
+            .collect();
+        // SAFETY: the value is not moved.
+        unsafe { slab.get_mut(keys[42]).unwrap().get_unchecked_mut() }.a = -1;
+
+        assert_eq!(
+            slab.par_force_init().err(),
+            Some(vec![(keys[42], Error::Failed)])
+        );
+        assert_eq!(slab.len(), 99);
+        assert!(slab.get(keys[42]).is_none());
+        assert_eq!(*slab.get(keys[41]).unwrap().b, 41);
+
+        // The freed slot is reused.
+        assert_eq!(AStruct::emplace(&mut slab, 7).unwrap(), keys[42]);
+        assert_eq!(slab.len(), 100);
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod async_init {
+    use super::*;
+    use core::future::Future;
+    use core::task::{Context, Poll, Waker};
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(async_init)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        #[phase(2)]
+        pub c: &'a i32,
+
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+
+        pub head1: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> Result<&'a i32, Error> {
+            Ok(b)
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    impl<'a> AStructInitAsync<'a> for AStruct<'a> {
+        async fn init_field_c(b: &'a Ref<'a, i32>) -> Result<&'a i32, Error> {
+            YieldOnce(false).await;
+
+            if **b < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(b)
+            }
+        }
+
+        async fn init_field_b(head1: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(head1.borrow())
+        }
+    }
+
+    /// Returns pending once, like a network handshake.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = core::pin::pin!(f);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn new_box_async_works() {
+        let a = block_on(AStruct::new_box_async(RefCell::new(42))).unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn new_arc_async_fails_gracefully() {
+        assert_eq!(
+            block_on(AStruct::new_arc_async(RefCell::new(-1))).err(),
+            Some(Error::Failed)
+        );
+    }
+
+    #[test]
+    fn ensure_init_async_works() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42)) };
+        let aref = block_on(unsafe { AStruct::ensure_init_async(&mut a) }).unwrap();
+
+        assert_eq!(*aref.c, 42);
+
+        unsafe { drop_in_place(aref) };
+    }
+
+    #[test]
+    fn dropped_future_drops_tails() {
+        let mut a = unsafe { AStruct::new_uninit(RefCell::new(42)) };
+
+        {
+            let mut f = Box::pin(unsafe { AStruct::ensure_init_async(&mut a) });
+            let mut cx = Context::from_waker(Waker::noop());
+
+            assert!(f.as_mut().poll(&mut cx).is_pending());
+        }
+
+        // The first phase is complete, and the second was undone.
+        assert!(unsafe { AStruct::try_b(&a) }.is_some());
+        assert!(unsafe { AStruct::try_c(&a) }.is_none());
+
+        unsafe { AStruct::drop_uninit(a) };
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod lazy_tail {
+    use super::*;
+    use core::cell::{Cell, OnceCell};
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a, calls)]
+        #[lazy]
+        pub b: OnceCell<Ref<'a, i32>>,
+
+        pub a: RefCell<i32>,
+        pub calls: Cell<u32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>, calls: &'a Cell<u32>) -> Result<Ref<'a, i32>, Error> {
+            calls.set(calls.get() + 1);
+
+            if *a.borrow() < 0 {
+                return Err(Error::Failed);
+            }
+
+            Ok(a.borrow())
+        }
+    }
+
+    #[test]
+    fn init_on_first_access() {
+        let a = AStruct::new_box(RefCell::new(42), Cell::new(0)).unwrap();
+
+        assert_eq!(a.calls.get(), 0);
+        assert!(a.a.try_borrow_mut().is_ok());
+
+        assert_eq!(**AStruct::b(&a).unwrap(), 42);
+        assert_eq!(**AStruct::b(&a).unwrap(), 42);
+
+        assert_eq!(a.calls.get(), 1);
+        assert!(a.a.try_borrow_mut().is_err());
+    }
+
+    #[test]
+    fn failed_init_leaves_cell_empty() {
+        let a = AStruct::new_box(RefCell::new(-1), Cell::new(0)).unwrap();
+
+        assert_eq!(AStruct::b(&a).unwrap_err(), Error::Failed);
+        assert!(a.b.get().is_none());
+
+        *a.a.borrow_mut() = 42;
+
+        assert_eq!(**AStruct::b(&a).unwrap(), 42);
+        assert_eq!(a.calls.get(), 2);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod refresh {
+    use super::*;
+    use core::cell::Cell;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        pub c: &'a i32,
+
+        #[borrows(calls)]
+        pub d: u32,
+
+        #[borrows(input, calls)]
+        pub b: i32,
+
+        pub input: Cell<i32>,
+        pub calls: Cell<u32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a i32) -> Result<&'a i32, Error> {
+            Ok(b)
+        }
+
+        fn init_field_d(calls: &'a Cell<u32>) -> Result<u32, Error> {
+            calls.set(calls.get() + 1);
+
+            Ok(calls.get())
+        }
+
+        fn init_field_b(input: &'a Cell<i32>, calls: &'a Cell<u32>) -> Result<i32, Error> {
+            calls.set(calls.get() + 1);
+
+            if input.get() < 0 {
+                return Err(Error::Failed);
+            }
+
+            Ok(input.get())
+        }
+    }
+
+    fn new_box(input: i32) -> Pin<Box<AStruct<'static>>> {
+        AStruct::new_box_from_heads(AStructHeads {
+            input: Cell::new(input),
+            calls: Cell::new(0),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn refresh_skips_unrelated_tails() {
+        let mut a = new_box(42);
+
+        assert_eq!(a.calls.get(), 2);
+
+        a.input.set(43);
+        AStruct::refresh_b(a.as_mut()).unwrap();
+
+        assert_eq!(a.b, 43);
+        assert_eq!(*a.c, 43);
+        assert_eq!(a.calls.get(), 3);
+    }
+
+    #[test]
+    fn recompute_only_dirty_tails() {
+        let mut a = new_box(42);
+
+        AStruct::recompute(a.as_mut()).unwrap();
+        assert_eq!(a.calls.get(), 2);
+
+        a.input.set(43);
+        AStruct::mark_input_dirty(a.as_mut());
+        AStruct::recompute(a.as_mut()).unwrap();
+
+        assert_eq!(a.b, 43);
+        assert_eq!(*a.c, 43);
+        assert_eq!(a.d, 2);
+        assert_eq!(a.calls.get(), 3);
+
+        AStruct::recompute(a.as_mut()).unwrap();
+        assert_eq!(a.calls.get(), 3);
+    }
+
+    #[test]
+    fn refresh_after_force_init_works() {
+        let mut a = new_box(42);
+
+        a.input.set(43);
+        AStruct::force_init(unsafe { a.as_mut().get_unchecked_mut() }).unwrap();
+        a.input.set(44);
+        AStruct::refresh_b(a.as_mut()).unwrap();
+
+        assert_eq!(*a.c, 44);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod reorder {
+    use super::*;
+
+    #[incrstruct::incrstruct]
+    struct AStruct<'a> {
+        #[header]
+        hdr: incrstruct::Header,
+
+        pub head1: RefCell<i32>,
+
+        #[borrows(b)]
+        pub c: &'a i32,
+
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> &'a i32 {
+            b
+        }
+
+        fn init_field_b(head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+            head1.borrow()
+        }
+    }
+
+    #[incrstruct::incrstruct]
+    struct BStruct<'a> {
+        pub head1: RefCell<i32>,
+
+        #[borrows(head1)]
+        pub b: Ref<'a, i32>,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(head1: &'a RefCell<i32>) -> Ref<'a, i32> {
+            head1.borrow()
+        }
+    }
+
+    #[test]
+    fn header_is_added() {
+        let a = BStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn reordered_struct_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod plain {
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        pub a: &'a str,
+        pub b: i32,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {}
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box("answer", 42);
+
+        assert_eq!(a.a, "answer");
+        assert_eq!(a.b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod cyclic {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[cyclic(rc)]
+        me: incrstruct::PinRcWeak<Self>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn new_rc_cyclic_works() {
+        let a = AStruct::new_rc_cyclic(RefCell::new(42));
+        let me = a.me.upgrade().unwrap();
+
+        assert!(core::ptr::eq(&*me, &*a));
+        assert_eq!(*me.b, 42);
+
+        let weak = a.me.clone();
+        drop(me);
+        drop(a);
+
+        assert!(weak.upgrade().is_none());
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod mut_borrows {
+    use incrstruct::Lent;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(mut buf)]
+        pub halves: (&'a mut [u8], &'a mut [u8]),
+
+        #[borrows(len)]
+        pub double_len: usize,
+
+        buf: Lent<[u8; 4]>,
+        pub len: usize,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_halves(buf: &'a mut [u8; 4]) -> (&'a mut [u8], &'a mut [u8]) {
+            buf.split_at_mut(2)
+        }
+
+        fn init_field_double_len(len: &'a usize) -> usize {
+            len * 2
+        }
+    }
+
+    #[test]
+    fn with_mut_works() {
+        let mut a = AStruct::new_box(Lent::new([1, 2, 3, 4]), 4);
+
+        AStruct::with_halves_mut(a.as_mut(), |halves| {
+            halves.0[1] = 5;
+            halves.1[0] = 6;
+        });
+
+        assert_eq!(*a.halves.0, [1, 5]);
+        assert_eq!(a.double_len, 8);
+
+        let heads = AStruct::into_heads(a);
+
+        assert_eq!(Lent::into_inner(heads.buf), [1, 5, 6, 4]);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod default_heads {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a, scale)]
+        pub b: i32,
+
+        pub a: RefCell<i32>,
+        #[default = 2]
+        pub scale: i32,
+        #[default]
+        pub name: String,
+        #[default]
+        _ph: core::marker::PhantomData<&'a ()>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>, scale: &'a i32) -> i32 {
+            *a.borrow() * scale
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(21));
+
+        assert_eq!(a.b, 42);
+        assert_eq!(a.scale, 2);
+        assert_eq!(a.name, "");
+    }
+
+    #[test]
+    fn new_box_from_heads_works() {
+        let a = AStruct::new_box_from_heads(AStructHeads {
+            a: RefCell::new(21),
+            scale: 3,
+            name: "answer".to_owned(),
+            _ph: core::marker::PhantomData,
+        });
+
+        assert_eq!(a.b, 63);
+        assert_eq!(a.name, "answer");
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct BStruct {
+        pub a: i32,
+        #[default = 4711]
+        pub b: i64,
+    }
+
+    impl BStructInit for BStruct {}
+
+    #[test]
+    fn plain_new_box_works() {
+        let a = BStruct::new_box(42);
+
+        assert_eq!(a.a, 42);
+        assert_eq!(a.b, 4711);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod inline_init {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        pub c: i32,
+
+        #[borrows(a)]
+        #[init(a.borrow())]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a Ref<'a, i32>) -> i32 {
+            **b + 1
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(a.c, 43);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod head_err {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Parse,
+        Negative,
+    }
+
+    impl From<InitError> for Error {
+        fn from(_: InitError) -> Self {
+            Error::Negative
+        }
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct InitError;
+
+    #[derive(Debug, incrstruct::IncrStruct)]
+    #[constructor(parse(s: &str))]
+    #[head_err(Error)]
+    #[init_err(InitError)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn parse(s: &str) -> Result<(RefCell<i32>,), Error> {
+            match s.parse() {
+                Ok(v) => Ok((RefCell::new(v),)),
+                Err(_) => Err(Error::Parse),
+            }
+        }
+
+        fn init_field_b(a: &'a RefCell<i32>) -> Result<Ref<'a, i32>, InitError> {
+            if *a.borrow() < 0 {
+                return Err(InitError);
+            }
+
+            Ok(a.borrow())
+        }
+    }
+
+    #[test]
+    fn new_box_parse_works() {
+        let a = AStruct::new_box_parse("42").unwrap();
+
+        assert_eq!(*a.b, 42);
+    }
+
+    #[test]
+    fn head_error_is_returned() {
+        assert_eq!(AStruct::new_box_parse("x").unwrap_err(), Error::Parse);
+    }
+
+    #[test]
+    fn init_error_is_converted() {
+        assert_eq!(AStruct::new_rc_parse("-1").unwrap_err(), Error::Negative);
+    }
+}
+
+#[cfg(test)]
+mod sync_header {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    static INITS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(sync)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: i32,
+
+        #[header]
+        hdr: incrstruct::SyncHeader,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            INITS.fetch_add(1, Ordering::Relaxed);
+            a
+        }
+    }
+
+    #[test]
+    fn ensure_init_sync_inits_once() {
+        let a = Arc::new(unsafe { AStruct::new_uninit(42) });
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || *unsafe { AStruct::ensure_init_sync(Arc::as_ptr(&a)) }.b)
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+
+        assert_eq!(INITS.load(Ordering::Relaxed), 1);
+
+        let mut a = Arc::try_unwrap(a).ok().unwrap();
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod cells {
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            a
+        }
+    }
+
+    #[test]
+    fn new_rc_refcell_works() {
+        let a = AStruct::new_rc_refcell(42);
+
+        assert_eq!(*a.borrow().b, 42);
+        assert_eq!(*a.as_ref().borrow_mut().b, 42);
+    }
+
+    #[test]
+    fn new_rc_refcell_tracks_borrows() {
+        let a = AStruct::new_rc_refcell(42);
+        let b = a.as_ref().borrow_mut();
+
+        assert!(a.try_borrow().is_err());
+        drop(b);
+        assert!(a.try_borrow().is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn new_arc_mutex_works() {
+        let a = AStruct::new_arc_mutex(42);
+
+        assert_eq!(*a.as_ref().lock().unwrap().b, 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod send_sync {
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(unsafe_send, unsafe_sync)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            a
+        }
+    }
+
+    fn is_send_sync<T: Send + Sync>(_: &T) {}
+
+    #[test]
+    fn struct_is_send_sync() {
+        let a = AStruct::new_arc(42);
+
+        is_send_sync(&a);
+        assert_eq!(std::thread::spawn(move || *a.b).join().unwrap(), 42);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod incr_vec {
+    use super::*;
+    use incrstruct::IncrVec;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const A_FAIL: i32 = -1;
+
+    std::thread_local! {
+        static A_PANIC: core::cell::Cell<i32> = const { core::cell::Cell::new(i32::MIN) };
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            if *a.borrow() == A_FAIL {
+                Err(Error::Failed)
+            } else if *a.borrow() == A_PANIC.get() {
+                panic!("init_field_b panicked");
+            } else {
+                Ok(a.borrow())
+            }
+        }
+    }
+
+    fn values(v: &IncrVec<AStruct>) -> Vec<i32> {
+        v.iter().map(|a| *a.get_ref().b).collect()
+    }
+
+    #[test]
+    fn push_reinits_on_growth() {
+        let mut v = IncrVec::with_capacity(1);
+
+        for i in 0..10 {
+            unsafe { v.push(AStruct::new_uninit(RefCell::new(i))) }.unwrap();
+        }
+
+        assert!(v.capacity() > 1);
+        assert_eq!(values(&v), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn push_fails_gracefully() {
+        let mut v = IncrVec::new();
+
+        unsafe { v.push(AStruct::new_uninit(RefCell::new(1))) }.unwrap();
+        assert_eq!(
+            unsafe { v.push(AStruct::new_uninit(RefCell::new(A_FAIL))) }.err(),
+            Some(Error::Failed)
+        );
+
+        assert_eq!(values(&v), vec![1]);
+    }
+
+    #[test]
+    fn remove_reinits_later_elements() {
+        let mut v = IncrVec::new();
+
+        for i in 0..4 {
+            unsafe { v.push(AStruct::new_uninit(RefCell::new(i))) }.unwrap();
+        }
+        v.remove(1).unwrap();
+
+        assert_eq!(values(&v), vec![0, 2, 3]);
+
+        v.truncate(1);
+
+        assert_eq!(values(&v), vec![0]);
+    }
+
+    #[test]
+    fn remove_panic_drops_later_elements() {
+        let mut v = IncrVec::new();
+
+        for i in 0..4 {
+            unsafe { v.push(AStruct::new_uninit(RefCell::new(i))) }.unwrap();
+        }
+        A_PANIC.set(2);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| v.remove(0)));
+        A_PANIC.set(i32::MIN);
+
+        assert!(res.is_err());
+        assert_eq!(values(&v), vec![1]);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod slab {
+    use super::*;
+    use incrstruct::IncrSlab;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn emplace_keeps_addresses() {
+        let mut slab = IncrSlab::new();
+
+        let first = AStruct::emplace(&mut slab, RefCell::new(0));
+        let addr = &*slab.get(first).unwrap() as *const AStruct;
+        let keys: Vec<usize> = (1..100)
+            .map(|i| AStruct::emplace(&mut slab, RefCell::new(i)))
+            .collect();
+
+        assert_eq!(slab.len(), 100);
+        assert_eq!(&*slab.get(first).unwrap() as *const AStruct, addr);
+        assert_eq!(*slab.get(keys[98]).unwrap().b, 99);
+    }
+
+    #[test]
+    fn remove_reuses_slot() {
+        let mut slab = IncrSlab::new();
+
+        let a = AStruct::emplace(&mut slab, RefCell::new(1));
+        let b = AStruct::emplace(&mut slab, RefCell::new(2));
+
+        assert!(slab.remove(a));
+        assert!(!slab.remove(a));
+        assert!(slab.get(a).is_none());
+
+        assert_eq!(AStruct::emplace(&mut slab, RefCell::new(3)), a);
+        assert_eq!(*slab.get(a).unwrap().b, 3);
+        assert_eq!(*slab.get(b).unwrap().b, 2);
+        assert_eq!(slab.len(), 2);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod pinned_vec {
+    use super::*;
+    use incrstruct::PinnedVec;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn emplace_returns_ref() {
+        let mut v = PinnedVec::new();
+
+        assert_eq!(*AStruct::emplace(&mut v, RefCell::new(42)).b, 42);
+        assert_eq!(v.len(), 1);
+    }
+
+    #[test]
+    fn retain_keeps_addresses() {
+        let mut v = PinnedVec::new();
+
+        for i in 0..10 {
+            AStruct::emplace(&mut v, RefCell::new(i));
+        }
+        let addr = v.get(4).unwrap() as *const AStruct;
+        v.retain(|a| *a.b % 2 == 0);
+
+        assert_eq!(
+            v.iter().map(|a| *a.b).collect::<Vec<_>>(),
+            vec![0, 2, 4, 6, 8]
+        );
+        assert_eq!(v.get(2).unwrap() as *const AStruct, addr);
+
+        let removed = v.remove(2);
+
+        assert_eq!(&*removed as *const AStruct, addr);
+        assert_eq!(*v.get(2).unwrap().b, 6);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod arena {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use incrstruct::IncrArena;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted(i32);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: Counted,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a Counted) -> &'a i32 {
+            &a.0
+        }
+    }
+
+    #[test]
+    fn new_in_arena_drops_with_arena() {
+        let arena = IncrArena::new();
+
+        let values: Vec<_> = (0..100)
+            .map(|i| AStruct::new_in_arena(&arena, Counted(i)))
+            .collect();
+
+        assert_eq!(arena.len(), 100);
+        assert_eq!(*values[99].b, 99);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+
+        drop(values);
+        drop(arena);
+
+        assert_eq!(DROPS.load(Ordering::Relaxed), 100);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod boxed_slice {
+    use std::cell::Cell;
+
+    thread_local! {
+        static DROPS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    struct Counted(i32);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.set(DROPS.get() + 1);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: Counted,
+        c: i64,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const A_FAIL: i32 = -1;
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a Counted) -> Result<&'a i32, Error> {
+            if a.0 == A_FAIL {
+                Err(Error::Failed)
+            } else {
+                Ok(&a.0)
+            }
+        }
+    }
+
+    #[test]
+    fn new_boxed_slice_works() {
+        let v = AStruct::new_boxed_slice((0..3).map(|i| (Counted(i), i as i64))).unwrap();
+
+        assert_eq!(v.len(), 3);
+        assert_eq!(*v[2].b, 2);
+        assert_eq!(v[2].c, 2);
+    }
+
+    #[test]
+    fn new_boxed_slice_fails_gracefully() {
+        let res =
+            AStruct::new_boxed_slice([(Counted(1), 1), (Counted(A_FAIL), 2), (Counted(3), 3)]);
+
+        assert_eq!(res.err(), Some(Error::Failed));
+        assert_eq!(DROPS.get(), 3);
+    }
+}
+
+#[cfg(test)]
+mod in_place {
+    use super::*;
+    use core::mem::MaybeUninit;
+    use std::cell::Cell;
+
+    thread_local! {
+        static DROPS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    struct Counted(i32);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.set(DROPS.get() + 1);
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: Counted,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    const A_FAIL: i32 = -1;
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a Counted) -> Result<&'a i32, Error> {
+            if a.0 == A_FAIL {
+                Err(Error::Failed)
+            } else {
+                Ok(&a.0)
+            }
+        }
+    }
+
+    #[test]
+    fn init_at_works() {
+        let mut buf: [MaybeUninit<AStruct>; 2] = [MaybeUninit::uninit(), MaybeUninit::uninit()];
+        let ptr = buf[1].as_mut_ptr();
+
+        let a = unsafe { AStruct::init_at(ptr, Counted(42)) }.unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(&*a as *const AStruct, ptr as *const AStruct);
+
+        unsafe { drop_in_place(ptr) };
+        assert_eq!(DROPS.get(), 1);
+    }
+
+    #[test]
+    fn new_in_place_drops() {
+        let mut slot = MaybeUninit::uninit();
+
+        {
+            let mut a = unsafe { AStruct::new_in_place(&mut slot, Counted(42)) }.unwrap();
+
+            assert_eq!(*a.b, 42);
+            assert_eq!(*a.as_mut().b, 42);
+            assert_eq!(DROPS.get(), 0);
+        }
+
+        assert_eq!(DROPS.get(), 1);
+    }
+
+    #[test]
+    fn new_in_place_fails_gracefully() {
+        let mut slot = MaybeUninit::uninit();
+
+        assert_eq!(
+            unsafe { AStruct::new_in_place(&mut slot, Counted(A_FAIL)) }.err(),
+            Some(Error::Failed)
+        );
+        assert_eq!(DROPS.get(), 1);
+    }
+}
+
+#[cfg(test)]
+mod ensure_init_pin {
+    use super::*;
+    use core::pin::pin;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn ensure_init_pin_works() {
+        let a = pin!(unsafe { AStruct::new_uninit(RefCell::new(42)) });
+        let addr = a.as_ptr();
+        let mut a = unsafe { AStruct::ensure_init_pin(a) };
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(&*a as *const AStruct, addr);
+
+        unsafe { drop_in_place(a.as_mut().get_unchecked_mut()) };
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod static_lazy {
+    use incrstruct::StaticLazy;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(default)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a str,
+        a: String,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a String) -> &'a str {
+            a.as_str()
+        }
+    }
+
+    static A: StaticLazy<AStruct<'static>> =
+        unsafe { StaticLazy::new(|| AStruct::new_uninit("hello".to_string())) };
+
+    static DEFAULT: StaticLazy<AStruct<'static>> = StaticLazy::new_default();
+
+    #[test]
+    fn get_keeps_address() {
+        let first = &*A.get() as *const AStruct;
+
+        assert_eq!(A.get().b, "hello");
+        assert_eq!(&*A.get() as *const AStruct, first);
+    }
+
+    #[test]
+    fn new_default_works() {
+        assert_eq!(DEFAULT.get().b, "");
+    }
+
+    #[test]
+    fn get_from_threads() {
+        let handles: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| A.get().b.len()))
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 5);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod pinned_once_lock {
+    use incrstruct::{pinned_thread_local, PinnedOnceLock};
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a str,
+        a: String,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a String) -> &'a str {
+            a.as_str()
+        }
+    }
+
+    static LOCK: PinnedOnceLock<AStruct<'static>> = PinnedOnceLock::new();
+
+    #[test]
+    fn get_or_init_works() {
+        let a = LOCK.get_or_init(|| AStruct::new_uninit_guard("hello".to_string()));
+        let b = LOCK.get_or_init(|| unreachable!());
+
+        assert_eq!(a.b, "hello");
+        assert_eq!(&*a as *const AStruct, &*b as *const AStruct);
+        assert!(LOCK.get().is_some());
+    }
+
+    pinned_thread_local! {
+        static LOCAL: AStruct<'static> = AStruct::new_uninit_guard("local".to_string());
+    }
+
+    #[test]
+    fn thread_local_works() {
+        std::thread::spawn(|| {
+            let addr = LOCAL.with(|a| &*a.get() as *const AStruct);
+
+            LOCAL.with(|a| {
+                assert_eq!(a.get().b, "local");
+                assert_eq!(&*a.get() as *const AStruct, addr);
+            });
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(serde)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+        c: String,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            if *a.borrow() < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(a.borrow())
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_works() {
+        let a = AStruct::new_box(RefCell::new(42), "hello".to_string()).unwrap();
+        let json = serde_json::to_value(&*a).unwrap();
+
+        assert_eq!(json, serde_json::json!({ "a": 42, "c": "hello" }));
+        assert_eq!(
+            serde_json::to_string(&*a).unwrap(),
+            r#"{"a":42,"c":"hello"}"#
+        );
+
+        let b = AStruct::deserialize_box(json).unwrap();
+
+        assert_eq!(*b.b, 42);
+        assert_eq!(b.c, "hello");
+    }
+
+    #[test]
+    fn deserialize_box_fails_gracefully() {
+        let json = serde_json::json!({ "a": -1, "c": "hello" });
+
+        assert_eq!(
+            AStruct::deserialize_box(json).err().unwrap().to_string(),
+            "AStruct initialization failed"
+        );
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(boxed, serde)]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: incrstruct::AliasableBox<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            a
+        }
+    }
+
+    #[test]
+    fn deserialize_boxed_mode() {
+        let a: BStruct = serde_json::from_str(r#"{"a":42}"#).unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(serde_json::to_string(&a).unwrap(), r#"{"a":42}"#);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod clone {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(clone)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn clone_box_works() {
+        let a = AStruct::new_box(RefCell::new(42));
+        let b = a.clone_box();
+        let c = b.clone_rc();
+
+        assert_eq!(*b.b, 42);
+        assert_eq!(*c.b, 42);
+        assert!(!core::ptr::eq(&*a.b, &*b.b));
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(boxed, clone)]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        b: &'a str,
+        a: incrstruct::AliasableBox<str>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a str) -> &'a str {
+            a
+        }
+    }
+
+    #[test]
+    fn clone_boxed_mode() {
+        let a = BStruct::new("hello".into());
+        let b = a.clone();
+
+        drop(a);
+
+        assert_eq!(b.b, "hello");
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod derive_heads {
+    use std::collections::HashSet;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(derive_heads(PartialEq, Eq, Hash))]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a str,
+        a: String,
+        c: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a String) -> &'a str {
+            a.as_str()
+        }
+    }
+
+    #[test]
+    fn eq_compares_heads() {
+        let a = AStruct::new_box("hello".to_string(), 1);
+        let b = AStruct::new_box("hello".to_string(), 1);
+        let c = AStruct::new_box("hello".to_string(), 2);
+
+        assert!(*a == *b);
+        assert!(*a != *c);
+    }
+
+    #[test]
+    fn hash_uses_heads() {
+        let mut set = HashSet::new();
+
+        set.insert(AStruct::new_box("hello".to_string(), 1));
+        set.insert(AStruct::new_box("hello".to_string(), 1));
+        set.insert(AStruct::new_box("world".to_string(), 1));
+
+        assert_eq!(set.len(), 2);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod debug {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(debug)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: Ref<'a, i32>,
+        a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn debug_prints_fields() {
+        let a = AStruct::new_box(RefCell::new(42));
+        let s = format!("{:?}", a);
+
+        assert!(s.starts_with("AStruct { b: 42, a: RefCell { value: 42 }, hdr: Header {"));
+    }
+
+    #[test]
+    fn debug_uninit_skips_tails() {
+        let a = unsafe { AStruct::new_uninit(RefCell::new(42)) };
+        let s = format!("{:?}", unsafe { AStruct::debug_uninit(&a) });
+
+        assert!(s.starts_with("AStruct { b: <uninit>, a: RefCell { value: 42 }, hdr: Header {"));
+
+        unsafe { AStruct::drop_uninit(a) };
+    }
+}
+
+#[cfg(test)]
+mod header_niche {
+    use core::mem::size_of;
+
+    #[test]
+    fn option_header_does_not_grow() {
+        assert_eq!(
+            size_of::<Option<incrstruct::Header>>(),
+            size_of::<incrstruct::Header>()
+        );
+        assert_eq!(
+            size_of::<Option<incrstruct::Header<u8, ()>>>(),
+            size_of::<incrstruct::Header<u8, ()>>()
+        );
+    }
+
+    #[cfg(not(feature = "move_check"))]
+    #[test]
+    fn header_is_sized_to_bitsets() {
+        assert_eq!(size_of::<incrstruct::Header<u8, ()>>(), 3);
+        assert_eq!(size_of::<incrstruct::Header<u8>>(), 4);
+        assert_eq!(size_of::<incrstruct::Header<u16, ()>>(), 4);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod small_header {
+    use core::cell::Cell;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        c: &'a i32,
+        #[borrows(a)]
+        b: i32,
+        a: Cell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header<u8, ()>,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_c(b: &'a i32) -> Result<&'a i32, Error> {
+            Ok(b)
+        }
+
+        fn init_field_b(a: &'a Cell<i32>) -> Result<i32, Error> {
+            if a.get() < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(a.get())
+            }
+        }
+    }
+
+    #[incrstruct::incrstruct]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        b: &'a i32,
+        a: i32,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            a
+        }
+    }
+
+    #[test]
+    fn refresh_works() {
+        let mut a = AStruct::new_box(Cell::new(42)).unwrap();
+
+        a.a.set(43);
+        AStruct::refresh_b(a.as_mut()).unwrap();
+
+        assert_eq!(*a.c, 43);
+
+        a.a.set(-1);
+
+        assert_eq!(AStruct::refresh_b(a.as_mut()), Err(Error::Failed));
+        assert_eq!(*a.c, 43);
+    }
+
+    #[test]
+    fn drops_partial_init() {
+        let mut a = unsafe { AStruct::new_uninit(Cell::new(-1)) };
+
+        assert_eq!(
+            unsafe { AStruct::ensure_init(&mut a) }.err(),
+            Some(Error::Failed)
+        );
+
+        unsafe { AStruct::drop_uninit(a) };
+    }
+
+    #[test]
+    fn added_header_is_sized() {
+        let b = BStruct::new_box(42);
+
+        assert_eq!(*b.b, 42);
+        assert_eq!(
+            core::mem::size_of_val(&b.hdr),
+            core::mem::size_of::<incrstruct::Header<u8>>()
+        );
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod no_header {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(no_header)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(b)]
+        pub c: Ref<'a, i32>,
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: core::marker::PhantomPinned,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Result<Ref<'a, i32>, Error> {
+            Ok(a.borrow())
+        }
+
+        fn init_field_c(b: &'a Ref<'a, i32>) -> Result<Ref<'a, i32>, Error> {
+            if **b < 0 {
+                Err(Error::Failed)
+            } else {
+                Ok(Ref::clone(b))
+            }
+        }
+    }
+
+    #[test]
+    fn new_box_works() {
+        let a = AStruct::new_box(RefCell::new(42)).unwrap();
+
+        assert_eq!(*a.b, 42);
+        assert_eq!(*a.c, 42);
+        assert!(a.a.try_borrow_mut().is_err());
+    }
+
+    #[test]
+    fn ensure_init_consumes_uninit() {
+        let a = AStruct::new_uninit(RefCell::new(42));
+        let a = a.ensure_init().unwrap();
+
+        assert_eq!(*a.c, 42);
+    }
+
+    #[test]
+    fn failed_init_drops_heads() {
+        let a = RefCell::new(-1);
+
+        assert_eq!(AStruct::new_box(a).err(), Some(Error::Failed));
+    }
+
+    #[test]
+    fn header_takes_no_space() {
+        assert_eq!(
+            core::mem::size_of::<AStruct>(),
+            2 * core::mem::size_of::<Ref<i32>>() + core::mem::size_of::<RefCell<i32>>(),
+        );
+    }
+
+    #[test]
+    fn dropping_uninit_drops_heads() {
+        let rc = std::rc::Rc::new(());
+
+        #[derive(incrstruct::IncrStruct)]
+        #[incrstruct(no_header)]
+        struct BStruct<'a> {
+            #[borrows(a)]
+            b: &'a std::rc::Rc<()>,
+            a: std::rc::Rc<()>,
+
+            #[header]
+            hdr: core::marker::PhantomPinned,
+        }
+
+        impl<'a> BStructInit<'a> for BStruct<'a> {
+            fn init_field_b(a: &'a std::rc::Rc<()>) -> &'a std::rc::Rc<()> {
+                a
+            }
+        }
+
+        drop(BStruct::new_uninit(rc.clone()));
+        assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+
+        let b = BStruct::new_box(rc.clone());
+        assert_eq!(std::rc::Rc::strong_count(b.b), 2);
+        drop(b);
+        assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod uninit_guard {
+    use core::mem::MaybeUninit;
+    use std::rc::Rc;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Error {
+        Failed,
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    #[init_err(Error)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: &'a Rc<()>,
+        pub a: Rc<()>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a Rc<()>) -> Result<&'a Rc<()>, Error> {
+            if Rc::strong_count(a) > 2 {
+                Err(Error::Failed)
+            } else {
+                Ok(a)
+            }
+        }
+    }
+
+    #[test]
+    fn drop_drops_heads() {
+        let rc = Rc::new(());
+
+        drop(AStruct::new_uninit_guard(rc.clone()));
+
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn ensure_init_works() {
+        let rc = Rc::new(());
+        let guard = AStruct::new_uninit_guard(rc.clone());
+        let a = guard.ensure_init().unwrap();
+
+        assert!(Rc::ptr_eq(a.b, &rc));
+        drop(a);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn ensure_init_in_works() {
+        let rc = Rc::new(());
+        let mut slot = MaybeUninit::uninit();
+        let a = unsafe { AStruct::new_uninit_guard(rc.clone()).ensure_init_in(&mut slot) }.unwrap();
+
+        assert!(Rc::ptr_eq(a.b, &rc));
+        drop(a);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn failed_ensure_init_drops_heads() {
+        let rc = Rc::new(());
+        let _extra = rc.clone();
+        let guard = AStruct::new_uninit_guard(rc.clone());
+
+        assert_eq!(guard.ensure_init().err(), Some(Error::Failed));
+        assert_eq!(Rc::strong_count(&rc), 2);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod nested {
+    use core::cell::Cell;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(boxed)]
+    struct Inner<'a> {
+        #[borrows(a)]
+        b: i32,
+
+        a: incrstruct::AliasableBox<Cell<i32>>,
+        #[default]
+        _ph: core::marker::PhantomData<&'a ()>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> InnerInit<'a> for Inner<'a> {
+        fn init_field_b(a: &'a Cell<i32>) -> i32 {
+            a.get()
+        }
+    }
+
+    #[derive(incrstruct::IncrStruct)]
+    struct Outer<'a> {
+        #[borrows(inner)]
+        pub c: i32,
+
+        #[nested]
+        pub inner: Inner<'a>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> OuterInit<'a> for Outer<'a> {
+        fn init_field_c(inner: &'a Inner<'a>) -> i32 {
+            inner.b * 2
+        }
+    }
+
+    #[test]
+    fn force_init_cascades() {
+        let mut a = Outer::new_box(Inner::new(incrstruct::AliasableBox::new(Cell::new(21))));
+
+        assert_eq!(a.inner.b, 21);
+        assert_eq!(a.c, 42);
+
+        Inner::a(&a.inner).set(5);
+        Outer::force_init(unsafe { a.as_mut().get_unchecked_mut() });
+
+        assert_eq!(a.inner.b, 5);
+        assert_eq!(a.c, 10);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod project {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(project)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+
+        pub a: RefCell<i32>,
+        pub count: u32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn project_works() {
+        let mut a = AStruct::new_box(RefCell::new(42), 0);
+
+        let p = a.as_mut().project();
+        *p.count.get_mut() += 1;
+        let a_ref: &RefCell<i32> = p.a;
+        assert_eq!(**p.b, 42);
+        assert!(a_ref.try_borrow_mut().is_err());
+
+        assert_eq!(a.count, 1);
+    }
+
+    // Without projection, nothing pinned is exposed, so Drop is fine.
+    #[derive(incrstruct::IncrStruct)]
+    struct BStruct<'a> {
+        #[borrows(a)]
+        pub b: &'a i32,
+
+        pub a: i32,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> BStructInit<'a> for BStruct<'a> {
+        fn init_field_b(a: &'a i32) -> &'a i32 {
+            a
+        }
+    }
+
+    static B_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    impl Drop for BStruct<'_> {
+        fn drop(&mut self) {
+            B_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn drop_without_projection_works() {
+        drop(BStruct::new_box(42));
+
+        assert_eq!(B_DROPS.load(Ordering::Relaxed), 1);
+    }
+}
+
+#[cfg(all(test, feature = "pinned_init"))]
+mod pinned_init {
+    use super::*;
+
+    #[derive(incrstruct::IncrStruct)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        pub b: Ref<'a, i32>,
+        pub a: RefCell<i32>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a RefCell<i32>) -> Ref<'a, i32> {
+            a.borrow()
+        }
+    }
+
+    #[test]
+    fn stack_pin_init_works() {
+        incrstruct::pinned_init::stack_pin_init!(let a = AStruct::pin_init(RefCell::new(42)));
+
+        assert_eq!(*a.b, 42);
+        assert!(a.a.try_borrow_mut().is_err());
+    }
+}
+
+#[cfg(all(test, feature = "yoke"))]
+mod yoke {
+    use std::rc::Rc;
+
+    #[derive(incrstruct::IncrStruct)]
+    #[incrstruct(yoke)]
+    struct AStruct<'a> {
+        #[borrows(a)]
+        b: &'a str,
+
+        #[stable_deref]
+        a: Rc<str>,
+
+        #[header]
+        hdr: incrstruct::Header,
+    }
+
+    impl<'a> AStructInit<'a> for AStruct<'a> {
+        fn init_field_b(a: &'a str) -> &'a str {
+            a.split(' ').next().unwrap()
+        }
+    }
+
+    #[test]
+    fn into_yoke_works() {
+        let a = AStruct::new("hello world".into());
+        let y = AStruct::into_yoke(a);
+
+        assert_eq!(*y.get(), "hello");
+        assert_eq!(&**y.backing_cart(), "hello world");
+    }
+
+    #[test]
+    fn from_yoke_works() {
+        let y = AStruct::into_yoke(AStruct::new("hello world".into()));
+        let a = AStruct::from_yoke(y);
+
+        assert_eq!(a.b, "hello");
+        assert_eq!(&**AStruct::a(&a), "hello world");
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod pair {
+    use incrstruct::Pair;
+    use std::rc::Rc;
+
+    #[test]
+    fn new_works() {
+        let pair: Pair<String, Vec<&'static str>> =
+            Pair::new("hello new world".to_string(), |s| s.split(' ').collect());
+        let moved = [pair];
+
+        assert_eq!(moved[0].owner(), "hello new world");
+        assert_eq!(
+            moved[0].with_dependent(|_, words| words[1].to_string()),
+            "new"
+        );
+    }
+
+    #[test]
+    fn into_owner_works() {
+        let pair: Pair<String, &'static str> = Pair::new("hello".to_string(), |s| &s[1..]);
+
+        assert_eq!(pair.into_owner(), "hello");
+    }
+
+    #[test]
+    fn try_new_or_recover_returns_owner() {
+        let res: Result<Pair<String, &'static str>, _> =
+            Pair::try_new_or_recover("hi".to_string(), |s| s.get(3..).ok_or("short"));
+
+        assert_eq!(res.err(), Some(("hi".to_string(), "short")));
+    }
+
+    #[test]
+    fn drop_drops_owner() {
+        let rc = Rc::new(());
+        let pair: Pair<Rc<()>, &'static ()> = Pair::new(rc.clone(), |o| &**o);
+
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(pair);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
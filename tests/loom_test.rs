@@ -0,0 +1,128 @@
+//! Models `ensure_init_sync` with loom. Run using
+//! `RUSTFLAGS="--cfg loom" cargo test --release --test loom_test`.
+#![cfg(loom)]
+
+use core::ptr::drop_in_place;
+use std::sync::Arc;
+
+use loom::sync::atomic::{AtomicUsize, Ordering};
+use loom::thread;
+
+#[derive(incrstruct::IncrStruct)]
+#[incrstruct(sync)]
+struct AStruct<'a> {
+    #[borrows(a)]
+    b: &'a i32,
+    a: i32,
+
+    #[header]
+    hdr: incrstruct::SyncHeader,
+}
+
+impl<'a> AStructInit<'a> for AStruct<'a> {
+    fn init_field_b(a: &'a i32) -> &'a i32 {
+        a
+    }
+}
+
+#[test]
+fn ensure_init_sync_races() {
+    loom::model(|| {
+        let a = Arc::new(unsafe { AStruct::new_uninit(42) });
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || *unsafe { AStruct::ensure_init_sync(Arc::as_ptr(&a)) }.b)
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+
+        let mut a = Arc::try_unwrap(a).ok().unwrap();
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    });
+}
+
+#[test]
+fn ensure_init_sync_reads_published() {
+    loom::model(|| {
+        let a = Arc::new(unsafe { AStruct::new_uninit(42) });
+        let first = unsafe { AStruct::ensure_init_sync(Arc::as_ptr(&a)) } as *const AStruct;
+
+        // These take the fast path, while the main thread holds a
+        // shared reference.
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    let b = unsafe { AStruct::ensure_init_sync(Arc::as_ptr(&a)) };
+                    (*b.b, b as *const AStruct as usize)
+                })
+            })
+            .collect();
+        assert_eq!(*unsafe { &*first }.b, 42);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (42, first as usize));
+        }
+
+        let mut a = Arc::try_unwrap(a).ok().unwrap();
+        unsafe { drop_in_place(a.as_mut_ptr()) };
+    });
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Error {
+    Failed,
+}
+
+#[derive(incrstruct::IncrStruct)]
+#[incrstruct(sync, no_panic)]
+#[init_err(Error)]
+struct BStruct<'a> {
+    #[borrows(a)]
+    b: &'a i32,
+    a: i32,
+
+    #[header]
+    hdr: incrstruct::SyncHeader,
+}
+
+impl<'a> BStructInit<'a> for BStruct<'a> {
+    fn init_field_b(_a: &'a i32) -> Result<&'a i32, Error> {
+        Err(Error::Failed)
+    }
+}
+
+#[test]
+fn ensure_init_sync_fails_once() {
+    loom::model(|| {
+        let a = Arc::new(unsafe { BStruct::new_uninit(42) });
+        let failures = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let a = a.clone();
+                let failures = failures.clone();
+                thread::spawn(
+                    move || match unsafe { BStruct::ensure_init_sync(Arc::as_ptr(&a)) } {
+                        Err(incrstruct::Error::Init(Error::Failed)) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(incrstruct::Error::State(incrstruct::StateError::Poisoned)) => {}
+                        _ => panic!("unexpected result"),
+                    },
+                )
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(failures.load(Ordering::Relaxed), 1);
+
+        let a = Arc::try_unwrap(a).ok().unwrap();
+        unsafe { BStruct::drop_uninit(a) }.unwrap();
+    });
+}